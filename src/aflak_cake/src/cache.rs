@@ -49,10 +49,12 @@ impl<T, E> CacheRef<T, E> {
     ///
     /// If cached value is present and newer than the providedd instant, then
     /// do not do the heavy computation and return the cached value.
+    /// A cached value is always returned if `frozen` is true.
     pub(crate) fn compute<F>(
         &self,
         t_idx: TransformIdx,
         t_instant: Instant,
+        frozen: bool,
         f: F,
     ) -> Option<Timed<Vec<Result<Arc<T>, Arc<E>>>>>
     where
@@ -63,7 +65,7 @@ impl<T, E> CacheRef<T, E> {
         } else {
             self.in_use.fetch_add(1, Ordering::SeqCst);
 
-            let ret = unsafe { (*self.inner).compute(t_idx, t_instant, f) };
+            let ret = unsafe { (*self.inner).compute(t_idx, t_instant, frozen, f) };
 
             self.in_use.fetch_sub(1, Ordering::SeqCst);
 
@@ -102,6 +104,19 @@ impl<T, E> Cache<T, E> {
         None
     }
 
+    /// Same as `get`, along with the instant the value was computed.
+    pub fn get_timed(&self, output: &Output) -> Option<Timed<Result<Arc<T>, Arc<E>>>> {
+        if let Some(some_cache_box) = self.cache.get(&output.t_idx) {
+            if let Some(ref cache_box) = *some_cache_box {
+                return cache_box
+                    .values
+                    .get(output.index())
+                    .map(|value| Timed::from_instant(value.clone(), cache_box.time));
+            }
+        }
+        None
+    }
+
     pub(crate) fn get_ref(&self) -> CacheRef<T, E> {
         CacheRef {
             inner: self,
@@ -122,6 +137,7 @@ impl<T, E> Cache<T, E> {
         &self,
         t_idx: TransformIdx,
         t_instant: Instant,
+        frozen: bool,
         f: F,
     ) -> Timed<Vec<Result<Arc<T>, Arc<E>>>>
     where
@@ -129,7 +145,7 @@ impl<T, E> Cache<T, E> {
     {
         if let Some(some_cache_box) = self.cache.get(&t_idx) {
             if let Some(ref cache_box) = *some_cache_box {
-                if cache_box.time >= t_instant {
+                if frozen || cache_box.time >= t_instant {
                     return Timed::from_instant(cache_box.values.clone(), cache_box.time);
                 }
             }
@@ -8,7 +8,7 @@ use variant_name::VariantName;
 use super::super::ConvertibleVariants;
 use dst::node::{Node, NodeId};
 use dst::{DSTError, Input, InputDefaultsMut, InputList, Output, OutputId, TransformIdx, DST};
-use dst::{MetaTransform, TransformAndDefaults};
+use dst::{InputSlot, MetaTransform, NodeSnapshot, TransformAndDefaults, TransformMode};
 use transform::Transform;
 
 impl<'t, T: 't, E: 't> DST<'t, T, E>
@@ -39,6 +39,26 @@ where
     ///
     /// If input is already connector to another output, delete this output.
     pub fn connect(&mut self, output: Output, input: Input) -> Result<(), DSTError>
+    where
+        T: ConvertibleVariants,
+    {
+        self.check_connect(output, input)?;
+        // Delete input if it is already attached somewhere
+        for input_list in self.edges.values_mut() {
+            input_list.inputs.retain(|input_| input_ != &input)
+        }
+        let inputs = self
+            .edges
+            .entry(output)
+            .or_insert_with(|| InputList::new(vec![]));
+        inputs.push(input);
+        self.transforms.get_mut(&input.t_idx).unwrap().updated_now();
+        Ok(())
+    }
+
+    /// Check that an output can be connected to an input, without connecting
+    /// them. Return the error [`DST::connect`] would return otherwise.
+    pub fn check_connect(&self, output: Output, input: Input) -> Result<(), DSTError>
     where
         T: ConvertibleVariants,
     {
@@ -62,22 +82,13 @@ where
                 "Connecting {} to {} would create a cycle!",
                 output, input
             )))
-        } else if !self.is_edge_compatible(&input, &output) {
+        } else if let Some((output_type, input_type)) = self.incompatible_types(&input, &output) {
             Err(DSTError::IncompatibleTypes(format!(
-                "Cannot connect {} to {}. Output does not provide the required input type.",
-                output, input
+                "Cannot connect {} to {}. Output of type '{}' cannot be converted into \
+                 the required input type '{}'.",
+                output, input, output_type, input_type
             )))
         } else {
-            // Delete input if it is already attached somewhere
-            for input_list in self.edges.values_mut() {
-                input_list.inputs.retain(|input_| input_ != &input)
-            }
-            let inputs = self
-                .edges
-                .entry(output)
-                .or_insert_with(|| InputList::new(vec![]));
-            inputs.push(input);
-            self.transforms.get_mut(&input.t_idx).unwrap().updated_now();
             Ok(())
         }
     }
@@ -179,21 +190,25 @@ where
     }
 
     /// Check if edge can be added to the current graph.
-    /// Especially check if the input type is the same as the output type.
-    fn is_edge_compatible(&self, input: &Input, output: &Output) -> bool
+    /// Especially check if the output type can be converted into the input type.
+    ///
+    /// Return the names of the output and input types if they are incompatible.
+    fn incompatible_types(
+        &self,
+        input: &Input,
+        output: &Output,
+    ) -> Option<(&'static str, &'static str)>
     where
         T: ConvertibleVariants,
     {
-        match (
-            self.get_transform(input.t_idx),
-            self.get_transform(output.t_idx),
-        ) {
-            (Some(input_t), Some(output_t)) => {
-                let input_type = input_t.nth_input_type(input.input_i.into());
-                let output_type = output_t.nth_output_type(output.output_i.into());
-                T::convertible(output_type.name(), input_type.name())
-            }
-            _ => false,
+        let input_t = self.get_transform(input.t_idx)?;
+        let output_t = self.get_transform(output.t_idx)?;
+        let input_type = input_t.nth_input_type(input.input_i.into()).name();
+        let output_type = output_t.nth_output_type(output.output_i.into()).name();
+        if T::convertible(output_type, input_type) {
+            None
+        } else {
+            Some((output_type, input_type))
         }
     }
 }
@@ -219,6 +234,162 @@ where
     }
 }
 
+impl<'t, T: 't, E: 't> DST<'t, T, E>
+where
+    T: Clone,
+{
+    /// Copy the node with given ID and the links attached to it, so that it
+    /// can be restored after it is removed.
+    ///
+    /// Return [`None`] if the node does not exist.
+    pub fn node_snapshot(&self, node_id: &NodeId) -> Option<NodeSnapshot<'t, T, E>> {
+        match *node_id {
+            NodeId::Transform(t_idx) => self.transforms.get(&t_idx).map(|meta| {
+                let inputs = self
+                    .outputs_attached_to_transform(t_idx)
+                    .unwrap_or_default();
+                let links = self
+                    .links_iter()
+                    .filter(|(output, _)| output.t_idx == t_idx)
+                    .map(|(output, input_slot)| (*output, input_slot))
+                    .collect();
+                NodeSnapshot::Transform {
+                    t_idx,
+                    meta: meta.clone(),
+                    inputs,
+                    links,
+                }
+            }),
+            NodeId::Output(output_id) => {
+                self.outputs
+                    .get(&output_id)
+                    .map(|output| NodeSnapshot::Output {
+                        output_id,
+                        output: *output,
+                    })
+            }
+        }
+    }
+
+    /// Put back a node removed from the graph with the links it had when
+    /// `snapshot` was taken.
+    ///
+    /// The node replaces any node with the same ID. Links to nodes that do not
+    /// exist anymore are not restored.
+    pub fn restore_node(&mut self, snapshot: NodeSnapshot<'t, T, E>) {
+        match snapshot {
+            NodeSnapshot::Transform {
+                t_idx,
+                mut meta,
+                inputs,
+                links,
+            } => {
+                meta.updated_now();
+                self.transforms.insert(t_idx, meta);
+                for (i, some_output) in inputs.into_iter().enumerate() {
+                    if let Some(output) = some_output {
+                        if self.transforms.contains_key(&output.t_idx) {
+                            self.insert_edge(output, Input::new(t_idx, i));
+                        }
+                    }
+                }
+                for (output, input_slot) in links {
+                    match input_slot {
+                        InputSlot::Transform(input) => {
+                            if self.transforms.contains_key(&input.t_idx) {
+                                self.insert_edge(output, input);
+                            }
+                        }
+                        InputSlot::Output(output_id) => {
+                            if let Some(some_output) = self.outputs.get_mut(&output_id) {
+                                *some_output = Some(output);
+                            }
+                        }
+                    }
+                }
+            }
+            NodeSnapshot::Output { output_id, output } => {
+                self.outputs.insert(output_id, output);
+            }
+        }
+    }
+
+    /// Make a new [`DST`] out of the nodes with given IDs and of the links
+    /// between them. Nodes keep their ID.
+    ///
+    /// Output nodes attached to a transform that is not part of the new graph
+    /// are detached.
+    pub fn extract(&self, node_ids: &[NodeId]) -> DST<'t, T, E> {
+        let mut dst = DST::new();
+        for node_id in node_ids {
+            if let NodeId::Transform(t_idx) = *node_id {
+                if let Some(meta) = self.transforms.get(&t_idx) {
+                    dst.transforms.insert(t_idx, meta.clone());
+                }
+            }
+        }
+        for (output, input_list) in self.edges.iter() {
+            if dst.transforms.contains_key(&output.t_idx) {
+                for input in input_list.inputs.iter() {
+                    if dst.transforms.contains_key(&input.t_idx) {
+                        dst.insert_edge(*output, *input);
+                    }
+                }
+            }
+        }
+        for node_id in node_ids {
+            if let NodeId::Output(output_id) = *node_id {
+                if let Some(some_output) = self.outputs.get(&output_id) {
+                    let some_output =
+                        some_output.filter(|output| dst.transforms.contains_key(&output.t_idx));
+                    dst.outputs.insert(output_id, some_output);
+                }
+            }
+        }
+        dst
+    }
+
+    /// Add all the nodes and links of `other` to this graph. Nodes are given
+    /// new IDs.
+    ///
+    /// Return the ID of each node of `other` along with its new ID.
+    pub fn merge(&mut self, other: DST<'t, T, E>) -> Vec<(NodeId, NodeId)> {
+        let mut new_ids = Vec::with_capacity(other.transforms.len() + other.outputs.len());
+        let mut new_t_idx = BTreeMap::new();
+        for (t_idx, mut meta) in other.transforms {
+            let new_idx = self.new_transform_idx();
+            meta.updated_now();
+            self.transforms.insert(new_idx, meta);
+            new_t_idx.insert(t_idx, new_idx);
+            new_ids.push((NodeId::Transform(t_idx), NodeId::Transform(new_idx)));
+        }
+        let new_output = |output: Output| Output::new(new_t_idx[&output.t_idx], output.index());
+        for (output, input_list) in other.edges {
+            for input in input_list.inputs {
+                let input = Input::new(new_t_idx[&input.t_idx], input.index());
+                self.insert_edge(new_output(output), input);
+            }
+        }
+        for (output_id, some_output) in other.outputs {
+            let new_id = self.new_output_id();
+            self.outputs.insert(new_id, some_output.map(new_output));
+            new_ids.push((NodeId::Output(output_id), NodeId::Output(new_id)));
+        }
+        new_ids
+    }
+
+    fn insert_edge(&mut self, output: Output, input: Input) {
+        for input_list in self.edges.values_mut() {
+            input_list.inputs.retain(|input_| input_ != &input)
+        }
+        self.edges
+            .entry(output)
+            .or_insert_with(|| InputList::new(vec![]))
+            .push(input);
+        self.transforms.get_mut(&input.t_idx).unwrap().updated_now();
+    }
+}
+
 impl<'t, T: 't, E: 't> DST<'t, T, E> {
     /// Make a new empty [`DST`].
     pub fn new() -> Self {
@@ -265,6 +436,20 @@ impl<'t, T: 't, E: 't> DST<'t, T, E> {
         self.transforms.get_mut(&idx).map(|t| t.defaults_mut())
     }
 
+    /// Get the mode of a transform from its [`TransformIdx`].
+    /// Return [`None`] if the target transform does not exist.
+    pub fn get_transform_mode(&self, idx: TransformIdx) -> Option<TransformMode> {
+        self.transforms.get(&idx).map(|t| t.mode())
+    }
+
+    /// Set the mode of a transform from its [`TransformIdx`].
+    /// Do nothing if the target transform does not exist.
+    pub fn set_transform_mode(&mut self, idx: TransformIdx, mode: TransformMode) {
+        if let Some(t) = self.transforms.get_mut(&idx) {
+            t.set_mode(mode);
+        }
+    }
+
     /// Get a node from its [`NodeId`].
     pub fn get_node(&self, idx: &NodeId) -> Option<Node<'_, 't, T, E>> {
         match *idx {
@@ -1,5 +1,5 @@
 //! Data types for computational results.
-use std::borrow::Cow;
+use std::borrow::{Borrow, Cow};
 use std::collections;
 use std::error;
 use std::fmt;
@@ -10,10 +10,10 @@ use rayon;
 
 use super::super::ConvertibleVariants;
 use cache::{Cache, CacheRef};
-use dst::{Input, Output, OutputId, TransformIdx, DST};
+use dst::{Input, Output, OutputId, TransformIdx, TransformMode, DST};
 use future::Task;
 use timed::Timed;
-use transform::{ArgumentError, CallError};
+use transform::{ArgumentError, CallError, Context, Transform};
 use variant_name::VariantName;
 
 /// The successful result of a computation.
@@ -46,6 +46,11 @@ pub enum ComputeError<E> {
     },
     UnusableCache(Output),
     NothingDoneYet,
+    /// No input of a bypassed node can be passed through to `output`.
+    BypassError {
+        output: Output,
+        t_name: Cow<'static, str>,
+    },
     /// Represent an error during computing, caused by user-defined
     /// transformations. This is usually caused by an unexpected input causing
     /// the calculation to abort.
@@ -113,6 +118,11 @@ impl<E: fmt::Display> fmt::Display for ComputeError<E> {
                 t_name,
             } => write!(f, "{}\n    in node #{} {}", cause, t_idx.0, t_name),
             NothingDoneYet => write!(f, "Nothing done yet!"),
+            BypassError { output, t_name } => write!(
+                f,
+                "No input of bypassed '{}' can be passed through to {}",
+                t_name, output
+            ),
             ErrorStack {
                 cause,
                 t_idx,
@@ -142,6 +152,82 @@ impl<E: fmt::Display> fmt::Display for ComputeError<E> {
     }
 }
 
+impl<E> ComputeError<E> {
+    /// Get the error at the bottom of the stack, i.e. the error that caused
+    /// this one. The error itself is returned if it is not an error stack.
+    pub fn root_cause(&self) -> &ComputeError<E> {
+        let mut error = self;
+        while let ComputeError::ErrorStack { cause, .. } = error {
+            error = cause;
+        }
+        error
+    }
+
+    /// Get the nodes the error went through with their names, from the node
+    /// where the root cause occurred to the node where the error is.
+    ///
+    /// These are the nodes shown on the `in node #N` lines of the error
+    /// message.
+    pub fn node_stack(&self) -> Vec<(TransformIdx, &str)> {
+        use self::ComputeError::*;
+
+        let mut stack = vec![];
+        let mut error = self;
+        loop {
+            match error {
+                ErrorStack {
+                    cause,
+                    t_idx,
+                    t_name,
+                } => {
+                    stack.push((*t_idx, t_name.as_ref()));
+                    error = cause;
+                }
+                RuntimeError { t_idx, t_name, .. } | ArgumentError { t_idx, t_name, .. } => {
+                    stack.push((*t_idx, t_name.as_ref()));
+                    break;
+                }
+                _ => break,
+            }
+        }
+        stack.reverse();
+        stack
+    }
+
+    /// Get the node where the error occurred, if any.
+    ///
+    /// For an error stack, this is the node where the stack is, not the node
+    /// where the root cause occurred.
+    pub fn t_idx(&self) -> Option<TransformIdx> {
+        use self::ComputeError::*;
+
+        match self {
+            MissingOutput(output) | UnusableCache(output) | BypassError { output, .. } => {
+                Some(output.t_idx)
+            }
+            MissingNode(t_idx) => Some(*t_idx),
+            MissingDependency { input, .. } => Some(input.t_idx),
+            RuntimeError { t_idx, .. } | ArgumentError { t_idx, .. } | ErrorStack { t_idx, .. } => {
+                Some(*t_idx)
+            }
+            UnattachedOutputID(_) | MissingOutputID(_) | NothingDoneYet => None,
+        }
+    }
+}
+
+impl<E: fmt::Display> ComputeError<E> {
+    /// Get the message of the root cause of the error, without the `in node
+    /// #N` lines of the nodes it went through. See
+    /// [`node_stack`](#method.node_stack) for these nodes.
+    pub fn root_message(&self) -> String {
+        match self.root_cause() {
+            ComputeError::RuntimeError { cause, .. } => cause.to_string(),
+            ComputeError::ArgumentError { cause, .. } => cause.to_string(),
+            error => error.to_string(),
+        }
+    }
+}
+
 impl<T, E> DST<'static, T, E>
 where
     T: Clone + VariantName + ConvertibleVariants + Send + Sync,
@@ -193,7 +279,8 @@ where
             return Err(Timed::from(Arc::new(ComputeError::MissingOutput(output))));
         }
 
-        if let Some(result) = cache.compute(t_idx, updated_on, || {
+        let frozen = meta.mode() == TransformMode::Frozen;
+        if let Some(result) = cache.compute(t_idx, updated_on, frozen, || {
             let deps = self
                 .outputs_attached_to_transform(t_idx)
                 .expect("Tranform not found!");
@@ -224,6 +311,14 @@ where
             });
 
             let output_count = t.outputs().len();
+            if meta.mode() == TransformMode::Bypassed {
+                return (0..output_count)
+                    .map(|out_i| match bypass(t, t_idx, out_i, &results)? {
+                        (input_i, None) => results[input_i].clone(),
+                        (_, Some(converted)) => Ok(Arc::new(converted)),
+                    })
+                    .collect();
+            }
             let mut op = t.start();
             for result in &results {
                 match result {
@@ -248,7 +343,7 @@ where
                 }
             }
             let mut out = Vec::with_capacity(output_count);
-            for output in op.call() {
+            for output in op.call_in(&Context::new(self, t_idx)) {
                 out.push(output.map(Arc::new).map_err(|e| {
                     Arc::new(match e {
                         CallError::FunctionError(e) => ComputeError::RuntimeError {
@@ -364,6 +459,13 @@ where
             };
         }
 
+        if meta.mode() == TransformMode::Bypassed {
+            return match bypass(t, t_idx, index, &results)? {
+                (input_i, None) => results[input_i].clone(),
+                (_, Some(converted)) => Ok(converted),
+            };
+        }
+
         let mut op = t.start();
         for result in &results {
             match result {
@@ -388,7 +490,7 @@ where
             }
         }
 
-        let out = if let Some(out) = op.call().nth(index) {
+        let out = if let Some(out) = op.call_in(&Context::new(self, t_idx)).nth(index) {
             out.map_err(|e| {
                 Arc::new(match e {
                     CallError::FunctionError(e) => ComputeError::RuntimeError {
@@ -419,3 +521,63 @@ where
         out
     }
 }
+
+/// Get output `out_i` of the bypassed transform `t` out of the results of its
+/// inputs. The output is the value of the first input whose type can be
+/// converted into the type of the output.
+///
+/// Return the index of this input, and the value converted into the type of
+/// the output if the value is not already of this type.
+fn bypass<T, E, V>(
+    t: &Transform<'_, T, E>,
+    t_idx: TransformIdx,
+    out_i: usize,
+    results: &[Result<V, Arc<ComputeError<E>>>],
+) -> Result<(usize, Option<T>), Arc<ComputeError<E>>>
+where
+    T: Clone + VariantName + ConvertibleVariants,
+    V: Borrow<T>,
+{
+    let output = Output::new(t_idx, out_i);
+    let output_type = if let Some(output_type) = t.outputs().get(out_i) {
+        output_type.name()
+    } else {
+        return Err(Arc::new(ComputeError::MissingOutput(output)));
+    };
+    let bypass_error = || {
+        Arc::new(ComputeError::BypassError {
+            output,
+            t_name: t.name(),
+        })
+    };
+    let input_types = t.input_types();
+    let input_i = input_types
+        .iter()
+        .position(|input_type| T::convertible(input_type.name(), output_type))
+        .ok_or_else(bypass_error)?;
+    let value = match results.get(input_i) {
+        Some(Ok(value)) => value.borrow(),
+        Some(Err(e)) => {
+            return Err(Arc::new(ComputeError::ErrorStack {
+                cause: e.clone(),
+                t_idx,
+                t_name: t.name(),
+            }))
+        }
+        None => return Err(bypass_error()),
+    };
+    if value.variant_name() == output_type {
+        return Ok((input_i, None));
+    }
+    // The value may have been converted into the type of the input first
+    let input_type = input_types[input_i].name();
+    T::convert(value.variant_name(), input_type, value)
+        .and_then(|input| {
+            T::convert(input_type, output_type, &input).map(|converted| match converted {
+                Bow::Borrowed(v) => v.clone(),
+                Bow::Owned(v) => v,
+            })
+        })
+        .map(|converted| (input_i, Some(converted)))
+        .ok_or_else(bypass_error)
+}
@@ -1,6 +1,6 @@
 use boow::Bow;
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use std::error::Error;
 use std::fmt;
 use std::time::Instant;
@@ -57,16 +57,84 @@ where
     T: VariantName,
 {
     /// Get max(updated_on) for all this transform's dependencies.
+    /// The dependencies of frozen transforms are ignored, as they are not used
+    /// until the transforms are unfrozen.
     /// panic if TransformIdx does not exist
     pub fn updated_on(&self, t_idx: TransformIdx) -> Instant {
         let mut updated_on = self.transforms[&t_idx].updated_on();
-        for dep in self._dependencies(Output::new(t_idx, 0)) {
-            let dep_t_idx = dep.transform_idx();
-            let dep_updated_on = self.transforms[&dep_t_idx].updated_on();
-            updated_on = updated_on.max(dep_updated_on);
+        let mut visited = BTreeSet::new();
+        let mut stack = vec![t_idx];
+        while let Some(t_idx) = stack.pop() {
+            if !visited.insert(t_idx) {
+                continue;
+            }
+            let meta = &self.transforms[&t_idx];
+            updated_on = updated_on.max(meta.updated_on());
+            if meta.mode() == TransformMode::Frozen {
+                continue;
+            }
+            if let Some(parent_outputs) = self.outputs_attached_to_transform(t_idx) {
+                stack.extend(parent_outputs.into_iter().flatten().map(|o| o.t_idx));
+            }
         }
         updated_on
     }
+
+    /// Summarize the given transform and the transforms it depends on, one
+    /// line per node, e.g. `#3 plus1 <- #1.1, -`. The arrow is followed by
+    /// the output connected to each input, as `#<node>.<output>`, or by `-`
+    /// if the input is not connected. Values of constants are left out.
+    ///
+    /// At most `max_nodes` nodes are listed, closest to the given transform
+    /// first. A last line then counts the nodes left out.
+    pub fn summary(&self, t_idx: TransformIdx, max_nodes: usize) -> Vec<String>
+    where
+        T: VariantName,
+    {
+        let mut visited = BTreeSet::new();
+        let mut order = vec![];
+        let mut queue = VecDeque::new();
+        queue.push_back(t_idx);
+        while let Some(t_idx) = queue.pop_front() {
+            if !self.transforms.contains_key(&t_idx) || !visited.insert(t_idx) {
+                continue;
+            }
+            order.push(t_idx);
+            if let Some(parent_outputs) = self.outputs_attached_to_transform(t_idx) {
+                queue.extend(parent_outputs.into_iter().flatten().map(|o| o.t_idx));
+            }
+        }
+
+        let mut lines: Vec<_> = order
+            .iter()
+            .take(max_nodes)
+            .map(|&t_idx| {
+                let t = self.transforms[&t_idx].transform();
+                let mut line = match t.algorithm() {
+                    Algorithm::Constant(_) => format!("#{} constant {}", t_idx.id(), t.name()),
+                    _ => format!("#{} {}", t_idx.id(), t.name()),
+                };
+                let inputs: Vec<_> = self
+                    .outputs_attached_to_transform(t_idx)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|output| match output {
+                        Some(output) => format!("#{}.{}", output.t_idx.id(), output.output_i.0 + 1),
+                        None => "-".to_owned(),
+                    })
+                    .collect();
+                if !inputs.is_empty() {
+                    line.push_str(" <- ");
+                    line.push_str(&inputs.join(", "));
+                }
+                line
+            })
+            .collect();
+        if order.len() > max_nodes {
+            lines.push(format!("... and {} more nodes", order.len() - max_nodes));
+        }
+        lines
+    }
 }
 
 /// An owned or borrowed Transform to which meta-data is added.
@@ -77,9 +145,28 @@ where
 pub struct MetaTransform<'t, T: 't, E: 't> {
     t: Bow<'t, Transform<'t, T, E>>,
     input_defaults: Vec<Option<T>>,
+    mode: TransformMode,
     updated_on: Instant,
 }
 
+/// Define how the outputs of a transformation node are computed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TransformMode {
+    /// The outputs are computed by calling the transformation.
+    Normal,
+    /// The transformation is skipped. Each output is the value of the first
+    /// input whose type can be converted into the type of the output.
+    Bypassed,
+    /// The last computed outputs are used, whatever changed upstream.
+    Frozen,
+}
+
+impl Default for TransformMode {
+    fn default() -> Self {
+        TransformMode::Normal
+    }
+}
+
 impl<'t, T, E> Clone for MetaTransform<'t, T, E>
 where
     T: Clone,
@@ -88,6 +175,7 @@ where
         Self {
             t: self.t.clone(),
             input_defaults: self.input_defaults.clone(),
+            mode: self.mode,
             updated_on: self.updated_on,
         }
     }
@@ -106,6 +194,7 @@ where
         Self {
             t,
             input_defaults,
+            mode: TransformMode::Normal,
             updated_on: Instant::now(),
         }
     }
@@ -119,6 +208,7 @@ impl<'t, T, E> MetaTransform<'t, T, E> {
         Self {
             t,
             input_defaults,
+            mode: TransformMode::Normal,
             updated_on: Instant::now(),
         }
     }
@@ -173,6 +263,17 @@ impl<'t, T, E> MetaTransform<'t, T, E> {
         self.updated_on.max(self.t.updated_on())
     }
 
+    pub fn mode(&self) -> TransformMode {
+        self.mode
+    }
+
+    pub(crate) fn set_mode(&mut self, mode: TransformMode) {
+        if self.mode != mode {
+            self.mode = mode;
+            self.updated_on = Instant::now();
+        }
+    }
+
     pub(crate) fn updated_now(&mut self) {
         self.updated_on = Instant::now();
     }
@@ -195,6 +296,37 @@ impl<'a, 't, T, E> InputDefaultsMut<'a, 't, T, E> {
 /// Tuple of a transformation and the default input values set up for it
 pub type TransformAndDefaults<'t, T, E> = (Bow<'t, Transform<'t, T, E>>, Vec<Option<T>>);
 
+/// Copy of a node and of the links attached to it, taken with
+/// [`DST::node_snapshot`].
+///
+/// Use [`DST::restore_node`] to put the node back in the graph after it was
+/// removed.
+#[derive(Debug)]
+pub enum NodeSnapshot<'t, T: 't, E: 't> {
+    Transform {
+        t_idx: TransformIdx,
+        meta: MetaTransform<'t, T, E>,
+        /// Outputs connected to each input of the transform
+        inputs: Vec<Option<Output>>,
+        /// Links starting from the outputs of the transform
+        links: Vec<(Output, InputSlot)>,
+    },
+    Output {
+        output_id: OutputId,
+        output: Option<Output>,
+    },
+}
+
+impl<'t, T, E> NodeSnapshot<'t, T, E> {
+    /// Get the [`NodeId`] of the node this snapshot was taken from.
+    pub fn node_id(&self) -> NodeId {
+        match *self {
+            NodeSnapshot::Transform { t_idx, .. } => NodeId::Transform(t_idx),
+            NodeSnapshot::Output { output_id, .. } => NodeId::Output(output_id),
+        }
+    }
+}
+
 /// Uniquely identify an ouput of a transformation node
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct Output {
@@ -10,7 +10,7 @@ use uuid::Uuid;
 use variant_name::VariantName;
 
 use super::ConvertibleVariants;
-use dst::{DSTError, Input, Output, OutputId, TransformIdx, DST};
+use dst::{DSTError, Input, Output, OutputId, TransformIdx, TransformMode, DST};
 use macros::MacroManager;
 use transform::{Algorithm, Transform, Version};
 
@@ -184,6 +184,7 @@ pub struct SerialDST<'d, T: 'd> {
 struct SerialMetaTransform<'d, T: 'd> {
     t: SerialTransform<'d, T>,
     input_defaults: Vec<Option<T>>,
+    mode: TransformMode,
 }
 
 impl<'d, T> SerialDST<'d, T>
@@ -201,6 +202,7 @@ where
                         SerialMetaTransform {
                             t: SerialTransform::new(meta.transform()),
                             input_defaults: meta.defaults().to_vec(),
+                            mode: meta.mode(),
                         },
                     )
                 })
@@ -225,6 +227,9 @@ pub struct DeserDST<T> {
 struct DeserMetaTransform<T> {
     t: DeserTransform<T>,
     input_defaults: Vec<Option<T>>,
+    /// Missing in exports made before transforms had a mode
+    #[serde(default)]
+    mode: TransformMode,
 }
 
 impl<T> DeserDST<T> {
@@ -241,6 +246,7 @@ impl<T> DeserDST<T> {
                         DeserMetaTransform {
                             t: DeserTransform::from_transform(meta.transform()),
                             input_defaults: meta.defaults().to_vec(),
+                            mode: meta.mode(),
                         },
                     )
                 })
@@ -305,6 +311,7 @@ where
             }
 
             dst.add_transform_with_idx(t_idx, t, input_defaults);
+            dst.set_transform_mode(t_idx, meta.mode);
         }
         for (output, input) in self.edges {
             dst.connect(output, input).map_err(|err| {
@@ -28,7 +28,8 @@ pub use boow::Bow;
 pub use cache::Cache;
 pub use dst::{
     compute, DSTError, Input, InputDefaultsMut, InputSlot, LinkIter, MetaTransform, Node, NodeId,
-    NodeIter, Output, OutputId, TransformAndDefaults, TransformIdx, DST,
+    NodeIter, NodeSnapshot, Output, OutputId, TransformAndDefaults, TransformIdx, TransformMode,
+    DST,
 };
 pub use export::{DeserDST, ImportError, NamedAlgorithms, SerialDST};
 pub use future::Task;
@@ -119,16 +120,24 @@ pub trait ConvertibleVariants: VariantName + Sized + 'static {
 macro_rules! cake_fn {
     // Special case where no argument is provided
     ($fn_name: ident<$enum_name: ident, $err_type: ty>() $fn_block: block) => {
+        cake_fn!{$fn_name<$enum_name, $err_type>() with _context $fn_block}
+    };
+    ($fn_name: ident<$enum_name: ident, $err_type: ty>() with $ctx: ident $fn_block: block) => {
         fn $fn_name(
-            _: Vec<&$enum_name>,
+            _: Vec<$crate::Bow<$enum_name>>,
+            $ctx: &$crate::Context<$enum_name, $err_type>,
         ) -> Vec<Result<$enum_name, $err_type>> {
             $fn_block
         }
     };
     // Standard case
     ($fn_name: ident<$enum_name: ident, $err_type: ty>($($x: ident: $x_type: ident),*) $fn_block: block) => {
+        cake_fn!{$fn_name<$enum_name, $err_type>($($x: $x_type),*) with _context $fn_block}
+    };
+    ($fn_name: ident<$enum_name: ident, $err_type: ty>($($x: ident: $x_type: ident),*) with $ctx: ident $fn_block: block) => {
         fn $fn_name(
             input: Vec<$crate::Bow<$enum_name>>,
+            $ctx: &$crate::Context<$enum_name, $err_type>,
         ) -> Vec<Result<$enum_name, $err_type>> {
             #[allow(non_camel_case_types)]
             enum Args { $($x,)* }
@@ -168,11 +177,32 @@ macro_rules! cake_fn {
 ///     // Must return a Vec<Result<AlgoIO, !>>!
 ///     vec![Ok(AlgoIO::Integer(i + 1))]
 /// });
+///
+/// // Optionally, the transform can be given a category, used to sort
+/// // transforms in menus.
+/// let minus_one_trans = cake_transform!(
+///     category: "Arithmetic", "Subtract 1", 1, 0, 0,
+///     minus1<AlgoIO, E>(i: Integer = 1) -> Integer {
+///     vec![Ok(AlgoIO::Integer(i - 1))]
+/// });
+/// assert_eq!(minus_one_trans.category(), Some("Arithmetic"));
+///
+/// // The body can also be given the context in which the transform is
+/// // called, for example to summarize the program producing its inputs.
+/// let depth_trans = cake_transform!(
+///     "Count the nodes producing the input", 1, 0, 0,
+///     depth<AlgoIO, E>(_i: Integer = 0) -> Integer with context {
+///     let nodes = context.program_summary(100).len().saturating_sub(1);
+///     vec![Ok(AlgoIO::Integer(nodes as u64))]
+/// });
 /// ```
 #[macro_export]
 macro_rules! cake_transform {
-    ($description: expr, $major: expr, $minor: expr, $patch: expr, $fn_name: ident<$enum_name: ident, $err_type: ty>($($x: ident: $x_type: ident $(= $x_default_val: expr), *),*) -> $($out_type: ident),* $fn_block: block) => {{
-        cake_fn!{$fn_name<$enum_name, $err_type>($($x: $x_type),*) $fn_block}
+    (@category $category: expr, $description: expr, $major: expr, $minor: expr, $patch: expr, $fn_name: ident<$enum_name: ident, $err_type: ty>($($x: ident: $x_type: ident $(= $x_default_val: expr), *),*) -> $($out_type: ident),* $fn_block: block) => {
+        cake_transform!(@category $category, $description, $major, $minor, $patch, $fn_name<$enum_name, $err_type>($($x: $x_type $(= $x_default_val),*),*) -> $($out_type),* with _context $fn_block)
+    };
+    (@category $category: expr, $description: expr, $major: expr, $minor: expr, $patch: expr, $fn_name: ident<$enum_name: ident, $err_type: ty>($($x: ident: $x_type: ident $(= $x_default_val: expr), *),*) -> $($out_type: ident),* with $ctx: ident $fn_block: block) => {{
+        cake_fn!{$fn_name<$enum_name, $err_type>($($x: $x_type),*) with $ctx $fn_block}
 
         $crate::Transform::from_algorithm($crate::Algorithm::Function {
                 f: $fn_name,
@@ -183,6 +213,7 @@ macro_rules! cake_transform {
                     patch: $patch
                 },
                 description: $description,
+                category: $category,
                 inputs: vec![$(
                     $crate::TransformInputSlot {
                         type_id: $crate::TypeId(stringify!($x_type)),
@@ -192,6 +223,12 @@ macro_rules! cake_transform {
                 outputs: vec![$($crate::TypeId(stringify!($out_type)), )*],
         })
     }};
+    (category: $category: expr, $($rest: tt)*) => {
+        cake_transform!(@category Some($category), $($rest)*)
+    };
+    ($description: expr, $($rest: tt)*) => {
+        cake_transform!(@category None, $description, $($rest)*)
+    };
 }
 
 /// Helper macro for internal use.
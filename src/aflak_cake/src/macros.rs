@@ -270,6 +270,8 @@ impl<'t, T, E> Macro<'t, T, E> {
     where
         T: Clone + VariantName + ConvertibleVariants,
     {
+        // The macro is computed anew on each call, so frozen transforms are
+        // computed as usual
         let mut cache = HashMap::new();
 
         let mut dst = self.dst.clone();
@@ -415,13 +417,20 @@ impl<'t, T, E> MacroManager<'t, T, E> {
     /// Used internally for import/export.
     /// Fail if a macro with the same ID is already added.
     pub fn add_macro(&mut self, macr: Macro<'t, T, E>) -> Result<(), ImportError> {
+        self.add_handle(MacroHandle::from(macr))
+    }
+
+    /// Manage a macro that is already in use, e.g. by pasted nodes.
+    /// Fail if a macro with the same ID is already added.
+    pub fn add_handle(&mut self, handle: MacroHandle<'t, T, E>) -> Result<(), ImportError> {
         use std::collections::btree_map::Entry;
-        match self.macros.entry(macr.id) {
+        let id = handle.id();
+        match self.macros.entry(id) {
             Entry::Vacant(entry) => {
-                entry.insert(MacroHandle::from(macr));
+                entry.insert(handle);
                 Ok(())
             }
-            Entry::Occupied(_) => Err(ImportError::DuplicateMacroId(macr.id)),
+            Entry::Occupied(_) => Err(ImportError::DuplicateMacroId(id)),
         }
     }
 }
@@ -577,6 +586,37 @@ impl<T> SerdeDSTStandAlone<T> {
             .into_dst(&macro_manager)
             .map(|dst| (dst, macro_manager))
     }
+
+    /// Convert into a DST using the macros of `macro_manager`.
+    ///
+    /// `macro_manager` is left untouched. The macros that it does not manage
+    /// are returned along with the DST, so that the caller may add them with
+    /// `MacroManager::add_handle` once it accepts the DST.
+    pub fn into_dst_with_macros<E>(
+        self,
+        macro_manager: &MacroManager<'static, T, E>,
+    ) -> Result<(DST<'static, T, E>, Vec<MacroHandle<'static, T, E>>), ImportError>
+    where
+        T: Clone + VariantName + ConvertibleVariants + NamedAlgorithms<E>,
+    {
+        let mut all_macros = MacroManager {
+            macros: macro_manager.macros.clone(),
+        };
+        for macr in self.subs {
+            if all_macros.get_macro(macr.id).is_none() {
+                let sub = macr.into_macro(&all_macros)?;
+                all_macros.add_macro(sub)?;
+            }
+        }
+        let dst = self.main.into_dst(&all_macros)?;
+        let new_macros = all_macros
+            .macros
+            .into_iter()
+            .filter(|(id, _)| macro_manager.get_macro(*id).is_none())
+            .map(|(_, handle)| handle)
+            .collect();
+        Ok((dst, new_macros))
+    }
 }
 
 impl<'t, 'd, T, E> From<&'d DST<'t, T, E>> for SerdeDSTStandAlone<T>
@@ -8,6 +8,7 @@ use boow::Bow;
 
 use super::ConvertibleVariants;
 use compute::ComputeError;
+use dst::{TransformIdx, DST};
 use macros::MacroHandle;
 use variant_name::VariantName;
 
@@ -40,6 +41,8 @@ pub enum Algorithm<'t, T: 't, E: 't> {
         /// Version of the transform
         version: Version,
         description: &'static str,
+        /// Category of the transform, used to sort transforms in menus
+        category: Option<&'static str>,
         /// Inputs of the transformation, may include a default value
         inputs: Vec<TransformInputSlot<T>>,
         /// Outputs of the transformation
@@ -74,7 +77,7 @@ impl<T> TransformInputSlot<T> {
     }
 }
 
-type PlainFunction<T, E> = fn(Vec<Bow<'_, T>>) -> Vec<Result<T, E>>;
+type PlainFunction<T, E> = fn(Vec<Bow<'_, T>>, &Context<'_, '_, T, E>) -> Vec<Result<T, E>>;
 
 impl<'t, T: fmt::Debug, E> fmt::Debug for Algorithm<'t, T, E> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -100,6 +103,7 @@ where
                 id,
                 version,
                 description,
+                category,
                 ref inputs,
                 ref outputs,
             } => Function {
@@ -107,6 +111,7 @@ where
                 id,
                 version,
                 description,
+                category,
                 inputs: inputs.clone(),
                 outputs: outputs.clone(),
             },
@@ -308,6 +313,14 @@ where
             }
         }
     }
+
+    /// Category of a function transform, if it was given one.
+    pub fn category(&self) -> Option<&'static str> {
+        match self.algorithm {
+            Algorithm::Function { category, .. } => category,
+            _ => None,
+        }
+    }
 }
 
 impl<'a, 't, 'i, T, E> TransformCaller<'a, 't, 'i, T, E>
@@ -335,6 +348,47 @@ where
     }
 }
 
+/// Program in which a transform is called.
+///
+/// Transforms only see the values of their inputs. A transform writing a data
+/// product may also want to record how these values were made, which it can
+/// get from its context.
+pub struct Context<'a, 't: 'a, T: 't, E: 't> {
+    program: Option<(&'a DST<'t, T, E>, TransformIdx)>,
+}
+
+impl<'a, 't, T, E> Context<'a, 't, T, E> {
+    /// Context of the transform at `t_idx` in `dst`.
+    pub fn new(dst: &'a DST<'t, T, E>, t_idx: TransformIdx) -> Self {
+        Self {
+            program: Some((dst, t_idx)),
+        }
+    }
+
+    /// Context of a transform called outside of any program.
+    pub fn none() -> Self {
+        Self { program: None }
+    }
+}
+
+impl<'a, 't, T, E> Context<'a, 't, T, E>
+where
+    T: VariantName,
+{
+    /// Summarize the program that produced the inputs of the transform, with
+    /// at most `max_nodes` nodes. See [`DST::summary`].
+    ///
+    /// Macros are computed on their own, so a transform inside a macro only
+    /// gets the program of the macro. The summary is empty outside of any
+    /// program.
+    pub fn program_summary(&self, max_nodes: usize) -> Vec<String> {
+        match self.program {
+            Some((dst, t_idx)) => dst.summary(t_idx, max_nodes),
+            None => vec![],
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 pub enum ArgumentError {
     ConversionError { from: TypeId, to: TypeId },
@@ -360,14 +414,21 @@ impl<'a, 't, 'i, T, E> TransformCaller<'a, 't, 'i, T, E>
 where
     T: Clone + VariantName + ConvertibleVariants,
 {
-    /// Compute the transformation with the provided arguments
-    pub fn call(mut self) -> TransformResult<Result<T, CallError<E>>> {
+    /// Compute the transformation with the provided arguments, outside of
+    /// any program.
+    pub fn call(self) -> TransformResult<Result<T, CallError<E>>> {
+        self.call_in(&Context::none())
+    }
+
+    /// Compute the transformation with the provided arguments, in the given
+    /// context.
+    pub fn call_in(mut self, context: &Context<T, E>) -> TransformResult<Result<T, CallError<E>>> {
         if self.expected_input_types.next().is_some() {
             panic!("Missing input arguments!");
         } else {
             TransformResult {
                 output: match self.algorithm {
-                    Algorithm::Function { f, .. } => f(self.input)
+                    Algorithm::Function { f, .. } => f(self.input, context)
                         .into_iter()
                         .map(|r| r.map_err(CallError::FunctionError))
                         .collect::<Vec<_>>()
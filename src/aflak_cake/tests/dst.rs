@@ -110,6 +110,37 @@ fn test_connect_convertible_types() {
     );
 }
 
+#[test]
+fn test_check_connect() {
+    // a, image    b, get1
+    // c, plus1
+    let image = Transform::new_constant(AlgoIO::Image2d(vec![vec![10.0; 2]; 2]));
+    let mut dst = DST::new();
+    let a = dst.add_owned_transform(image);
+    let b = dst.add_owned_transform(get_get1_transform());
+    let c = dst.add_owned_transform(get_plus1_transform());
+    if let Err(DSTError::IncompatibleTypes(_)) =
+        dst.check_connect(Output::new(a, 0), Input::new(c, 0))
+    {
+        assert!(true);
+    } else {
+        assert!(false, "IncompatibleTypes expected!");
+    }
+    assert!(dst
+        .check_connect(Output::new(b, 0), Input::new(c, 0))
+        .is_ok());
+    // Checking does not connect
+    assert_eq!(dst.links_iter().count(), 0);
+
+    dst.connect(Output::new(b, 0), Input::new(c, 0)).unwrap();
+    if let Err(DSTError::DuplicateEdge(_)) = dst.check_connect(Output::new(b, 0), Input::new(c, 0))
+    {
+        assert!(true);
+    } else {
+        assert!(false, "DuplicateEdge expected!");
+    }
+}
+
 #[test]
 fn test_cache_reset() {
     let [plus1, minus1, get1] = if let &[plus1, minus1, get1, _image, _] = *TRANSFORMATIONS_REF {
@@ -142,6 +173,78 @@ fn test_cache_reset() {
     assert_output_eq!(dst, out1, AlgoIO::Integer(2), &mut cache);
 }
 
+#[test]
+fn test_error_root_cause() {
+    // a, minus1 (missing input) -> b, plus1 -> OUT1
+    let mut dst = DST::new();
+    let a = dst.add_owned_transform(get_minus1_transform());
+    let b = dst.add_owned_transform(get_plus1_transform());
+    let out1 = dst.attach_output(Output::new(b, 0)).unwrap();
+    dst.connect(Output::new(a, 0), Input::new(b, 0)).unwrap();
+
+    let mut cache = Cache::new();
+    let mut promise = dst.compute(out1, &mut cache);
+    let error = loop {
+        match promise.poll() {
+            Ok(Async::Ready(_)) => panic!("Computation should fail"),
+            Ok(Async::NotReady) => ::std::thread::yield_now(),
+            Err(e) => break e,
+        }
+    };
+    assert_eq!(error.t_idx(), Some(b));
+    assert_eq!(error.root_cause().t_idx(), Some(a));
+    assert_eq!(error.node_stack(), vec![(a, "minus1"), (b, "plus1")]);
+    assert_eq!(error.root_message(), error.root_cause().to_string());
+    assert!(!error.root_message().contains("in node #"));
+    if let compute::ComputeError::MissingDependency { input, .. } = error.root_cause() {
+        assert_eq!(*input, Input::new(a, 0));
+    } else {
+        panic!("Unexpected root cause: {}", error.root_cause());
+    }
+    // The error of each node is cached
+    match cache.get(&Output::new(a, 0)) {
+        Some(Err(e)) => assert_eq!(e.t_idx(), Some(a)),
+        _ => panic!("Error should be cached"),
+    }
+}
+
+#[test]
+fn test_bypass_and_freeze() {
+    // a, constant -> b, plus1 -> c, plus1 -> OUT1
+    let mut dst = DST::new();
+    let a = dst.add_owned_transform(Transform::new_constant(AlgoIO::Integer(1)));
+    let b = dst.add_owned_transform(get_plus1_transform());
+    let c = dst.add_owned_transform(get_plus1_transform());
+    let out1 = dst.attach_output(Output::new(c, 0)).unwrap();
+    dst.connect(Output::new(a, 0), Input::new(b, 0)).unwrap();
+    dst.connect(Output::new(b, 0), Input::new(c, 0)).unwrap();
+
+    let mut cache = Cache::new();
+    assert_output_eq!(dst, out1, AlgoIO::Integer(3), &mut cache);
+
+    // The value of a is passed through b
+    dst.set_transform_mode(b, TransformMode::Bypassed);
+    assert_eq!(dst.get_transform_mode(b), Some(TransformMode::Bypassed));
+    assert_output_eq!(dst, out1, AlgoIO::Integer(2), &mut cache);
+    dst.set_transform_mode(b, TransformMode::Normal);
+    assert_output_eq!(dst, out1, AlgoIO::Integer(3), &mut cache);
+
+    // b keeps its last output though a changed
+    dst.set_transform_mode(b, TransformMode::Frozen);
+    dst.get_transform_mut(a)
+        .unwrap()
+        .set_constant(AlgoIO::Integer(5));
+    assert_output_eq!(dst, out1, AlgoIO::Integer(3), &mut cache);
+
+    // The mode is exported
+    let s = ser::to_string(&dst).unwrap();
+    let dst_back: DST<AlgoIO, E> = de::from_str(&s).unwrap();
+    assert_eq!(dst_back.get_transform_mode(b), Some(TransformMode::Frozen));
+
+    dst.set_transform_mode(b, TransformMode::Normal);
+    assert_output_eq!(dst, out1, AlgoIO::Integer(7), &mut cache);
+}
+
 #[test]
 fn test_remove_node() {
     let [plus1, minus1, get1, _image, _] = get_all_transforms();
@@ -189,3 +292,119 @@ fn test_remove_node() {
         vec
     });
 }
+
+#[test]
+fn test_restore_node() {
+    // a, get1 -> b, plus1 -> c, plus1 -> OUT1
+    let mut dst = DST::new();
+    let a = dst.add_owned_transform(get_get1_transform());
+    let b = dst.add_owned_transform(get_plus1_transform());
+    let c = dst.add_owned_transform(get_plus1_transform());
+    let out1 = dst.attach_output(Output::new(c, 0)).unwrap();
+    dst.connect(Output::new(a, 0), Input::new(b, 0)).unwrap();
+    dst.connect(Output::new(b, 0), Input::new(c, 0)).unwrap();
+
+    let mut links_before: Vec<_> = dst
+        .links_iter()
+        .map(|(output, input_slot)| (*output, input_slot))
+        .collect();
+    links_before.sort();
+
+    let b_id = aflak_cake::NodeId::Transform(b);
+    let snapshot = dst.node_snapshot(&b_id).unwrap();
+    assert_eq!(snapshot.node_id(), b_id);
+    dst.remove_node(&b_id);
+    assert_eq!(dst.links_iter().count(), 1);
+
+    dst.restore_node(snapshot);
+    let mut links_after: Vec<_> = dst
+        .links_iter()
+        .map(|(output, input_slot)| (*output, input_slot))
+        .collect();
+    links_after.sort();
+    assert_eq!(links_before, links_after);
+
+    let mut cache = Cache::new();
+    assert_output_eq!(dst, out1, AlgoIO::Integer(3), &mut cache);
+}
+
+#[test]
+fn test_extract_and_merge() {
+    // a, get1 -> b, plus1 -> c, plus1 -> OUT1
+    let mut dst = DST::new();
+    let a = dst.add_owned_transform(get_get1_transform());
+    let b = dst.add_owned_transform(get_plus1_transform());
+    let c = dst.add_owned_transform(get_plus1_transform());
+    let out1 = dst.attach_output(Output::new(c, 0)).unwrap();
+    dst.connect(Output::new(a, 0), Input::new(b, 0)).unwrap();
+    dst.connect(Output::new(b, 0), Input::new(c, 0)).unwrap();
+
+    // Copy a -> b -> OUT1. OUT1 is detached as c is not copied.
+    let copy = dst.extract(&[
+        aflak_cake::NodeId::Transform(a),
+        aflak_cake::NodeId::Transform(b),
+        aflak_cake::NodeId::Output(out1),
+    ]);
+    assert_eq!(copy.node_ids().len(), 3);
+    assert_eq!(copy.links_iter().count(), 1);
+
+    let new_ids = dst.merge(copy);
+    assert_eq!(new_ids.len(), 3);
+    assert_eq!(dst.node_ids().len(), 7);
+    assert_eq!(dst.links_iter().count(), 4);
+    let new_b = new_ids
+        .iter()
+        .find(|(old, _)| *old == aflak_cake::NodeId::Transform(b))
+        .map(|(_, new)| *new)
+        .unwrap();
+    if let aflak_cake::NodeId::Transform(new_b) = new_b {
+        let out2 = dst.attach_output(Output::new(new_b, 0)).unwrap();
+        let mut cache = Cache::new();
+        assert_output_eq!(dst, out2, AlgoIO::Integer(2), &mut cache);
+    } else {
+        panic!("Transform was pasted as an output");
+    }
+}
+
+#[test]
+fn test_program_summary() {
+    let count_nodes: Transform<'static, AlgoIO, E> = cake_transform!(
+        "Count the nodes of the program", 1, 0, 0,
+        count_nodes<AlgoIO, E>(_i: Integer) -> Integer with context {
+            let count = context.program_summary(100).len();
+            vec![Ok(AlgoIO::Integer(count as u64))]
+        }
+    );
+
+    // a, get1 -> b, plus1 -> c, count_nodes -> OUT1
+    //                        d, plus1 -> OUT2
+    let mut dst = DST::new();
+    let a = dst.add_owned_transform(get_get1_transform());
+    let b = dst.add_owned_transform(get_plus1_transform());
+    let c = dst.add_owned_transform(count_nodes);
+    let d = dst.add_owned_transform(get_plus1_transform());
+    let out1 = dst.attach_output(Output::new(c, 0)).unwrap();
+    let _out2 = dst.attach_output(Output::new(d, 0)).unwrap();
+    dst.connect(Output::new(a, 0), Input::new(b, 0)).unwrap();
+    dst.connect(Output::new(b, 0), Input::new(c, 0)).unwrap();
+
+    let mut cache = Cache::new();
+    assert_output_eq!(dst, out1, AlgoIO::Integer(3), &mut cache);
+
+    assert_eq!(
+        dst.summary(c, 100),
+        vec![
+            format!("#{} count_nodes <- #{}.1", c.id(), b.id()),
+            format!("#{} plus1 <- #{}.1", b.id(), a.id()),
+            format!("#{} constant Integer", a.id()),
+        ]
+    );
+    assert_eq!(
+        dst.summary(c, 1),
+        vec![
+            format!("#{} count_nodes <- #{}.1", c.id(), b.id()),
+            "... and 2 more nodes".to_owned(),
+        ]
+    );
+    assert_eq!(dst.summary(d, 100), vec![format!("#{} plus1 <- -", d.id())]);
+}
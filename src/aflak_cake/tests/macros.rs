@@ -145,3 +145,40 @@ fn test_nested_macro_standalone_serde() {
 
     assert_eq!(out, out2);
 }
+
+#[test]
+fn test_dst_standalone_macros_added_on_accept() {
+    let mut manager = aflak_cake::macros::MacroManager::new();
+    let edited = manager.create_macro().clone();
+    let new_macro = make_macro();
+
+    // Copy a node of the macro being edited and a node of an unknown macro
+    let mut dst = DST::new();
+    dst.add_owned_transform(aflak_cake::Transform::from_macro(edited.clone()));
+    dst.add_owned_transform(aflak_cake::Transform::from_macro(new_macro.clone()));
+    let serde = aflak_cake::macros::SerdeDSTStandAlone::from(&dst);
+    let out = ron::ser::to_string_pretty(&serde, Default::default()).unwrap();
+    let back: aflak_cake::macros::SerdeDSTStandAlone<AlgoIO> = ron::de::from_str(&out).unwrap();
+
+    let (pasted, new_macros) = back.into_dst_with_macros(&manager).unwrap();
+    // The snippet uses the edited macro, so it is rejected and the manager
+    // must be left untouched
+    let uses_edited = pasted.nodes_iter().any(|(_, node)| match node {
+        aflak_cake::Node::Transform(t) => match t.algorithm() {
+            aflak_cake::Algorithm::Macro { handle } => *handle == edited,
+            _ => false,
+        },
+        aflak_cake::Node::Output(_) => false,
+    });
+    assert!(uses_edited);
+    assert_eq!(manager.macros().count(), 1);
+    assert_eq!(new_macros.len(), 1);
+    assert_eq!(new_macros[0].id(), new_macro.id());
+
+    // Once accepted, the new macros are added
+    for handle in new_macros.iter().cloned() {
+        manager.add_handle(handle).unwrap();
+    }
+    assert_eq!(manager.macros().count(), 2);
+    assert!(manager.add_handle(new_macros[0].clone()).is_err());
+}
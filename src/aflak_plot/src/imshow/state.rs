@@ -949,9 +949,14 @@ where
                     center,
                     radius,
                     parametersfill,
-                    pixels: _pixels,
                 }) => {
                     let selected = self.circle_input.is_selected(*id);
+                    let mouse_pos = self.mouse_pos;
+                    let mouse_radius = |center: (usize, usize)| {
+                        let dx = mouse_pos.0 - (center.0 as f32 + 0.5);
+                        let dy = mouse_pos.1 - (center.1 as f32 + 0.5);
+                        (dx * dx + dy * dy).sqrt()
+                    };
                     if selected && is_image_hovered && ui.is_mouse_clicked(MouseButton::Left) {
                         let pixel = (self.mouse_pos.0 as usize, self.mouse_pos.1 as usize);
                         if parametersfill.0 == false {
@@ -959,32 +964,22 @@ where
                             *center = pixel;
                         } else if parametersfill.1 == false {
                             parametersfill.1 = true;
-                            let x0 = p[0] + center.0 as f32 / tex_size.0 as f32 * size[0];
-                            let y0 = p[1] + size[1] - center.1 as f32 / tex_size.1 as f32 * size[1];
-                            let x1 = p[0] + self.mouse_pos.0 as f32 / tex_size.0 as f32 * size[0];
-                            let y1 = p[1] + size[1]
-                                - self.mouse_pos.1 as f32 / tex_size.1 as f32 * size[1];
-                            let rad = ((x0 - x1) * (x0 - x1) + (y0 - y1) * (y0 - y1)).sqrt();
-                            *radius = rad;
+                            *radius = mouse_radius(*center);
                         }
                     }
 
+                    // Radius is in pixels, draw it at the scale of the image
+                    let x0 = p[0] + (center.0 as f32 + 0.5) / tex_size.0 as f32 * size[0];
+                    let y0 = p[1] + size[1] - (center.1 as f32 + 0.5) / tex_size.1 as f32 * size[1];
+                    let pixel_size_x = size[0] / tex_size.0 as f32;
                     if parametersfill.0 && parametersfill.1 {
-                        let x0 = p[0] + center.0 as f32 / tex_size.0 as f32 * size[0];
-                        let y0 = p[1] + size[1] - center.1 as f32 / tex_size.1 as f32 * size[1];
                         draw_list
-                            .add_circle([x0, y0], *radius as f32, LINE_COLOR)
+                            .add_circle([x0, y0], *radius * pixel_size_x, LINE_COLOR)
                             .num_segments(50)
                             .build();
                     } else if parametersfill.0 {
-                        let x0 = p[0] + center.0 as f32 / tex_size.0 as f32 * size[0];
-                        let y0 = p[1] + size[1] - center.1 as f32 / tex_size.1 as f32 * size[1];
-                        let x1 = p[0] + self.mouse_pos.0 as f32 / tex_size.0 as f32 * size[0];
-                        let y1 =
-                            p[1] + size[1] - self.mouse_pos.1 as f32 / tex_size.1 as f32 * size[1];
-                        let rad = ((x0 - x1) * (x0 - x1) + (y0 - y1) * (y0 - y1)).sqrt();
                         draw_list
-                            .add_circle([x0, y0], rad, LINE_COLOR)
+                            .add_circle([x0, y0], mouse_radius(*center) * pixel_size_x, LINE_COLOR)
                             .num_segments(50)
                             .build();
                     }
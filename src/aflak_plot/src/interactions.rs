@@ -12,7 +12,8 @@ pub enum Value {
     Float3([f32; 3]),
     FinedGrainedROI(Vec<(usize, usize)>),
     Line(Vec<(usize, usize)>),
-    Circle(Vec<(usize, usize)>),
+    /// Center (x, y) and radius of a circle, in pixels.
+    Circle((f32, f32), f32),
 }
 
 impl From<i64> for Value {
@@ -77,9 +78,9 @@ pub struct Line {
 pub struct Circle {
     pub(crate) id: usize,
     pub center: (usize, usize),
+    /// Radius in pixels
     pub radius: f32,
     pub parametersfill: (bool, bool),
-    pub pixels: Vec<(usize, usize)>,
 }
 
 impl HorizontalLine {
@@ -129,7 +130,6 @@ impl Circle {
             center: (0, 0),
             radius: 0.0,
             parametersfill: (false, false),
-            pixels: vec![],
         }
     }
 }
@@ -184,7 +184,10 @@ impl Interaction {
                 Value::FinedGrainedROI(pixels.clone())
             }
             Interaction::Line(Line { pixels, .. }) => Value::Line(pixels.clone()),
-            Interaction::Circle(Circle { pixels, .. }) => Value::Circle(pixels.clone()),
+            Interaction::Circle(Circle { center, radius, .. }) => {
+                let center = (center.0 as f32 + 0.5, center.1 as f32 + 0.5);
+                Value::Circle(center, *radius)
+            }
         }
     }
 
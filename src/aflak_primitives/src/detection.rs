@@ -0,0 +1,366 @@
+//! Source detection by thresholding and connected-component labelling, with
+//! optional multi-threshold deblending.
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+
+use ndarray::{Array2, ArrayView2, Zip};
+
+/// Parameters for [`detect_sources`].
+#[derive(Copy, Clone, Debug)]
+pub struct DetectionParams {
+    /// A pixel is detected if its value is above `threshold` times the RMS
+    pub threshold: f32,
+    /// Minimum number of pixels of a source
+    pub min_pixels: usize,
+    /// Whether to split blended sources
+    pub deblend: bool,
+    /// Number of threshold levels used for deblending
+    pub deblend_levels: usize,
+    /// Minimum fraction of the total flux of a source that a branch must
+    /// contain to be split as a separate source
+    pub deblend_contrast: f32,
+}
+
+/// Detect sources on a background-subtracted image.
+///
+/// Return a segmentation map where each pixel contains the label of the
+/// source it belongs to, starting from 1. Background pixels are labelled 0.
+pub fn detect_sources(
+    image: ArrayView2<f32>,
+    rms: ArrayView2<f32>,
+    params: DetectionParams,
+) -> Array2<usize> {
+    let mut mask = Array2::from_elem(image.dim(), false);
+    Zip::from(&mut mask)
+        .and(&image)
+        .and(&rms)
+        .apply(|m, &val, &rms| *m = val > params.threshold * rms);
+
+    let mut sources = vec![];
+    for component in connected_components(&mask) {
+        if component.len() < params.min_pixels {
+            continue;
+        }
+        if params.deblend {
+            sources.extend(deblend(image, component, &params, 0));
+        } else {
+            sources.push(component);
+        }
+    }
+
+    let mut segmap = Array2::zeros(image.dim());
+    for (label, pixels) in sources.iter().enumerate() {
+        for &idx in pixels {
+            segmap[idx] = label + 1;
+        }
+    }
+    segmap
+}
+
+/// Maximum depth of recursion when deblending
+const MAX_DEBLEND_DEPTH: usize = 8;
+
+/// Try to split a component into several sources.
+///
+/// The component is thresholded at exponentially spaced levels between its
+/// lowest and highest values. At the first level where it breaks into at
+/// least two significant branches, the remaining pixels are attributed to the
+/// branches by flooding from the brightest pixels down. Each branch is then
+/// deblended again.
+fn deblend(
+    image: ArrayView2<f32>,
+    component: Vec<(usize, usize)>,
+    params: &DetectionParams,
+    depth: usize,
+) -> Vec<Vec<(usize, usize)>> {
+    if depth >= MAX_DEBLEND_DEPTH || params.deblend_levels == 0 {
+        return vec![component];
+    }
+    let values: Vec<_> = component.iter().map(|&idx| image[idx]).collect();
+    let low = values.iter().cloned().fold(::std::f32::INFINITY, f32::min);
+    let high = values
+        .iter()
+        .cloned()
+        .fold(::std::f32::NEG_INFINITY, f32::max);
+    let total: f32 = values.iter().sum();
+    if !(low > 0.0 && high > low) {
+        return vec![component];
+    }
+
+    let (i0, j0, i1, j1) = bounding_box(&component);
+    let dim = (i1 - i0 + 1, j1 - j0 + 1);
+    for level in 1..=params.deblend_levels {
+        let t = low * (high / low).powf(level as f32 / (params.deblend_levels + 1) as f32);
+        let mut mask = Array2::from_elem(dim, false);
+        for (&(i, j), &val) in component.iter().zip(&values) {
+            mask[(i - i0, j - j0)] = val > t;
+        }
+        let branches: Vec<_> = connected_components(&mask)
+            .into_iter()
+            .filter(|branch| {
+                let flux: f32 = branch.iter().map(|&(i, j)| image[(i + i0, j + j0)]).sum();
+                branch.len() >= params.min_pixels && flux >= params.deblend_contrast * total
+            })
+            .collect();
+        if branches.len() < 2 {
+            continue;
+        }
+
+        let split = flood(image, &component, (i0, j0), dim, branches);
+        let mut out = vec![];
+        for branch in split {
+            out.extend(deblend(image, branch, params, depth + 1));
+        }
+        return out;
+    }
+    vec![component]
+}
+
+/// Attribute each pixel of the component to one of the seed branches (given
+/// in coordinates relative to `origin`). Pixels are visited from the
+/// brightest to the faintest, and join the branch of an already attributed
+/// neighbour.
+fn flood(
+    image: ArrayView2<f32>,
+    component: &[(usize, usize)],
+    origin: (usize, usize),
+    dim: (usize, usize),
+    seeds: Vec<Vec<(usize, usize)>>,
+) -> Vec<Vec<(usize, usize)>> {
+    let (i0, j0) = origin;
+    let mut owner = Array2::<Option<usize>>::from_elem(dim, None);
+    for (k, seed) in seeds.iter().enumerate() {
+        for &idx in seed {
+            owner[idx] = Some(k);
+        }
+    }
+
+    let mut remaining: Vec<_> = component
+        .iter()
+        .map(|&(i, j)| (i - i0, j - j0))
+        .filter(|&idx| owner[idx].is_none())
+        .collect();
+    remaining.sort_by(|&a, &b| {
+        let va = image[(a.0 + i0, a.1 + j0)];
+        let vb = image[(b.0 + i0, b.1 + j0)];
+        vb.partial_cmp(&va).unwrap_or(Ordering::Equal)
+    });
+    // Pixels that have no attributed neighbour yet are retried until no more
+    // progress can be made.
+    loop {
+        let before = remaining.len();
+        remaining.retain(|&idx| {
+            let neighbour_owner = neighbours(idx, dim).filter_map(|n| owner[n]).next();
+            if let Some(k) = neighbour_owner {
+                owner[idx] = Some(k);
+                false
+            } else {
+                true
+            }
+        });
+        if remaining.is_empty() || remaining.len() == before {
+            break;
+        }
+    }
+
+    let mut out = vec![vec![]; seeds.len()];
+    for ((i, j), owner) in owner.indexed_iter() {
+        if let Some(k) = *owner {
+            out[k].push((i + i0, j + j0));
+        }
+    }
+    out
+}
+
+fn bounding_box(pixels: &[(usize, usize)]) -> (usize, usize, usize, usize) {
+    let mut bbox = (usize::max_value(), usize::max_value(), 0, 0);
+    for &(i, j) in pixels {
+        bbox.0 = bbox.0.min(i);
+        bbox.1 = bbox.1.min(j);
+        bbox.2 = bbox.2.max(i);
+        bbox.3 = bbox.3.max(j);
+    }
+    bbox
+}
+
+/// Iterate over the 8 neighbours of a pixel within an array of given dim.
+fn neighbours(
+    (i, j): (usize, usize),
+    (h, w): (usize, usize),
+) -> impl Iterator<Item = (usize, usize)> {
+    let rows = i.saturating_sub(1)..(i + 2).min(h);
+    rows.flat_map(move |ni| {
+        let cols = j.saturating_sub(1)..(j + 2).min(w);
+        cols.map(move |nj| (ni, nj))
+    })
+    .filter(move |&n| n != (i, j))
+}
+
+/// Find the 8-connected components of the true pixels of a mask.
+pub fn connected_components(mask: &Array2<bool>) -> Vec<Vec<(usize, usize)>> {
+    let dim = mask.dim();
+    let mut visited = Array2::from_elem(dim, false);
+    let mut components = vec![];
+    for (start, &m) in mask.indexed_iter() {
+        if !m || visited[start] {
+            continue;
+        }
+        let mut component = vec![];
+        let mut queue = VecDeque::new();
+        visited[start] = true;
+        queue.push_back(start);
+        while let Some(idx) = queue.pop_front() {
+            component.push(idx);
+            for n in neighbours(idx, dim) {
+                if mask[n] && !visited[n] {
+                    visited[n] = true;
+                    queue.push_back(n);
+                }
+            }
+        }
+        components.push(component);
+    }
+    components
+}
+
+/// Measurements on a detected source.
+///
+/// Coordinates are pixel coordinates: `x` along the columns and `y` along the
+/// rows of the image.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SourceProperties {
+    pub label: usize,
+    /// Flux-weighted centroid
+    pub x: f32,
+    pub y: f32,
+    /// Sum of the values of the pixels of the source
+    pub flux: f32,
+    /// Number of pixels of the source
+    pub area: usize,
+    /// 1 - b/a, where a and b are the semi-major and semi-minor axes computed
+    /// from the second-order moments of the source
+    pub ellipticity: f32,
+}
+
+/// Measure each source of a segmentation map. Labels that are not present in
+/// the map are skipped.
+pub fn measure_sources(image: ArrayView2<f32>, segmap: ArrayView2<usize>) -> Vec<SourceProperties> {
+    let n = segmap.iter().cloned().max().unwrap_or(0);
+    // Accumulate sum(f), sum(f*x), sum(f*y), sum(f*x*x), sum(f*y*y), sum(f*x*y), area
+    let mut acc = vec![[0.0f64; 6]; n + 1];
+    let mut area = vec![0; n + 1];
+    for ((i, j), &label) in segmap.indexed_iter() {
+        let val = image[(i, j)];
+        if label == 0 || val.is_nan() {
+            continue;
+        }
+        let (f, x, y) = (f64::from(val), j as f64, i as f64);
+        let a = &mut acc[label];
+        a[0] += f;
+        a[1] += f * x;
+        a[2] += f * y;
+        a[3] += f * x * x;
+        a[4] += f * y * y;
+        a[5] += f * x * y;
+        area[label] += 1;
+    }
+
+    let mut out = vec![];
+    for label in 1..=n {
+        if area[label] == 0 {
+            continue;
+        }
+        let a = acc[label];
+        let x = a[1] / a[0];
+        let y = a[2] / a[0];
+        let x2 = a[3] / a[0] - x * x;
+        let y2 = a[4] / a[0] - y * y;
+        let xy = a[5] / a[0] - x * y;
+        let half_sum = (x2 + y2) / 2.0;
+        let root = (((x2 - y2) / 2.0).powi(2) + xy * xy).sqrt();
+        let major = (half_sum + root).max(0.0).sqrt();
+        let minor = (half_sum - root).max(0.0).sqrt();
+        let ellipticity = if major > 0.0 {
+            1.0 - minor / major
+        } else {
+            0.0
+        };
+        out.push(SourceProperties {
+            label,
+            x: x as f32,
+            y: y as f32,
+            flux: a[0] as f32,
+            area: area[label],
+            ellipticity: ellipticity as f32,
+        });
+    }
+    out
+}
+
+/// Get the (x, y) coordinates of the pixels of a segment, as used by
+/// [`ROI::PixelList`](../roi/enum.ROI.html).
+pub fn segment_pixels(segmap: ArrayView2<usize>, label: usize) -> Vec<(usize, usize)> {
+    segmap
+        .indexed_iter()
+        .filter(|&(_, &l)| l == label)
+        .map(|((i, j), _)| (j, i))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{detect_sources, measure_sources, DetectionParams};
+    use ndarray::Array2;
+
+    fn params(deblend: bool) -> DetectionParams {
+        DetectionParams {
+            threshold: 1.0,
+            min_pixels: 2,
+            deblend,
+            deblend_levels: 32,
+            deblend_contrast: 0.05,
+        }
+    }
+
+    /// Two gaussian blobs joined by a faint bridge
+    fn blended() -> Array2<f32> {
+        Array2::from_shape_fn((9, 21), |(i, j)| {
+            let g = |x0: f32| {
+                let (dx, dy) = (j as f32 - x0, i as f32 - 4.0);
+                100.0 * (-(dx * dx + dy * dy) / 8.0).exp()
+            };
+            g(5.0) + g(15.0) + if i == 4 { 2.0 } else { 0.0 }
+        })
+    }
+
+    #[test]
+    fn detect_and_measure() {
+        let mut image = Array2::zeros((10, 10));
+        image[(2, 2)] = 5.0;
+        image[(2, 3)] = 5.0;
+        image[(7, 7)] = 5.0;
+        let rms = Array2::from_elem((10, 10), 1.0);
+        let segmap = detect_sources(image.view(), rms.view(), params(false));
+        let sources = measure_sources(image.view(), segmap.view());
+        // The single-pixel source is below min_pixels
+        assert_eq!(sources.len(), 1);
+        assert_eq!(sources[0].area, 2);
+        assert_eq!(sources[0].flux, 10.0);
+        assert_eq!((sources[0].x, sources[0].y), (2.5, 2.0));
+        assert_eq!(sources[0].ellipticity, 1.0);
+    }
+
+    #[test]
+    fn deblend_splits_blended_sources() {
+        let image = blended();
+        let rms = Array2::from_elem(image.dim(), 1.0);
+        let segmap = detect_sources(image.view(), rms.view(), params(false));
+        assert_eq!(measure_sources(image.view(), segmap.view()).len(), 1);
+
+        let segmap = detect_sources(image.view(), rms.view(), params(true));
+        let sources = measure_sources(image.view(), segmap.view());
+        assert_eq!(sources.len(), 2);
+        assert!((sources[0].x - 5.0).abs() < 0.5);
+        assert!((sources[1].x - 15.0).abs() < 0.5);
+    }
+}
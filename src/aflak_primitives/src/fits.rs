@@ -1,9 +1,43 @@
 use std::error;
 use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
 
-use fitrs::FitsDataArray;
+use fitrs::{Fits, FitsData, FitsDataArray, Hdu, HeaderValue};
 use ndarray::{self, ArrayD, IxDyn};
 
+/// A FITS file opened with fitrs, which remembers its path so that its data
+/// arrays can be mapped in memory.
+#[derive(Debug)]
+pub struct FitsFile {
+    path: PathBuf,
+    fits: Fits,
+}
+
+impl FitsFile {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        Fits::open(path).map(|fits| FitsFile {
+            path: path.to_owned(),
+            fits,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Deref for FitsFile {
+    type Target = Fits;
+
+    fn deref(&self) -> &Fits {
+        &self.fits
+    }
+}
+
 pub trait FitsDataToArray<Dimension> {
     type Target;
 
@@ -101,3 +135,226 @@ impl FitsDataToArray<IxDyn> for FitsDataArray<Option<u32>> {
         .map_err(FitsArrayReadError::ShapeError)
     }
 }
+
+/// Make an array from FITS data, whose shape is given in FITS axis order.
+pub fn to_ndarray<T>(shape: Vec<usize>, data: Vec<T>) -> Result<ArrayD<T>, FitsArrayReadError> {
+    let sh: Vec<_> = shape.into_iter().rev().collect();
+    ArrayD::from_shape_vec(sh, data).map_err(FitsArrayReadError::ShapeError)
+}
+
+/// Read the value of an integer header keyword.
+pub fn integer(hdu: &Hdu, key: &str) -> Option<i64> {
+    match hdu.value(key) {
+        Some(HeaderValue::IntegerNumber(i)) => Some(i64::from(*i)),
+        Some(HeaderValue::RealFloatingNumber(f)) => Some(*f as i64),
+        _ => None,
+    }
+}
+
+/// Read the value of a numeric header keyword.
+pub fn float(hdu: &Hdu, key: &str) -> Option<f64> {
+    match hdu.value(key) {
+        Some(HeaderValue::IntegerNumber(i)) => Some(f64::from(*i)),
+        Some(HeaderValue::RealFloatingNumber(f)) => Some(*f),
+        _ => None,
+    }
+}
+
+/// Read the data array of a HDU as bit flags, such as in a data quality
+/// extension. Unlike `FitsDataToArray`, integers are not converted to
+/// floating point, so that all bits are kept. Null integers have no flag set.
+pub fn read_flags(hdu: &Hdu) -> Result<ArrayD<u32>, FitsArrayReadError> {
+    let (shape, data): (_, Vec<_>) = match hdu.read_data() {
+        FitsData::IntegersI32(array) => (
+            array.shape,
+            array
+                .data
+                .into_iter()
+                .map(|int| int.unwrap_or(0) as u32)
+                .collect(),
+        ),
+        FitsData::IntegersU32(array) => (
+            array.shape,
+            array.data.into_iter().map(|int| int.unwrap_or(0)).collect(),
+        ),
+        FitsData::FloatingPoint32(array) => (
+            array.shape,
+            array.data.into_iter().map(|f| f as u32).collect(),
+        ),
+        FitsData::FloatingPoint64(array) => (
+            array.shape,
+            array.data.into_iter().map(|f| f as u32).collect(),
+        ),
+        FitsData::Characters(_) => {
+            return Err(FitsArrayReadError::UnsupportedData("Characters"));
+        }
+    };
+    let sh: Vec<_> = shape.into_iter().rev().collect();
+    ArrayD::from_shape_vec(sh, data).map_err(FitsArrayReadError::ShapeError)
+}
+
+/// Parse header keywords written as `KEY = value`, separated by new lines or
+/// semicolons. Keys are converted to upper case. Values are kept as written
+/// and can be read with `parse_value`.
+pub fn parse_keywords(text: &str) -> Result<Vec<(String, String)>, String> {
+    let mut keywords = vec![];
+    for card in text.split(|c| c == '\n' || c == ';') {
+        let card = card.trim();
+        if card.is_empty() {
+            continue;
+        }
+        let mut split = card.splitn(2, '=');
+        let key = split.next().unwrap().trim().to_uppercase();
+        let value = split
+            .next()
+            .ok_or_else(|| format!("Expected 'KEY = value', got '{}'", card))?
+            .trim();
+        if key.is_empty()
+            || key.len() > 8
+            || !key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(format!("Invalid FITS keyword '{}'", key));
+        }
+        keywords.push((key, value.to_owned()));
+    }
+    Ok(keywords)
+}
+
+/// Parse the value of a header card.
+///
+/// Values are read as a logical (T or F), an integer, a floating point number
+/// or otherwise as a character string. Quotes around a string are optional.
+pub fn parse_value(value: &str) -> HeaderValue {
+    let value = value.trim();
+    if value == "T" {
+        HeaderValue::Logical(true)
+    } else if value == "F" {
+        HeaderValue::Logical(false)
+    } else if let Ok(int) = value.parse::<i32>() {
+        HeaderValue::IntegerNumber(int)
+    } else if let Ok(float) = value.parse::<f64>() {
+        HeaderValue::RealFloatingNumber(float)
+    } else {
+        HeaderValue::CharacterString(value.trim_matches('\'').to_owned())
+    }
+}
+
+/// Split `lines` into the texts of HISTORY cards, each holding at most 72
+/// characters. Characters that may not appear in a FITS header, i.e. that are
+/// not printable ASCII, are replaced with '?'.
+pub fn history_cards(lines: &[String]) -> Vec<String> {
+    let mut cards = vec![];
+    for line in lines {
+        let chars: Vec<_> = line
+            .chars()
+            .map(|c| {
+                if c == ' ' || c.is_ascii_graphic() {
+                    c
+                } else {
+                    '?'
+                }
+            })
+            .collect();
+        if chars.is_empty() {
+            cards.push(String::new());
+        }
+        for chunk in chars.chunks(72) {
+            cards.push(chunk.iter().collect());
+        }
+    }
+    cards
+}
+
+/// Keyword of the card holding the place of the HISTORY card at `index`.
+fn history_placeholder(index: usize) -> String {
+    format!("HIST{:04}", index)
+}
+
+/// Add a card to `hdu` holding the place of each HISTORY card in `cards`.
+///
+/// fitrs only writes cards with a value, and a card overrides the cards that
+/// have the same keyword, so HISTORY cards cannot be inserted directly. Once
+/// the file is written, [`write_history`] replaces the placeholders.
+pub fn insert_history_placeholders(hdu: &mut Hdu, cards: &[String]) -> Result<(), String> {
+    if cards.len() > 10_000 {
+        return Err(format!("Too many HISTORY cards: {}", cards.len()));
+    }
+    for index in 0..cards.len() {
+        let key = history_placeholder(index);
+        if hdu.value(&key).is_some() {
+            return Err(format!("Keyword {} is reserved for HISTORY cards", key));
+        }
+        hdu.insert(key, "");
+    }
+    Ok(())
+}
+
+/// Replace the placeholder cards inserted with
+/// [`insert_history_placeholders`] in the primary header of the FITS file at
+/// `path` with HISTORY commentary cards.
+///
+/// Each card takes exactly 80 bytes, so the cards are replaced in place.
+pub fn write_history(path: &Path, cards: &[String]) -> io::Result<()> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let mut card = [0; 80];
+    let mut offset = 0;
+    loop {
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut card)?;
+        let key = String::from_utf8_lossy(&card[..8]);
+        let key = key.trim_end();
+        if key == "END" {
+            return Ok(());
+        }
+        let text = cards
+            .iter()
+            .enumerate()
+            .find(|&(index, _)| history_placeholder(index) == key)
+            .map(|(_, text)| text);
+        if let Some(text) = text {
+            file.seek(SeekFrom::Start(offset))?;
+            file.write_all(format!("HISTORY {:<72}", text).as_bytes())?;
+        }
+        offset += 80;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{history_cards, parse_keywords, parse_value};
+    use fitrs::HeaderValue;
+
+    #[test]
+    fn parse_header_keywords() {
+        let keywords =
+            parse_keywords("object = 'NGC 253'; EXPTIME=1200.5\nNCOMBINE = 3;FLUXCAL = T").unwrap();
+        let values: Vec<_> = keywords
+            .iter()
+            .map(|(key, value)| (key.as_str(), parse_value(value)))
+            .collect();
+        assert_eq!(
+            values,
+            vec![
+                ("OBJECT", HeaderValue::CharacterString("NGC 253".to_owned())),
+                ("EXPTIME", HeaderValue::RealFloatingNumber(1200.5)),
+                ("NCOMBINE", HeaderValue::IntegerNumber(3)),
+                ("FLUXCAL", HeaderValue::Logical(true)),
+            ]
+        );
+        assert!(parse_keywords("TOO_LONG_KEY = 1").is_err());
+        assert!(parse_keywords("NOVALUE").is_err());
+    }
+
+    #[test]
+    fn split_history_cards() {
+        let long = "x".repeat(100);
+        let cards = history_cards(&["été".to_owned(), "".to_owned(), long]);
+        assert_eq!(cards.len(), 4);
+        assert_eq!(cards[0], "?t?");
+        assert_eq!(cards[1], "");
+        assert_eq!(cards[2].len(), 72);
+        assert_eq!(cards[3].len(), 28);
+    }
+}
@@ -0,0 +1,194 @@
+//! Reading and writing of images stored as HDF5 datasets.
+//!
+//! HDF5 support needs the HDF5 library, and is only built with the `hdf5`
+//! feature.
+//!
+//! Attributes of the dataset are read as FITS header cards: the unit of the
+//! values is read from the `BUNIT` (or `units`) attribute and the world
+//! coordinates from the `CTYPEn`, `CUNITn`, `CRPIXn`, `CRVALn` and `CDELTn`
+//! attributes. The same attributes are written along with the dataset.
+use std::path::Path;
+
+use fitrs::{Hdu, HeaderValue};
+use hdf5::{
+    self,
+    types::{FloatSize, IntSize, TypeDescriptor, VarLenUnicode},
+    Dataset,
+};
+use ndarray::ArrayD;
+
+use unit::{NativeArray, WcsArray};
+
+const AXIS_KEYS: [&str; 5] = ["CTYPE", "CUNIT", "CRPIX", "CRVAL", "CDELT"];
+
+/// Read the dataset at `dataset` (e.g. `/group/data`) in the HDF5 file at
+/// `path`.
+pub fn read<P: AsRef<Path>>(path: P, dataset: &str) -> Result<WcsArray, String> {
+    let file = hdf5::File::open(path).map_err(|e| format!("{}", e))?;
+    let ds = file.dataset(dataset).map_err(|e| format!("{}", e))?;
+    let shape = ds.shape();
+    let descriptor = ds
+        .dtype()
+        .and_then(|dtype| dtype.to_descriptor())
+        .map_err(|e| format!("{}", e))?;
+    let hdu = read_attributes(&ds);
+
+    let native = match descriptor {
+        TypeDescriptor::Float(FloatSize::U4) => {
+            let image = read_array::<f32>(&ds, &shape)?;
+            return Ok(WcsArray::from_hdu_header(&hdu, image));
+        }
+        TypeDescriptor::Float(FloatSize::U8)
+        | TypeDescriptor::Integer(IntSize::U8)
+        | TypeDescriptor::Unsigned(IntSize::U4)
+        | TypeDescriptor::Unsigned(IntSize::U8) => NativeArray::F64(read_array(&ds, &shape)?),
+        TypeDescriptor::Integer(_) | TypeDescriptor::Unsigned(IntSize::U2) => {
+            NativeArray::I32(read_array(&ds, &shape)?)
+        }
+        TypeDescriptor::Unsigned(IntSize::U1) | TypeDescriptor::Boolean => {
+            NativeArray::U8(read_array(&ds, &shape)?)
+        }
+        other => {
+            return Err(format!("Unsupported data type {:?}", other));
+        }
+    };
+    Ok(WcsArray::from_hdu_header(&hdu, native))
+}
+
+/// Read the values of the dataset, converted to `T`.
+fn read_array<T: hdf5::H5Type>(ds: &Dataset, shape: &[usize]) -> Result<ArrayD<T>, String> {
+    let data = ds.read_raw::<T>().map_err(|e| format!("{}", e))?;
+    ArrayD::from_shape_vec(shape, data).map_err(|e| format!("{}", e))
+}
+
+/// Read the attributes describing the unit and the axes of the dataset
+/// into an empty HDU.
+fn read_attributes(ds: &Dataset) -> Hdu {
+    let read_str = |name: &str| {
+        ds.attr(name)
+            .and_then(|attr| attr.read_scalar::<VarLenUnicode>())
+            .ok()
+            .map(|value| value.as_str().to_owned())
+    };
+    let read_f64 = |name: &str| {
+        ds.attr(name)
+            .and_then(|attr| attr.read_scalar::<f64>())
+            .ok()
+    };
+
+    let mut hdu = Hdu::empty();
+    if let Some(unit) = read_str("BUNIT").or_else(|| read_str("units")) {
+        hdu.insert("BUNIT", unit);
+    }
+    for n in 1..=4 {
+        for key in &AXIS_KEYS[..2] {
+            let key = format!("{}{}", key, n);
+            if let Some(value) = read_str(&key) {
+                hdu.insert(key, value);
+            }
+        }
+        for key in &AXIS_KEYS[2..] {
+            let key = format!("{}{}", key, n);
+            if let Some(value) = read_f64(&key) {
+                hdu.insert(key, value);
+            }
+        }
+    }
+    hdu
+}
+
+/// Write `image` as the dataset at `dataset` in the HDF5 file at `path`.
+/// The file is created if it does not exist. An existing dataset at the same
+/// path is replaced.
+pub fn write<P: AsRef<Path>>(path: P, dataset: &str, image: &WcsArray) -> Result<(), String> {
+    let file = hdf5::File::append(path).map_err(|e| format!("{}", e))?;
+    if file.link_exists(dataset) {
+        file.unlink(dataset).map_err(|e| format!("{}", e))?;
+    }
+    let shape = image.shape();
+    let ds = match image.native() {
+        None => write_dataset(&file, dataset, shape, &image.scalar()),
+        Some(NativeArray::F64(array)) => write_dataset(&file, dataset, shape, array),
+        Some(NativeArray::I32(array)) => write_dataset(&file, dataset, shape, array),
+        Some(NativeArray::U8(array)) => write_dataset(&file, dataset, shape, array),
+    }
+    .map_err(|e| format!("{}", e))?;
+
+    // The shape and type of the values are those of the dataset, so only
+    // the cards describing the values and the axes are written.
+    for (key, value) in image.header_cards() {
+        if is_structural(&key) {
+            continue;
+        }
+        match value {
+            HeaderValue::CharacterString(string) => {
+                let string: VarLenUnicode = string.parse().map_err(|e| format!("{}", e))?;
+                ds.new_attr::<VarLenUnicode>()
+                    .create(key.as_str())
+                    .and_then(|attr| attr.write_scalar(&string))
+            }
+            HeaderValue::RealFloatingNumber(float) => ds
+                .new_attr::<f64>()
+                .create(key.as_str())
+                .and_then(|attr| attr.write_scalar(&float)),
+            HeaderValue::IntegerNumber(int) => ds
+                .new_attr::<i32>()
+                .create(key.as_str())
+                .and_then(|attr| attr.write_scalar(&int)),
+            HeaderValue::Logical(b) => ds
+                .new_attr::<bool>()
+                .create(key.as_str())
+                .and_then(|attr| attr.write_scalar(&b)),
+            _ => Ok(()),
+        }
+        .map_err(|e| format!("{}", e))?;
+    }
+    Ok(())
+}
+
+/// Whether `key` describes the layout of a FITS data array.
+fn is_structural(key: &str) -> bool {
+    match key {
+        "SIMPLE" | "XTENSION" | "BITPIX" | "EXTEND" | "PCOUNT" | "GCOUNT" => true,
+        _ => key.starts_with("NAXIS"),
+    }
+}
+
+fn write_dataset<T: hdf5::H5Type + Clone>(
+    file: &hdf5::File,
+    dataset: &str,
+    shape: &[usize],
+    array: &ArrayD<T>,
+) -> hdf5::Result<Dataset> {
+    let ds = file.new_dataset::<T>().shape(shape).create(dataset)?;
+    ds.write_raw(&array.iter().cloned().collect::<Vec<_>>())?;
+    Ok(ds)
+}
+
+#[cfg(test)]
+mod test {
+    use tempfile;
+
+    use super::{read, write};
+    use ndarray::{ArrayD, IxDyn};
+    use unit::{DataType, Dimensioned, Unit, WcsArray};
+
+    #[test]
+    fn write_and_read_hdf5() {
+        let array =
+            ArrayD::from_shape_vec(IxDyn(&[2, 3]), vec![1.0, -2.0, 3.0, 4.0, 5.0, 6.0]).unwrap();
+        let image = WcsArray::from_array(Dimensioned::new(array, Unit::Custom("Jy".into())))
+            .into_dtype(DataType::I32);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.h5");
+        write(&path, "/data", &image).unwrap();
+        // The dataset is replaced
+        write(&path, "/data", &image).unwrap();
+        let read_image = read(&path, "/data").unwrap();
+
+        assert_eq!(read_image.dtype(), DataType::I32);
+        assert_eq!(read_image.array().unit(), &Unit::Custom("Jy".into()));
+        assert_eq!(*read_image.scalar(), *image.scalar());
+        assert!(read(&path, "/flux").is_err());
+    }
+}
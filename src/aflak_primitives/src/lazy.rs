@@ -0,0 +1,629 @@
+//! Data arrays of FITS files mapped in memory.
+//!
+//! Headers are read by fitrs when the file is opened, but the data array of
+//! an image is only mapped. Values are converted when they are needed, so
+//! that a few frames or spectra can be extracted from a large cube without
+//! loading it whole in memory.
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::ops::Range;
+use std::path::PathBuf;
+
+use fitrs::Hdu;
+use memmap::Mmap;
+use ndarray::{Array3, ArrayD, IxDyn};
+use once_cell::sync::OnceCell;
+
+use fits::{float, integer, FitsFile};
+use unit::{DataType, NativeArray};
+
+const BLOCK_SIZE: usize = 2880;
+const CARD_SIZE: usize = 80;
+
+/// Data array of an HDU in a FITS file, mapped in memory.
+pub struct MappedArray {
+    path: PathBuf,
+    map: Mmap,
+    /// Position of the first byte of the data array in `map`
+    offset: usize,
+    bitpix: i64,
+    /// Shape of the data array, in the order of `ndarray` (the last FITS
+    /// axis comes first)
+    shape: Vec<usize>,
+    bscale: f64,
+    bzero: f64,
+    blank: Option<i64>,
+    /// Type in which the values are read
+    dtype: DataType,
+    /// All the values of the array as f32, once they were all needed
+    values: OnceCell<ArrayD<f32>>,
+    /// All the values of the array in their type if it is not f32, once they
+    /// were all needed
+    native: OnceCell<NativeArray>,
+}
+
+impl fmt::Debug for MappedArray {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MappedArray({:?}, {:?})", self.path, self.shape)
+    }
+}
+
+impl MappedArray {
+    /// Map the data array of the HDU at `index` in `fits`.
+    ///
+    /// The headers are those read by fitrs. Only the position of the data
+    /// arrays in the file is computed here.
+    pub fn open(fits: &FitsFile, index: usize) -> io::Result<Self> {
+        let file = File::open(fits.path())?;
+        // The map is read-only. Like any other program reading the file, we
+        // expect it not to be modified while it is open.
+        let map = unsafe { Mmap::map(&file)? };
+        let mut offset = 0;
+        for (i, hdu) in fits.iter().enumerate() {
+            let data_offset = header_end(&map, offset)?;
+            let data_size = data_size(&hdu);
+            if i == index {
+                if data_offset + data_size > map.len() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        format!("Data array of HDU #{} is truncated", index),
+                    ));
+                }
+                return Self::new(fits.path().to_owned(), map, data_offset, &hdu);
+            }
+            offset = data_offset + (data_size + BLOCK_SIZE - 1) / BLOCK_SIZE * BLOCK_SIZE;
+        }
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Could not find HDU #{}", index),
+        ))
+    }
+
+    fn new(path: PathBuf, map: Mmap, offset: usize, hdu: &Hdu) -> io::Result<Self> {
+        let bitpix = integer(hdu, "BITPIX").unwrap_or(0);
+        match bitpix {
+            8 | 16 | 32 | 64 | -32 | -64 => (),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Unsupported BITPIX: {}", bitpix),
+                ))
+            }
+        }
+        let bscale = float(hdu, "BSCALE").unwrap_or(1.0);
+        let bzero = float(hdu, "BZERO").unwrap_or(0.0);
+        let blank = integer(hdu, "BLANK");
+        Ok(MappedArray {
+            path,
+            map,
+            offset,
+            bitpix,
+            shape: naxis(hdu).into_iter().rev().collect(),
+            bscale,
+            bzero,
+            blank,
+            dtype: value_type(bitpix, bscale, bzero, blank),
+            values: OnceCell::new(),
+            native: OnceCell::new(),
+        })
+    }
+
+    /// Read the values as f32 whatever their type, e.g. for a variance.
+    pub fn into_f32(mut self) -> Self {
+        self.dtype = DataType::F32;
+        self
+    }
+
+    /// Shape of the data array, in the order of `ndarray`.
+    pub fn shape(&self) -> &[usize] {
+        &self.shape
+    }
+
+    /// Type in which the values are read, see `value_type`.
+    pub fn dtype(&self) -> DataType {
+        self.dtype
+    }
+
+    /// Get all the values of the array as f32, converting them the first
+    /// time.
+    ///
+    /// Values of another type should rather be read with [`native`], which
+    /// does not lose precision.
+    pub fn values(&self) -> &ArrayD<f32> {
+        self.values.get_or_init(|| {
+            let len = self.shape.iter().product();
+            ArrayD::from_shape_vec(IxDyn(&self.shape), self.read(0, len))
+                .expect("Shape matches the number of values")
+        })
+    }
+
+    /// Get all the values of the array in their type, converting them the
+    /// first time.
+    ///
+    /// Panic if the values are read as f32: use [`values`] instead.
+    pub fn native(&self) -> &NativeArray {
+        self.native.get_or_init(|| {
+            let len = self.shape.iter().product();
+            let values = ArrayD::from_shape_vec(IxDyn(&self.shape), self.read_f64(0, len))
+                .expect("Shape matches the number of values");
+            NativeArray::from_f64(values, self.dtype)
+        })
+    }
+
+    /// Whether all the values were already converted by [`values`] or
+    /// [`native`].
+    pub fn is_read(&self) -> bool {
+        self.values.get().is_some() || self.native.get().is_some()
+    }
+
+    /// Convert the frames from `start` (included) to `end` (excluded) along
+    /// the first axis.
+    ///
+    /// Panic if the array has no dimension or if the frames are out of
+    /// bounds.
+    pub fn frames(&self, start: usize, end: usize) -> ArrayD<f32> {
+        let (shape, first, count) = self.frames_position(start, end);
+        ArrayD::from_shape_vec(shape, self.read(first, count))
+            .expect("Shape matches the number of values")
+    }
+
+    /// Convert the frames from `start` (included) to `end` (excluded) along
+    /// the first axis, in their type.
+    ///
+    /// Panic if the values are read as f32, if the array has no dimension or
+    /// if the frames are out of bounds.
+    pub fn native_frames(&self, start: usize, end: usize) -> NativeArray {
+        let (shape, first, count) = self.frames_position(start, end);
+        let values = ArrayD::from_shape_vec(shape, self.read_f64(first, count))
+            .expect("Shape matches the number of values");
+        NativeArray::from_f64(values, self.dtype)
+    }
+
+    /// Get the shape of the frames from `start` to `end`, the index of their
+    /// first value and their number of values.
+    fn frames_position(&self, start: usize, end: usize) -> (Vec<usize>, usize, usize) {
+        assert!(start <= end && end <= self.shape[0]);
+        let frame_len: usize = self.shape[1..].iter().product();
+        let mut shape = self.shape.clone();
+        shape[0] = end - start;
+        (shape, start * frame_len, (end - start) * frame_len)
+    }
+
+    /// Convert the values of a 3D array in the given frames (along the first
+    /// axis), rows and columns. Only the values in the window are read.
+    ///
+    /// Panic if the array does not have 3 dimensions or if the window is out
+    /// of bounds.
+    pub fn window(
+        &self,
+        frames: Range<usize>,
+        rows: Range<usize>,
+        columns: Range<usize>,
+    ) -> Array3<f32> {
+        assert_eq!(self.shape.len(), 3);
+        for (range, &len) in [&frames, &rows, &columns].iter().zip(&self.shape) {
+            assert!(range.start <= range.end && range.end <= len);
+        }
+        let (height, width) = (self.shape[1], self.shape[2]);
+        let shape = (frames.len(), rows.len(), columns.len());
+        let mut values = Vec::with_capacity(shape.0 * shape.1 * shape.2);
+        for frame in frames {
+            for row in rows.clone() {
+                let start = (frame * height + row) * width + columns.start;
+                values.extend(self.read(start, columns.len()));
+            }
+        }
+        Array3::from_shape_vec(shape, values).expect("Shape matches the number of values")
+    }
+
+    /// Convert `count` consecutive values of the data array to f32, starting
+    /// from the value at index `start` in row-major order.
+    ///
+    /// Values are scaled with BSCALE and BZERO. Integer values equal to BLANK
+    /// are converted to NaN.
+    fn read(&self, start: usize, count: usize) -> Vec<f32> {
+        self.read_f64(start, count)
+            .into_iter()
+            .map(|v| v as f32)
+            .collect()
+    }
+
+    /// Same as `read`, in double precision.
+    fn read_f64(&self, start: usize, count: usize) -> Vec<f64> {
+        let size = (self.bitpix.abs() / 8) as usize;
+        let bytes = &self.map[self.offset + start * size..self.offset + (start + count) * size];
+
+        let (bscale, bzero) = (self.bscale, self.bzero);
+        let scale = |raw: i64| {
+            if Some(raw) == self.blank {
+                ::std::f64::NAN
+            } else {
+                bzero + bscale * raw as f64
+            }
+        };
+        let values = bytes.chunks(size).map(|b| match self.bitpix {
+            8 => scale(i64::from(b[0])),
+            16 => scale(i64::from(i16::from_be_bytes([b[0], b[1]]))),
+            32 => scale(i64::from(i32::from_be_bytes([b[0], b[1], b[2], b[3]]))),
+            64 => scale(i64::from_be_bytes([
+                b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7],
+            ])),
+            -32 => {
+                let f = f32::from_bits(u32::from_be_bytes([b[0], b[1], b[2], b[3]]));
+                bzero + bscale * f64::from(f)
+            }
+            _ => {
+                let f = f64::from_bits(u64::from_be_bytes([
+                    b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7],
+                ]));
+                bzero + bscale * f
+            }
+        });
+        values.collect()
+    }
+}
+
+/// Type in which values are read, given their BITPIX, BSCALE, BZERO and
+/// BLANK, so that they keep their precision.
+///
+/// Integers with null values (BLANK) are read as f32, to represent them with
+/// NaN. Other integers stay integers if their scaled values are all integers
+/// that fit in i32 (or u8 for unscaled 8-bit integers), and are read as f64
+/// otherwise.
+fn value_type(bitpix: i64, bscale: f64, bzero: f64, blank: Option<i64>) -> DataType {
+    let unscaled = bscale == 1.0 && bzero == 0.0;
+    match bitpix {
+        -32 => DataType::F32,
+        -64 => DataType::F64,
+        _ if blank.is_some() => DataType::F32,
+        8 if unscaled => DataType::U8,
+        8 | 16 | 32 if bscale == 1.0 && bzero.fract() == 0.0 => {
+            let (min, max) = match bitpix {
+                8 => (0.0, 255.0),
+                16 => (f64::from(::std::i16::MIN), f64::from(::std::i16::MAX)),
+                _ => (f64::from(::std::i32::MIN), f64::from(::std::i32::MAX)),
+            };
+            let fits_i32 =
+                |v: f64| v >= f64::from(::std::i32::MIN) && v <= f64::from(::std::i32::MAX);
+            if fits_i32(min + bzero) && fits_i32(max + bzero) {
+                DataType::I32
+            } else {
+                DataType::F64
+            }
+        }
+        _ => DataType::F64,
+    }
+}
+
+/// Shape of the data array, in FITS order (NAXIS1 first)
+fn naxis(hdu: &Hdu) -> Vec<usize> {
+    let naxis = integer(hdu, "NAXIS").unwrap_or(0);
+    (1..=naxis)
+        .map(|n| integer(hdu, &format!("NAXIS{}", n)).unwrap_or(0) as usize)
+        .collect()
+}
+
+/// Size in bytes of the data following the header, without padding.
+fn data_size(hdu: &Hdu) -> usize {
+    let naxis = naxis(hdu);
+    if naxis.is_empty() {
+        return 0;
+    }
+    let bitpix = integer(hdu, "BITPIX").unwrap_or(8).abs() as usize;
+    let pcount = integer(hdu, "PCOUNT").unwrap_or(0) as usize;
+    let gcount = integer(hdu, "GCOUNT").unwrap_or(1) as usize;
+    let count: usize = naxis.iter().product();
+    bitpix / 8 * gcount * (pcount + count)
+}
+
+/// Find the position of the data array following the header that starts at
+/// `start`, i.e. the start of the block following the END card.
+fn header_end(map: &[u8], start: usize) -> io::Result<usize> {
+    let mut pos = start;
+    while pos + CARD_SIZE <= map.len() {
+        if map[pos..pos + 8] == *b"END     " {
+            let end = pos + CARD_SIZE;
+            return Ok((end + BLOCK_SIZE - 1) / BLOCK_SIZE * BLOCK_SIZE);
+        }
+        pos += CARD_SIZE;
+    }
+    Err(io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "Could not find the END of a header",
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use tempfile;
+
+    use super::MappedArray;
+    use fits::FitsFile;
+    use roi::ROI;
+    use std::fs::File;
+    use std::io::Write;
+    use std::path::Path;
+    use std::sync::Arc;
+    use unit::{DataType, WcsArray};
+    use {run_extract_wave, run_fits_to_image, IOValue};
+
+    /// Make a header with the given cards, padded to a full block.
+    fn header(cards: &[&str]) -> Vec<u8> {
+        let mut header: String = cards.iter().map(|c| format!("{:<80}", c)).collect();
+        header.push_str(&format!("{:<80}", "END"));
+        while header.len() % 2880 != 0 {
+            header.push(' ');
+        }
+        header.into_bytes()
+    }
+
+    /// Write a file with a primary HDU of the given header cards and data.
+    fn write_primary(path: &Path, cards: &[&str], mut data: Vec<u8>) {
+        let mut file = File::create(path).unwrap();
+        file.write_all(&header(cards)).unwrap();
+        data.resize((data.len() + 2879) / 2880 * 2880, 0);
+        file.write_all(&data).unwrap();
+    }
+
+    /// Read the primary HDU of the FITS file at `path` as an image.
+    fn read_primary(path: &Path) -> WcsArray {
+        let fits = Arc::new(FitsFile::open(path).unwrap());
+        match run_fits_to_image(&fits, 0, "", "") {
+            Ok(IOValue::Image(image)) => image,
+            _ => panic!("Expected an image"),
+        }
+    }
+
+    /// Write a cube of shape (3, 2, 4) with 16-bit integers, scaled by
+    /// BSCALE = 0.5 and BZERO = 10, after an empty primary HDU.
+    fn write_cube(path: &Path) {
+        let mut file = File::create(path).unwrap();
+        file.write_all(&header(&[
+            "SIMPLE  =                    T",
+            "BITPIX  =                    8",
+            "NAXIS   =                    0",
+            "EXTEND  =                    T",
+        ]))
+        .unwrap();
+        file.write_all(&header(&[
+            "XTENSION= 'IMAGE   '",
+            "BITPIX  =                   16",
+            "NAXIS   =                    3",
+            "NAXIS1  =                    4",
+            "NAXIS2  =                    2",
+            "NAXIS3  =                    3",
+            "PCOUNT  =                    0",
+            "GCOUNT  =                    1",
+            "EXTNAME = 'DATA    '           / extension name",
+            "BSCALE  =                  0.5",
+            "BZERO   =                 10.0",
+            "BLANK   =                   -1",
+        ]))
+        .unwrap();
+        let mut data = vec![];
+        for v in 0..24i16 {
+            let v = if v == 5 { -1 } else { v };
+            data.extend_from_slice(&v.to_be_bytes());
+        }
+        data.resize(2880, 0);
+        file.write_all(&data).unwrap();
+    }
+
+    #[test]
+    fn map_values_and_frames() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cube.fits");
+        write_cube(&path);
+        let fits = FitsFile::open(&path).unwrap();
+
+        let mapped = MappedArray::open(&fits, 1).unwrap();
+        assert_eq!(mapped.shape(), &[3, 2, 4]);
+        assert_eq!(mapped.dtype(), DataType::F32);
+
+        let frames = mapped.frames(1, 3);
+        assert!(!mapped.is_read());
+        assert_eq!(frames.shape(), &[2, 2, 4]);
+        let window = mapped.window(1..3, 1..2, 1..4);
+        assert!(!mapped.is_read());
+        assert_eq!(window.shape(), &[2, 1, 3]);
+
+        let all = mapped.values();
+        assert!(mapped.is_read());
+        assert_eq!(all[[0, 0, 1]], 10.5);
+        assert!(all[[0, 1, 1]].is_nan());
+        assert_eq!(frames[[0, 0, 0]], all[[1, 0, 0]]);
+        assert_eq!(frames[[1, 1, 3]], all[[2, 1, 3]]);
+        assert_eq!(window[[0, 0, 0]], all[[1, 1, 1]]);
+        assert_eq!(window[[1, 0, 2]], all[[2, 1, 3]]);
+
+        assert!(MappedArray::open(&fits, 2).is_err());
+
+        let image =
+            WcsArray::from_mapped(&fits.get(1).unwrap(), MappedArray::open(&fits, 1).unwrap());
+        assert_eq!(image.shape(), &[3, 2, 4]);
+        let frame = image.index_frame(2);
+        assert_eq!(frame.scalar()[[1, 3]], 21.5);
+        assert_eq!(image.scalar()[[2, 1, 3]], 21.5);
+    }
+
+    #[test]
+    fn extract_spectrum_from_mapped_cube() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cube.fits");
+        write_cube(&path);
+        let fits = FitsFile::open(&path).unwrap();
+
+        let image =
+            WcsArray::from_mapped(&fits.get(1).unwrap(), MappedArray::open(&fits, 1).unwrap());
+        // Pixels (x, y) = (1, 0) and (3, 1) hold the values 1 and 7 in the
+        // first frame, 9 and 15 in the second, etc., before scaling.
+        let roi = ROI::PixelList(vec![(1, 0), (3, 1)]);
+        let spectrum = match run_extract_wave(&image, &roi) {
+            Ok(IOValue::Image(spectrum)) => spectrum,
+            _ => panic!("Expected a spectrum"),
+        };
+        assert!(image.is_partly_read());
+        assert_eq!(spectrum.scalar().as_slice().unwrap(), &[24.0, 32.0, 40.0]);
+    }
+
+    #[test]
+    fn map_values_in_their_type() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("f64.fits");
+        let mut data = vec![];
+        for v in 0..8 {
+            data.extend_from_slice(&(f64::from(v) + 0.25).to_be_bytes());
+        }
+        write_primary(
+            &path,
+            &[
+                "SIMPLE  =                    T",
+                "BITPIX  =                  -64",
+                "NAXIS   =                    3",
+                "NAXIS1  =                    2",
+                "NAXIS2  =                    2",
+                "NAXIS3  =                    2",
+            ],
+            data,
+        );
+        let image = read_primary(&path);
+        assert_eq!(image.dtype(), DataType::F64);
+        let frame = image.index_frame(1);
+        assert!(image.is_partly_read());
+        assert_eq!(frame.dtype(), DataType::F64);
+        assert_eq!(frame.scalar_f64()[[1, 1]], 7.25);
+        assert_eq!(image.scalar_f64()[[1, 1, 1]], 7.25);
+        assert!(!image.is_partly_read());
+
+        let path = dir.path().join("i16.fits");
+        let mut data = vec![];
+        for v in 0..8i16 {
+            data.extend_from_slice(&(v - 4).to_be_bytes());
+        }
+        write_primary(
+            &path,
+            &[
+                "SIMPLE  =                    T",
+                "BITPIX  =                   16",
+                "NAXIS   =                    2",
+                "NAXIS1  =                    4",
+                "NAXIS2  =                    2",
+                "BZERO   =                  100",
+            ],
+            data,
+        );
+        let image = read_primary(&path);
+        assert_eq!(image.dtype(), DataType::I32);
+        let sum = image.with_frames(1, 2, |frame| frame.sum());
+        assert!(image.is_partly_read());
+        assert_eq!(sum, 406.0);
+    }
+
+    #[test]
+    fn map_variance_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("variance.fits");
+        let mut file = File::create(&path).unwrap();
+        file.write_all(&header(&[
+            "SIMPLE  =                    T",
+            "BITPIX  =                    8",
+            "NAXIS   =                    0",
+            "EXTEND  =                    T",
+        ]))
+        .unwrap();
+        for (name, bitpix, values) in &[
+            (
+                "'DATA    '",
+                "                  -32",
+                [1.0f32, 2.0, 3.0, 4.0],
+            ),
+            ("'VAR     '", "                   16", [1.0, 4.0, 9.0, 16.0]),
+        ] {
+            file.write_all(&header(&[
+                "XTENSION= 'IMAGE   '",
+                &format!("BITPIX  = {}", bitpix),
+                "NAXIS   =                    2",
+                "NAXIS1  =                    2",
+                "NAXIS2  =                    2",
+                "PCOUNT  =                    0",
+                "GCOUNT  =                    1",
+                &format!("EXTNAME = {}", name),
+            ]))
+            .unwrap();
+            let mut data = vec![];
+            for &v in values {
+                if *bitpix == "                  -32" {
+                    data.extend_from_slice(&v.to_be_bytes());
+                } else {
+                    data.extend_from_slice(&(v as i16).to_be_bytes());
+                }
+            }
+            data.resize(2880, 0);
+            file.write_all(&data).unwrap();
+        }
+
+        let fits = Arc::new(FitsFile::open(&path).unwrap());
+        let image = match run_fits_to_image(&fits, 0, "DATA", "VAR") {
+            Ok(IOValue::Image(image)) => image,
+            _ => panic!("Expected an image"),
+        };
+        assert_eq!(
+            image.variance_frames(1, 2).unwrap().as_slice().unwrap(),
+            &[9.0, 16.0]
+        );
+        let frame = image.index_frame(0);
+        assert_eq!(frame.variance().unwrap().as_slice().unwrap(), &[1.0, 4.0]);
+        assert_eq!(image.variance().unwrap()[[1, 1]], 16.0);
+
+        assert!(run_fits_to_image(&fits, 0, "DATA", "NOISE").is_err());
+    }
+
+    #[test]
+    fn scale_integers_without_blank() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("u16.fits");
+        // Unsigned 16-bit integers are stored shifted by BZERO = 32768
+        let mut data = vec![];
+        for v in &[-32768i16, 0, 32767] {
+            data.extend_from_slice(&v.to_be_bytes());
+        }
+        write_primary(
+            &path,
+            &[
+                "SIMPLE  =                    T",
+                "BITPIX  =                   16",
+                "NAXIS   =                    1",
+                "NAXIS1  =                    3",
+                "BZERO   =                32768",
+            ],
+            data,
+        );
+        let image = read_primary(&path);
+        assert_eq!(image.dtype(), DataType::I32);
+        assert_eq!(image.scalar().as_slice().unwrap(), &[0.0, 32768.0, 65535.0]);
+    }
+
+    #[test]
+    fn read_8_bit_integers() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("u8.fits");
+        let cards = [
+            "SIMPLE  =                    T",
+            "BITPIX  =                    8",
+            "NAXIS   =                    1",
+            "NAXIS1  =                    3",
+        ];
+        write_primary(&path, &cards, vec![0, 200, 255]);
+        let image = read_primary(&path);
+        assert_eq!(image.dtype(), DataType::U8);
+        assert_eq!(image.scalar().as_slice().unwrap(), &[0.0, 200.0, 255.0]);
+
+        let mut cards = cards.to_vec();
+        cards.push("BSCALE  =                  0.5");
+        write_primary(&path, &cards, vec![0, 200, 255]);
+        let image = read_primary(&path);
+        assert_eq!(image.dtype(), DataType::F64);
+        assert_eq!(image.scalar().as_slice().unwrap(), &[0.0, 100.0, 127.5]);
+    }
+}
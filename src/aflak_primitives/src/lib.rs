@@ -22,21 +22,42 @@ extern crate variant_name_derive;
 #[macro_use]
 extern crate aflak_cake as cake;
 pub extern crate fitrs;
+#[cfg(feature = "hdf5")]
+extern crate hdf5;
 #[macro_use]
 pub extern crate ndarray;
+extern crate memmap;
 extern crate nalgebra;
+extern crate once_cell;
+#[cfg(test)]
+extern crate ron;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+#[cfg(test)]
+extern crate tempfile;
 
+mod detection;
 mod fits;
+#[cfg(feature = "hdf5")]
+mod h5;
+mod lazy;
+mod mask;
+mod npy;
+mod photometry;
 #[macro_use]
 mod precond;
+mod regions;
+mod reproject;
 mod roi;
+mod slicing;
+mod stats;
 mod unit;
+mod voronoi;
 
+pub use fits::FitsFile;
 pub use roi::ROI;
-pub use unit::{Dimensioned, Unit, WcsArray};
+pub use unit::{DataType, Dimensioned, Unit, Values, WcsArray};
 
 use std::error::Error;
 use std::fmt;
@@ -45,9 +66,14 @@ use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use nalgebra::{Matrix3, Vector3};
-use ndarray::{Array, Array1, Array2, ArrayD, ArrayViewD, Axis, Dimension, ShapeBuilder, Slice};
+use ndarray::{
+    Array, Array1, Array2, ArrayD, ArrayView1, ArrayView2, ArrayViewD, Axis, Dimension, Ix1, Ix2,
+    Ix3, ShapeBuilder, Slice,
+};
 use variant_name::VariantName;
 
+use lazy::MappedArray;
+
 /// Value used for I/O in astronomical transforms.
 ///
 /// If new use cases arise, please add a new variant to this enumeration.
@@ -62,8 +88,10 @@ pub enum IOValue {
     Path(PathBuf),
     #[serde(skip_serializing)]
     #[serde(skip_deserializing)]
-    Fits(Arc<fitrs::Fits>),
+    Fits(Arc<FitsFile>),
     Image(WcsArray),
+    /// Images to write to a FITS file, each with the name of its extension.
+    FitsExtensions(Vec<(String, WcsArray)>),
     Map2dTo3dCoords(Array2<[f32; 3]>),
     Roi(roi::ROI),
 }
@@ -79,6 +107,7 @@ impl PartialEq for IOValue {
             (Str(s1), Str(s2)) => s1 == s2,
             (Bool(b1), Bool(b2)) => b1 == b2,
             (Image(i1), Image(i2)) => i1 == i2,
+            (FitsExtensions(e1), FitsExtensions(e2)) => e1 == e2,
             (Map2dTo3dCoords(m1), Map2dTo3dCoords(m2)) => m1 == m2,
             (Roi(r1), Roi(r2)) => r1 == r2,
             (Path(p1), Path(p2)) => p1 == p2,
@@ -111,6 +140,21 @@ impl fmt::Display for IOErr {
     }
 }
 
+/// Errors are cloned when a transform with several outputs fails, so that
+/// each output gets its own copy of the error.
+impl Clone for IOErr {
+    fn clone(&self) -> Self {
+        use IOErr::*;
+
+        match self {
+            IoError(e, s) => IoError(io::Error::new(e.kind(), e.to_string()), s.clone()),
+            FITSErr(s) => FITSErr(s.clone()),
+            UnexpectedInput(s) => UnexpectedInput(s.clone()),
+            ShapeError(e, s) => ShapeError(e.clone(), s.clone()),
+        }
+    }
+}
+
 impl Error for IOErr {
     fn description(&self) -> &str {
         "aflak_primitives::IOErr"
@@ -123,8 +167,10 @@ pub type SuccessOut = cake::compute::SuccessOut<IOValue>;
 lazy_static! {
     /// The exhaustive list of all staticly loaded astrophysical transforms.
     pub static ref TRANSFORMATIONS: Vec<cake::Transform<'static, IOValue, IOErr>> = {
-        vec![
+        #[cfg_attr(not(feature = "hdf5"), allow(unused_mut))]
+        let mut transforms = vec![
             cake_transform!(
+                category: "I/O",
                 "Open FITS file from a Path.",
                 1, 0, 0,
                 open_fits<IOValue, IOErr>(path: Path) -> Fits {
@@ -132,13 +178,17 @@ lazy_static! {
                 }
             ),
             cake_transform!(
-                "Extract dataset from FITS file.",
-                1, 0, 0,
-                fits_to_image<IOValue, IOErr>(fits: Fits, hdu_idx: Integer = 0, extension: Str = "".to_owned()) -> Image {
-                    vec![run_fits_to_image(fits, *hdu_idx, extension)]
+                category: "I/O",
+                "Extract dataset from FITS file.
+Single precision data is mapped from the file and read when it is needed, so that slicing frames or extracting spectra does not load the whole cube.
+If variance_extension is provided (for example 'STAT'), the variance of the data is loaded from this extension and propagated through computations.",
+                1, 1, 0,
+                fits_to_image<IOValue, IOErr>(fits: Fits, hdu_idx: Integer = 0, extension: Str = "".to_owned(), variance_extension: Str = "".to_owned()) -> Image {
+                    vec![run_fits_to_image(fits, *hdu_idx, extension, variance_extension)]
                 }
             ),
             cake_transform!(
+                category: "Slicing",
                 "Slice one frame of a n-dimensional dataset turning it into an (n-1)-dimensional dataset.",
                 1, 0, 0,
                 slice_one_frame<IOValue, IOErr>(image: Image, frame: Integer = 0) -> Image {
@@ -146,13 +196,17 @@ lazy_static! {
                 }
             ),
             cake_transform!(
-                "Slice an arbitrary plane through a 3D dataset and return the slice.",
-                0, 1, 0,
+                category: "Slicing",
+                "Slice an arbitrary plane through a 3D dataset and return the slice.
+Values are interpolated trilinearly between pixels. Points of the map out of the dataset are NaN.
+Axes of the slice parallel to an axis of the dataset keep its world coordinates, while oblique axes measure the offset from the first point of the map.",
+                0, 2, 0,
                 slice_3d_to_2d<IOValue, IOErr>(image: Image, map: Map2dTo3dCoords) -> Image {
                     vec![run_slice_3d_to_2d(image, map)]
                 }
             ),
             cake_transform!(
+                category: "Slicing",
                 "Make a 2D mesh on a specific plane.
 Parameters:
 1. Starting point: (x, y, z)
@@ -172,13 +226,22 @@ for 0 <= i < n and 0 <= j < m",
                 }
             ),
             cake_transform!(
+                category: "Slicing",
                 "Extract waveform from image with the provided region of interest.",
                 1, 0, 0,
                 extract_wave<IOValue, IOErr>(image: Image, roi: Roi = roi::ROI::All) -> Image {
                     vec![run_extract_wave(image, roi)]
                 }
             ),
-            cake_transform!("Replace all values above or below a threshold in a image with NaN.
+            cake_transform!(
+                category: "Slicing",
+                "Extract waveform from image with the provided region of interest, weighting each pixel by the fraction of its surface covered by the region.",
+                1, 0, 0,
+                extract_wave_weighted<IOValue, IOErr>(image: Image, roi: Roi = roi::ROI::All) -> Image {
+                    vec![run_extract_wave_weighted(image, roi)]
+                }
+            ),
+            cake_transform!(category: "Arithmetic", "Replace all values above or below a threshold in a image with NaN.
 Takes two parameters: a threshold and a bool.
 If bool value is checked, then replaces the values above the threshold with NaN, else replace the values below the threshold with NaN.",
                 1, 0, 0,
@@ -186,21 +249,24 @@ If bool value is checked, then replaces the values above the threshold with NaN,
                     vec![run_clip(image, *ceiling_threshold, *ceiling, *floor_threshold, *floor)]
                 }
             ),
-            cake_transform!("Replace all NaN values in image with the provided value.",
+            cake_transform!(category: "Arithmetic", "Replace all NaN values in image with the provided value.",
                 1, 0, 0,
                 replace_nan_image<IOValue, IOErr>(image: Image, placeholder: Float = 0.0) -> Image {
                     vec![run_replace_nan_image(image, *placeholder)]
                 }
             ),
             cake_transform!(
+                category: "Arithmetic",
                 "Compose 2 vectors. Parameters: u, v, a, b.
-Compute a*u + b*v.",
-                1, 0, 0,
+Compute a*u + b*v.
+The output type is the promoted type of the inputs: integers stay integers if a and b are integers.",
+                1, 1, 0,
                 linear_composition<IOValue, IOErr>(u: Image, v: Image, a: Float = 1.0, b: Float = 1.0) -> Image {
                     vec![run_linear_composition(u, v, *a, *b)]
                 }
             ),
             cake_transform!(
+                category: "Constants",
                 "Make a Float3 from 3 float values.",
                 1, 0, 0,
                 make_float3<IOValue, IOErr>(f1: Float = 0.0, f2: Float = 0.0, f3: Float = 0.0) -> Float3 {
@@ -208,6 +274,7 @@ Compute a*u + b*v.",
                 }
             ),
             cake_transform!(
+                category: "Spectral",
                 "Integral for Image. Parameters: a=start, b=end (a <= b).
 Compute Sum[k, {a, b}]image[k]. image[k] is k-th slice of image.
 Second output contains (a + b) / 2
@@ -221,6 +288,7 @@ Note: indices for a and b start from 0",
                 }
             ),
             cake_transform!(
+                category: "Spectral",
                 "Ratio from bands' center wavelength.
 Parameters: z(on-band's center wavelength), z1, z2(off-bands' centerwavelength) (z1 < z < z2).
 Compute off_ratio = 1 - (z - z1) / (z2 - z1), off_ratio_2 = 1 - (z2 - z) / (z2 - z1)",
@@ -241,6 +309,7 @@ Compute off_ratio = 1 - (z - z1) / (z2 - z1), off_ratio_2 = 1 - (z2 - z) / (z2 -
                 }
             ),
             cake_transform!(
+                category: "Spectral",
                 "Average for Image. Parameters: a=start, b=end (a <= b).
 Compute (Sum[k, {a, b}]image[k]) / (b - a). image[k] is k-th slice of image.
 Second output contains (a + b) / 2
@@ -254,16 +323,19 @@ Note: indices for a and b start from 0",
                 }
             ),
             cake_transform!(
+                category: "Spectral",
                 "Create Equivalent-Width map from off-band and on-band.
 Parameters i_off, i_on, onband-width, min, is_emission.
 Compute value = (i1 - i2) * fl / i1 (if is_emission is true, the sign of this value turns over).
-if value > max, value changes to 0.",
+if value > max, value changes to 0.
+The variances of i_off and i_on, if any, are propagated.",
                 0, 1, 0,
                 create_equivalent_width<IOValue, IOErr>(i_off: Image, i_on: Image, fl: Float = 1.0, max: Float = ::std::f32::INFINITY, is_emission: Bool = false) -> Image {
                     vec![run_create_equivalent_width(i_off, i_on, *fl, *max, *is_emission)]
                 }
             ),
             cake_transform!(
+                category: "Arithmetic",
                 "Convert to log-scale. Parameter: image, a, v_min, v_max.
 Compute y = log(ax + 1) / log(a)  (x = (value - v_min) / (v_max - v_min))",
                 1, 0, 0,
@@ -272,6 +344,7 @@ Compute y = log(ax + 1) / log(a)  (x = (value - v_min) / (v_max - v_min))",
                 }
             ),
             cake_transform!(
+                category: "Statistics",
                 "Image's min and max value. Parameter: image.
 Compute v_min(first), v_max(second)",
                 1, 0, 0,
@@ -280,7 +353,7 @@ Compute v_min(first), v_max(second)",
                     let mut max = std::f32::MIN;
                     let image_arr = image.scalar();
 
-                    for i in image_arr {
+                    for i in image_arr.iter() {
                         min = min.min(*i);
                         max = max.max(*i);
                     }
@@ -289,6 +362,7 @@ Compute v_min(first), v_max(second)",
                 }
             ),
             cake_transform!(
+                category: "Spectral",
                 "Extract min/max wavelength value of each pixel.
 Parameter: image, start, end, is_min (start <= end)
 Output argmax/argmin map of flux; wavelength
@@ -300,6 +374,7 @@ Note: output wavelength values are discrete. indices for start and end start fro
                 }
             ),
             cake_transform!(
+                category: "Spectral",
                 "peak-based_wavelength_range.
 Parameter: image, start, end, range, is_min (start <= end)
 Output [argmax - range, argmax + range] [argmin - range, argmin + range] map of flux; wavelength
@@ -310,6 +385,7 @@ Note: output wavelength values are discrete. indices for start and end start fro
                 }
             ),
             cake_transform!(
+                category: "Spectral",
                 "Extract centrobaric wavelength value of each pixel.
 Parameter: image (which has wavelength value w_i and flux f_i), start, end
 Compute Sum[k, (start, end)](f_k * w_k) / Sum(k, (start, end)(f_k))
@@ -320,6 +396,7 @@ Note: indices for start and end start from 0",
                 }
             ),
             cake_transform!(
+                category: "Spectral",
                 "Extract centrobaric wavelength value of each pixel with mask.
 Parameter: image (which has wavelength value w_i and flux f_i), start_mask, end_mask
 Compute Sum[k, (start, end)](f_k * w_k) / Sum(k, (start, end)(f_k))",
@@ -329,6 +406,7 @@ Compute Sum[k, (start, end)](f_k * w_k) / Sum(k, (start, end)(f_k))",
                 }
             ),
             cake_transform!(
+                category: "Spectral",
                 "Create velocity field map
 Parameter: image (which has wavelength value w_i in each pixel), representative wavelength w_0
 Compute Velocity v = c * (w_i - w_0) / w_0   (c = 3e5 [km/s])",
@@ -345,6 +423,7 @@ Compute Velocity v = c * (w_i - w_0) / w_0   (c = 3e5 [km/s])",
                 }
             ),
             cake_transform!(
+                category: "Arithmetic",
                 "Negation. Parameter: image. Compute -i.",
                 1, 0, 0,
                 negation<IOValue, IOErr>(image: Image) -> Image {
@@ -352,6 +431,7 @@ Compute Velocity v = c * (w_i - w_0) / w_0   (c = 3e5 [km/s])",
                 }
             ),
             cake_transform!(
+                category: "Slicing",
                 "Extrude along the wavelength",
                 0, 1, 0,
                 extrude<IOValue, IOErr>(image: Image, roi: Roi = roi::ROI::All) -> Image {
@@ -359,6 +439,7 @@ Compute Velocity v = c * (w_i - w_0) / w_0   (c = 3e5 [km/s])",
                 }
             ),
             cake_transform!(
+                category: "Slicing",
                 "range specification",
                 0, 1, 0,
                 range_specification<IOValue, IOErr>(image: Image, start: Integer = 0, end: Integer = 1) -> Image {
@@ -366,6 +447,7 @@ Compute Velocity v = c * (w_i - w_0) / w_0   (c = 3e5 [km/s])",
                 }
             ),
             cake_transform!(
+                category: "Spectral",
             "Gaussian. Parameter: image, start, end
 Compute mean, when the (x, y) is fitted as y = A * exp(-(x - mean) ^ 2 / (2 * sigma ^ 2))",
                 0, 1, 0,
@@ -374,6 +456,7 @@ Compute mean, when the (x, y) is fitted as y = A * exp(-(x - mean) ^ 2 / (2 * si
                 }
             ),
             cake_transform!(
+                category: "Spectral",
                 "Gaussian with mask. Parameter: image, start_mask, end_mask
 Compute mean, when the (x, y) is fitted as y = A * exp(-(x - mean) ^ 2 / (2 * sigma ^ 2))",
                 0, 1, 0,
@@ -381,7 +464,407 @@ Compute mean, when the (x, y) is fitted as y = A * exp(-(x - mean) ^ 2 / (2 * si
                     vec![run_gaussian_mean_with_mask(image, start_mask, end_mask)]
                 }
             ),
-        ]
+            cake_transform!(
+                category: "Binning",
+                "Voronoi binning. Parameters: signal, noise, target_sn, wvt.
+Bin the pixels of 2D signal and noise images to reach the target signal-to-noise ratio (Cappellari & Copin 2003).
+If wvt is checked, regularize the bins with a weighted Voronoi tessellation.
+First output is the bin map (bin index of each pixel, NaN for pixels that could not be binned).
+Second and third outputs contain the signal and noise of each bin.",
+                1, 0, 0,
+                voronoi_binning<IOValue, IOErr>(signal: Image, noise: Image, target_sn: Float = 10.0, wvt: Bool = true) -> Image, Image, Image {
+                    run_voronoi_binning(signal, noise, *target_sn, *wvt)
+                }
+            ),
+            cake_transform!(
+                category: "Binning",
+                "Apply a bin map to a cube. Parameters: image, bin_map.
+Sum the spectra of all the spaxels in each bin of the bin map.
+Output a 2D image whose first axis is the wavelength axis of the cube and whose second axis is the bin index.
+The k-th column is the spectrum of the k-th bin, so that e.g. integral outputs the value of each bin.",
+                1, 0, 0,
+                apply_bin_map<IOValue, IOErr>(image: Image, bin_map: Image) -> Image {
+                    vec![run_apply_bin_map(image, bin_map)]
+                }
+            ),
+            cake_transform!(
+                category: "Binning",
+                "Scatter per-bin values back to a 2D map. Parameters: values, bin_map.
+Each pixel of the bin map is replaced by the value of its bin (NaN for pixels out of any bin).",
+                1, 0, 0,
+                scatter_bins<IOValue, IOErr>(values: Image, bin_map: Image) -> Image {
+                    vec![run_scatter_bins(values, bin_map)]
+                }
+            ),
+            cake_transform!(
+                category: "Alignment",
+                "Reproject an image onto the pixel grid of a target image. Parameters: source, target, interpolation.
+The target is only used for its WCS and the shape of its frames.
+Interpolation is one of 'nearest', 'bilinear' or 'flux' (flux-conserving, exact overlap of pixels).",
+                1, 0, 0,
+                reproject<IOValue, IOErr>(source: Image, target: Image, interpolation: Str = "bilinear".to_owned()) -> Image {
+                    vec![run_reproject(source, target, interpolation)]
+                }
+            ),
+            cake_transform!(
+                category: "Alignment",
+                "Align an image on a reference image. Parameters: reference, image, max_shift.
+Estimate the sub-pixel shift maximizing the cross-correlation of both images (up to max_shift pixels in each direction), then apply it.
+First output is the aligned image. Second output is the applied shift (dx, dy) in pixels.",
+                1, 0, 0,
+                align_images<IOValue, IOErr>(reference: Image, image: Image, max_shift: Integer = 5) -> Image, Float2 {
+                    match run_align_images(reference, image, *max_shift) {
+                        Ok((aligned, shift)) => vec![Ok(IOValue::Image(aligned)), Ok(IOValue::Float2(shift))],
+                        Err(e) => vec![Err(e); 2],
+                    }
+                }
+            ),
+            cake_transform!(
+                category: "Statistics",
+                "Statistics of an image. Parameters: image, roi, sigma, iterations, percentile_low, percentile_high.
+NaN values are ignored. If a region of interest is provided, only the pixels within it are used (in each frame for a 3D image).
+Outputs: mean, median, standard deviation, median absolute deviation, low and high percentiles, count of NaN values,
+then mean, median and standard deviation after sigma-clipping (values further than sigma standard deviations from the median are rejected, up to 'iterations' times).",
+                1, 0, 0,
+                statistics<IOValue, IOErr>(image: Image, roi: Roi = roi::ROI::All, sigma: Float = 3.0, iterations: Integer = 5, percentile_low: Float = 5.0, percentile_high: Float = 95.0) -> Float, Float, Float, Float, Float, Float, Integer, Float, Float, Float {
+                    run_statistics(image, roi, *sigma, *iterations, *percentile_low, *percentile_high)
+                }
+            ),
+            cake_transform!(
+                category: "Statistics",
+                "Estimate a smooth 2D background. Parameters: image, box_size, filter_size, sigma.
+The image is divided in boxes of box_size pixels, whose sigma-clipped median and standard deviation make a background mesh.
+The mesh is median-filtered with a window of filter_size boxes, then interpolated to the resolution of the image.
+First output is the background map. Second output is the background RMS map.",
+                1, 0, 0,
+                background_2d<IOValue, IOErr>(image: Image, box_size: Integer = 32, filter_size: Integer = 3, sigma: Float = 3.0) -> Image, Image {
+                    match run_background_2d(image, *box_size, *filter_size, *sigma) {
+                        Ok((bkg, rms)) => vec![Ok(IOValue::Image(bkg)), Ok(IOValue::Image(rms))],
+                        Err(e) => vec![Err(e); 2],
+                    }
+                }
+            ),
+            cake_transform!(
+                category: "Photometry",
+                "Detect sources on a background-subtracted image. Parameters: image, rms, threshold, min_pixels, deblend.
+Pixels above threshold times the background RMS map are grouped in connected sources of at least min_pixels pixels.
+If deblend is checked, blended sources are split using multiple threshold levels.
+First output is the segmentation map, where each pixel contains the label of its source (starting from 1), or 0 for the background.
+Second output is a table with one row per source and the columns: label, x, y, flux, area, ellipticity.",
+                1, 0, 0,
+                detect_sources<IOValue, IOErr>(image: Image, rms: Image, threshold: Float = 3.0, min_pixels: Integer = 5, deblend: Bool = false) -> Image, Image {
+                    match run_detect_sources(image, rms, *threshold, *min_pixels, *deblend) {
+                        Ok((segmap, table)) => vec![Ok(IOValue::Image(segmap)), Ok(IOValue::Image(table))],
+                        Err(e) => vec![Err(e); 2],
+                    }
+                }
+            ),
+            cake_transform!(
+                category: "Photometry",
+                "Make a region of interest from the pixels of a segmentation map with the provided label.",
+                1, 0, 0,
+                segment_to_roi<IOValue, IOErr>(segmentation: Image, label: Integer = 1) -> Roi {
+                    vec![run_segment_to_roi(segmentation, *label)]
+                }
+            ),
+            cake_transform!(
+                category: "Photometry",
+                "Circular aperture photometry. Parameters: image, variance, position, sky, radius, annulus_in, annulus_out.
+Position is in pixel coordinates (x, y), or in world coordinates if sky is checked. Radii are in pixels.
+Pixels are weighted by their exact overlap with the aperture.
+The background per pixel is the sigma-clipped median of the annulus between annulus_in and annulus_out, and is subtracted from the flux.
+Set annulus_out to 0 to disable background subtraction.
+variance is optional. If it is not connected, the variance attached to image is used, if any.
+Outputs: flux, flux error (propagated from the variance), background per pixel, aperture area and flux unit.",
+                1, 0, 0,
+                aperture_photometry<IOValue, IOErr>(image: Image, variance: Image = no_image(), position: Float2 = [0.0; 2], sky: Bool = false, radius: Float = 5.0, annulus_in: Float = 8.0, annulus_out: Float = 12.0) -> Float, Float, Float, Float, Str {
+                    run_aperture_photometry(image, variance, *position, *sky, (1.0, 0.0), [*radius, *annulus_in, *annulus_out])
+                }
+            ),
+            cake_transform!(
+                category: "Photometry",
+                "Elliptical aperture photometry. Parameters: image, variance, position, sky, a, b, theta, annulus_in, annulus_out.
+a and b are the semi-major and semi-minor axes in pixels, theta is the angle of the major axis in degrees from the x axis.
+The annulus is made of ellipses of same shape, with semi-major axes annulus_in and annulus_out.
+variance is optional, as for aperture_photometry.
+Outputs: flux, flux error, background per pixel, aperture area and flux unit.",
+                1, 0, 0,
+                elliptical_aperture_photometry<IOValue, IOErr>(image: Image, variance: Image = no_image(), position: Float2 = [0.0; 2], sky: Bool = false, a: Float = 5.0, b: Float = 3.0, theta: Float = 0.0, annulus_in: Float = 8.0, annulus_out: Float = 12.0) -> Float, Float, Float, Float, Str {
+                    run_aperture_photometry(image, variance, *position, *sky, (*b / *a, *theta), [*a, *annulus_in, *annulus_out])
+                }
+            ),
+            cake_transform!(
+                category: "Photometry",
+                "Circular aperture photometry on a table of sources, as output by detect_sources. Parameters: image, variance, sources, radius, annulus_in, annulus_out.
+variance is optional, as for aperture_photometry.
+Outputs: a table with one row per source and the columns: label, x, y, flux, flux error, background, area; and the unit of the flux, flux error and background columns, that of the image.
+The table itself has no unit, as its columns have different units.",
+                1, 0, 0,
+                photometry_table<IOValue, IOErr>(image: Image, variance: Image = no_image(), sources: Image, radius: Float = 5.0, annulus_in: Float = 8.0, annulus_out: Float = 12.0) -> Image, Str {
+                    run_photometry_table(image, variance, sources, *radius, *annulus_in, *annulus_out)
+                }
+            ),
+            cake_transform!(
+                category: "Regions",
+                "Make a circular region of interest. Parameters: center (x, y), radius, in pixels.
+Pixels on the edge are weighted by their overlap with the circle.",
+                1, 0, 0,
+                roi_circle<IOValue, IOErr>(center: Float2 = [0.0; 2], radius: Float = 5.0) -> Roi {
+                    vec![Ok(IOValue::Roi(roi::ROI::Circle { center: (center[0], center[1]), radius: *radius }))]
+                }
+            ),
+            cake_transform!(
+                category: "Regions",
+                "Make an elliptical region of interest. Parameters: center (x, y), a, b, angle.
+a and b are the semi-axes in pixels. angle is the angle of the a axis in degrees, counter-clockwise from the x axis.",
+                1, 0, 0,
+                roi_ellipse<IOValue, IOErr>(center: Float2 = [0.0; 2], a: Float = 5.0, b: Float = 3.0, angle: Float = 0.0) -> Roi {
+                    vec![Ok(IOValue::Roi(roi::ROI::Ellipse { center: (center[0], center[1]), a: *a, b: *b, angle: *angle }))]
+                }
+            ),
+            cake_transform!(
+                category: "Regions",
+                "Make a rectangular region of interest from its corners with the smallest and the largest (x, y) coordinates, in pixels.",
+                1, 0, 0,
+                roi_rect<IOValue, IOErr>(min: Float2 = [0.0; 2], max: Float2 = [10.0; 2]) -> Roi {
+                    vec![Ok(IOValue::Roi(roi::ROI::Rect { min: (min[0], min[1]), max: (max[0], max[1]) }))]
+                }
+            ),
+            cake_transform!(
+                category: "Regions",
+                "Make an annular region of interest. Parameters: center (x, y), inner radius r_in, outer radius r_out, in pixels.",
+                1, 0, 0,
+                roi_annulus<IOValue, IOErr>(center: Float2 = [0.0; 2], r_in: Float = 5.0, r_out: Float = 10.0) -> Roi {
+                    vec![Ok(IOValue::Roi(roi::ROI::Annulus { center: (center[0], center[1]), r_in: *r_in, r_out: *r_out }))]
+                }
+            ),
+            cake_transform!(
+                category: "Regions",
+                "Combine two regions of interest. Parameters: a, b, operation.
+Operation is one of 'union', 'intersection' or 'difference' (pixels of a that are not in b).",
+                1, 0, 0,
+                roi_combine<IOValue, IOErr>(a: Roi, b: Roi, operation: Str = "union".to_owned()) -> Roi {
+                    vec![run_roi_combine(a, b, operation)]
+                }
+            ),
+            cake_transform!(
+                category: "Regions",
+                "Rasterize a region of interest on the grid of an image.
+Output is an image of same dimension whose pixels contain the fraction of their surface within the region of interest, between 0 and 1.",
+                1, 0, 0,
+                roi_mask<IOValue, IOErr>(image: Image, roi: Roi) -> Image {
+                    vec![run_roi_mask(image, roi)]
+                }
+            ),
+            cake_transform!(
+                category: "I/O",
+                "Load regions of interest from a DS9 region file. Parameters: path, image.
+Supported shapes are circle, ellipse, box, polygon and annulus, in image or fk5 coordinates.
+Sky coordinates are converted to pixels using the WCS of the image.",
+                1, 0, 0,
+                load_regions<IOValue, IOErr>(path: Path, image: Image) -> Roi {
+                    vec![run_load_regions(path, image)]
+                }
+            ),
+            cake_transform!(
+                category: "Statistics",
+                "Attach a variance map to an image. Parameters: image, variance.
+The variance is then propagated through arithmetic operations and reductions.",
+                1, 0, 0,
+                attach_variance<IOValue, IOErr>(image: Image, variance: Image) -> Image {
+                    vec![run_attach_variance(image, variance)]
+                }
+            ),
+            cake_transform!(
+                category: "Statistics",
+                "Extract the error map (square root of the variance) of an image.",
+                1, 0, 0,
+                error_map<IOValue, IOErr>(image: Image) -> Image {
+                    vec![run_error_map(image)]
+                }
+            ),
+            cake_transform!(
+                category: "Masks",
+                "Make a mask from a data quality extension of a FITS file. Parameters: fits, extension, flags.
+Pixels with any of the bits of flags set are masked. The default (-1) masks all pixels with a non-zero quality flag.
+Masks are images whose masked pixels are 1 and good pixels are 0.",
+                1, 0, 0,
+                fits_to_mask<IOValue, IOErr>(fits: Fits, extension: Str = "DQ".to_owned(), flags: Integer = -1) -> Image {
+                    vec![run_fits_to_mask(fits, extension, *flags)]
+                }
+            ),
+            cake_transform!(
+                category: "Masks",
+                "Make a mask of the pixels of an image above a threshold (or below if above is false). Parameters: image, threshold, above.
+NaN pixels are always masked.",
+                1, 0, 0,
+                threshold_mask<IOValue, IOErr>(image: Image, threshold: Float = 0.0, above: Bool = true) -> Image {
+                    vec![run_threshold_mask(image, *threshold, *above)]
+                }
+            ),
+            cake_transform!(
+                category: "Masks",
+                "Combine two masks. Parameters: a, b, operation.
+Operation is one of 'or' (pixels masked in a or b) or 'and' (pixels masked in both a and b).",
+                1, 0, 0,
+                mask_combine<IOValue, IOErr>(a: Image, b: Image, operation: Str = "or".to_owned()) -> Image {
+                    vec![run_mask_combine(a, b, operation)]
+                }
+            ),
+            cake_transform!(
+                category: "Masks",
+                "Invert a mask.",
+                1, 0, 0,
+                mask_not<IOValue, IOErr>(mask: Image) -> Image {
+                    vec![run_mask_not(mask)]
+                }
+            ),
+            cake_transform!(
+                category: "Masks",
+                "Grow a mask by a radius in pixels. Parameters: mask, radius.
+Masks of cubes are grown in each wavelength plane.",
+                1, 0, 0,
+                mask_dilate<IOValue, IOErr>(mask: Image, radius: Integer = 1) -> Image {
+                    vec![run_mask_dilate(mask, *radius)]
+                }
+            ),
+            cake_transform!(
+                category: "Masks",
+                "Apply a mask to an image. Parameters: image, mask, replace_with_nan.
+Masked pixels are ignored by reductions such as integral, average, min and max. If replace_with_nan is true, masked pixels are also replaced with NaN.",
+                1, 0, 0,
+                apply_mask<IOValue, IOErr>(image: Image, mask: Image, replace_with_nan: Bool = false) -> Image {
+                    vec![run_apply_mask(image, mask, *replace_with_nan)]
+                }
+            ),
+            cake_transform!(
+                category: "Masks",
+                "Get the mask applied to an image.",
+                1, 0, 0,
+                get_mask<IOValue, IOErr>(image: Image) -> Image {
+                    vec![run_get_mask(image)]
+                }
+            ),
+            cake_transform!(
+                category: "I/O",
+                "Make a list of FITS extensions out of an image. Parameters: image, name.
+The extension is named after name, unless name is empty.",
+                1, 0, 0,
+                fits_extensions<IOValue, IOErr>(image: Image, name: Str = "SCI".to_owned()) -> FitsExtensions {
+                    vec![Ok(IOValue::FitsExtensions(vec![(name.to_owned(), image.clone())]))]
+                }
+            ),
+            cake_transform!(
+                category: "I/O",
+                "Add an image to a list of FITS extensions. Parameters: extensions, image, name.
+The extension is named after name, unless name is empty.",
+                1, 0, 0,
+                add_fits_extension<IOValue, IOErr>(extensions: FitsExtensions, image: Image, name: Str = "".to_owned()) -> FitsExtensions {
+                    let mut extensions = extensions.clone();
+                    extensions.push((name.to_owned(), image.clone()));
+                    vec![Ok(IOValue::FitsExtensions(extensions))]
+                }
+            ),
+            cake_transform!(
+                category: "I/O",
+                "Write a list of extensions to a multi-extension FITS file. Parameters: path, extensions, keywords.
+Each image is written in its own extension, with its unit and world coordinates. An image can also be connected as a single unnamed extension.
+keywords are added to the primary header, written as 'KEY = value' separated by semicolons.
+A summary of the node program that produced the extensions is recorded in the HISTORY cards of the primary header. It lists at most 50 nodes, without the values of constants.
+Output the path of the written file.",
+                1, 0, 0,
+                write_fits<IOValue, IOErr>(path: Path, extensions: FitsExtensions, keywords: Str = "".to_owned()) -> Path with context {
+                    let program = context.program_summary(WRITE_FITS_HISTORY_NODES);
+                    vec![run_write_fits(path, extensions, keywords, &program)]
+                }
+            ),
+            cake_transform!(
+                category: "I/O",
+                "Read a keyword in the header of a HDU of a FITS file. Parameters: fits, hdu_idx, extension, key.
+The HDU is found by extension name, or else by index. The value is output as a string, and as a float, an integer and a boolean if it can be converted.",
+                1, 0, 0,
+                read_fits_keyword<IOValue, IOErr>(fits: Fits, hdu_idx: Integer = 0, extension: Str = "".to_owned(), key: Str = "EXPTIME".to_owned()) -> Str, Float, Integer, Bool {
+                    run_read_fits_keyword(fits, *hdu_idx, extension, key)
+                }
+            ),
+            cake_transform!(
+                category: "I/O",
+                "Attach FITS header keywords to an image, overriding keywords with the same key. Parameters: image, keywords.
+Keywords are written as 'KEY = value' separated by semicolons, and are written in the header when the image is saved.",
+                1, 0, 0,
+                set_keywords<IOValue, IOErr>(image: Image, keywords: Str = "".to_owned()) -> Image {
+                    vec![run_set_keywords(image, keywords)]
+                }
+            ),
+            cake_transform!(
+                category: "Arithmetic",
+                "Convert the values of an image to type 'dtype', one of 'u8', 'i32', 'f32' or 'f64'.
+Values are rounded and saturated when converted to integers.",
+                1, 0, 0,
+                convert_type<IOValue, IOErr>(image: Image, dtype: Str = "f32".to_owned()) -> Image {
+                    vec![run_convert_type(image, dtype)]
+                }
+            ),
+            cake_transform!(
+                category: "Arithmetic",
+                "Get the type of the values of an image.",
+                1, 0, 0,
+                image_type<IOValue, IOErr>(image: Image) -> Str {
+                    vec![Ok(IOValue::Str(image.dtype().name().to_owned()))]
+                }
+            ),
+            cake_transform!(
+                category: "I/O",
+                "Open a NumPy array saved as a .npy file.",
+                1, 0, 0,
+                open_npy<IOValue, IOErr>(path: Path) -> Image {
+                    vec![run_open_npy(path)]
+                }
+            ),
+            cake_transform!(
+                category: "I/O",
+                "Save the values of an image as a NumPy .npy file. Metadata such as units and world coordinates are not written.
+Output the path of the written file.",
+                1, 0, 0,
+                write_npy<IOValue, IOErr>(path: Path, image: Image) -> Path {
+                    vec![run_write_npy(path, image)]
+                }
+            ),
+            cake_transform!(
+                category: "Slicing",
+                "Extract a position-velocity diagram from a 3D dataset, along the path drawn with a Line (or the edges of a polygon).
+The path is sampled every 'step' pixels. Values are averaged across a slit of 'width' pixels perpendicular to the path.
+The first axis of the output is the offset along the path, and the second axis is the third FITS axis (NAXIS3) of the dataset (e.g. wavelength).",
+                1, 0, 0,
+                pv_diagram<IOValue, IOErr>(image: Image, path: Roi = roi::ROI::All, step: Float = 1.0, width: Float = 1.0) -> Image {
+                    vec![run_pv_diagram(image, path, *step, *width)]
+                }
+            ),
+        ];
+        // HDF5 transforms are only available if the HDF5 library is linked
+        #[cfg(feature = "hdf5")]
+        transforms.extend(vec![
+            cake_transform!(
+                category: "I/O",
+                "Open the dataset at 'dataset' in an HDF5 file.
+The unit and world coordinates are read from the BUNIT (or units), CTYPEn, CUNITn, CRPIXn, CRVALn and CDELTn attributes of the dataset, if present.",
+                1, 0, 0,
+                open_hdf5<IOValue, IOErr>(path: Path, dataset: Str = "/data".to_owned()) -> Image {
+                    vec![run_open_hdf5(path, dataset)]
+                }
+            ),
+            cake_transform!(
+                category: "I/O",
+                "Write an image as the dataset at 'dataset' in an HDF5 file, with its unit and world coordinates as attributes.
+An existing dataset with the same name is replaced.
+Output the path of the written file.",
+                1, 0, 0,
+                write_hdf5<IOValue, IOErr>(path: Path, image: Image, dataset: Str = "/data".to_owned()) -> Path {
+                    vec![run_write_hdf5(path, image, dataset)]
+                }
+            ),
+        ]);
+        transforms
     };
 }
 
@@ -432,6 +915,11 @@ impl cake::ConvertibleVariants for IOValue {
             into: "Integer",
             f: float_to_integer,
         },
+        cake::ConvertibleVariant {
+            from: "Image",
+            into: "FitsExtensions",
+            f: image_to_fits_extensions,
+        },
     ];
 }
 
@@ -449,25 +937,37 @@ fn float_to_integer(from: &IOValue) -> IOValue {
         panic!("Unexpected input!")
     }
 }
+fn image_to_fits_extensions(from: &IOValue) -> IOValue {
+    if let IOValue::Image(image) = from {
+        IOValue::FitsExtensions(vec![(String::new(), image.clone())])
+    } else {
+        panic!("Unexpected input!")
+    }
+}
 
 /// Open FITS file
 fn run_open_fits<P: AsRef<Path>>(path: P) -> Result<IOValue, IOErr> {
     let path = path.as_ref();
-    fitrs::Fits::open(path)
+    FitsFile::open(path)
         .map(|fits| IOValue::Fits(Arc::new(fits)))
         .map_err(|err| IOErr::IoError(err, format!("Could not open file {:?}", path)))
 }
 
-/// Turn a FITS file into an image
-fn run_fits_to_image(
-    fits: &Arc<fitrs::Fits>,
+/// Find the HDU named `extension` in a FITS file, along with its index. Fall
+/// back to the HDU at index `hdu_idx` if there is no such extension.
+fn find_hdu(
+    fits: &fitrs::Fits,
     hdu_idx: i64,
     extension: &str,
-) -> Result<IOValue, IOErr> {
+) -> Result<(usize, fitrs::Hdu), IOErr> {
     let hdu_idx = try_into_unsigned!(hdu_idx)?;
-    let primary_hdu = fits
-        .get_by_name(extension)
-        .or_else(|| fits.get(hdu_idx))
+    let named = if extension == "" {
+        None
+    } else {
+        find_named_hdu(fits, extension)
+    };
+    named
+        .or_else(|| fits.get(hdu_idx).map(|hdu| (hdu_idx, hdu)))
         .ok_or_else(|| {
             let hdu_name = if hdu_idx == 0 {
                 "Primary HDU".to_owned()
@@ -482,25 +982,63 @@ fn run_fits_to_image(
                     extension, hdu_name
                 ))
             }
+        })
+}
+
+/// Find the HDU named `extension` (EXTNAME) and its index.
+fn find_named_hdu(fits: &fitrs::Fits, extension: &str) -> Option<(usize, fitrs::Hdu)> {
+    fits.iter()
+        .enumerate()
+        .find(|(_, hdu)| match hdu.value("EXTNAME") {
+            Some(fitrs::HeaderValue::CharacterString(name)) => name.trim_end() == extension,
+            _ => false,
+        })
+}
+
+/// Turn a FITS file into an image
+fn run_fits_to_image(
+    fits: &Arc<FitsFile>,
+    hdu_idx: i64,
+    extension: &str,
+    variance_extension: &str,
+) -> Result<IOValue, IOErr> {
+    let (index, primary_hdu) = find_hdu(fits, hdu_idx, extension)?;
+    // Map the data instead of reading it all. Values are read in their own
+    // type when they are needed.
+    let image = match MappedArray::open(fits, index).ok() {
+        Some(mapped) => WcsArray::from_mapped(&primary_hdu, mapped),
+        None => WcsArray::from_hdu(&primary_hdu).map_err(|e| IOErr::FITSErr(format!("{}", e)))?,
+    };
+    if variance_extension == "" {
+        return Ok(IOValue::Image(image));
+    }
+
+    let (variance_index, variance_hdu) =
+        find_named_hdu(fits, variance_extension).ok_or_else(|| {
+            IOErr::UnexpectedInput(format!(
+                "Could not find HDU '{}' in FITS file.",
+                variance_extension
+            ))
         })?;
-    WcsArray::from_hdu(&primary_hdu)
-        .map(IOValue::Image)
-        .map_err(|e| IOErr::FITSErr(format!("{}", e)))
+    // The variance is mapped as the data, and read when it is first needed
+    match MappedArray::open(fits, variance_index) {
+        Ok(variance) => Ok(IOValue::Image(image.with_mapped_variance(variance)?)),
+        Err(_) => {
+            let variance =
+                WcsArray::from_hdu(&variance_hdu).map_err(|e| IOErr::FITSErr(format!("{}", e)))?;
+            are_same_dim!(image, variance)?;
+            Ok(IOValue::Image(
+                image.with_variance(Some(variance.scalar_owned()))?,
+            ))
+        }
+    }
 }
 
 fn run_slice_one_frame(input_img: &WcsArray, frame_idx: i64) -> Result<IOValue, IOErr> {
     let frame_idx = try_into_unsigned!(frame_idx)?;
     is_sliceable!(input_img, frame_idx)?;
 
-    let image_val = input_img.scalar();
-    let out = image_val.index_axis(Axis(0), frame_idx);
-
-    let wrap_with_unit = input_img.make_slice(
-        &(0..out.ndim()).map(|i| (i, 0.0, 1.0)).collect::<Vec<_>>(),
-        input_img.array().with_new_value(out.to_owned()),
-    );
-
-    Ok(IOValue::Image(wrap_with_unit))
+    Ok(IOValue::Image(input_img.index_frame(frame_idx)))
 }
 
 /// Slice a 3D image through an arbitrary 2D plane
@@ -597,99 +1135,82 @@ fn run_slice_3d_to_2d(image: &WcsArray, map: &Array2<[f32; 3]>) -> Result<IOValu
             Self { origin, dir1, dir2 }
         }
 
-        fn sliced_axes(&self) -> Option<[(usize, f32, f32); 2]> {
-            enum HowToSlice {
-                KeepDirection,
-                RemoveDirection,
-            }
-            impl HowToSlice {
-                fn how(dx1: Option<f32>, dx2: Option<f32>) -> Option<Self> {
-                    if let (Some(dx1), Some(dx2)) = (dx1, dx2) {
-                        if dx1 < EPSILON && dx2 > EPSILON || dx1 > EPSILON && dx2 < EPSILON {
-                            Some(HowToSlice::KeepDirection)
-                        } else if dx1 < EPSILON && dx2 < EPSILON {
-                            Some(HowToSlice::RemoveDirection)
-                        } else {
-                            None
-                        }
-                    } else {
-                        None
-                    }
+        /// Get the world coordinates of the axis of the slice going along
+        /// `dir` from the origin of the map, as expected by
+        /// `WcsArray::make_linear_axes`.
+        ///
+        /// An axis parallel to an axis of the image keeps its world
+        /// coordinates. An oblique axis measures the offset from the origin,
+        /// in the unit of the crossed axes if they all have the same unit,
+        /// or else in pixels.
+        fn world_axis(
+            &self,
+            image: &WcsArray,
+            dir: &[Option<f32>; 3],
+        ) -> Option<(unit::Axis, f32, f32)> {
+            let origin = self.origin?;
+            let axes = image.axes()?;
+            // Axis of index a in the array is FITS axis 2 - a
+            let world = |a: usize, pixel: f32| image.pix2world(2 - a, pixel);
+            let mut crossed = vec![];
+            for a in 0..3 {
+                let d = dir[a]?;
+                if d.abs() > EPSILON {
+                    crossed.push((a, d));
                 }
             }
-
-            let remove_x = match HowToSlice::how(self.dir1[0], self.dir2[0]) {
-                Some(HowToSlice::KeepDirection) => false,
-                Some(HowToSlice::RemoveDirection) => true,
-                None => false,
-            };
-            let remove_y = match HowToSlice::how(self.dir1[1], self.dir2[1]) {
-                Some(HowToSlice::KeepDirection) => false,
-                Some(HowToSlice::RemoveDirection) => true,
-                None => false,
-            };
-            let remove_z = match HowToSlice::how(self.dir1[2], self.dir2[2]) {
-                Some(HowToSlice::KeepDirection) => false,
-                Some(HowToSlice::RemoveDirection) => true,
-                None => false,
-            };
-
-            if let Some(origin) = self.origin {
-                match (remove_x, remove_y, remove_z) {
-                    (true, false, false) => self.non_zero_factor(2).and_then(|factor2| {
-                        self.non_zero_factor(1)
-                            .map(|factor1| [(0, origin[2], factor2), (1, origin[1], factor1)])
-                    }),
-                    (false, true, false) => self.non_zero_factor(2).and_then(|factor2| {
-                        self.non_zero_factor(0)
-                            .map(|factor0| [(0, origin[2], factor2), (2, origin[0], factor0)])
-                    }),
-                    (false, false, true) => self.non_zero_factor(1).and_then(|factor1| {
-                        self.non_zero_factor(0)
-                            .map(|factor0| [(1, origin[1], factor1), (2, origin[0], factor0)])
-                    }),
-                    _ => None,
+            match crossed.len() {
+                0 => None,
+                1 => {
+                    let (a, d) = crossed[0];
+                    let start = world(a, origin[a])?;
+                    let step = world(a, origin[a] + d)? - start;
+                    Some((axes[2 - a].clone(), start, step))
                 }
-            } else {
-                None
-            }
-        }
-        fn non_zero_factor(&self, index: usize) -> Option<f32> {
-            match (self.dir1[index], self.dir2[index]) {
-                (Some(x), Some(y)) => {
-                    if x.abs() < EPSILON {
-                        Some(y)
-                    } else {
-                        Some(x)
+                _ => {
+                    let unit = axes[2 - crossed[0].0].unit();
+                    let same_unit = crossed.iter().all(|&(a, _)| axes[2 - a].unit() == unit);
+                    let mut length2 = 0.0;
+                    for &(a, d) in &crossed {
+                        let delta = if same_unit {
+                            world(a, origin[a] + d)? - world(a, origin[a])?
+                        } else {
+                            d
+                        };
+                        length2 += delta * delta;
                     }
+                    let unit = if !same_unit || unit.is_empty() {
+                        Unit::None
+                    } else {
+                        Unit::Custom(unit.to_owned())
+                    };
+                    Some((
+                        unit::Axis::new(Some("OFFSET".to_owned()), unit),
+                        0.0,
+                        length2.sqrt(),
+                    ))
                 }
-                _ => None,
             }
         }
     }
 
-    let mut out = Vec::with_capacity(map.len());
-    for &[x, y, z] in map {
-        // Interpolate to nearest
-        let input_img = image.scalar();
-
-        let out_val = *input_img
-            .get([x as usize, y as usize, z as usize])
-            .ok_or_else(|| {
-                IOErr::UnexpectedInput(format!(
-                    "Input maps to out of bound pixel!: [{}, {}, {}]",
-                    x, y, z
-                ))
-            })?;
-        out.push(out_val);
-    }
+    let image_val = image.scalar();
+    let cube = image_val
+        .view()
+        .into_dimensionality::<Ix3>()
+        .expect("Image is 3-dimensional");
+    let out = map
+        .iter()
+        .map(|&pos| slicing::trilinear(&cube, pos))
+        .collect();
     Array2::from_shape_vec(map.dim(), out)
         .map(|array| {
-            let array = array.into_dyn();
-            let array = image.array().with_new_value(array);
+            let array = image.array().with_new_value(array.into_dyn());
             let params = MapReverseParams::new(map);
-            let array = if let Some(axes) = params.sliced_axes() {
-                image.make_slice(&axes, array)
+            let axis1 = params.world_axis(image, &params.dir1);
+            let axis2 = params.world_axis(image, &params.dir2);
+            let array = if let (Some(axis1), Some(axis2)) = (axis1, axis2) {
+                image.make_linear_axes(&[axis1, axis2], array)
             } else {
                 WcsArray::from_array(array)
             };
@@ -726,29 +1247,58 @@ fn run_make_plane3d(
 fn run_extract_wave(image: &WcsArray, roi: &roi::ROI) -> Result<IOValue, IOErr> {
     dim_is!(image, 3)?;
 
-    let image_val = image.scalar();
+    let shape = image.shape();
+    let pixels: Vec<_> = roi
+        .pixels((shape[1], shape[2]))
+        .into_iter()
+        .map(|idx| (idx, 1.0))
+        .collect();
+    Ok(IOValue::Image(extract_spectrum(image, &pixels)))
+}
 
-    let wave_size = *image_val.dim().as_array_view().first().unwrap();
+fn run_extract_wave_weighted(image: &WcsArray, roi: &roi::ROI) -> Result<IOValue, IOErr> {
+    dim_is!(image, 3)?;
+
+    let shape = image.shape();
+    let pixels = roi.weighted_pixels((shape[1], shape[2]));
+    Ok(IOValue::Image(extract_spectrum(image, &pixels)))
+}
+
+/// Sum the given pixels of each frame of a 3D image, each multiplied by its
+/// weight.
+fn extract_spectrum(image: &WcsArray, pixels: &[((usize, usize), f32)]) -> WcsArray {
+    // Only the rows and columns covering the pixels are read, frame by frame,
+    // in case the image is mapped from a file
+    let indices = || pixels.iter().map(|&(idx, _)| idx);
+    let rows = indices().map(|(row, _)| row).min().unwrap_or(0)
+        ..indices().map(|(row, _)| row + 1).max().unwrap_or(0);
+    let columns = indices().map(|(_, column)| column).min().unwrap_or(0)
+        ..indices().map(|(_, column)| column + 1).max().unwrap_or(0);
+
+    let wave_size = image.shape()[0];
     let mut wave = Vec::with_capacity(wave_size);
     for i in 0..wave_size {
         let mut res = 0.0;
-        for (_, val) in roi.filterx(image_val.slice(s![i, .., ..])) {
-            res += val;
-        }
+        image.with_window(i..i + 1, rows.clone(), columns.clone(), |window| {
+            for &((row, column), weight) in pixels {
+                res += weight * window[(0, row - rows.start, column - columns.start)];
+            }
+        });
         wave.push(res);
     }
-    Ok(IOValue::Image(
-        image.make_slice(
-            &[(2, 0.0, 1.0)],
-            image
-                .array()
-                .with_new_value(Array1::from_vec(wave).into_dyn()),
-        ),
-    ))
+    image.make_slice(
+        &[(2, 0.0, 1.0)],
+        image
+            .array()
+            .with_new_value(Array1::from_vec(wave).into_dyn()),
+    )
 }
 
 fn run_range_specification(image: &WcsArray, start: i64, end: i64) -> Result<IOValue, IOErr> {
-    reduce_array_slice(image, start, end, |slices| slices.to_owned())
+    let start = try_into_unsigned!(start)?;
+    let end = try_into_unsigned!(end)?;
+    is_sliceable!(image, start, end)?;
+    Ok(IOValue::Image(image.slice_frames(start, end)))
 }
 
 fn run_extrude(image: &WcsArray, roi: &roi::ROI) -> Result<IOValue, IOErr> {
@@ -757,16 +1307,17 @@ fn run_extrude(image: &WcsArray, roi: &roi::ROI) -> Result<IOValue, IOErr> {
     let image_val = image.scalar();
     let wave_size = *image_val.dim().as_array_view().first().unwrap();
 
-    let new_size = (wave_size, roi.datalen());
-    let mut result = Vec::with_capacity(wave_size * roi.datalen());
+    let mut result = vec![];
+    let mut pixel_count = 0;
 
     for i in (0..wave_size).rev() {
-        for (_, val) in roi.filterx(image_val.slice(s![i, .., ..])) {
-            result.push(val);
-        }
+        let values = roi.filterx(image_val.slice(s![i, .., ..]));
+        pixel_count = values.len();
+        result.extend(values.into_iter().map(|(_, val)| val));
     }
 
-    let waveimg = Array::from_shape_vec(new_size.strides((roi.datalen(), 1)), result).unwrap();
+    let new_size = (wave_size, pixel_count);
+    let waveimg = Array::from_shape_vec(new_size.strides((pixel_count, 1)), result).unwrap();
 
     Ok(IOValue::Image(WcsArray::from_array(Dimensioned::new(
         waveimg.into_dyn(),
@@ -781,27 +1332,29 @@ fn run_clip(
     floor_threshold: f32,
     floor: bool,
 ) -> Result<IOValue, IOErr> {
-    let mut image = image.clone();
-
-    for f in image.scalar_mut().iter_mut() {
-        if (ceiling && *f >= ceiling_threshold) || (floor && *f <= floor_threshold) {
-            *f = ::std::f32::NAN;
+    let (ceiling_threshold, floor_threshold) =
+        (f64::from(ceiling_threshold), f64::from(floor_threshold));
+    Ok(IOValue::Image(image.map_values(DataType::F32, |v| {
+        if (ceiling && v >= ceiling_threshold) || (floor && v <= floor_threshold) {
+            ::std::f64::NAN
+        } else {
+            v
         }
-    }
-
-    Ok(IOValue::Image(image))
+    })))
 }
 
 fn run_replace_nan_image(image: &WcsArray, placeholder: f32) -> Result<IOValue, IOErr> {
-    let mut image = image.clone();
-
-    for f in image.scalar_mut().iter_mut() {
-        if f.is_nan() {
-            *f = placeholder;
-        }
+    if image.dtype().is_integer() {
+        return Ok(IOValue::Image(image.clone()));
     }
-
-    Ok(IOValue::Image(image))
+    let placeholder = f64::from(placeholder);
+    Ok(IOValue::Image(image.map_values(image.dtype(), |v| {
+        if v.is_nan() {
+            placeholder
+        } else {
+            v
+        }
+    })))
 }
 
 fn run_linear_composition(
@@ -811,59 +1364,113 @@ fn run_linear_composition(
     coef2: f32,
 ) -> Result<IOValue, IOErr> {
     are_same_dim!(i1, i2)?;
-    let out = i1 * coef1 + i2 * coef2;
-    Ok(IOValue::Image(out))
+    Ok(IOValue::Image(i1 * coef1 + i2 * coef2))
 }
 
 fn run_make_float3(f1: f32, f2: f32, f3: f32) -> Result<IOValue, IOErr> {
     Ok(IOValue::Float3([f1, f2, f3]))
 }
 
+/// Reduce the slices of `image` from `start` to `end` along its first axis.
+///
+/// `f` is given the slices and their mask of bad values, if any. If the
+/// reduction removes the first axis, the values where all the slices are
+/// masked are masked in the output.
 fn reduce_array_slice<F>(image: &WcsArray, start: i64, end: i64, f: F) -> Result<IOValue, IOErr>
 where
-    F: Fn(&ArrayViewD<f32>) -> ArrayD<f32>,
+    F: Fn(&ArrayViewD<f32>, Option<&ArrayViewD<bool>>) -> ArrayD<f32>,
+{
+    reduce_array_slice_with_variance(image, start, end, f, |_, _| None)
+}
+
+/// Same as `reduce_array_slice`, where `g` computes the variance of the
+/// reduced array from the variance of the slices, if any.
+fn reduce_array_slice_with_variance<F, G>(
+    image: &WcsArray,
+    start: i64,
+    end: i64,
+    f: F,
+    g: G,
+) -> Result<IOValue, IOErr>
+where
+    F: Fn(&ArrayViewD<f32>, Option<&ArrayViewD<bool>>) -> ArrayD<f32>,
+    G: Fn(&ArrayViewD<f32>, Option<&ArrayViewD<bool>>) -> Option<ArrayD<f32>>,
 {
     let start = try_into_unsigned!(start)?;
     let end = try_into_unsigned!(end)?;
     is_sliceable!(image, start, end)?;
 
-    let image_val = image.scalar();
-
-    let slices = image_val.slice_axis(Axis(0), Slice::from(start..end));
-    let raw = f(&slices);
+    let mask = image
+        .mask()
+        .map(|mask| mask.slice_axis(Axis(0), Slice::from(start..end)));
+    let raw = image.with_frames(start, end, |slices| f(&slices, mask.as_ref()));
     let ndim = raw.ndim();
 
-    let wrap_with_unit = image.make_slice(
-        &(0..ndim).map(|i| (i, 0.0, 1.0)).collect::<Vec<_>>(),
-        image.array().with_new_value(raw),
-    );
+    let variance = image
+        .variance_frames(start, end)
+        .and_then(|variance| g(&variance.view(), mask.as_ref()));
+    let mask = mask.map(|mask| {
+        if ndim == mask.ndim() {
+            mask.to_owned()
+        } else {
+            mask.fold_axis(Axis(0), true, |all, &masked| *all && masked)
+        }
+    });
+
+    let wrap_with_unit = image
+        .make_slice(
+            &(0..ndim).map(|i| (i, 0.0, 1.0)).collect::<Vec<_>>(),
+            image.array().with_new_value(raw),
+        )
+        .with_variance(variance)?
+        .with_mask(mask)?;
 
     Ok(IOValue::Image(wrap_with_unit))
 }
 
 fn run_integral(image: &WcsArray, start: i64, end: i64) -> Result<IOValue, IOErr> {
-    reduce_array_slice(image, start, end, |slices| slices.sum_axis(Axis(0)))
+    reduce_array_slice_with_variance(
+        image,
+        start,
+        end,
+        |slices, mask| mask::fold_axis0(slices, mask, 0.0, |a, b| a + b),
+        |variance, mask| Some(mask::fold_axis0(variance, mask, 0.0, |a, b| a + b)),
+    )
 }
 
 fn run_average(image: &WcsArray, start: i64, end: i64) -> Result<IOValue, IOErr> {
-    reduce_array_slice(image, start, end, |slices| slices.mean_axis(Axis(0)))
+    reduce_array_slice_with_variance(
+        image,
+        start,
+        end,
+        |slices, mask| {
+            let n = mask::count_axis0(slices.shape(), mask);
+            mask::fold_axis0(slices, mask, 0.0, |a, b| a + b) / n
+        },
+        |variance, mask| {
+            let n = mask::count_axis0(variance.shape(), mask);
+            Some(mask::fold_axis0(variance, mask, 0.0, |a, b| a + b) / (&n * &n))
+        },
+    )
 }
 
 fn run_minmax(image: &WcsArray, start: i64, end: i64, is_min: bool) -> Result<IOValue, IOErr> {
     if !is_min {
-        reduce_array_slice(image, start, end, |slices| {
-            slices.fold_axis(
-                Axis(0),
+        reduce_array_slice(image, start, end, |slices, mask| {
+            mask::fold_axis0(
+                slices,
+                mask,
                 -std::f32::INFINITY,
-                |x, y| if x > y { *x } else { *y },
+                |x, y| if x > y { x } else { y },
             )
         })
     } else {
-        reduce_array_slice(image, start, end, |slices| {
-            slices.fold_axis(
-                Axis(0),
+        reduce_array_slice(image, start, end, |slices, mask| {
+            mask::fold_axis0(
+                slices,
+                mask,
                 std::f32::INFINITY,
-                |x, y| if x < y { *x } else { *y },
+                |x, y| if x < y { x } else { y },
             )
         })
     }
@@ -1040,10 +1647,39 @@ fn run_create_equivalent_width(
     max: f32,
     is_emission: bool,
 ) -> Result<IOValue, IOErr> {
+    // Values are computed in double precision unless both inputs are f32
+    let dtype = i_off.dtype().promote(i_on.dtype()).promote(DataType::F32);
+    let (off_f64, on_f64) = (i_off.scalar_f64(), i_on.scalar_f64());
+    let sign = if is_emission { -1.0 } else { 1.0 };
+    let out = (&off_f64 - &on_f64) * f64::from(fl) / &off_f64 * sign;
+    let result = out.mapv(|v| if v > f64::from(max) { 0.0 } else { v });
+
     let i_off_arr = i_off.scalar();
     let i_on_arr = i_on.scalar();
-    let out = (i_off_arr - i_on_arr) * fl / i_off_arr * (if is_emission { -1.0 } else { 1.0 });
-    let result = out.map(|v| if *v > max { 0.0 } else { *v });
+
+    // Variance of fl * (1 - i_on / i_off)
+    let variance = if i_off.variance().is_some() || i_on.variance().is_some() {
+        let zeros = ArrayD::zeros(i_off_arr.dim());
+        let var_off = i_off.variance().unwrap_or(&zeros);
+        let var_on = i_on.variance().unwrap_or(&zeros);
+        let mut variance = ArrayD::zeros(i_off_arr.dim());
+        ndarray::Zip::from(&mut variance)
+            .and(&*i_off_arr)
+            .and(&*i_on_arr)
+            .and(var_off)
+            .and(var_on)
+            .and(&out)
+            .apply(|var, &off, &on, &var_off, &var_on, &v| {
+                *var = if v > f64::from(max) {
+                    0.0
+                } else {
+                    fl * fl * (var_on / (off * off) + on * on * var_off / off.powi(4))
+                };
+            });
+        Some(variance)
+    } else {
+        None
+    };
 
     // FIXME: Unit support
     // implementation of &WcsArray / &WcsArray is needed
@@ -1051,10 +1687,13 @@ fn run_create_equivalent_width(
     // in above program...
     // variable 'fl' is width of on-band, so unit of length should be adopted (e.g. [Ang]).
 
-    Ok(IOValue::Image(WcsArray::from_array(Dimensioned::new(
-        result,
-        Unit::None,
-    ))))
+    Ok(IOValue::Image(
+        WcsArray::from_array(Dimensioned::new(
+            Values::from_f64(result, dtype),
+            Unit::None,
+        ))
+        .with_variance(variance)?,
+    ))
 }
 
 fn run_convert_to_logscale(
@@ -1063,22 +1702,26 @@ fn run_convert_to_logscale(
     v_min: f32,
     v_max: f32,
 ) -> Result<IOValue, IOErr> {
-    let mut out = image.clone();
-    for v in out.scalar_mut().iter_mut() {
-        *v = (*v - v_min) / (v_max - v_min);
-        *v = (a * *v + 1.0).ln() / a.ln();
-    }
-
-    Ok(IOValue::Image(out))
+    let (a, v_min, v_max) = (f64::from(a), f64::from(v_min), f64::from(v_max));
+    // The variance is scaled by the square of the derivative of the map
+    let variance = image.variance().map(|variance| {
+        let mut out = variance.clone();
+        out.zip_mut_with(&*image.scalar(), |var, &v| {
+            let v = (f64::from(v) - v_min) / (v_max - v_min);
+            let derivative = a / ((a * v + 1.0) * a.ln() * (v_max - v_min));
+            *var = (f64::from(*var) * derivative * derivative) as f32;
+        });
+        out
+    });
+    let logscale = image.map_values(DataType::F32, |v| {
+        let v = (v - v_min) / (v_max - v_min);
+        (a * v + 1.0).ln() / a.ln()
+    });
+    Ok(IOValue::Image(logscale.with_variance(variance)?))
 }
 
 fn run_negation(image: &WcsArray) -> Result<IOValue, IOErr> {
-    let mut out = image.clone();
-    for v in out.scalar_mut().iter_mut() {
-        *v = -*v;
-    }
-
-    Ok(IOValue::Image(out))
+    Ok(IOValue::Image(image.map_values(image.dtype(), |v| -v)))
 }
 
 fn run_gaussian_mean(image: &WcsArray, start: i64, end: i64) -> Result<IOValue, IOErr> {
@@ -1204,14 +1847,950 @@ fn run_gaussian_mean_with_mask(
     }
 }
 
+fn run_voronoi_binning(
+    signal: &WcsArray,
+    noise: &WcsArray,
+    target_sn: f32,
+    wvt: bool,
+) -> Vec<Result<IOValue, IOErr>> {
+    let checks = dim_is!(signal, 2)
+        .and_then(|_| are_same_dim!(signal, noise))
+        .and_then(|_| {
+            precheck!(
+                target_sn > 0.0,
+                "'target_sn' must be positive, but got {}",
+                target_sn
+            )
+        });
+    if let Err(e) = checks {
+        return vec![Err(e); 3];
+    }
+
+    let (signal_arr, noise_arr) = (signal.scalar(), noise.scalar());
+    let bins = voronoi::voronoi_binning(view2(&signal_arr), view2(&noise_arr), target_sn, wvt);
+
+    let bin_map = bins
+        .bin_map
+        .map(|bin| bin.map_or(::std::f32::NAN, |bin| bin as f32));
+    let bin_map = signal.make_slice(
+        &[(0, 0.0, 1.0), (1, 0.0, 1.0)],
+        Dimensioned::new(bin_map.into_dyn(), Unit::None),
+    );
+    let binned_signal = WcsArray::from_array(
+        signal
+            .array()
+            .with_new_value(Array1::from_vec(bins.signal).into_dyn()),
+    );
+    let binned_noise = WcsArray::from_array(
+        noise
+            .array()
+            .with_new_value(Array1::from_vec(bins.noise).into_dyn()),
+    );
+    vec![
+        Ok(IOValue::Image(bin_map)),
+        Ok(IOValue::Image(binned_signal)),
+        Ok(IOValue::Image(binned_noise)),
+    ]
+}
+
+fn run_apply_bin_map(image: &WcsArray, bin_map: &WcsArray) -> Result<IOValue, IOErr> {
+    dim_is!(image, 3)?;
+    dim_is!(bin_map, 2)?;
+    let (image_val, map) = (image.scalar(), bin_map.scalar());
+    let cube = image_val.view().into_dimensionality::<Ix3>().unwrap();
+    let map = view2(&map);
+    let (_, h, w) = cube.dim();
+    precheck!(
+        (h, w) == map.dim(),
+        "Bin map has shape {:?}, while the frames of the image have shape {:?}",
+        map.dim(),
+        (h, w)
+    )?;
+
+    let binned = voronoi::sum_cube_per_bin(cube, map);
+    let array = image.array().with_new_value(binned.into_dyn());
+    let out = match image.axes() {
+        Some(axes) => {
+            let wave_start = image.pix2world(2, 0.0).unwrap_or(0.0);
+            let wave_step = image
+                .pix2world(2, 1.0)
+                .map(|end| end - wave_start)
+                .unwrap_or(1.0);
+            let bin_axis = unit::Axis::new(Some("BIN".to_owned()), Unit::None);
+            image.make_linear_axes(
+                &[
+                    (bin_axis, 0.0, 1.0),
+                    (axes[2].clone(), wave_start, wave_step),
+                ],
+                array,
+            )
+        }
+        None => WcsArray::from_array(array),
+    };
+    Ok(IOValue::Image(out))
+}
+
+fn run_scatter_bins(values: &WcsArray, bin_map: &WcsArray) -> Result<IOValue, IOErr> {
+    dim_is!(values, 1)?;
+    dim_is!(bin_map, 2)?;
+    let values_arr = values.scalar();
+    let values_arr = view1(&values_arr);
+    let map = bin_map.scalar();
+    let map = view2(&map);
+
+    let mut out = Array2::from_elem(map.dim(), ::std::f32::NAN);
+    for ((i, j), &bin) in map.indexed_iter() {
+        if let Some(bin) = voronoi::bin_index(bin) {
+            let value = values_arr.get(bin).ok_or_else(|| {
+                IOErr::UnexpectedInput(format!(
+                    "Bin map refers to bin #{}, but only {} values were provided",
+                    bin,
+                    values_arr.len()
+                ))
+            })?;
+            out[(i, j)] = *value;
+        }
+    }
+
+    Ok(IOValue::Image(bin_map.make_slice(
+        &[(0, 0.0, 1.0), (1, 0.0, 1.0)],
+        values.array().with_new_value(out.into_dyn()),
+    )))
+}
+
+fn run_reproject(
+    source: &WcsArray,
+    target: &WcsArray,
+    interpolation: &str,
+) -> Result<IOValue, IOErr> {
+    dim_is!(source, 2)?;
+    let target_dim = target.shape().len();
+    precheck!(
+        target_dim == 2 || target_dim == 3,
+        "'target' is a {}-dimensional image, while dimension 2 or 3 was expected",
+        target_dim
+    )?;
+    let method = reproject::Interpolation::from_name(interpolation).ok_or_else(|| {
+        IOErr::UnexpectedInput(format!(
+            "Unknown interpolation '{}'. Expected 'nearest', 'bilinear' or 'flux'.",
+            interpolation
+        ))
+    })?;
+    let (source_wcs, target_wcs) = match (source.wcs(), target.wcs()) {
+        (Some(source_wcs), Some(target_wcs)) => (source_wcs, target_wcs),
+        _ => {
+            return Err(IOErr::UnexpectedInput(
+                "Both 'source' and 'target' must have WCS metadata attached".to_owned(),
+            ))
+        }
+    };
+
+    let source_to_world = reproject::Affine::from_wcs(source_wcs);
+    let world_to_source = source_to_world
+        .inverse()
+        .ok_or_else(|| IOErr::UnexpectedInput("WCS of 'source' is not invertible".to_owned()))?;
+    let target_to_source = reproject::Affine::from_wcs(target_wcs).then(&world_to_source);
+
+    let shape = target.shape();
+    let dim = (shape[target_dim - 2], shape[target_dim - 1]);
+    let out = reproject::resample(view2(&source.scalar()), dim, &target_to_source, method);
+
+    Ok(IOValue::Image(target.make_slice(
+        &[(0, 0.0, 1.0), (1, 0.0, 1.0)],
+        source.array().with_new_value(out.into_dyn()),
+    )))
+}
+
+fn run_align_images(
+    reference: &WcsArray,
+    image: &WcsArray,
+    max_shift: i64,
+) -> Result<(WcsArray, [f32; 2]), IOErr> {
+    let max_shift = try_into_unsigned!(max_shift)?;
+    dim_is!(reference, 2)?;
+    dim_is!(image, 2)?;
+
+    let (reference_val, image_val) = (reference.scalar(), image.scalar());
+    let (dx, dy) = reproject::estimate_shift(view2(&reference_val), view2(&image_val), max_shift);
+    let aligned = reproject::resample(
+        view2(&image_val),
+        view2(&reference_val).dim(),
+        &reproject::Affine::translation(-dx, -dy),
+        reproject::Interpolation::Bilinear,
+    );
+
+    let aligned = reference.make_slice(
+        &[(0, 0.0, 1.0), (1, 0.0, 1.0)],
+        image.array().with_new_value(aligned.into_dyn()),
+    );
+    Ok((aligned, [dx, dy]))
+}
+
+/// Collect the values of the image within the region of interest.
+fn values_in_roi(image: &WcsArray, roi: &roi::ROI) -> Result<Vec<f32>, IOErr> {
+    if let roi::ROI::All = roi {
+        return Ok(image.scalar().iter().cloned().collect());
+    }
+    let image_val = image.scalar();
+    match image_val.ndim() {
+        2 => Ok(roi
+            .filterx(view2(&image_val))
+            .into_iter()
+            .map(|(_, val)| val)
+            .collect()),
+        3 => {
+            let mut values = vec![];
+            for frame in image_val.axis_iter(Axis(0)) {
+                let frame = frame.into_dimensionality::<Ix2>().unwrap();
+                values.extend(roi.filterx(frame).into_iter().map(|(_, val)| val));
+            }
+            Ok(values)
+        }
+        ndim => Err(IOErr::UnexpectedInput(format!(
+            "Cannot apply a region of interest to a {}-dimensional image",
+            ndim
+        ))),
+    }
+}
+
+fn run_statistics(
+    image: &WcsArray,
+    roi: &roi::ROI,
+    sigma: f32,
+    iterations: i64,
+    percentile_low: f32,
+    percentile_high: f32,
+) -> Vec<Result<IOValue, IOErr>> {
+    let values = try_into_unsigned!(iterations).and_then(|iterations| {
+        precheck!(sigma > 0.0, "'sigma' must be positive, but got {}", sigma)?;
+        values_in_roi(image, roi).map(|values| (values, iterations))
+    });
+    let (mut values, iterations) = match values {
+        Ok(values) => values,
+        Err(e) => return vec![Err(e); 10],
+    };
+
+    let clipped = stats::sigma_clip(&values, sigma, iterations);
+    let clipped_stats = stats::Statistics::new(&mut clipped.clone());
+    let all_stats = stats::Statistics::new(&mut values);
+    // 'values' is now sorted and free of NaN
+    let low = stats::percentile_sorted(&values, percentile_low);
+    let high = stats::percentile_sorted(&values, percentile_high);
+
+    vec![
+        Ok(IOValue::Float(all_stats.mean)),
+        Ok(IOValue::Float(all_stats.median)),
+        Ok(IOValue::Float(all_stats.std_dev)),
+        Ok(IOValue::Float(all_stats.mad)),
+        Ok(IOValue::Float(low)),
+        Ok(IOValue::Float(high)),
+        Ok(IOValue::Integer(all_stats.nan_count as i64)),
+        Ok(IOValue::Float(clipped_stats.mean)),
+        Ok(IOValue::Float(clipped_stats.median)),
+        Ok(IOValue::Float(clipped_stats.std_dev)),
+    ]
+}
+
+fn run_background_2d(
+    image: &WcsArray,
+    box_size: i64,
+    filter_size: i64,
+    sigma: f32,
+) -> Result<(WcsArray, WcsArray), IOErr> {
+    let box_size = try_into_unsigned!(box_size)?;
+    let filter_size = try_into_unsigned!(filter_size)?;
+    dim_is!(image, 2)?;
+    precheck!(box_size > 0, "'box_size' must be strictly positive")?;
+
+    let params = stats::BackgroundParams {
+        box_size,
+        filter_size,
+        sigma,
+        exclude_fraction: 0.5,
+    };
+    let (bkg, rms) = stats::background_2d(view2(&image.scalar()), params).ok_or_else(|| {
+        IOErr::UnexpectedInput("Not enough valid pixels to estimate the background".to_owned())
+    })?;
+
+    let wrap = |array: Array2<f32>| {
+        image.make_slice(
+            &[(0, 0.0, 1.0), (1, 0.0, 1.0)],
+            image.array().with_new_value(array.into_dyn()),
+        )
+    };
+    Ok((wrap(bkg), wrap(rms)))
+}
+
+fn run_detect_sources(
+    image: &WcsArray,
+    rms: &WcsArray,
+    threshold: f32,
+    min_pixels: i64,
+    deblend: bool,
+) -> Result<(WcsArray, WcsArray), IOErr> {
+    let min_pixels = try_into_unsigned!(min_pixels)?;
+    dim_is!(image, 2)?;
+    are_same_dim!(image, rms)?;
+
+    let params = detection::DetectionParams {
+        threshold,
+        min_pixels,
+        deblend,
+        deblend_levels: 32,
+        deblend_contrast: 0.005,
+    };
+    let image_val = image.scalar();
+    let segmap = detection::detect_sources(view2(&image_val), view2(&rms.scalar()), params);
+    let sources = detection::measure_sources(view2(&image_val), segmap.view());
+
+    let mut table = Array2::zeros((sources.len(), 6));
+    for (mut row, source) in table.outer_iter_mut().zip(&sources) {
+        row[0] = source.label as f32;
+        row[1] = source.x;
+        row[2] = source.y;
+        row[3] = source.flux;
+        row[4] = source.area as f32;
+        row[5] = source.ellipticity;
+    }
+
+    let segmap = image.make_slice(
+        &[(0, 0.0, 1.0), (1, 0.0, 1.0)],
+        Dimensioned::new(segmap.mapv(|label| label as f32).into_dyn(), Unit::None),
+    );
+    let table = WcsArray::from_array(Dimensioned::new(table.into_dyn(), Unit::None));
+    Ok((segmap, table))
+}
+
+fn run_segment_to_roi(segmentation: &WcsArray, label: i64) -> Result<IOValue, IOErr> {
+    let label = try_into_unsigned!(label)?;
+    dim_is!(segmentation, 2)?;
+    precheck!(label > 0, "'label' must be strictly positive")?;
+
+    let segmap = view2(&segmentation.scalar()).mapv(|val| {
+        if val.is_nan() || val < 0.0 {
+            0
+        } else {
+            val.round() as usize
+        }
+    });
+    Ok(IOValue::Roi(roi::ROI::PixelList(
+        detection::segment_pixels(segmap.view(), label),
+    )))
+}
+
+/// Make the aperture and its background annulus.
+/// The annulus is disabled if 'annulus_out' is not strictly positive.
+fn make_apertures(
+    (x, y): (f32, f32),
+    axis_ratio: f32,
+    theta: f32,
+    radius: f32,
+    annulus_in: f32,
+    annulus_out: f32,
+) -> Result<
+    (
+        photometry::Ellipse,
+        Option<(photometry::Ellipse, photometry::Ellipse)>,
+    ),
+    IOErr,
+> {
+    precheck!(radius > 0.0, "'radius' must be strictly positive")?;
+    precheck!(
+        axis_ratio > 0.0 && axis_ratio <= 1.0,
+        "Expected 0 < b <= a, but got b/a = {}",
+        axis_ratio
+    )?;
+    let aperture = photometry::Ellipse {
+        x,
+        y,
+        a: radius,
+        b: radius * axis_ratio,
+        theta: theta.to_radians(),
+    };
+    let annulus = if annulus_out > 0.0 {
+        precheck!(
+            annulus_in < annulus_out,
+            "Expected annulus_in < annulus_out, but got {} >= {}",
+            annulus_in,
+            annulus_out
+        )?;
+        Some((
+            aperture.with_major_axis(annulus_in.max(0.0)),
+            aperture.with_major_axis(annulus_out),
+        ))
+    } else {
+        None
+    };
+    Ok((aperture, annulus))
+}
+
+/// View an array as a 1-dimensional array.
+///
+/// Panic if the array is not 1-dimensional.
+fn view1(array: &ArrayD<f32>) -> ArrayView1<f32> {
+    array
+        .view()
+        .into_dimensionality::<Ix1>()
+        .expect("Array is 1-dimensional")
+}
+
+/// View an array as a 2-dimensional array.
+///
+/// Panic if the array is not 2-dimensional.
+fn view2(array: &ArrayD<f32>) -> ArrayView2<f32> {
+    array
+        .view()
+        .into_dimensionality::<Ix2>()
+        .expect("Array is 2-dimensional")
+}
+
+/// Default value of an optional image input: an image without any value.
+fn no_image() -> WcsArray {
+    WcsArray::from_array(Dimensioned::new(ArrayD::zeros(vec![0]), Unit::None))
+}
+
+/// Get the variance to use for the photometry of `image`: `variance`, or the
+/// variance attached to `image` if `variance` is not connected.
+fn photometry_variance<'a>(
+    image: &'a WcsArray,
+    variance: &'a WcsArray,
+) -> Result<Option<cake::Bow<'a, ArrayD<f32>>>, IOErr> {
+    // The default value of an unconnected input is an image without values
+    if variance.shape() == [0] {
+        Ok(image.variance().map(cake::Bow::Borrowed))
+    } else {
+        are_same_dim!(image, variance)?;
+        Ok(Some(variance.scalar()))
+    }
+}
+
+fn run_aperture_photometry(
+    image: &WcsArray,
+    variance: &WcsArray,
+    position: [f32; 2],
+    sky: bool,
+    (axis_ratio, theta): (f32, f32),
+    radii: [f32; 3],
+) -> Vec<Result<IOValue, IOErr>> {
+    let (radius, annulus_in, annulus_out) = (radii[0], radii[1], radii[2]);
+    let result = dim_is!(image, 2)
+        .and_then(|_| photometry_variance(image, variance))
+        .and_then(|variance| {
+            let position = if sky {
+                let to_pixel = image
+                    .wcs()
+                    .and_then(|wcs| reproject::Affine::from_wcs(wcs).inverse())
+                    .ok_or_else(|| {
+                        IOErr::UnexpectedInput(
+                            "Image has no valid WCS, cannot use sky coordinates".to_owned(),
+                        )
+                    })?;
+                to_pixel.apply((position[0], position[1]))
+            } else {
+                (position[0], position[1])
+            };
+            let (aperture, annulus) =
+                make_apertures(position, axis_ratio, theta, radius, annulus_in, annulus_out)?;
+            photometry::aperture_photometry(
+                view2(&image.scalar()),
+                variance.as_ref().map(|variance| view2(variance)),
+                &aperture,
+                annulus.as_ref().map(|(inner, outer)| (inner, outer)),
+            )
+        });
+    match result {
+        Ok(phot) => vec![
+            Ok(IOValue::Float(phot.flux)),
+            Ok(IOValue::Float(phot.flux_err)),
+            Ok(IOValue::Float(phot.background)),
+            Ok(IOValue::Float(phot.area)),
+            Ok(IOValue::Str(image.array().unit().repr().to_owned())),
+        ],
+        Err(e) => vec![Err(e); 5],
+    }
+}
+
+fn run_photometry_table(
+    image: &WcsArray,
+    variance: &WcsArray,
+    sources: &WcsArray,
+    radius: f32,
+    annulus_in: f32,
+    annulus_out: f32,
+) -> Vec<Result<IOValue, IOErr>> {
+    match photometry_table(image, variance, sources, radius, annulus_in, annulus_out) {
+        Ok(table) => vec![
+            Ok(IOValue::Image(WcsArray::from_array(Dimensioned::new(
+                table.into_dyn(),
+                Unit::None,
+            )))),
+            Ok(IOValue::Str(image.array().unit().repr().to_owned())),
+        ],
+        Err(e) => vec![Err(e); 2],
+    }
+}
+
+/// Measure the sources in `sources` with circular apertures, returning one
+/// row per source: label, x, y, flux, flux error, background and area.
+fn photometry_table(
+    image: &WcsArray,
+    variance: &WcsArray,
+    sources: &WcsArray,
+    radius: f32,
+    annulus_in: f32,
+    annulus_out: f32,
+) -> Result<Array2<f32>, IOErr> {
+    dim_is!(image, 2)?;
+    let variance = photometry_variance(image, variance)?;
+    let image_val = image.scalar();
+    dim_is!(sources, 2)?;
+    let sources = sources.scalar();
+    let sources = view2(&sources);
+    precheck!(
+        sources.dim().1 >= 3,
+        "'sources' must have at least 3 columns (label, x, y), but got {}",
+        sources.dim().1
+    )?;
+
+    let mut table = Array2::zeros((sources.dim().0, 7));
+    for (mut row, source) in table.outer_iter_mut().zip(sources.outer_iter()) {
+        let (aperture, annulus) = make_apertures(
+            (source[1], source[2]),
+            1.0,
+            0.0,
+            radius,
+            annulus_in,
+            annulus_out,
+        )?;
+        let phot = photometry::aperture_photometry(
+            view2(&image_val),
+            variance.as_ref().map(|variance| view2(variance)),
+            &aperture,
+            annulus.as_ref().map(|(inner, outer)| (inner, outer)),
+        )?;
+        row[0] = source[0];
+        row[1] = source[1];
+        row[2] = source[2];
+        row[3] = phot.flux;
+        row[4] = phot.flux_err;
+        row[5] = phot.background;
+        row[6] = phot.area;
+    }
+    Ok(table)
+}
+
+fn run_roi_combine(a: &roi::ROI, b: &roi::ROI, operation: &str) -> Result<IOValue, IOErr> {
+    let (a, b) = (Box::new(a.clone()), Box::new(b.clone()));
+    let roi = match operation {
+        "union" => roi::ROI::Union(a, b),
+        "intersection" => roi::ROI::Intersection(a, b),
+        "difference" => roi::ROI::Difference(a, b),
+        _ => {
+            return Err(IOErr::UnexpectedInput(format!(
+                "Unknown operation '{}'. Expected 'union', 'intersection' or 'difference'",
+                operation
+            )))
+        }
+    };
+    Ok(IOValue::Roi(roi))
+}
+
+fn run_roi_mask(image: &WcsArray, roi: &roi::ROI) -> Result<IOValue, IOErr> {
+    dim_is!(image, 2)?;
+    let mask = roi.rasterize(view2(&image.scalar()).dim());
+    Ok(IOValue::Image(image.make_slice(
+        &[(0, 0.0, 1.0), (1, 0.0, 1.0)],
+        Dimensioned::new(mask.into_dyn(), Unit::None),
+    )))
+}
+
+fn run_load_regions(path: &Path, image: &WcsArray) -> Result<IOValue, IOErr> {
+    let text = ::std::fs::read_to_string(path)
+        .map_err(|e| IOErr::IoError(e, format!("Could not open file {:?}", path)))?;
+    let pix2world = image.wcs().map(reproject::Affine::from_wcs);
+    // Sky coordinates are converted at the center of the image
+    let shape = image.shape();
+    let center = match shape.len() {
+        0 => (0.0, 0.0),
+        1 => ((shape[0] as f32 - 1.0) / 2.0, 0.0),
+        n => (
+            (shape[n - 1] as f32 - 1.0) / 2.0,
+            (shape[n - 2] as f32 - 1.0) / 2.0,
+        ),
+    };
+    regions::parse(&text, pix2world.as_ref(), center)
+        .map(IOValue::Roi)
+        .map_err(|e| IOErr::UnexpectedInput(format!("Could not parse {:?}. {}", path, e)))
+}
+
+fn run_attach_variance(image: &WcsArray, variance: &WcsArray) -> Result<IOValue, IOErr> {
+    are_same_dim!(image, variance)?;
+    Ok(IOValue::Image(
+        image.clone().with_variance(Some(variance.scalar_owned()))?,
+    ))
+}
+
+fn run_error_map(image: &WcsArray) -> Result<IOValue, IOErr> {
+    let variance = image
+        .variance()
+        .ok_or_else(|| IOErr::UnexpectedInput("Image has no variance attached".to_owned()))?;
+    let error = variance.mapv(f32::sqrt);
+    let ndim = error.ndim();
+    Ok(IOValue::Image(image.make_slice(
+        &(0..ndim).map(|i| (i, 0.0, 1.0)).collect::<Vec<_>>(),
+        image.array().with_new_value(error),
+    )))
+}
+
+/// Read a mask image, where non-zero pixels are masked.
+fn read_mask(mask: &WcsArray) -> ArrayD<bool> {
+    mask.scalar().map(|&val| val != 0.0)
+}
+
+/// Make a mask image with the metadata of `image`.
+fn make_mask_image(image: &WcsArray, mask: &ArrayD<bool>) -> WcsArray {
+    let ndim = mask.ndim();
+    image.make_slice(
+        &(0..ndim).map(|i| (i, 0.0, 1.0)).collect::<Vec<_>>(),
+        Dimensioned::new(unit::NativeArray::U8(mask.mapv(u8::from)), Unit::None),
+    )
+}
+
+fn run_fits_to_mask(fits: &Arc<FitsFile>, extension: &str, flags: i64) -> Result<IOValue, IOErr> {
+    let hdu = fits.get_by_name(extension).ok_or_else(|| {
+        IOErr::UnexpectedInput(format!("Could not find HDU '{}' in FITS file.", extension))
+    })?;
+    let dq = fits::read_flags(&hdu).map_err(|e| IOErr::FITSErr(format!("{}", e)))?;
+    // Keep the world coordinates of the extension
+    let image = WcsArray::from_hdu(&hdu).map_err(|e| IOErr::FITSErr(format!("{}", e)))?;
+    let flags = flags as u32;
+    let mask = dq.map(|&dq| dq & flags != 0);
+    Ok(IOValue::Image(make_mask_image(&image, &mask)))
+}
+
+fn run_threshold_mask(image: &WcsArray, threshold: f32, above: bool) -> Result<IOValue, IOErr> {
+    let mask = image.scalar().map(|&val| {
+        val.is_nan()
+            || if above {
+                val > threshold
+            } else {
+                val < threshold
+            }
+    });
+    Ok(IOValue::Image(make_mask_image(image, &mask)))
+}
+
+fn run_mask_combine(a: &WcsArray, b: &WcsArray, operation: &str) -> Result<IOValue, IOErr> {
+    are_same_dim!(a, b)?;
+    let mut mask = read_mask(a);
+    let other = read_mask(b);
+    match operation {
+        "or" => mask.zip_mut_with(&other, |a, &b| *a = *a || b),
+        "and" => mask.zip_mut_with(&other, |a, &b| *a = *a && b),
+        _ => {
+            return Err(IOErr::UnexpectedInput(format!(
+                "Unknown operation '{}'. Expected 'or' or 'and'",
+                operation
+            )))
+        }
+    }
+    Ok(IOValue::Image(make_mask_image(a, &mask)))
+}
+
+fn run_mask_not(mask: &WcsArray) -> Result<IOValue, IOErr> {
+    let inverted = mask.scalar().map(|&val| val == 0.0);
+    Ok(IOValue::Image(make_mask_image(mask, &inverted)))
+}
+
+fn run_mask_dilate(mask: &WcsArray, radius: i64) -> Result<IOValue, IOErr> {
+    let radius = try_into_unsigned!(radius)?;
+    let dilated = mask::dilate(&read_mask(mask), radius);
+    Ok(IOValue::Image(make_mask_image(mask, &dilated)))
+}
+
+fn run_apply_mask(
+    image: &WcsArray,
+    mask: &WcsArray,
+    replace_with_nan: bool,
+) -> Result<IOValue, IOErr> {
+    are_same_dim!(image, mask)?;
+    let mut new_mask = read_mask(mask);
+    if let Some(old_mask) = image.mask() {
+        new_mask.zip_mut_with(old_mask, |a, &b| *a = *a || b);
+    }
+    let out = if replace_with_nan {
+        image.zip_values(DataType::F32, &new_mask, |val, &masked| {
+            if masked {
+                ::std::f64::NAN
+            } else {
+                val
+            }
+        })
+    } else {
+        image.clone()
+    };
+    Ok(IOValue::Image(out.with_mask(Some(new_mask))?))
+}
+
+fn run_get_mask(image: &WcsArray) -> Result<IOValue, IOErr> {
+    let mask = image
+        .mask()
+        .cloned()
+        .unwrap_or_else(|| ArrayD::from_elem(image.scalar().dim(), false));
+    Ok(IOValue::Image(make_mask_image(image, &mask)))
+}
+
+/// Maximum number of nodes in the summary of the program written by
+/// `write_fits` in the HISTORY cards.
+const WRITE_FITS_HISTORY_NODES: usize = 50;
+
+/// `program` is the summary of the node program that produced `extensions`.
+fn run_write_fits(
+    path: &Path,
+    extensions: &[(String, WcsArray)],
+    keywords: &str,
+    program: &[String],
+) -> Result<IOValue, IOErr> {
+    let keywords = fits::parse_keywords(keywords).map_err(IOErr::UnexpectedInput)?;
+
+    let mut history = vec!["Written by aflak with write_fits".to_owned()];
+    if !program.is_empty() {
+        history.push("Node program:".to_owned());
+        history.extend(program.iter().cloned());
+    }
+    let history = fits::history_cards(&history);
+
+    let mut primary_hdu = fitrs::Hdu::empty();
+    for (key, value) in keywords {
+        primary_hdu.insert(key, fits::parse_value(&value));
+    }
+    fits::insert_history_placeholders(&mut primary_hdu, &history)
+        .map_err(IOErr::UnexpectedInput)?;
+
+    let write_err = |err| IOErr::IoError(err, format!("Could not write file {:?}", path));
+    {
+        let mut fits = fitrs::Fits::create(path, primary_hdu).map_err(write_err)?;
+        for (name, image) in extensions {
+            let mut hdu = image.to_hdu();
+            if !name.is_empty() {
+                hdu.insert("EXTNAME", name.as_str());
+            }
+            fits.push(hdu).map_err(write_err)?;
+        }
+    }
+    fits::write_history(path, &history).map_err(write_err)?;
+    Ok(IOValue::Path(path.to_owned()))
+}
+
+fn run_read_fits_keyword(
+    fits: &Arc<FitsFile>,
+    hdu_idx: i64,
+    extension: &str,
+    key: &str,
+) -> Vec<Result<IOValue, IOErr>> {
+    use fitrs::HeaderValue::*;
+
+    let hdu = match find_hdu(fits, hdu_idx, extension) {
+        Ok((_, hdu)) => hdu,
+        Err(e) => return vec![Err(e); 4],
+    };
+    let key = key.trim().to_uppercase();
+    let value = match hdu.value(&key) {
+        Some(value) => value,
+        None => {
+            return vec![
+                Err(IOErr::UnexpectedInput(format!(
+                    "Keyword '{}' not found in header.",
+                    key
+                )));
+                4
+            ]
+        }
+    };
+    let string = match *value {
+        CharacterString(ref s) => s.clone(),
+        Logical(true) => "T".to_owned(),
+        Logical(false) => "F".to_owned(),
+        IntegerNumber(i) => i.to_string(),
+        RealFloatingNumber(f) => f.to_string(),
+        ComplexIntegerNumber(a, b) => format!("{} + {}i", a, b),
+        ComplexFloatingNumber(a, b) => format!("{} + {}i", a, b),
+    };
+    let not_convertible = |type_name| {
+        Err(IOErr::UnexpectedInput(format!(
+            "Keyword '{}' cannot be converted to {}: '{}'",
+            key, type_name, string
+        )))
+    };
+
+    let float = match *value {
+        IntegerNumber(i) => Ok(IOValue::Float(i as f32)),
+        RealFloatingNumber(f) => Ok(IOValue::Float(f as f32)),
+        CharacterString(ref s) => match s.trim().parse() {
+            Ok(f) => Ok(IOValue::Float(f)),
+            Err(_) => not_convertible("a float"),
+        },
+        _ => not_convertible("a float"),
+    };
+    let integer = match *value {
+        IntegerNumber(i) => Ok(IOValue::Integer(i64::from(i))),
+        RealFloatingNumber(f) if f.fract() == 0.0 => Ok(IOValue::Integer(f as i64)),
+        CharacterString(ref s) => match s.trim().parse() {
+            Ok(i) => Ok(IOValue::Integer(i)),
+            Err(_) => not_convertible("an integer"),
+        },
+        _ => not_convertible("an integer"),
+    };
+    let boolean = match *value {
+        Logical(b) => Ok(IOValue::Bool(b)),
+        _ => not_convertible("a boolean"),
+    };
+    vec![Ok(IOValue::Str(string)), float, integer, boolean]
+}
+
+fn run_set_keywords(image: &WcsArray, keywords: &str) -> Result<IOValue, IOErr> {
+    let keywords = fits::parse_keywords(keywords).map_err(IOErr::UnexpectedInput)?;
+    Ok(IOValue::Image(image.clone().with_keywords(keywords)))
+}
+
+fn run_convert_type(image: &WcsArray, dtype: &str) -> Result<IOValue, IOErr> {
+    let dtype = DataType::from_name(dtype).ok_or_else(|| {
+        IOErr::UnexpectedInput(format!(
+            "Unknown type '{}'. Expected 'u8', 'i32', 'f32' or 'f64'.",
+            dtype
+        ))
+    })?;
+    Ok(IOValue::Image(image.clone().into_dtype(dtype)))
+}
+
+#[cfg(feature = "hdf5")]
+fn run_open_hdf5(path: &Path, dataset: &str) -> Result<IOValue, IOErr> {
+    h5::read(path, dataset).map(IOValue::Image).map_err(|e| {
+        IOErr::UnexpectedInput(format!(
+            "Could not read dataset '{}' in {:?}: {}",
+            dataset, path, e
+        ))
+    })
+}
+
+#[cfg(feature = "hdf5")]
+fn run_write_hdf5(path: &Path, image: &WcsArray, dataset: &str) -> Result<IOValue, IOErr> {
+    h5::write(path, dataset, image)
+        .map(|_| IOValue::Path(path.to_owned()))
+        .map_err(|e| {
+            IOErr::UnexpectedInput(format!(
+                "Could not write dataset '{}' in {:?}: {}",
+                dataset, path, e
+            ))
+        })
+}
+
+fn run_open_npy(path: &Path) -> Result<IOValue, IOErr> {
+    npy::read(path)
+        .map(IOValue::Image)
+        .map_err(|err| IOErr::IoError(err, format!("Could not open file {:?}", path)))
+}
+
+fn run_write_npy(path: &Path, image: &WcsArray) -> Result<IOValue, IOErr> {
+    npy::write(path, image)
+        .map(|_| IOValue::Path(path.to_owned()))
+        .map_err(|err| IOErr::IoError(err, format!("Could not write file {:?}", path)))
+}
+
+fn run_pv_diagram(
+    image: &WcsArray,
+    path: &roi::ROI,
+    step: f32,
+    width: f32,
+) -> Result<IOValue, IOErr> {
+    dim_is!(image, 3)?;
+    precheck!(step > 0.0, "'step' must be positive, but got {}", step)?;
+    precheck!(width > 0.0, "'width' must be positive, but got {}", width)?;
+    let vertices = slicing::path_vertices(path).ok_or_else(|| {
+        IOErr::UnexpectedInput("'path' must be a line, a pixel list or a polygon".to_owned())
+    })?;
+    precheck!(!vertices.is_empty(), "'path' is empty")?;
+
+    let points = slicing::sample_path(&vertices, step);
+    let offsets = slicing::slit_offsets(width);
+    let image_val = image.scalar();
+    let cube = image_val
+        .view()
+        .into_dimensionality::<Ix3>()
+        .expect("Image is 3-dimensional");
+    let out = Array2::from_shape_fn((cube.dim().0, points.len()), |(k, i)| {
+        let slicing::PathPoint {
+            position: (x, y),
+            normal: (nx, ny),
+        } = points[i];
+        let mut sum = 0.0;
+        let mut count = 0;
+        for &t in &offsets {
+            let val = slicing::trilinear(&cube, [k as f32, y + t * ny, x + t * nx]);
+            if !val.is_nan() {
+                sum += val;
+                count += 1;
+            }
+        }
+        if count == 0 {
+            ::std::f32::NAN
+        } else {
+            sum / count as f32
+        }
+    });
+
+    let array = image.array().with_new_value(out.into_dyn());
+    let out = match image.axes() {
+        Some(axes) => {
+            let world_step = |axis| {
+                image
+                    .pix2world(axis, 1.0)
+                    .and_then(|end| image.pix2world(axis, 0.0).map(|start| end - start))
+                    .unwrap_or(1.0)
+            };
+            // The offset is in world units if both spatial axes have the
+            // same unit, or else in pixels.
+            let spatial_unit = axes[0].unit();
+            let (offset_unit, scale) = if !spatial_unit.is_empty() && axes[1].unit() == spatial_unit
+            {
+                (
+                    Unit::Custom(spatial_unit.to_owned()),
+                    (world_step(0) * world_step(1)).abs().sqrt(),
+                )
+            } else {
+                (Unit::None, 1.0)
+            };
+            let offset_axis = unit::Axis::new(Some("OFFSET".to_owned()), offset_unit);
+            let wave_start = image.pix2world(2, 0.0).unwrap_or(0.0);
+            image.make_linear_axes(
+                &[
+                    (offset_axis, 0.0, step * scale),
+                    (axes[2].clone(), wave_start, world_step(2)),
+                ],
+                array,
+            )
+        }
+        None => WcsArray::from_array(array),
+    };
+    Ok(IOValue::Image(out))
+}
+
 #[cfg(test)]
 mod test {
-    use super::{run_fits_to_image, run_make_plane3d, run_open_fits, run_slice_3d_to_2d, IOValue};
+    use super::{
+        no_image, run_apply_bin_map, run_average, run_convert_to_logscale,
+        run_create_equivalent_width, run_error_map, run_fits_to_image, run_integral,
+        run_linear_composition, run_make_plane3d, run_negation, run_open_fits,
+        run_photometry_table, run_scatter_bins, run_slice_3d_to_2d, IOValue,
+    };
+    use ndarray::{Array2, ArrayD};
+    use unit::{DataType, Dimensioned, Unit, WcsArray};
+
     #[test]
     fn test_open_fits() {
         let path = "test/test.fits";
         if let IOValue::Fits(fits) = run_open_fits(path).unwrap() {
-            if let IOValue::Image(image) = run_fits_to_image(&fits, 0, "").unwrap() {
+            if let IOValue::Image(image) = run_fits_to_image(&fits, 0, "", "").unwrap() {
                 if let IOValue::Map2dTo3dCoords(map) =
                     run_make_plane3d(&[0.0, 0.0, 0.0], &[1.0, 0.5, 0.0], &[0.0, 0.5, 1.0], 10, 20)
                         .unwrap()
@@ -1223,4 +2802,182 @@ mod test {
         }
         panic!("Failed somewhere!");
     }
+
+    #[test]
+    fn linear_composition_promotes_types() {
+        let image = |values: Vec<f32>| {
+            WcsArray::from_array(Dimensioned::new(
+                ArrayD::from_shape_vec(vec![3], values).unwrap(),
+                Unit::None,
+            ))
+        };
+        let a = image(vec![1.0, 2.0, 3.0]).into_dtype(DataType::U8);
+        let b = image(vec![255.0, 255.0, 255.0]).into_dtype(DataType::U8);
+        let sum = match run_linear_composition(&a, &b, 1.0, 1.0).unwrap() {
+            IOValue::Image(image) => image,
+            _ => unreachable!(),
+        };
+        assert_eq!(sum.dtype(), DataType::I32);
+        assert_eq!(sum.scalar().as_slice().unwrap(), &[256.0, 257.0, 258.0]);
+
+        let scaled = match run_linear_composition(&a, &image(vec![0.5; 3]), 0.5, 1.0).unwrap() {
+            IOValue::Image(image) => image,
+            _ => unreachable!(),
+        };
+        assert_eq!(scaled.dtype(), DataType::F64);
+        assert_eq!(scaled.scalar_f64().as_slice().unwrap(), &[1.0, 1.5, 2.0]);
+    }
+
+    /// Make an image of the given values, with the given variance.
+    fn image_with_variance(values: Vec<f32>, variance: Vec<f32>) -> WcsArray {
+        let shape = vec![values.len()];
+        WcsArray::from_array(Dimensioned::new(
+            ArrayD::from_shape_vec(shape.clone(), values).unwrap(),
+            Unit::None,
+        ))
+        .with_variance(Some(ArrayD::from_shape_vec(shape, variance).unwrap()))
+        .unwrap()
+    }
+
+    #[test]
+    fn average_propagates_variance() {
+        let cube = WcsArray::from_array(Dimensioned::new(
+            ArrayD::from_elem(vec![5, 2, 3], 1.0),
+            Unit::None,
+        ))
+        .with_variance(Some(ArrayD::from_elem(vec![5, 2, 3], 4.0)))
+        .unwrap();
+        let average = match run_average(&cube, 0, 4).unwrap() {
+            IOValue::Image(average) => average,
+            _ => unreachable!(),
+        };
+        match run_error_map(&average).unwrap() {
+            IOValue::Image(error) => {
+                assert_eq!(*error.scalar(), ArrayD::from_elem(vec![2, 3], 1.0))
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn linear_composition_propagates_variance() {
+        let a = image_with_variance(vec![1.0, 2.0], vec![1.0, 2.0]);
+        let b = image_with_variance(vec![3.0, 4.0], vec![4.0, 8.0]);
+        let sum = match run_linear_composition(&a, &b, 2.0, 0.5).unwrap() {
+            IOValue::Image(image) => image,
+            _ => unreachable!(),
+        };
+        assert_eq!(sum.variance().unwrap().as_slice().unwrap(), &[5.0, 10.0]);
+
+        // A missing variance is null
+        let c = WcsArray::from_array(Dimensioned::new(
+            ArrayD::from_elem(vec![2], 1.0),
+            Unit::None,
+        ));
+        let sum = match run_linear_composition(&c, &b, 1.0, -1.0).unwrap() {
+            IOValue::Image(image) => image,
+            _ => unreachable!(),
+        };
+        assert_eq!(sum.variance().unwrap().as_slice().unwrap(), &[4.0, 8.0]);
+    }
+
+    #[test]
+    fn equivalent_width_propagates_variance() {
+        let off = image_with_variance(vec![2.0, 2.0], vec![1.0, 1.0]);
+        let on = image_with_variance(vec![1.0, -100.0], vec![1.0, 1.0]);
+        let width = match run_create_equivalent_width(&off, &on, 10.0, 100.0, false).unwrap() {
+            IOValue::Image(image) => image,
+            _ => unreachable!(),
+        };
+        // Width is 10 * (1 - on / off), of variance
+        // 100 * (var_on / off^2 + on^2 * var_off / off^4). Widths above the
+        // maximum are replaced with 0, of null variance.
+        assert_eq!(width.scalar().as_slice().unwrap(), &[5.0, 0.0]);
+        assert_eq!(width.variance().unwrap().as_slice().unwrap(), &[31.25, 0.0]);
+    }
+
+    #[test]
+    fn logscale_propagates_variance() {
+        let image = WcsArray::from_array(Dimensioned::new(
+            ArrayD::from_shape_vec(vec![2], vec![0.0, 1.0]).unwrap(),
+            Unit::None,
+        ))
+        .with_variance(Some(ArrayD::from_elem(vec![2], 1.0)))
+        .unwrap();
+        let negated = match run_negation(&image).unwrap() {
+            IOValue::Image(image) => image,
+            _ => unreachable!(),
+        };
+        assert_eq!(negated.variance(), image.variance());
+
+        let logscale = match run_convert_to_logscale(&image, 10.0, 0.0, 1.0).unwrap() {
+            IOValue::Image(image) => image,
+            _ => unreachable!(),
+        };
+        // The derivative of ln(10 v + 1) / ln(10) is 10 / ln(10) at 0 and
+        // 10 / (11 ln(10)) at 1
+        let variance = logscale.variance().unwrap();
+        let ln10 = 10f32.ln();
+        assert!((variance[0] - (10.0 / ln10).powi(2)).abs() < 1e-4);
+        assert!((variance[1] - (10.0 / (11.0 * ln10)).powi(2)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn photometry_table_outputs_flux_unit() {
+        let image = WcsArray::from_array(Dimensioned::new(
+            ArrayD::from_elem(vec![20, 20], 2.0),
+            Unit::Custom("Jy".into()),
+        ));
+        let sources = WcsArray::from_array(Dimensioned::new(
+            Array2::from_shape_vec((1, 3), vec![1.0, 10.0, 10.0])
+                .unwrap()
+                .into_dyn(),
+            Unit::None,
+        ));
+        let outputs = run_photometry_table(&image, &no_image(), &sources, 3.0, 0.0, 0.0);
+        match (&outputs[0], &outputs[1]) {
+            (Ok(IOValue::Image(table)), Ok(IOValue::Str(unit))) => {
+                assert_eq!(table.shape(), &[1, 7]);
+                assert_eq!(table.array().unit(), &Unit::None);
+                assert_eq!(unit, "Jy");
+            }
+            _ => panic!("Expected a table and a unit"),
+        }
+    }
+
+    #[test]
+    fn integrate_binned_cube_and_scatter_bins() {
+        // Cube of 3 frames of 2x2 pixels, where each pixel is worth 1, 2, 3
+        // and 4 in each frame
+        let cube = WcsArray::from_array(Dimensioned::new(
+            ArrayD::from_shape_fn(vec![3, 2, 2], |idx| (idx[1] * 2 + idx[2] + 1) as f32),
+            Unit::None,
+        ));
+        // Bin 0 is the first row, bin 1 the last pixel
+        let bin_map = WcsArray::from_array(Dimensioned::new(
+            Array2::from_shape_vec((2, 2), vec![0.0, 0.0, ::std::f32::NAN, 1.0])
+                .unwrap()
+                .into_dyn(),
+            Unit::None,
+        ));
+        let binned = match run_apply_bin_map(&cube, &bin_map).unwrap() {
+            IOValue::Image(binned) => binned,
+            _ => unreachable!(),
+        };
+        assert_eq!(binned.shape(), &[3, 2]);
+        let values = match run_integral(&binned, 0, 2).unwrap() {
+            IOValue::Image(values) => values,
+            _ => unreachable!(),
+        };
+        assert_eq!(values.scalar().as_slice().unwrap(), &[6.0, 8.0]);
+        let map = match run_scatter_bins(&values, &bin_map).unwrap() {
+            IOValue::Image(map) => map,
+            _ => unreachable!(),
+        };
+        let map = map.scalar();
+        assert_eq!(map[[0, 0]], 6.0);
+        assert_eq!(map[[0, 1]], 6.0);
+        assert!(map[[1, 0]].is_nan());
+        assert_eq!(map[[1, 1]], 8.0);
+    }
 }
@@ -0,0 +1,125 @@
+//! Masks of bad values.
+//!
+//! A mask is a boolean array with the same dimension as the data it applies
+//! to, where `true` flags a bad value that should be ignored.
+use ndarray::{ArrayD, ArrayViewD, Axis, Dimension, Zip};
+
+/// Grow the masked regions of `mask` by `radius` pixels.
+///
+/// Dilation is done in the plane of the last two axes (the spatial axes of
+/// an image or a cube), with a disk of radius `radius` as structuring
+/// element. A 1-dimensional mask is dilated along its only axis.
+pub fn dilate(mask: &ArrayD<bool>, radius: usize) -> ArrayD<bool> {
+    let ndim = mask.ndim();
+    if ndim == 0 || radius == 0 {
+        return mask.clone();
+    }
+    let r = radius as isize;
+    let mut offsets = vec![];
+    if ndim == 1 {
+        offsets.extend((-r..=r).map(|dx| (0, dx)));
+    } else {
+        for dy in -r..=r {
+            for dx in -r..=r {
+                if dy * dy + dx * dx <= r * r {
+                    offsets.push((dy, dx));
+                }
+            }
+        }
+    }
+
+    let shape = mask.shape().to_vec();
+    let mut out = mask.clone();
+    for (idx, &masked) in mask.indexed_iter() {
+        if !masked {
+            continue;
+        }
+        let mut neighbour = idx.clone();
+        for &(dy, dx) in &offsets {
+            let x = idx[ndim - 1] as isize + dx;
+            if x < 0 || x >= shape[ndim - 1] as isize {
+                continue;
+            }
+            neighbour[ndim - 1] = x as usize;
+            if ndim > 1 {
+                let y = idx[ndim - 2] as isize + dy;
+                if y < 0 || y >= shape[ndim - 2] as isize {
+                    continue;
+                }
+                neighbour[ndim - 2] = y as usize;
+            }
+            out[neighbour.slice()] = true;
+        }
+    }
+    out
+}
+
+/// Fold `array` along its first axis, skipping the masked values.
+///
+/// Values where all the folded elements are masked are equal to `init`.
+pub fn fold_axis0<F>(
+    array: &ArrayViewD<f32>,
+    mask: Option<&ArrayViewD<bool>>,
+    init: f32,
+    fold: F,
+) -> ArrayD<f32>
+where
+    F: Fn(f32, f32) -> f32,
+{
+    let mut out = ArrayD::from_elem(array.index_axis(Axis(0), 0).dim(), init);
+    for (k, slice) in array.outer_iter().enumerate() {
+        if let Some(mask) = mask {
+            Zip::from(&mut out)
+                .and(&slice)
+                .and(&mask.index_axis(Axis(0), k))
+                .apply(|acc, &val, &masked| {
+                    if !masked {
+                        *acc = fold(*acc, val);
+                    }
+                });
+        } else {
+            Zip::from(&mut out)
+                .and(&slice)
+                .apply(|acc, &val| *acc = fold(*acc, val));
+        }
+    }
+    out
+}
+
+/// Count the values that are not masked along the first axis of an array
+/// of shape `shape`.
+pub fn count_axis0(shape: &[usize], mask: Option<&ArrayViewD<bool>>) -> ArrayD<f32> {
+    match mask {
+        Some(mask) => mask
+            .map(|&masked| if masked { 0.0 } else { 1.0 })
+            .sum_axis(Axis(0)),
+        None => ArrayD::from_elem(&shape[1..], shape[0] as f32),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{dilate, fold_axis0};
+    use ndarray::{ArrayD, IxDyn};
+
+    #[test]
+    fn dilate_disk() {
+        let mut mask = ArrayD::from_elem(IxDyn(&[2, 5, 5]), false);
+        mask[[1, 2, 2]] = true;
+        let dilated = dilate(&mask, 1);
+        assert_eq!(dilated.iter().filter(|&&m| m).count(), 5);
+        assert!(dilated[[1, 1, 2]] && dilated[[1, 2, 3]]);
+        assert!(!dilated[[1, 1, 1]] && !dilated[[0, 2, 2]]);
+    }
+
+    #[test]
+    fn fold_skips_masked_values() {
+        let array =
+            ArrayD::from_shape_vec(IxDyn(&[3, 2]), vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]).unwrap();
+        let mask =
+            ArrayD::from_shape_vec(IxDyn(&[3, 2]), vec![false, true, true, true, false, true])
+                .unwrap();
+        let sum = fold_axis0(&array.view(), Some(&mask.view()), 0.0, |a, b| a + b);
+        assert_eq!(sum.into_raw_vec(), vec![6.0, 0.0]);
+    }
+}
@@ -0,0 +1,289 @@
+//! Reading and writing of NumPy arrays saved as `.npy` files.
+//!
+//! Arrays of booleans, integers and floating point numbers are supported.
+//! Their values keep their type when possible: integers wider than 32 bits
+//! are read as f64. A `.npy` file has no metadata, so that the unit and the
+//! world coordinates of an image are not written.
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use ndarray::{ArrayD, IxDyn, ShapeBuilder};
+
+use unit::{Dimensioned, NativeArray, Unit, Values, WcsArray};
+
+const MAGIC: &[u8] = b"\x93NUMPY";
+
+/// Read the array saved in the `.npy` file at `path`.
+pub fn read<P: AsRef<Path>>(path: P) -> io::Result<WcsArray> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut preamble = [0; 8];
+    file.read_exact(&mut preamble)?;
+    if &preamble[..6] != MAGIC {
+        return Err(invalid("Not a NumPy array file".to_owned()));
+    }
+    let header_len = match preamble[6] {
+        1 => {
+            let mut len = [0; 2];
+            file.read_exact(&mut len)?;
+            u16::from_le_bytes(len) as usize
+        }
+        2 | 3 => {
+            let mut len = [0; 4];
+            file.read_exact(&mut len)?;
+            u32::from_le_bytes(len) as usize
+        }
+        version => {
+            return Err(invalid(format!(
+                "Unsupported .npy format version {}",
+                version
+            )));
+        }
+    };
+    let mut header = vec![0; header_len];
+    file.read_exact(&mut header)?;
+    let header = String::from_utf8(header).map_err(|_| invalid("Header is not text".to_owned()))?;
+
+    let descr = dict_value(&header, "descr")?.trim_matches(|c| c == '\'' || c == '"');
+    let fortran_order = dict_value(&header, "fortran_order")? == "True";
+    let shape = parse_shape(dict_value(&header, "shape")?)?;
+    let (little_endian, kind) = match descr.chars().next() {
+        Some('<') => (true, &descr[1..]),
+        Some('>') => (false, &descr[1..]),
+        Some('|') | Some('=') => (cfg!(target_endian = "little"), &descr[1..]),
+        _ => (cfg!(target_endian = "little"), descr),
+    };
+    let mut data = vec![];
+    file.read_to_end(&mut data)?;
+    let values = decode(kind, little_endian, &shape, fortran_order, &data)?;
+    Ok(WcsArray::from_array(Dimensioned::new(values, Unit::None)))
+}
+
+/// Convert the values in `data`, of the NumPy type `kind` (e.g. `f4`), to an
+/// array of the given shape.
+///
+/// The values keep their type when possible: integers wider than 32 bits are
+/// converted to f64.
+fn decode(
+    kind: &str,
+    little_endian: bool,
+    shape: &[usize],
+    fortran_order: bool,
+    data: &[u8],
+) -> io::Result<Values> {
+    let size = match kind {
+        "b1" | "i1" | "u1" => 1,
+        "i2" | "u2" => 2,
+        "i4" | "u4" | "f4" => 4,
+        "i8" | "u8" | "f8" => 8,
+        _ => return Err(invalid(format!("Unsupported data type '{}'", kind))),
+    };
+
+    let len = shape.iter().product::<usize>();
+    if data.len() < len * size {
+        return Err(invalid(
+            "Data is shorter than expected from the shape of the array".to_owned(),
+        ));
+    }
+    let words: Vec<_> = data
+        .chunks(size)
+        .take(len)
+        .map(|bytes| read_word(bytes, little_endian))
+        .collect();
+    let bits = 64 - 8 * size as u32;
+    let signed = |word: u64| ((word << bits) as i64) >> bits;
+
+    let native = match kind {
+        "f4" => {
+            let data = words.iter().map(|&w| f32::from_bits(w as u32)).collect();
+            return to_array(shape, fortran_order, data).map(Values::from);
+        }
+        "f8" => NativeArray::F64(to_array(
+            shape,
+            fortran_order,
+            words.iter().map(|&w| f64::from_bits(w)).collect(),
+        )?),
+        "b1" | "u1" => NativeArray::U8(to_array(
+            shape,
+            fortran_order,
+            words.iter().map(|&w| w as u8).collect(),
+        )?),
+        "u2" => NativeArray::I32(to_array(
+            shape,
+            fortran_order,
+            words.iter().map(|&w| w as i32).collect(),
+        )?),
+        "i1" | "i2" | "i4" => NativeArray::I32(to_array(
+            shape,
+            fortran_order,
+            words.iter().map(|&w| signed(w) as i32).collect(),
+        )?),
+        "i8" => NativeArray::F64(to_array(
+            shape,
+            fortran_order,
+            words.iter().map(|&w| signed(w) as f64).collect(),
+        )?),
+        _ => NativeArray::F64(to_array(
+            shape,
+            fortran_order,
+            words.iter().map(|&w| w as f64).collect(),
+        )?),
+    };
+    Ok(native.into())
+}
+
+/// Save the values of `image` as a `.npy` file at `path`, in their native
+/// type.
+pub fn write<P: AsRef<Path>>(path: P, image: &WcsArray) -> io::Result<()> {
+    let shape = image.shape();
+    let (kind, data) = encode(image);
+    let descr = if kind == "u1" {
+        "|u1".to_owned()
+    } else {
+        format!("<{}", kind)
+    };
+    let shape = match shape.len() {
+        1 => format!("({},)", shape[0]),
+        _ => format!(
+            "({})",
+            shape
+                .iter()
+                .map(|n| n.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+    let mut header = format!(
+        "{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}",
+        descr, shape
+    );
+    // The data should start at a multiple of 64 bytes, after the new line
+    // ending the header.
+    let preamble_len = MAGIC.len() + 4;
+    while (preamble_len + header.len() + 1) % 64 != 0 {
+        header.push(' ');
+    }
+    header.push('\n');
+
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(MAGIC)?;
+    file.write_all(&[1, 0])?;
+    file.write_all(&(header.len() as u16).to_le_bytes())?;
+    file.write_all(header.as_bytes())?;
+    file.write_all(&data)?;
+    file.flush()
+}
+
+/// Get the NumPy type (e.g. `f4`) of the values of `image` in their native
+/// type, and their bytes in little-endian order.
+fn encode(image: &WcsArray) -> (&'static str, Vec<u8>) {
+    match image.native() {
+        None => (
+            "f4",
+            image
+                .scalar()
+                .iter()
+                .flat_map(|v| v.to_bits().to_le_bytes().to_vec())
+                .collect(),
+        ),
+        Some(NativeArray::F64(array)) => (
+            "f8",
+            array
+                .iter()
+                .flat_map(|v| v.to_bits().to_le_bytes().to_vec())
+                .collect(),
+        ),
+        Some(NativeArray::I32(array)) => (
+            "i4",
+            array
+                .iter()
+                .flat_map(|v| v.to_le_bytes().to_vec())
+                .collect(),
+        ),
+        Some(NativeArray::U8(array)) => ("u1", array.iter().cloned().collect()),
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Find the value of `key` in the Python dictionary describing the array.
+fn dict_value<'a>(header: &'a str, key: &str) -> io::Result<&'a str> {
+    let missing = || invalid(format!("Missing '{}' in header", key));
+    let start = header.find(&format!("'{}'", key)).ok_or_else(missing)?;
+    let value = header[start + key.len() + 2..]
+        .trim_start()
+        .trim_start_matches(':')
+        .trim_start();
+    let end = if value.starts_with('(') {
+        value.find(')').map(|i| i + 1)
+    } else {
+        value.find(|c| c == ',' || c == '}')
+    };
+    end.map(|end| value[..end].trim()).ok_or_else(missing)
+}
+
+fn parse_shape(shape: &str) -> io::Result<Vec<usize>> {
+    shape
+        .trim_matches(|c| c == '(' || c == ')')
+        .split(',')
+        .map(str::trim)
+        .filter(|n| !n.is_empty())
+        .map(|n| {
+            n.parse()
+                .map_err(|_| invalid(format!("Invalid shape {}", shape)))
+        })
+        .collect()
+}
+
+fn read_word(bytes: &[u8], little_endian: bool) -> u64 {
+    if little_endian {
+        bytes
+            .iter()
+            .rev()
+            .fold(0, |word, &byte| (word << 8) | u64::from(byte))
+    } else {
+        bytes
+            .iter()
+            .fold(0, |word, &byte| (word << 8) | u64::from(byte))
+    }
+}
+
+/// Make an array in standard layout from data in C or Fortran order.
+fn to_array<T: Clone>(shape: &[usize], fortran_order: bool, data: Vec<T>) -> io::Result<ArrayD<T>> {
+    let array = ArrayD::from_shape_vec(IxDyn(shape).set_f(fortran_order), data)
+        .map_err(|e| invalid(format!("{}", e)))?;
+    if fortran_order {
+        Ok(
+            ArrayD::from_shape_vec(IxDyn(shape), array.iter().cloned().collect())
+                .expect("Array has the same shape"),
+        )
+    } else {
+        Ok(array)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tempfile;
+
+    use super::{read, write};
+    use ndarray::{ArrayD, IxDyn};
+    use unit::{DataType, Dimensioned, Unit, WcsArray};
+
+    #[test]
+    fn write_and_read_npy() {
+        let array =
+            ArrayD::from_shape_vec(IxDyn(&[2, 3]), vec![1.0, -2.0, 3.0, 4.0, 5.0, 6.0]).unwrap();
+        let image =
+            WcsArray::from_array(Dimensioned::new(array, Unit::None)).into_dtype(DataType::I32);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.npy");
+        write(&path, &image).unwrap();
+        let read_image = read(&path).unwrap();
+
+        assert_eq!(read_image.dtype(), DataType::I32);
+        assert_eq!(*read_image.scalar(), *image.scalar());
+    }
+}
@@ -0,0 +1,286 @@
+//! Aperture photometry with exact pixel overlap.
+//!
+//! Pixel coordinates are (x, y) with `x` along the columns and `y` along the
+//! rows of the image. The center of pixel `[(row, col)]` is at (col, row).
+use ndarray::ArrayView2;
+
+use stats;
+use IOErr;
+
+/// An elliptical aperture. A circle is an ellipse whose semi-axes are equal.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Ellipse {
+    pub x: f32,
+    pub y: f32,
+    /// Semi-major axis, along the direction `theta`
+    pub a: f32,
+    /// Semi-minor axis
+    pub b: f32,
+    /// Angle of the major axis in radians, counter-clockwise from the x axis
+    pub theta: f32,
+}
+
+impl Ellipse {
+    /// Same ellipse with semi-major axis scaled to `a`.
+    pub fn with_major_axis(&self, a: f32) -> Self {
+        Ellipse {
+            a,
+            b: self.b * a / self.a,
+            ..*self
+        }
+    }
+
+    /// Fraction of the pixel centered on (x, y) covered by the ellipse.
+    pub fn pixel_overlap(&self, x: f32, y: f32) -> f32 {
+        if !(self.a > 0.0 && self.b > 0.0) {
+            return 0.0;
+        }
+        let (dx, dy) = (f64::from(x - self.x), f64::from(y - self.y));
+        let dist = (dx * dx + dy * dy).sqrt();
+        let half_diag = 0.5 * 2f64.sqrt();
+        if dist > f64::from(self.a) + half_diag {
+            return 0.0;
+        }
+        if dist + half_diag < f64::from(self.b) {
+            return 1.0;
+        }
+
+        // Map the pixel to the frame where the ellipse is the unit circle.
+        // The square pixel becomes a parallelogram, whose area is scaled by
+        // 1 / (a * b).
+        let (sin, cos) = f64::from(self.theta).sin_cos();
+        let (a, b) = (f64::from(self.a), f64::from(self.b));
+        let corners = [(-0.5, -0.5), (0.5, -0.5), (0.5, 0.5), (-0.5, 0.5)];
+        let mut polygon = [(0.0, 0.0); 4];
+        for (p, &(cx, cy)) in polygon.iter_mut().zip(&corners) {
+            let (px, py) = (dx + cx, dy + cy);
+            *p = ((px * cos + py * sin) / a, (-px * sin + py * cos) / b);
+        }
+        let area = polygon_circle_overlap(&polygon, 1.0) * a * b;
+        area.max(0.0).min(1.0) as f32
+    }
+}
+
+/// Area of the intersection between a polygon and the circle of radius `r`
+/// centered on the origin.
+///
+/// The polygon is decomposed in triangles formed by the origin and each edge,
+/// whose signed intersection areas with the circle are summed.
+pub fn polygon_circle_overlap(polygon: &[(f64, f64)], r: f64) -> f64 {
+    let n = polygon.len();
+    let mut area = 0.0;
+    for k in 0..n {
+        area += triangle_circle_overlap(polygon[k], polygon[(k + 1) % n], r);
+    }
+    area.abs()
+}
+
+/// Signed area of the intersection between the triangle (origin, p, q) and
+/// the circle of radius `r` centered on the origin.
+fn triangle_circle_overlap(p: (f64, f64), q: (f64, f64), r: f64) -> f64 {
+    // Split the segment pq at its intersections with the circle
+    let d = (q.0 - p.0, q.1 - p.1);
+    let qa = d.0 * d.0 + d.1 * d.1;
+    let qb = 2.0 * (p.0 * d.0 + p.1 * d.1);
+    let qc = p.0 * p.0 + p.1 * p.1 - r * r;
+    let mut ts = vec![0.0];
+    let disc = qb * qb - 4.0 * qa * qc;
+    if qa > 0.0 && disc > 0.0 {
+        let sqrt = disc.sqrt();
+        for &t in &[(-qb - sqrt) / (2.0 * qa), (-qb + sqrt) / (2.0 * qa)] {
+            if t > 0.0 && t < 1.0 {
+                ts.push(t);
+            }
+        }
+    }
+    ts.push(1.0);
+
+    let point = |t: f64| (p.0 + t * d.0, p.1 + t * d.1);
+    let mut area = 0.0;
+    for w in ts.windows(2) {
+        let (u, v) = (point(w[0]), point(w[1]));
+        let mid = point((w[0] + w[1]) / 2.0);
+        let cross = u.0 * v.1 - u.1 * v.0;
+        if mid.0 * mid.0 + mid.1 * mid.1 <= r * r {
+            area += cross / 2.0;
+        } else {
+            let dot = u.0 * v.0 + u.1 * v.1;
+            area += r * r * cross.atan2(dot) / 2.0;
+        }
+    }
+    area
+}
+
+/// Result of the photometry in an aperture.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Photometry {
+    /// Background-subtracted flux in the aperture
+    pub flux: f32,
+    /// Standard error on the flux
+    pub flux_err: f32,
+    /// Background level per pixel
+    pub background: f32,
+    /// Effective area of the aperture in pixels, NaN pixels excluded
+    pub area: f32,
+}
+
+/// Measure the flux within an aperture.
+///
+/// If an annulus is given as (inner, outer) ellipses, the local background is
+/// the sigma-clipped median of the pixels lying in the annulus, and is
+/// subtracted from the flux. Errors are propagated from the `variance` image
+/// if provided, and include the uncertainty on the background level.
+///
+/// Return an error if the annulus does not contain any valid pixel, e.g. if it
+/// lies outside of the image.
+pub fn aperture_photometry(
+    image: ArrayView2<f32>,
+    variance: Option<ArrayView2<f32>>,
+    aperture: &Ellipse,
+    annulus: Option<(&Ellipse, &Ellipse)>,
+) -> Result<Photometry, IOErr> {
+    let (h, w) = image.dim();
+    let (mut sum, mut var, mut area) = (0.0f64, 0.0f64, 0.0f64);
+    let mut annulus_values = vec![];
+
+    let reach = annulus.map(|(_, outer)| outer.a).unwrap_or(aperture.a) + 1.0;
+    let (x0, y0) = (aperture.x, aperture.y);
+    let range = |center: f32, len: usize| {
+        let lo = (center - reach).floor().max(0.0) as usize;
+        let hi = ((center + reach).ceil().max(0.0) as usize + 1).min(len);
+        lo..hi
+    };
+    for i in range(y0, h) {
+        for j in range(x0, w) {
+            let val = image[(i, j)];
+            if val.is_nan() {
+                continue;
+            }
+            let (x, y) = (j as f32, i as f32);
+            let weight = aperture.pixel_overlap(x, y);
+            if weight > 0.0 {
+                let weight = f64::from(weight);
+                sum += weight * f64::from(val);
+                area += weight;
+                if let Some(variance) = variance {
+                    var += weight * weight * f64::from(variance[(i, j)]);
+                }
+            }
+            if let Some((inner, outer)) = annulus {
+                // Only use pixels entirely within the annulus
+                if outer.pixel_overlap(x, y) >= 1.0 && inner.pixel_overlap(x, y) <= 0.0 {
+                    annulus_values.push(val);
+                }
+            }
+        }
+    }
+
+    let mut background = 0.0;
+    if annulus.is_some() {
+        if annulus_values.is_empty() {
+            return Err(IOErr::UnexpectedInput(format!(
+                "No valid pixel in the background annulus around ({}, {})",
+                x0, y0
+            )));
+        }
+        let mut clipped = stats::sigma_clip(&annulus_values, 3.0, 5);
+        let bkg_stats = stats::Statistics::new(&mut clipped);
+        background = bkg_stats.median;
+        let n = clipped.len() as f64;
+        sum -= f64::from(background) * area;
+        // Uncertainty on the background level, scaled to the aperture
+        var += area * area * f64::from(bkg_stats.std_dev).powi(2) / n;
+    }
+
+    let flux_err = if variance.is_some() || annulus.is_some() {
+        var.sqrt() as f32
+    } else {
+        ::std::f32::NAN
+    };
+    Ok(Photometry {
+        flux: sum as f32,
+        flux_err,
+        background,
+        area: area as f32,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{aperture_photometry, Ellipse};
+    use ndarray::Array2;
+
+    #[test]
+    fn overlap_sums_to_area() {
+        let ellipse = Ellipse {
+            x: 10.3,
+            y: 9.8,
+            a: 5.5,
+            b: 2.5,
+            theta: 0.7,
+        };
+        let mut total = 0.0;
+        for i in 0..20 {
+            for j in 0..20 {
+                total += ellipse.pixel_overlap(j as f32, i as f32);
+            }
+        }
+        let area = ::std::f32::consts::PI * ellipse.a * ellipse.b;
+        assert!((total - area).abs() < 1e-3);
+    }
+
+    #[test]
+    fn constant_background_is_subtracted() {
+        let mut image = Array2::from_elem((30, 30), 2.0);
+        image[(15, 15)] += 100.0;
+        let variance = Array2::from_elem((30, 30), 1.0);
+        let aperture = Ellipse {
+            x: 15.0,
+            y: 15.0,
+            a: 3.0,
+            b: 3.0,
+            theta: 0.0,
+        };
+        let annulus = (
+            aperture.with_major_axis(6.0),
+            aperture.with_major_axis(10.0),
+        );
+        let phot = aperture_photometry(
+            image.view(),
+            Some(variance.view()),
+            &aperture,
+            Some((&annulus.0, &annulus.1)),
+        )
+        .unwrap();
+        assert!((phot.flux - 100.0).abs() < 1e-2);
+        assert_eq!(phot.background, 2.0);
+        // The background is flat: the error comes from the variance map only,
+        // where partial pixels count less than their area
+        assert!(phot.flux_err <= phot.area.sqrt());
+        assert!(phot.flux_err > 0.9 * phot.area.sqrt());
+    }
+
+    #[test]
+    fn empty_annulus_is_an_error() {
+        let image = Array2::from_elem((10, 10), 1.0);
+        let aperture = Ellipse {
+            x: 5.0,
+            y: 5.0,
+            a: 2.0,
+            b: 2.0,
+            theta: 0.0,
+        };
+        // The annulus lies entirely outside of the image
+        let annulus = (
+            aperture.with_major_axis(20.0),
+            aperture.with_major_axis(30.0),
+        );
+        let phot = aperture_photometry(
+            image.view(),
+            None,
+            &aperture,
+            Some((&annulus.0, &annulus.1)),
+        );
+        assert!(phot.is_err());
+    }
+}
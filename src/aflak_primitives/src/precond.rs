@@ -44,7 +44,7 @@ macro_rules! precheck {
 /// If so, return the number of frames along the first dimension.
 macro_rules! has_gt_0_dim {
     ($wcs_array: ident) => {
-        if let Some(frame_cnt) = $wcs_array.scalar().dim().as_array_view().first() {
+        if let Some(frame_cnt) = $wcs_array.shape().first() {
             Ok(*frame_cnt)
         } else {
             Err($crate::IOErr::UnexpectedInput(format!(
@@ -59,7 +59,7 @@ macro_rules! has_gt_0_dim {
 macro_rules! dim_is {
     ($wcs_array: ident, $dim: expr) => {{
         let expected_dim = $dim;
-        let got_dim = $wcs_array.scalar().ndim();
+        let got_dim = $wcs_array.shape().len();
         if got_dim == expected_dim {
             Ok(())
         } else {
@@ -100,8 +100,8 @@ macro_rules! is_sliceable {
 /// Check that two WcsArray have the same dimensions
 macro_rules! are_same_dim {
     ($wcs_array1: ident, $wcs_array2: ident) => {{
-        let i1_dim = $wcs_array1.scalar().dim();
-        let i2_dim = $wcs_array2.scalar().dim();
+        let i1_dim = $wcs_array1.shape();
+        let i2_dim = $wcs_array2.shape();
         if i1_dim == i2_dim {
             Ok(())
         } else {
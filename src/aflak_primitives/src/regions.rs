@@ -0,0 +1,516 @@
+//! Read and write regions of interest in the DS9 region file format.
+//!
+//! DS9 image coordinates start from 1 at the center of the first pixel, while
+//! the continuous coordinates of [`ROI`](../roi/enum.ROI.html) start from 0 at
+//! the corner of the first pixel. Both count rows in the same direction.
+//!
+//! World coordinates in aflak are linear in pixels, with the scale of the
+//! right ascension axis measured on the sky (CDELT1), so a right ascension
+//! offset covers `cos(dec)` times less sky. Sky coordinates are converted with
+//! the declination of a reference pixel, usually the center of the image, so
+//! they are only exact near it.
+use std::fmt::Write;
+
+use reproject::Affine;
+use roi::ROI;
+
+/// Coordinate system used in a region file.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum System {
+    Image,
+    Sky,
+}
+
+/// Parse the content of a DS9 region file.
+///
+/// `pix2world` is the transform from pixel to world coordinates (in degrees)
+/// of the image, required to convert regions defined in sky coordinates.
+/// `reference` is the pixel where right ascension offsets are converted to
+/// offsets on the sky.
+///
+/// Included shapes are merged, then excluded shapes (prefixed with `-`) are
+/// removed. Shapes that do not define an area (points, lines, text...) are
+/// ignored.
+pub fn parse(text: &str, pix2world: Option<&Affine>, reference: (f32, f32)) -> Result<ROI, String> {
+    let sky = pix2world.and_then(|pix2world| {
+        pix2world.inverse().map(|world2pix| Sky {
+            world2pix,
+            reference: pix2world.apply(reference),
+        })
+    });
+    let mut system = System::Image;
+    let mut include = None;
+    let mut exclude = None;
+
+    for (line_no, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap();
+        for command in line.split(';') {
+            let command = command.trim();
+            let err = |msg: String| format!("Line {}: {}", line_no + 1, msg);
+            let name = command
+                .split(|c: char| c == '(' || c.is_whitespace())
+                .next()
+                .unwrap()
+                .to_lowercase();
+            match name.as_ref() {
+                "" | "global" => continue,
+                "image" | "physical" => {
+                    system = System::Image;
+                    continue;
+                }
+                "fk5" | "fk4" | "icrs" | "j2000" | "wcs" => {
+                    system = System::Sky;
+                    continue;
+                }
+                "galactic" | "ecliptic" | "linear" | "amplifier" | "detector" => {
+                    return Err(err(format!("Unsupported coordinate system '{}'", name)));
+                }
+                _ => (),
+            }
+
+            let converter = match system {
+                System::Image => Converter::Image,
+                System::Sky => match sky {
+                    Some(ref sky) => Converter::Sky(sky),
+                    None => {
+                        return Err(err("Sky coordinates require an image with WCS".to_owned()))
+                    }
+                },
+            };
+            if let Some((shape, excluded)) = parse_shape(command, &converter).map_err(err)? {
+                let target = if excluded { &mut exclude } else { &mut include };
+                *target = Some(match target.take() {
+                    Some(roi) => ROI::Union(Box::new(roi), Box::new(shape)),
+                    None => shape,
+                });
+            }
+        }
+    }
+
+    match (include, exclude) {
+        (Some(include), Some(exclude)) => Ok(ROI::Difference(Box::new(include), Box::new(exclude))),
+        (Some(include), None) => Ok(include),
+        (None, _) => Err("No region found".to_owned()),
+    }
+}
+
+/// Conversion of sky coordinates to the pixels of an image.
+struct Sky {
+    world2pix: Affine,
+    /// World coordinates of the reference pixel
+    reference: (f32, f32),
+}
+
+enum Converter<'a> {
+    Image,
+    Sky(&'a Sky),
+}
+
+impl<'a> Converter<'a> {
+    fn point(&self, x: &str, y: &str) -> Result<(f32, f32), String> {
+        match *self {
+            Converter::Image => Ok((parse_number(x)? - 0.5, parse_number(y)? - 0.5)),
+            Converter::Sky(sky) => {
+                let ra = parse_sexagesimal(x, 15.0)?;
+                let dec = parse_sexagesimal(y, 1.0)?;
+                let (ra_ref, dec_ref) = sky.reference;
+                let offset = (ra - ra_ref + 540.0) % 360.0 - 180.0;
+                let ra = ra_ref + offset * dec_ref.to_radians().cos();
+                let (x, y) = sky.world2pix.apply((ra, dec));
+                Ok((x + 0.5, y + 0.5))
+            }
+        }
+    }
+
+    /// Convert a length to pixels. Lengths on the sky need no correction, as
+    /// the world coordinates have the same scale as the sky along both axes.
+    fn length(&self, value: &str) -> Result<f32, String> {
+        let (number, unit) = split_unit(value);
+        let number = parse_number(number)?;
+        match (self, unit) {
+            (_, Some('i')) | (_, Some('p')) | (Converter::Image, None) => Ok(number),
+            (Converter::Image, Some(_)) => {
+                Err(format!("Unexpected unit in image coordinates: '{}'", value))
+            }
+            (Converter::Sky(sky), unit) => {
+                let degrees = match unit {
+                    Some('"') => number / 3600.0,
+                    Some('\'') => number / 60.0,
+                    Some('d') | None => number,
+                    Some('r') => number.to_degrees(),
+                    Some(_) => return Err(format!("Unknown unit in '{}'", value)),
+                };
+                Ok(degrees * sky.world2pix.det().abs().sqrt())
+            }
+        }
+    }
+
+    fn angle(&self, value: &str) -> Result<f32, String> {
+        let (number, unit) = split_unit(value);
+        let number = parse_number(number)?;
+        match unit {
+            Some('r') => Ok(number.to_degrees()),
+            Some('d') | None => Ok(number),
+            Some(_) => Err(format!("Unknown unit in angle '{}'", value)),
+        }
+    }
+}
+
+fn split_unit(value: &str) -> (&str, Option<char>) {
+    match value.chars().last() {
+        Some(c) if c.is_alphabetic() || c == '"' || c == '\'' => {
+            (&value[..value.len() - c.len_utf8()], Some(c))
+        }
+        _ => (value, None),
+    }
+}
+
+fn parse_number(value: &str) -> Result<f32, String> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| format!("Expected a number, got '{}'", value))
+}
+
+/// Parse a coordinate in degrees, or sexagesimal notation (for example
+/// "12:30:00.5"). In sexagesimal notation, the value is multiplied by
+/// `factor` (15 for hours of right ascension).
+fn parse_sexagesimal(value: &str, factor: f32) -> Result<f32, String> {
+    if !value.contains(':') {
+        let (number, unit) = split_unit(value);
+        return match unit {
+            Some('d') | None => parse_number(number),
+            _ => Err(format!("Unexpected unit in coordinate '{}'", value)),
+        };
+    }
+    let negative = value.trim().starts_with('-');
+    let mut degrees = 0.0;
+    for (part, scale) in value.split(':').zip(&[1.0, 60.0, 3600.0]) {
+        degrees += parse_number(part)?.abs() / scale;
+    }
+    let degrees = degrees * factor;
+    Ok(if negative { -degrees } else { degrees })
+}
+
+/// Parse a shape. Return `None` if the shape does not define an area.
+/// The boolean is true if the shape is excluded.
+fn parse_shape(command: &str, converter: &Converter) -> Result<Option<(ROI, bool)>, String> {
+    let (excluded, command) = if command.starts_with('-') {
+        (true, &command[1..])
+    } else if command.starts_with('+') {
+        (false, &command[1..])
+    } else {
+        (false, command)
+    };
+    let open = command
+        .find('(')
+        .ok_or_else(|| format!("Cannot parse '{}'", command))?;
+    let close = command
+        .rfind(')')
+        .ok_or_else(|| format!("Missing ')' in '{}'", command))?;
+    let name = command[..open].trim().to_lowercase();
+    let args: Vec<_> = command[open + 1..close]
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|arg| !arg.is_empty())
+        .collect();
+
+    let expect_args = |valid: &[usize]| {
+        if valid.contains(&args.len()) {
+            Ok(())
+        } else {
+            Err(format!(
+                "'{}' expects {:?} arguments, got {}",
+                name,
+                valid,
+                args.len()
+            ))
+        }
+    };
+    let shape = match name.as_ref() {
+        "circle" => {
+            expect_args(&[3])?;
+            ROI::Circle {
+                center: converter.point(args[0], args[1])?,
+                radius: converter.length(args[2])?,
+            }
+        }
+        "ellipse" => {
+            expect_args(&[4, 5])?;
+            ROI::Ellipse {
+                center: converter.point(args[0], args[1])?,
+                a: converter.length(args[2])?,
+                b: converter.length(args[3])?,
+                angle: args.get(4).map_or(Ok(0.0), |a| converter.angle(a))?,
+            }
+        }
+        "box" => {
+            expect_args(&[4, 5])?;
+            let (x, y) = converter.point(args[0], args[1])?;
+            let (w, h) = (converter.length(args[2])?, converter.length(args[3])?);
+            let angle = args.get(4).map_or(Ok(0.0), |a| converter.angle(a))?;
+            if angle == 0.0 {
+                ROI::Rect {
+                    min: (x - w / 2.0, y - h / 2.0),
+                    max: (x + w / 2.0, y + h / 2.0),
+                }
+            } else {
+                let (sin, cos) = angle.to_radians().sin_cos();
+                let corners = [(-0.5, -0.5), (0.5, -0.5), (0.5, 0.5), (-0.5, 0.5)];
+                ROI::Polygon(
+                    corners
+                        .iter()
+                        .map(|&(u, v)| {
+                            let (u, v) = (u * w, v * h);
+                            (x + u * cos - v * sin, y + u * sin + v * cos)
+                        })
+                        .collect(),
+                )
+            }
+        }
+        "polygon" => {
+            if args.len() < 6 || args.len() % 2 != 0 {
+                return Err(format!(
+                    "'polygon' expects at least 3 vertices, got {} arguments",
+                    args.len()
+                ));
+            }
+            let mut vertices = Vec::with_capacity(args.len() / 2);
+            for pair in args.chunks(2) {
+                vertices.push(converter.point(pair[0], pair[1])?);
+            }
+            ROI::Polygon(vertices)
+        }
+        "annulus" => {
+            if args.len() < 4 {
+                return Err(format!(
+                    "'annulus' expects at least 4 arguments, got {}",
+                    args.len()
+                ));
+            }
+            ROI::Annulus {
+                center: converter.point(args[0], args[1])?,
+                r_in: converter.length(args[2])?,
+                r_out: converter.length(args[args.len() - 1])?,
+            }
+        }
+        "point" | "line" | "vector" | "text" | "ruler" | "compass" | "projection" => {
+            return Ok(None)
+        }
+        _ => return Err(format!("Unsupported shape '{}'", name)),
+    };
+    Ok(Some((shape, excluded)))
+}
+
+impl ROI {
+    /// Write the region of interest in the DS9 region file format, in image
+    /// coordinates.
+    ///
+    /// DS9 removes excluded shapes from all the included shapes. So only
+    /// differences whose included part does not contain any other difference,
+    /// and whose excluded part is made of shapes or unions of shapes, can
+    /// be represented. Other regions return an error.
+    pub fn to_ds9(&self) -> Result<String, String> {
+        let mut out = "# Region file format: DS9 version 4.1\nimage\n".to_owned();
+        write_shape(self, Part::Top, &mut out)?;
+        Ok(out)
+    }
+}
+
+/// Part of the region file a shape is written to.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Part {
+    /// Included shapes, from which all excluded shapes are removed
+    Top,
+    /// Included shapes that are part of a union
+    Included,
+    Excluded,
+}
+
+fn write_shape(roi: &ROI, part: Part, out: &mut String) -> Result<(), String> {
+    // DS9 coordinates of a point
+    let p = |(x, y): (f32, f32)| (x + 0.5, y + 0.5);
+    let sign = if part == Part::Excluded { "-" } else { "" };
+    match *roi {
+        ROI::All => return Err("Cannot export the whole image as a region".to_owned()),
+        ROI::PixelList(ref pixels) => {
+            for &(x, y) in pixels {
+                writeln!(out, "{}box({},{},1,1,0)", sign, x + 1, y + 1).unwrap();
+            }
+        }
+        ROI::Circle { center, radius } => {
+            let (x, y) = p(center);
+            writeln!(out, "{}circle({},{},{})", sign, x, y, radius).unwrap();
+        }
+        ROI::Ellipse {
+            center,
+            a,
+            b,
+            angle,
+        } => {
+            let (x, y) = p(center);
+            writeln!(out, "{}ellipse({},{},{},{},{})", sign, x, y, a, b, angle).unwrap();
+        }
+        ROI::Rect { min, max } => {
+            let (x, y) = p(((min.0 + max.0) / 2.0, (min.1 + max.1) / 2.0));
+            let (w, h) = (max.0 - min.0, max.1 - min.1);
+            writeln!(out, "{}box({},{},{},{},0)", sign, x, y, w, h).unwrap();
+        }
+        ROI::Polygon(ref vertices) => {
+            let coords: Vec<_> = vertices
+                .iter()
+                .map(|&v| {
+                    let (x, y) = p(v);
+                    format!("{},{}", x, y)
+                })
+                .collect();
+            writeln!(out, "{}polygon({})", sign, coords.join(",")).unwrap();
+        }
+        ROI::Annulus {
+            center,
+            r_in,
+            r_out,
+        } => {
+            let (x, y) = p(center);
+            writeln!(out, "{}annulus({},{},{},{})", sign, x, y, r_in, r_out).unwrap();
+        }
+        ROI::Union(ref a, ref b) => {
+            let part = if part == Part::Top {
+                Part::Included
+            } else {
+                part
+            };
+            write_shape(a, part, out)?;
+            write_shape(b, part, out)?;
+        }
+        ROI::Difference(ref a, ref b) => {
+            // (a - b) - c is written as a, -b, -c
+            if part != Part::Top {
+                return Err(
+                    "Cannot export region differences within unions or differences".to_owned(),
+                );
+            }
+            write_shape(a, Part::Top, out)?;
+            write_shape(b, Part::Excluded, out)?;
+        }
+        ROI::Intersection(..) => {
+            return Err("Cannot export region intersections".to_owned());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse;
+    use reproject::Affine;
+    use roi::ROI;
+
+    #[test]
+    fn parse_image_regions() {
+        let text = "# Region file format: DS9 version 4.1
+global color=green width=1
+image
+circle(11,21,5) # color=red
+-box(11,21,2,4,0)
+point(3,3)";
+        let roi = parse(text, None, (0.0, 0.0)).unwrap();
+        assert_eq!(
+            roi,
+            ROI::Difference(
+                Box::new(ROI::Circle {
+                    center: (10.5, 20.5),
+                    radius: 5.0,
+                }),
+                Box::new(ROI::Rect {
+                    min: (9.5, 18.5),
+                    max: (11.5, 22.5),
+                }),
+            )
+        );
+    }
+
+    #[test]
+    fn parse_sky_regions() {
+        // 1 pixel is 1 arcsecond, pixel (0, 0) is at (10, 20) degrees
+        let pix2world = Affine::new([[1.0 / 3600.0, 0.0], [0.0, 1.0 / 3600.0]], [10.0, 20.0]);
+        let text = "fk5;circle(0:40:00.0,+20:00:10,2\")";
+        match parse(text, Some(&pix2world), (0.0, 0.0)).unwrap() {
+            ROI::Circle { center, radius } => {
+                assert!((center.0 - 0.5).abs() < 1e-2);
+                assert!((center.1 - 10.5).abs() < 1e-2);
+                assert!((radius - 2.0).abs() < 1e-3);
+            }
+            roi => panic!("Unexpected region {:?}", roi),
+        }
+        assert!(parse(text, None, (0.0, 0.0)).is_err());
+    }
+
+    #[test]
+    fn parse_sky_regions_at_high_declination() {
+        // 1 pixel is 1 arcsecond on the sky, pixel (0, 0) is at (10, 60)
+        // degrees, where 1 degree of right ascension is half a degree on the
+        // sky
+        let pix2world = Affine::new([[1.0 / 3600.0, 0.0], [0.0, 1.0 / 3600.0]], [10.0, 60.0]);
+        let text = "icrs;box(10.02,60.0,10\",4\",0)";
+        match parse(text, Some(&pix2world), (0.0, 0.0)).unwrap() {
+            ROI::Rect { min, max } => {
+                assert!((min.0 - 31.5).abs() < 1e-1);
+                assert!((max.0 - 41.5).abs() < 1e-1);
+                assert!((min.1 + 1.5).abs() < 1e-1);
+                assert!((max.1 - 2.5).abs() < 1e-1);
+            }
+            roi => panic!("Unexpected region {:?}", roi),
+        }
+    }
+
+    #[test]
+    fn export_roundtrip() {
+        let roi = ROI::Difference(
+            Box::new(ROI::Union(
+                Box::new(ROI::Ellipse {
+                    center: (4.0, 5.0),
+                    a: 3.0,
+                    b: 2.0,
+                    angle: 30.0,
+                }),
+                Box::new(ROI::Polygon(vec![(0.0, 0.0), (4.0, 0.0), (0.0, 3.0)])),
+            )),
+            Box::new(ROI::Annulus {
+                center: (1.0, 2.0),
+                r_in: 1.0,
+                r_out: 2.0,
+            }),
+        );
+        assert_eq!(
+            parse(&roi.to_ds9().unwrap(), None, (0.0, 0.0)).unwrap(),
+            roi
+        );
+    }
+
+    #[test]
+    fn export_only_faithful_differences() {
+        let circle = |x| {
+            Box::new(ROI::Circle {
+                center: (x, 0.0),
+                radius: 1.0,
+            })
+        };
+        let nested = ROI::Difference(
+            Box::new(ROI::Difference(circle(0.0), circle(1.0))),
+            circle(2.0),
+        );
+        assert_eq!(
+            parse(&nested.to_ds9().unwrap(), None, (0.0, 0.0)).unwrap(),
+            {
+                let excluded = ROI::Union(circle(1.0), circle(2.0));
+                ROI::Difference(circle(0.0), Box::new(excluded))
+            }
+        );
+        // Excluding the circle from the union would remove it from both shapes
+        let in_union = ROI::Union(
+            circle(0.0),
+            Box::new(ROI::Difference(circle(1.0), circle(2.0))),
+        );
+        assert!(in_union.to_ds9().is_err());
+    }
+}
@@ -0,0 +1,399 @@
+//! Resampling of 2D images onto another pixel grid.
+//!
+//! World coordinates in aflak are linear functions of pixel coordinates, so
+//! going from the pixel grid of an image to the pixel grid of another one is
+//! an affine transform.
+use std::f32;
+
+use fitrs::WCS;
+use ndarray::{Array2, ArrayView2};
+
+/// Interpolation method used for resampling.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Interpolation {
+    /// Value of the nearest pixel.
+    Nearest,
+    /// Bilinear interpolation of the 4 nearest pixels.
+    Bilinear,
+    /// Sum of the input pixels weighted by their exact overlap with the
+    /// output pixel. The total flux of the image is conserved.
+    FluxConserving,
+}
+
+impl Interpolation {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "nearest" => Some(Interpolation::Nearest),
+            "bilinear" => Some(Interpolation::Bilinear),
+            "flux" => Some(Interpolation::FluxConserving),
+            _ => None,
+        }
+    }
+}
+
+/// An affine transform of the plane: `p' = m * p + c`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Affine {
+    m: [[f32; 2]; 2],
+    c: [f32; 2],
+}
+
+impl Affine {
+    pub fn new(m: [[f32; 2]; 2], c: [f32; 2]) -> Self {
+        Affine { m, c }
+    }
+
+    pub fn translation(dx: f32, dy: f32) -> Self {
+        Affine::new([[1.0, 0.0], [0.0, 1.0]], [dx, dy])
+    }
+
+    /// Make the transform from the pixel coordinates (x, y) to the world
+    /// coordinates of the first two axes of the provided WCS.
+    pub fn from_wcs(wcs: &WCS) -> Self {
+        let origin = wcs.pix2world([0.0; 4]);
+        let ex = wcs.pix2world([1.0, 0.0, 0.0, 0.0]);
+        let ey = wcs.pix2world([0.0, 1.0, 0.0, 0.0]);
+        Affine::new(
+            [
+                [ex[0] - origin[0], ey[0] - origin[0]],
+                [ex[1] - origin[1], ey[1] - origin[1]],
+            ],
+            [origin[0], origin[1]],
+        )
+    }
+
+    pub fn apply(&self, (x, y): (f32, f32)) -> (f32, f32) {
+        (
+            self.m[0][0] * x + self.m[0][1] * y + self.c[0],
+            self.m[1][0] * x + self.m[1][1] * y + self.c[1],
+        )
+    }
+
+    pub fn det(&self) -> f32 {
+        self.m[0][0] * self.m[1][1] - self.m[0][1] * self.m[1][0]
+    }
+
+    /// Return `None` if the transform is not invertible.
+    pub fn inverse(&self) -> Option<Self> {
+        let det = self.det();
+        // Compare to the scale of the transform, as pixels may be tiny in
+        // world units
+        let scale = self
+            .m
+            .iter()
+            .flat_map(|row| row.iter())
+            .fold(0.0f32, |acc, v| acc.max(v.abs()));
+        if det.abs() <= f32::EPSILON * scale * scale {
+            return None;
+        }
+        let m = [
+            [self.m[1][1] / det, -self.m[0][1] / det],
+            [-self.m[1][0] / det, self.m[0][0] / det],
+        ];
+        let c = [
+            -(m[0][0] * self.c[0] + m[0][1] * self.c[1]),
+            -(m[1][0] * self.c[0] + m[1][1] * self.c[1]),
+        ];
+        Some(Affine::new(m, c))
+    }
+
+    /// Compose two transforms: apply `self` then `other`.
+    pub fn then(&self, other: &Affine) -> Self {
+        let (a, b) = (other.m, self.m);
+        Affine {
+            m: [
+                [
+                    a[0][0] * b[0][0] + a[0][1] * b[1][0],
+                    a[0][0] * b[0][1] + a[0][1] * b[1][1],
+                ],
+                [
+                    a[1][0] * b[0][0] + a[1][1] * b[1][0],
+                    a[1][0] * b[0][1] + a[1][1] * b[1][1],
+                ],
+            ],
+            c: [
+                a[0][0] * self.c[0] + a[0][1] * self.c[1] + other.c[0],
+                a[1][0] * self.c[0] + a[1][1] * self.c[1] + other.c[1],
+            ],
+        }
+    }
+}
+
+/// Resample `src` onto a grid of shape `dim`. `out_to_src` maps the pixel
+/// coordinates (x, y) of the output grid to the pixel coordinates of `src`.
+///
+/// Output pixels falling outside of `src` are NaN.
+pub fn resample(
+    src: ArrayView2<f32>,
+    dim: (usize, usize),
+    out_to_src: &Affine,
+    method: Interpolation,
+) -> Array2<f32> {
+    Array2::from_shape_fn(dim, |(i, j)| {
+        let (x, y) = (j as f32, i as f32);
+        match method {
+            Interpolation::Nearest => {
+                let (sx, sy) = out_to_src.apply((x, y));
+                nearest(src, sx, sy)
+            }
+            Interpolation::Bilinear => {
+                let (sx, sy) = out_to_src.apply((x, y));
+                bilinear(src, sx, sy)
+            }
+            Interpolation::FluxConserving => {
+                let corners = [(-0.5, -0.5), (0.5, -0.5), (0.5, 0.5), (-0.5, 0.5)];
+                let polygon: Vec<_> = corners
+                    .iter()
+                    .map(|&(dx, dy)| out_to_src.apply((x + dx, y + dy)))
+                    .collect();
+                overlap_sum(src, &polygon)
+            }
+        }
+    })
+}
+
+fn nearest(src: ArrayView2<f32>, x: f32, y: f32) -> f32 {
+    let (j, i) = (x.round(), y.round());
+    if i < 0.0 || j < 0.0 {
+        return f32::NAN;
+    }
+    src.get((i as usize, j as usize))
+        .cloned()
+        .unwrap_or(f32::NAN)
+}
+
+/// Bilinear interpolation of `src` at pixel coordinates (x, y).
+pub fn bilinear(src: ArrayView2<f32>, x: f32, y: f32) -> f32 {
+    let (h, w) = src.dim();
+    if x < 0.0 || y < 0.0 || x > (w - 1) as f32 || y > (h - 1) as f32 {
+        return f32::NAN;
+    }
+    let (j0, i0) = (x.floor() as usize, y.floor() as usize);
+    let (j1, i1) = ((j0 + 1).min(w - 1), (i0 + 1).min(h - 1));
+    let (fx, fy) = (x - j0 as f32, y - i0 as f32);
+    let top = src[(i0, j0)] * (1.0 - fx) + src[(i0, j1)] * fx;
+    let bottom = src[(i1, j0)] * (1.0 - fx) + src[(i1, j1)] * fx;
+    top * (1.0 - fy) + bottom * fy
+}
+
+/// Sum the pixels of `src` weighted by their overlap area with `polygon`.
+/// Return NaN if the polygon does not overlap with `src` at all.
+fn overlap_sum(src: ArrayView2<f32>, polygon: &[(f32, f32)]) -> f32 {
+    let (h, w) = src.dim();
+    let (mut x_min, mut x_max, mut y_min, mut y_max) = (f32::MAX, f32::MIN, f32::MAX, f32::MIN);
+    for &(x, y) in polygon {
+        x_min = x_min.min(x);
+        x_max = x_max.max(x);
+        y_min = y_min.min(y);
+        y_max = y_max.max(y);
+    }
+    let j_start = (x_min + 0.5).floor().max(0.0) as usize;
+    let i_start = (y_min + 0.5).floor().max(0.0) as usize;
+    let j_end = ((x_max + 0.5).ceil().max(0.0) as usize).min(w);
+    let i_end = ((y_max + 0.5).ceil().max(0.0) as usize).min(h);
+
+    let mut sum = 0.0;
+    let mut covered = false;
+    for i in i_start..i_end {
+        for j in j_start..j_end {
+            let (x, y) = (j as f32, i as f32);
+            let area = clipped_area(polygon, (x - 0.5, y - 0.5), (x + 0.5, y + 0.5));
+            if area > 0.0 {
+                let v = src[(i, j)];
+                if !v.is_nan() {
+                    sum += v * area;
+                    covered = true;
+                }
+            }
+        }
+    }
+    if covered {
+        sum
+    } else {
+        f32::NAN
+    }
+}
+
+/// Area of `polygon` clipped by the axis-aligned box from `min` to `max`
+/// (Sutherland-Hodgman algorithm).
+pub fn clipped_area(polygon: &[(f32, f32)], min: (f32, f32), max: (f32, f32)) -> f32 {
+    fn clip<F, G>(polygon: Vec<(f32, f32)>, inside: F, intersect: G) -> Vec<(f32, f32)>
+    where
+        F: Fn((f32, f32)) -> bool,
+        G: Fn((f32, f32), (f32, f32)) -> (f32, f32),
+    {
+        let mut out = Vec::with_capacity(polygon.len() + 2);
+        for k in 0..polygon.len() {
+            let current = polygon[k];
+            let previous = polygon[(k + polygon.len() - 1) % polygon.len()];
+            match (inside(previous), inside(current)) {
+                (true, true) => out.push(current),
+                (true, false) => out.push(intersect(previous, current)),
+                (false, true) => {
+                    out.push(intersect(previous, current));
+                    out.push(current);
+                }
+                (false, false) => (),
+            }
+        }
+        out
+    }
+    let at_x = |x: f32| {
+        move |(x0, y0): (f32, f32), (x1, y1): (f32, f32)| (x, y0 + (y1 - y0) * (x - x0) / (x1 - x0))
+    };
+    let at_y = |y: f32| {
+        move |(x0, y0): (f32, f32), (x1, y1): (f32, f32)| (x0 + (x1 - x0) * (y - y0) / (y1 - y0), y)
+    };
+
+    let mut clipped = polygon.to_vec();
+    clipped = clip(clipped, |(x, _)| x >= min.0, at_x(min.0));
+    clipped = clip(clipped, |(x, _)| x <= max.0, at_x(max.0));
+    clipped = clip(clipped, |(_, y)| y >= min.1, at_y(min.1));
+    clipped = clip(clipped, |(_, y)| y <= max.1, at_y(max.1));
+    polygon_area(&clipped)
+}
+
+/// Area of a simple polygon (shoelace formula).
+pub fn polygon_area(polygon: &[(f32, f32)]) -> f32 {
+    let n = polygon.len();
+    let mut area = 0.0;
+    for k in 0..n {
+        let (x0, y0) = polygon[k];
+        let (x1, y1) = polygon[(k + 1) % n];
+        area += x0 * y1 - x1 * y0;
+    }
+    (area / 2.0).abs()
+}
+
+/// Estimate the shift (dx, dy) to apply to `image` so that it matches
+/// `reference`, by maximizing their cross-correlation over integer shifts up to
+/// `max_shift` pixels. The peak is then refined to sub-pixel accuracy by
+/// fitting a parabola along each axis.
+pub fn estimate_shift(
+    reference: ArrayView2<f32>,
+    image: ArrayView2<f32>,
+    max_shift: usize,
+) -> (f32, f32) {
+    let mean = |a: ArrayView2<f32>| {
+        let (sum, n) = a
+            .iter()
+            .filter(|v| v.is_finite())
+            .fold((0.0, 0), |(sum, n), v| (sum + v, n + 1));
+        if n == 0 {
+            0.0
+        } else {
+            sum / n as f32
+        }
+    };
+    let (ref_mean, img_mean) = (mean(reference), mean(image));
+    let (h, w) = reference.dim();
+    let max_shift = max_shift as isize;
+
+    // Correlation of reference(x, y) with image(x - dx, y - dy)
+    let correlation = |dx: isize, dy: isize| {
+        let mut sum = 0.0;
+        for i in 0..h as isize {
+            for j in 0..w as isize {
+                let (si, sj) = (i - dy, j - dx);
+                if si < 0 || sj < 0 {
+                    continue;
+                }
+                if let Some(v) = image.get((si as usize, sj as usize)) {
+                    let r = reference[(i as usize, j as usize)];
+                    if r.is_finite() && v.is_finite() {
+                        sum += (r - ref_mean) * (v - img_mean);
+                    }
+                }
+            }
+        }
+        sum
+    };
+
+    let size = (2 * max_shift + 1) as usize;
+    let mut grid = Array2::zeros((size, size));
+    for ((i, j), c) in grid.indexed_iter_mut() {
+        *c = correlation(j as isize - max_shift, i as isize - max_shift);
+    }
+    let ((pi, pj), _) =
+        grid.indexed_iter().fold(
+            ((0, 0), f32::MIN),
+            |best, (idx, &c)| {
+                if c > best.1 {
+                    (idx, c)
+                } else {
+                    best
+                }
+            },
+        );
+
+    let refine = |c_minus: f32, c0: f32, c_plus: f32| {
+        let denom = c_minus - 2.0 * c0 + c_plus;
+        if denom.abs() > f32::EPSILON {
+            0.5 * (c_minus - c_plus) / denom
+        } else {
+            0.0
+        }
+    };
+    let sub_x = if pj > 0 && pj + 1 < size {
+        refine(grid[(pi, pj - 1)], grid[(pi, pj)], grid[(pi, pj + 1)])
+    } else {
+        0.0
+    };
+    let sub_y = if pi > 0 && pi + 1 < size {
+        refine(grid[(pi - 1, pj)], grid[(pi, pj)], grid[(pi + 1, pj)])
+    } else {
+        0.0
+    };
+    (
+        (pj as isize - max_shift) as f32 + sub_x,
+        (pi as isize - max_shift) as f32 + sub_y,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::{estimate_shift, resample, Affine, Interpolation};
+    use ndarray::Array2;
+
+    #[test]
+    fn flux_is_conserved_on_downsampling() {
+        let src = Array2::from_elem((8, 8), 1.0);
+        // Output pixels are twice as big as input pixels
+        let out_to_src = Affine {
+            m: [[2.0, 0.0], [0.0, 2.0]],
+            c: [0.5, 0.5],
+        };
+        let out = resample(
+            src.view(),
+            (4, 4),
+            &out_to_src,
+            Interpolation::FluxConserving,
+        );
+        assert!((out.scalar_sum() - src.scalar_sum()).abs() < 1e-3);
+    }
+
+    #[test]
+    fn affine_inverse() {
+        let a = Affine {
+            m: [[2.0, 1.0], [0.5, 3.0]],
+            c: [1.0, -2.0],
+        };
+        let p = a.then(&a.inverse().unwrap()).apply((3.0, 4.0));
+        assert!((p.0 - 3.0).abs() < 1e-4 && (p.1 - 4.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn recover_shift() {
+        let reference = Array2::from_shape_fn((20, 20), |(i, j)| {
+            let (x, y) = (j as f32 - 10.0, i as f32 - 9.0);
+            (-(x * x + y * y) / 8.0).exp()
+        });
+        let image = Array2::from_shape_fn((20, 20), |(i, j)| {
+            let (x, y) = (j as f32 - 8.0, i as f32 - 10.0);
+            (-(x * x + y * y) / 8.0).exp()
+        });
+        let (dx, dy) = estimate_shift(reference.view(), image.view(), 4);
+        assert!((dx - 2.0).abs() < 0.2, "dx = {}", dx);
+        assert!((dy + 1.0).abs() < 0.2, "dy = {}", dy);
+    }
+}
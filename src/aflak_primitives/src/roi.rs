@@ -1,69 +1,387 @@
-use ndarray::ArrayView2;
+use ndarray::{Array2, ArrayView2, Zip};
+
+use photometry;
+use reproject;
 
 /// A region of interest in a 2D image.
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+///
+/// Pixels are designated by their (x, y) image coordinates, where x is the
+/// column and y is the row of the image, both starting from 0. The pixel
+/// (x, y) corresponds to the element `[y, x]` of an array.
+///
+/// Analytic shapes are defined in the continuous plane, where the pixel
+/// (x, y) covers the square from (x, y) to (x + 1, y + 1). Each pixel is
+/// weighted by its fractional overlap with the shape.
+///
+/// Pixel lists are saved as `Pixels`. Pixel lists saved as `PixelList` by
+/// earlier versions counted rows from the bottom of the image, and are
+/// converted when they are loaded (see [`DeserROI`]).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(from = "DeserROI")]
 pub enum ROI {
     /// The whole image is a region of interest.
     All,
     /// The list of pixels selected by this region of interest.
+    #[serde(rename = "Pixels")]
     PixelList(Vec<(usize, usize)>),
+    Circle {
+        center: (f32, f32),
+        radius: f32,
+    },
+    Ellipse {
+        center: (f32, f32),
+        /// Semi-axis along the direction `angle`
+        a: f32,
+        b: f32,
+        /// Angle in degrees, counter-clockwise from the x axis
+        angle: f32,
+    },
+    /// Axis-aligned rectangle
+    Rect {
+        min: (f32, f32),
+        max: (f32, f32),
+    },
+    /// A simple polygon, given by its vertices.
+    Polygon(Vec<(f32, f32)>),
+    Annulus {
+        center: (f32, f32),
+        r_in: f32,
+        r_out: f32,
+    },
+    Union(Box<ROI>, Box<ROI>),
+    Intersection(Box<ROI>, Box<ROI>),
+    Difference(Box<ROI>, Box<ROI>),
+}
+
+/// Last row of the image in the pixel lists saved by earlier versions.
+///
+/// These versions read the pixel (x, y) of a pixel list at the array index
+/// `[73 - y, x]` whatever the height of the image.
+const LEGACY_LAST_ROW: usize = 73;
+
+/// A [`ROI`] as it is saved, or as it was saved by earlier versions.
+#[derive(Deserialize)]
+enum DeserROI {
+    All,
+    /// Pixel list of earlier versions, whose rows are counted from
+    /// [`LEGACY_LAST_ROW`] upwards.
+    PixelList(Vec<(usize, usize)>),
+    Pixels(Vec<(usize, usize)>),
+    Circle {
+        center: (f32, f32),
+        radius: f32,
+    },
+    Ellipse {
+        center: (f32, f32),
+        a: f32,
+        b: f32,
+        angle: f32,
+    },
+    Rect {
+        min: (f32, f32),
+        max: (f32, f32),
+    },
+    Polygon(Vec<(f32, f32)>),
+    Annulus {
+        center: (f32, f32),
+        r_in: f32,
+        r_out: f32,
+    },
+    Union(Box<ROI>, Box<ROI>),
+    Intersection(Box<ROI>, Box<ROI>),
+    Difference(Box<ROI>, Box<ROI>),
+}
+
+impl From<DeserROI> for ROI {
+    fn from(roi: DeserROI) -> Self {
+        match roi {
+            DeserROI::All => ROI::All,
+            DeserROI::PixelList(pixels) => ROI::PixelList(
+                pixels
+                    .into_iter()
+                    .filter(|&(_, y)| y <= LEGACY_LAST_ROW)
+                    .map(|(x, y)| (x, LEGACY_LAST_ROW - y))
+                    .collect(),
+            ),
+            DeserROI::Pixels(pixels) => ROI::PixelList(pixels),
+            DeserROI::Circle { center, radius } => ROI::Circle { center, radius },
+            DeserROI::Ellipse {
+                center,
+                a,
+                b,
+                angle,
+            } => ROI::Ellipse {
+                center,
+                a,
+                b,
+                angle,
+            },
+            DeserROI::Rect { min, max } => ROI::Rect { min, max },
+            DeserROI::Polygon(vertices) => ROI::Polygon(vertices),
+            DeserROI::Annulus {
+                center,
+                r_in,
+                r_out,
+            } => ROI::Annulus {
+                center,
+                r_in,
+                r_out,
+            },
+            DeserROI::Union(a, b) => ROI::Union(a, b),
+            DeserROI::Intersection(a, b) => ROI::Intersection(a, b),
+            DeserROI::Difference(a, b) => ROI::Difference(a, b),
+        }
+    }
 }
 
 impl ROI {
-    /// Get the value of each point of the 2D image in the region of interest,
-    /// along with the original coordinate of each selected pixel.
-    pub fn filter(&self, data: ArrayView2<f32>) -> Vec<((usize, usize), f32)> {
+    /// Compute the weight of each pixel of an image of dimension `dim`.
+    ///
+    /// The output array has the same dimension and indexing as the image.
+    /// Weights are between 0 and 1. For boolean combinations, the weights of
+    /// the combined regions are combined as fuzzy sets (maximum for an union,
+    /// minimum for an intersection), which is exact except on pixels on the
+    /// edges of both regions.
+    pub fn rasterize(&self, dim: (usize, usize)) -> Array2<f32> {
+        // Make weight map from the weight of each pixel (x, y)
+        let from_fn = |weight: &dyn Fn(f32, f32) -> f32| {
+            Array2::from_shape_fn(dim, |(i, j)| weight(j as f32, i as f32))
+        };
         match *self {
-            ROI::All => {
-                let mut out = Vec::with_capacity(data.len());
-                let size = data.dim();
-                for j in 0..size.1 {
-                    for i in 0..size.0 {
-                        out.push(((i, j), *data.get([i, j]).unwrap()));
+            ROI::All => Array2::ones(dim),
+            ROI::PixelList(ref pixels) => {
+                let mut out = Array2::zeros(dim);
+                for &(x, y) in pixels {
+                    if let Some(w) = out.get_mut([y, x]) {
+                        *w = 1.0;
                     }
                 }
                 out
             }
-            ROI::PixelList(ref pixels) => {
-                let mut out = Vec::with_capacity(pixels.len());
-                for &(i, j) in pixels {
-                    if let Some(val) = data.get([i, j]) {
-                        out.push(((i, j), *val));
-                    }
+            ROI::Circle { center, radius } => {
+                let circle = ellipse(center, radius, radius, 0.0);
+                from_fn(&|x, y| circle.pixel_overlap(x, y))
+            }
+            ROI::Ellipse {
+                center,
+                a,
+                b,
+                angle,
+            } => {
+                let ellipse = ellipse(center, a, b, angle);
+                from_fn(&|x, y| ellipse.pixel_overlap(x, y))
+            }
+            ROI::Rect { min, max } => {
+                let overlap = |lo: f32, hi: f32, p: f32| (hi.min(p + 1.0) - lo.max(p)).max(0.0);
+                from_fn(&|x, y| overlap(min.0, max.0, x) * overlap(min.1, max.1, y))
+            }
+            ROI::Polygon(ref vertices) => {
+                let (mut x_min, mut x_max) = (::std::f32::MAX, ::std::f32::MIN);
+                let (mut y_min, mut y_max) = (::std::f32::MAX, ::std::f32::MIN);
+                for &(x, y) in vertices {
+                    x_min = x_min.min(x);
+                    x_max = x_max.max(x);
+                    y_min = y_min.min(y);
+                    y_max = y_max.max(y);
                 }
-                out
+                from_fn(&|x, y| {
+                    if x + 1.0 < x_min || x > x_max || y + 1.0 < y_min || y > y_max {
+                        0.0
+                    } else {
+                        reproject::clipped_area(vertices, (x, y), (x + 1.0, y + 1.0))
+                    }
+                })
             }
+            ROI::Annulus {
+                center,
+                r_in,
+                r_out,
+            } => {
+                let inner = ellipse(center, r_in, r_in, 0.0);
+                let outer = ellipse(center, r_out, r_out, 0.0);
+                from_fn(&|x, y| (outer.pixel_overlap(x, y) - inner.pixel_overlap(x, y)).max(0.0))
+            }
+            ROI::Union(ref a, ref b) => combine(a, b, dim, f32::max),
+            ROI::Intersection(ref a, ref b) => combine(a, b, dim, f32::min),
+            ROI::Difference(ref a, ref b) => combine(a, b, dim, |a, b| (a - b).max(0.0)),
         }
     }
 
-    pub fn filterx(&self, data: ArrayView2<f32>) -> Vec<((usize, usize), f32)> {
+    /// Get the pixels of an image of dimension `dim` in the region of
+    /// interest, as array indices, along with their weight.
+    ///
+    /// For a pixel list, each pixel is returned in the order of the list.
+    pub fn weighted_pixels(&self, dim: (usize, usize)) -> Vec<((usize, usize), f32)> {
+        match *self {
+            ROI::PixelList(ref pixels) => pixels
+                .iter()
+                .filter(|&&(x, y)| x < dim.1 && y < dim.0)
+                .map(|&(x, y)| ((y, x), 1.0))
+                .collect(),
+            _ => self
+                .rasterize(dim)
+                .indexed_iter()
+                .filter(|&(_, &w)| w > 0.0)
+                .map(|(idx, &w)| (idx, w))
+                .collect(),
+        }
+    }
+
+    /// Get the value of each point of the 2D image in the region of interest,
+    /// along with the array index `[row, column]` of each selected pixel.
+    ///
+    /// Pixels are selected as in [`ROI::filterx`].
+    pub fn filter(&self, data: ArrayView2<f32>) -> Vec<((usize, usize), f32)> {
+        self.filterx(data)
+            .into_iter()
+            .map(|((x, y), val)| ((y, x), val))
+            .collect()
+    }
+
+    /// Get the pixels of an image of dimension `dim` in the region of
+    /// interest, as array indices, in the order of [`ROI::filterx`].
+    ///
+    /// For analytic shapes, pixels are selected if at least half of their
+    /// surface is covered by the shape.
+    pub fn pixels(&self, dim: (usize, usize)) -> Vec<(usize, usize)> {
         match *self {
             ROI::All => {
-                let mut out = Vec::with_capacity(data.len());
-                let size = data.dim();
-                for j in 0..size.1 {
-                    for i in 0..size.0 {
-                        out.push(((i, j), *data.get([i, j]).unwrap()));
-                    }
-                }
-                out
-            }
-            ROI::PixelList(ref pixels) => {
-                let mut out = Vec::with_capacity(pixels.len());
-                for &(i, j) in pixels {
-                    if let Some(val) = data.get([73 - j, i]) {
-                        out.push(((i, j), *val));
+                let mut out = Vec::with_capacity(dim.0 * dim.1);
+                for j in 0..dim.1 {
+                    for i in 0..dim.0 {
+                        out.push((i, j));
                     }
                 }
                 out
             }
+            ROI::PixelList(ref pixels) => pixels
+                .iter()
+                .filter(|&&(x, y)| x < dim.1 && y < dim.0)
+                .map(|&(x, y)| (y, x))
+                .collect(),
+            _ => self
+                .weighted_pixels(dim)
+                .into_iter()
+                .filter(|&(_, w)| w >= 0.5)
+                .map(|(idx, _)| idx)
+                .collect(),
         }
     }
 
+    /// Get the value of each point of the 2D image in the region of interest,
+    /// along with the (x, y) coordinate of each selected pixel.
+    ///
+    /// Pixels are selected as in [`ROI::pixels`].
+    pub fn filterx(&self, data: ArrayView2<f32>) -> Vec<((usize, usize), f32)> {
+        self.pixels(data.dim())
+            .into_iter()
+            .map(|(i, j)| ((j, i), data[(i, j)]))
+            .collect()
+    }
+
+    /// Number of pixels of a pixel list. Other regions do not have a fixed
+    /// number of pixels, as it depends on the image, and have a length of 0.
     pub fn datalen(&self) -> usize {
         match *self {
-            ROI::All => 0,
             ROI::PixelList(ref pixels) => pixels.len(),
+            _ => 0,
         }
     }
 }
+
+/// Make an ellipse in the pixel coordinates used by `photometry`, where the
+/// pixel (x, y) is centered on (x, y).
+fn ellipse(center: (f32, f32), a: f32, b: f32, angle: f32) -> photometry::Ellipse {
+    let (a, b, angle) = if a >= b {
+        (a, b, angle)
+    } else {
+        (b, a, angle + 90.0)
+    };
+    photometry::Ellipse {
+        x: center.0 - 0.5,
+        y: center.1 - 0.5,
+        a,
+        b,
+        theta: angle.to_radians(),
+    }
+}
+
+fn combine<F>(a: &ROI, b: &ROI, dim: (usize, usize), f: F) -> Array2<f32>
+where
+    F: Fn(f32, f32) -> f32,
+{
+    let mut out = a.rasterize(dim);
+    Zip::from(&mut out)
+        .and(&b.rasterize(dim))
+        .apply(|a, &b| *a = f(*a, b));
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::ROI;
+    use ndarray::Array2;
+    use ron::{de, ser};
+
+    #[test]
+    fn pixel_lists_of_earlier_versions_are_flipped() {
+        let roi: ROI = de::from_str("PixelList([(1, 0), (2, 73), (3, 80)])").unwrap();
+        assert_eq!(roi, ROI::PixelList(vec![(1, 73), (2, 0)]));
+
+        let saved = ser::to_string(&roi).unwrap();
+        assert_eq!(de::from_str::<ROI>(&saved).unwrap(), roi);
+        let roi = ROI::Union(Box::new(roi), Box::new(ROI::All));
+        let saved = ser::to_string(&roi).unwrap();
+        assert_eq!(de::from_str::<ROI>(&saved).unwrap(), roi);
+    }
+
+    #[test]
+    fn filterx_uses_image_coordinates() {
+        let data = Array2::from_shape_fn((3, 4), |(i, j)| (i * 10 + j) as f32);
+        let roi = ROI::PixelList(vec![(1, 0), (3, 2), (0, 3)]);
+        assert_eq!(
+            roi.filterx(data.view()),
+            vec![((1, 0), 1.0), ((3, 2), 23.0)]
+        );
+        assert_eq!(roi.filter(data.view()), vec![((0, 1), 1.0), ((2, 3), 23.0)]);
+        assert_eq!(roi.datalen(), 3);
+        assert_eq!(ROI::All.filterx(data.view())[1], ((0, 1), 10.0));
+    }
+
+    #[test]
+    fn annulus_is_difference_of_circles() {
+        let center = (10.3, 9.7);
+        let annulus = ROI::Annulus {
+            center,
+            r_in: 2.5,
+            r_out: 6.0,
+        };
+        let difference = ROI::Difference(
+            Box::new(ROI::Circle {
+                center,
+                radius: 6.0,
+            }),
+            Box::new(ROI::Circle {
+                center,
+                radius: 2.5,
+            }),
+        );
+        let weights = annulus.rasterize((20, 20));
+        assert_eq!(weights, difference.rasterize((20, 20)));
+        let area = ::std::f32::consts::PI * (6.0 * 6.0 - 2.5 * 2.5);
+        assert!((weights.sum() - area).abs() < 1e-3);
+    }
+
+    #[test]
+    fn polygon_and_rect_weights() {
+        let rect = ROI::Rect {
+            min: (0.5, 0.0),
+            max: (2.0, 1.0),
+        };
+        let polygon = ROI::Polygon(vec![(0.5, 0.0), (2.0, 0.0), (2.0, 1.0), (0.5, 1.0)]);
+        let weights = rect.rasterize((2, 3));
+        assert_eq!(weights.row(0).to_vec(), vec![0.5, 1.0, 0.0]);
+        assert_eq!(weights.row(1).to_vec(), vec![0.0, 0.0, 0.0]);
+        assert_eq!(weights, polygon.rasterize((2, 3)));
+    }
+}
@@ -0,0 +1,184 @@
+//! Sampling of 3D datasets along arbitrary planes and paths.
+//!
+//! Positions are fractional array indices: the value of a pixel is found at
+//! its integer index, and values in-between are interpolated.
+use ndarray::ArrayView3;
+
+use roi::ROI;
+
+/// Interpolate the value of `cube` at the fractional index `pos`, with
+/// trilinear interpolation. Return NaN if `pos` is out of the cube.
+pub fn trilinear(cube: &ArrayView3<f32>, pos: [f32; 3]) -> f32 {
+    let dim = cube.dim();
+    let dim = [dim.0, dim.1, dim.2];
+    let mut lo = [0; 3];
+    let mut frac = [0.0; 3];
+    for k in 0..3 {
+        let p = pos[k];
+        if dim[k] == 0 || !(p >= 0.0 && p <= (dim[k] - 1) as f32) {
+            return ::std::f32::NAN;
+        }
+        // Stay inside the cube when p is on its last index
+        lo[k] = (p.floor() as usize).min(dim[k].saturating_sub(2));
+        frac[k] = p - lo[k] as f32;
+    }
+
+    let mut out = 0.0;
+    for corner in 0..8 {
+        let mut idx = lo;
+        let mut weight = 1.0;
+        for k in 0..3 {
+            if corner & (1 << k) == 0 {
+                weight *= 1.0 - frac[k];
+            } else {
+                idx[k] += 1;
+                weight *= frac[k];
+            }
+        }
+        if weight > 0.0 {
+            out += weight * cube[idx];
+        }
+    }
+    out
+}
+
+/// Get the vertices of the path defined by a region of interest, as
+/// fractional (x, y) pixel positions.
+///
+/// A pixel list, such as the pixels drawn with the `Line` interaction, is a
+/// path going through each of its pixels in order. Its vertices are reduced
+/// to the corners of the path. A polygon is the path along its edges.
+/// Other regions of interest do not define a path.
+pub fn path_vertices(roi: &ROI) -> Option<Vec<(f32, f32)>> {
+    match *roi {
+        ROI::PixelList(ref pixels) => {
+            let points: Vec<_> = pixels.iter().map(|&(x, y)| (x as f32, y as f32)).collect();
+            // Pixels of a rasterized straight line are less than half a
+            // pixel away from it.
+            Some(simplify(&points, 0.75))
+        }
+        // Vertices of a polygon are pixel corners, while positions along a
+        // path are pixel centers.
+        ROI::Polygon(ref vertices) => {
+            Some(vertices.iter().map(|&(x, y)| (x - 0.5, y - 0.5)).collect())
+        }
+        _ => None,
+    }
+}
+
+/// A point on a path, with the unit vector normal to the path at this point.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PathPoint {
+    pub position: (f32, f32),
+    pub normal: (f32, f32),
+}
+
+/// Sample the polyline going through `vertices` every `step` pixels, starting
+/// from its first vertex.
+pub fn sample_path(vertices: &[(f32, f32)], step: f32) -> Vec<PathPoint> {
+    let mut out = vec![];
+    // Distance along the current segment of the next sample
+    let mut next = 0.0;
+    for segment in vertices.windows(2) {
+        let (x0, y0) = segment[0];
+        let (x1, y1) = segment[1];
+        let (dx, dy) = (x1 - x0, y1 - y0);
+        let length = (dx * dx + dy * dy).sqrt();
+        if length == 0.0 {
+            continue;
+        }
+        let (ux, uy) = (dx / length, dy / length);
+        while next <= length {
+            out.push(PathPoint {
+                position: (x0 + next * ux, y0 + next * uy),
+                normal: (-uy, ux),
+            });
+            next += step;
+        }
+        next -= length;
+    }
+    if out.is_empty() {
+        if let Some(&position) = vertices.first() {
+            out.push(PathPoint {
+                position,
+                normal: (0.0, 1.0),
+            });
+        }
+    }
+    out
+}
+
+/// Offsets of the samples across a slit of width `width` pixels, centered on
+/// 0 and about one pixel apart.
+pub fn slit_offsets(width: f32) -> Vec<f32> {
+    let n = width.ceil().max(1.0) as usize;
+    let spacing = width / n as f32;
+    (0..n)
+        .map(|k| (k as f32 - (n - 1) as f32 / 2.0) * spacing)
+        .collect()
+}
+
+/// Simplify a polyline with the Ramer-Douglas-Peucker algorithm, keeping
+/// the vertices more than `tolerance` away from the simplified line.
+fn simplify(points: &[(f32, f32)], tolerance: f32) -> Vec<(f32, f32)> {
+    if points.len() < 3 {
+        return points.to_vec();
+    }
+    let (x0, y0) = points[0];
+    let (x1, y1) = points[points.len() - 1];
+    let (dx, dy) = (x1 - x0, y1 - y0);
+    let length = (dx * dx + dy * dy).sqrt();
+    let distance = |&(x, y): &(f32, f32)| {
+        if length == 0.0 {
+            ((x - x0).powi(2) + (y - y0).powi(2)).sqrt()
+        } else {
+            ((x - x0) * dy - (y - y0) * dx).abs() / length
+        }
+    };
+    let (farthest, max_distance) = points[1..points.len() - 1]
+        .iter()
+        .map(distance)
+        .enumerate()
+        .fold((0, 0.0), |(i_max, d_max), (i, d)| {
+            if d > d_max {
+                (i + 1, d)
+            } else {
+                (i_max, d_max)
+            }
+        });
+    if max_distance > tolerance {
+        let mut out = simplify(&points[..=farthest], tolerance);
+        out.pop();
+        out.extend(simplify(&points[farthest..], tolerance));
+        out
+    } else {
+        vec![points[0], points[points.len() - 1]]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{path_vertices, sample_path, trilinear};
+    use ndarray::Array3;
+    use roi::ROI;
+
+    #[test]
+    fn trilinear_interpolation() {
+        let cube = Array3::from_shape_fn((2, 3, 4), |(k, j, i)| (k * 100 + j * 10 + i) as f32);
+        let cube = cube.view();
+        assert_eq!(trilinear(&cube, [1.0, 2.0, 3.0]), 123.0);
+        assert!((trilinear(&cube, [0.5, 1.5, 2.25]) - 67.25).abs() < 1e-4);
+        assert!(trilinear(&cube, [0.0, 0.0, 3.5]).is_nan());
+    }
+
+    #[test]
+    fn line_pixels_make_straight_path() {
+        let pixels = (0..9).map(|x| (x, x / 2)).collect();
+        let vertices = path_vertices(&ROI::PixelList(pixels)).unwrap();
+        assert_eq!(vertices, vec![(0.0, 0.0), (8.0, 4.0)]);
+        let points = sample_path(&[(0.0, 0.0), (3.0, 0.0), (3.0, 2.0)], 1.0);
+        assert_eq!(points.len(), 6);
+        assert_eq!(points[4].position, (3.0, 1.0));
+        assert_eq!(points[4].normal, (-1.0, 0.0));
+    }
+}
@@ -0,0 +1,225 @@
+//! Robust statistics on sets of values and background estimation on 2D
+//! images.
+use std::cmp::Ordering;
+use std::f32;
+
+use ndarray::{Array2, ArrayView2};
+
+use reproject;
+
+/// Summary statistics of a set of values. NaN values are ignored.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Statistics {
+    pub mean: f32,
+    pub median: f32,
+    pub std_dev: f32,
+    /// Median absolute deviation
+    pub mad: f32,
+    pub nan_count: usize,
+}
+
+impl Statistics {
+    /// Compute statistics. `values` is sorted in place.
+    pub fn new(values: &mut Vec<f32>) -> Self {
+        let len_with_nan = values.len();
+        values.retain(|v| !v.is_nan());
+        let nan_count = len_with_nan - values.len();
+        sort(values);
+
+        let median = percentile_sorted(values, 50.0);
+        let mut deviations: Vec<_> = values.iter().map(|v| (v - median).abs()).collect();
+        sort(&mut deviations);
+        Statistics {
+            mean: mean(values),
+            median,
+            std_dev: std_dev(values),
+            mad: percentile_sorted(&deviations, 50.0),
+            nan_count,
+        }
+    }
+}
+
+fn sort(values: &mut [f32]) {
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+}
+
+/// Mean of the values. NaN if there is no value.
+pub fn mean(values: &[f32]) -> f32 {
+    values.iter().sum::<f32>() / values.len() as f32
+}
+
+/// Population standard deviation of the values. NaN if there is no value.
+pub fn std_dev(values: &[f32]) -> f32 {
+    let mean = mean(values);
+    let var = values.iter().map(|v| (v - mean) * (v - mean)).sum::<f32>() / values.len() as f32;
+    var.sqrt()
+}
+
+/// Percentile `q` (between 0 and 100) of *sorted* values, linearly
+/// interpolated between the closest ranks. NaN if there is no value.
+pub fn percentile_sorted(sorted: &[f32], q: f32) -> f32 {
+    if sorted.is_empty() {
+        return f32::NAN;
+    }
+    let rank = (q / 100.0).max(0.0).min(1.0) * (sorted.len() - 1) as f32;
+    let (lo, hi) = (rank.floor() as usize, rank.ceil() as usize);
+    let frac = rank - lo as f32;
+    sorted[lo] * (1.0 - frac) + sorted[hi] * frac
+}
+
+/// Iteratively reject the values further than `sigma` standard deviations
+/// from the median, until no value is rejected or `iterations` is reached.
+///
+/// NaN values are always rejected. Return the remaining values.
+pub fn sigma_clip(values: &[f32], sigma: f32, iterations: usize) -> Vec<f32> {
+    let mut kept: Vec<_> = values.iter().cloned().filter(|v| !v.is_nan()).collect();
+    for _ in 0..iterations {
+        if kept.is_empty() {
+            break;
+        }
+        sort(&mut kept);
+        let median = percentile_sorted(&kept, 50.0);
+        let std = std_dev(&kept);
+        let before = kept.len();
+        kept.retain(|v| (v - median).abs() <= sigma * std);
+        if kept.len() == before {
+            break;
+        }
+    }
+    kept
+}
+
+/// Parameters for [`background_2d`].
+#[derive(Copy, Clone, Debug)]
+pub struct BackgroundParams {
+    /// Size of the boxes of the background mesh, in pixels
+    pub box_size: usize,
+    /// Size of the median filter applied to the mesh, in boxes
+    pub filter_size: usize,
+    /// Sigma used for sigma-clipping the pixels of each box
+    pub sigma: f32,
+    /// Boxes with a larger fraction of NaN pixels are excluded from the mesh
+    pub exclude_fraction: f32,
+}
+
+/// Estimate a smooth background and its RMS on a 2D image.
+///
+/// The image is divided in boxes of `box_size` pixels. The background and RMS
+/// of each box are the sigma-clipped median and standard deviation of its
+/// pixels. The resulting low-resolution mesh is median-filtered, then
+/// bilinearly interpolated back to full resolution.
+///
+/// Return `None` if no box can be used to estimate the background.
+pub fn background_2d(
+    image: ArrayView2<f32>,
+    params: BackgroundParams,
+) -> Option<(Array2<f32>, Array2<f32>)> {
+    let (h, w) = image.dim();
+    let size = params.box_size.max(1);
+    let mesh_dim = ((h + size - 1) / size, (w + size - 1) / size);
+
+    let mut bkg_mesh = Array2::from_elem(mesh_dim, f32::NAN);
+    let mut rms_mesh = Array2::from_elem(mesh_dim, f32::NAN);
+    for ((mi, mj), bkg) in bkg_mesh.indexed_iter_mut() {
+        let box_view = image.slice(s![
+            mi * size..((mi + 1) * size).min(h),
+            mj * size..((mj + 1) * size).min(w)
+        ]);
+        let values: Vec<_> = box_view.iter().cloned().collect();
+        let nan_count = values.iter().filter(|v| v.is_nan()).count();
+        if nan_count as f32 > params.exclude_fraction * values.len() as f32 {
+            continue;
+        }
+        let mut clipped = sigma_clip(&values, params.sigma, 10);
+        if clipped.is_empty() {
+            continue;
+        }
+        sort(&mut clipped);
+        *bkg = percentile_sorted(&clipped, 50.0);
+        rms_mesh[(mi, mj)] = std_dev(&clipped);
+    }
+
+    if bkg_mesh.iter().all(|v| v.is_nan()) {
+        return None;
+    }
+    let bkg_mesh = median_filter(fill_nan(bkg_mesh).view(), params.filter_size);
+    let rms_mesh = median_filter(fill_nan(rms_mesh).view(), params.filter_size);
+
+    // Interpolate from the center of each box
+    let to_mesh = |(i, j): (usize, usize)| {
+        let x = (j as f32 + 0.5) / size as f32 - 0.5;
+        let y = (i as f32 + 0.5) / size as f32 - 0.5;
+        let x = x.max(0.0).min((mesh_dim.1 - 1) as f32);
+        let y = y.max(0.0).min((mesh_dim.0 - 1) as f32);
+        (x, y)
+    };
+    let bkg = Array2::from_shape_fn((h, w), |idx| {
+        let (x, y) = to_mesh(idx);
+        reproject::bilinear(bkg_mesh.view(), x, y)
+    });
+    let rms = Array2::from_shape_fn((h, w), |idx| {
+        let (x, y) = to_mesh(idx);
+        reproject::bilinear(rms_mesh.view(), x, y)
+    });
+    Some((bkg, rms))
+}
+
+/// Replace NaN values of a mesh by the median of the valid values.
+fn fill_nan(mut mesh: Array2<f32>) -> Array2<f32> {
+    let mut valid: Vec<_> = mesh.iter().cloned().filter(|v| !v.is_nan()).collect();
+    sort(&mut valid);
+    let median = percentile_sorted(&valid, 50.0);
+    for v in mesh.iter_mut() {
+        if v.is_nan() {
+            *v = median;
+        }
+    }
+    mesh
+}
+
+/// Median filter with a square window of `size` pixels.
+fn median_filter(data: ArrayView2<f32>, size: usize) -> Array2<f32> {
+    if size <= 1 {
+        return data.to_owned();
+    }
+    let (h, w) = data.dim();
+    let half = size / 2;
+    Array2::from_shape_fn((h, w), |(i, j)| {
+        let window = data.slice(s![
+            i.saturating_sub(half)..(i + half + 1).min(h),
+            j.saturating_sub(half)..(j + half + 1).min(w)
+        ]);
+        let mut values: Vec<_> = window.iter().cloned().filter(|v| !v.is_nan()).collect();
+        sort(&mut values);
+        percentile_sorted(&values, 50.0)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{percentile_sorted, sigma_clip, Statistics};
+
+    #[test]
+    fn statistics_ignore_nan() {
+        let mut values = vec![1.0, 2.0, ::std::f32::NAN, 3.0, 4.0];
+        let stats = Statistics::new(&mut values);
+        assert_eq!(stats.nan_count, 1);
+        assert_eq!(stats.mean, 2.5);
+        assert_eq!(stats.median, 2.5);
+        assert_eq!(stats.mad, 1.0);
+    }
+
+    #[test]
+    fn percentile_interpolates() {
+        let values = [0.0, 10.0];
+        assert_eq!(percentile_sorted(&values, 25.0), 2.5);
+    }
+
+    #[test]
+    fn sigma_clip_rejects_outliers() {
+        let mut values = vec![1.0; 20];
+        values.push(100.0);
+        let clipped = sigma_clip(&values, 3.0, 5);
+        assert_eq!(clipped.len(), 20);
+    }
+}
@@ -1,9 +1,15 @@
+use std::ops::Range;
+use std::sync::Arc;
 use std::{fmt, ops};
 
 use fitrs::{FitsData, Hdu, HeaderValue, WCS};
-use ndarray::{ArrayD, ArrayView1, ArrayView2, IxDyn};
+use ndarray::{ArrayD, ArrayView3, ArrayViewD, Axis as ArrayAxis, Ix3, IxDyn, Slice};
+use serde::{Serialize, Serializer};
 
-use fits::{FitsArrayReadError, FitsDataToArray};
+use cake::Bow;
+use fits::{self, FitsArrayReadError, FitsDataToArray};
+use lazy::MappedArray;
+use IOErr;
 
 /// A unit of measurement.
 ///
@@ -55,10 +61,438 @@ pub struct Dimensioned<V> {
 /// This is the main data structure used by `aflak_primitives` to represent
 /// multi-dimensional astrophysical data.
 ///
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(from = "WcsArrayData")]
 pub struct WcsArray {
+    meta: Option<MetaWcsArray>,
+    array: Dimensioned<Values>,
+    /// Variance of each value of `array`, if known, as f32
+    variance: Option<Values>,
+    /// Values of `array` flagged as bad (`true`), if any
+    mask: Option<ArrayD<bool>>,
+    /// Additional FITS header keywords, as (key, value) pairs. Values are
+    /// written as in a FITS header card, e.g. `'NGC 253'`, `1200.5` or `T`.
+    keywords: Vec<(String, String)>,
+}
+
+/// Serialized representation of `WcsArray`.
+///
+/// Values whose type is not f32 are serialized in their own type in `native`,
+/// and `array` is then empty.
+#[derive(Deserialize)]
+struct WcsArrayData {
     meta: Option<MetaWcsArray>,
     array: Dimensioned<ArrayD<f32>>,
+    #[serde(default)]
+    variance: Option<ArrayD<f32>>,
+    #[serde(default)]
+    mask: Option<ArrayD<bool>>,
+    #[serde(default)]
+    keywords: Vec<(String, String)>,
+    #[serde(default)]
+    native: Option<NativeArray>,
+}
+
+#[derive(Serialize)]
+struct WcsArrayDataRef<'a> {
+    meta: &'a Option<MetaWcsArray>,
+    array: Dimensioned<&'a ArrayD<f32>>,
+    variance: Option<&'a ArrayD<f32>>,
+    mask: &'a Option<ArrayD<bool>>,
+    keywords: &'a [(String, String)],
+    native: Option<&'a NativeArray>,
+}
+
+impl From<WcsArrayData> for WcsArray {
+    fn from(data: WcsArrayData) -> Self {
+        let native = data.native;
+        WcsArray {
+            meta: data.meta,
+            array: data.array.map(|array| match native {
+                Some(native) => Values::from(native),
+                None => Values::from(array),
+            }),
+            variance: data.variance.map(Values::from),
+            mask: data.mask,
+            keywords: data.keywords,
+        }
+    }
+}
+
+impl Serialize for WcsArray {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let native = self.native();
+        // Values in their own type are serialized in `native`
+        let values = match native {
+            Some(_) => Bow::Owned(ArrayD::zeros(IxDyn(&[0]))),
+            None => self.scalar(),
+        };
+        WcsArrayDataRef {
+            meta: &self.meta,
+            array: self.array.with_new_value(&*values),
+            variance: self.variance(),
+            mask: &self.mask,
+            keywords: &self.keywords,
+            native,
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Apply `$f` to the array of `$native`, a `NativeArray`, bound to the
+/// pattern `$arr`, keeping the type of the values.
+macro_rules! map_native {
+    ($native:expr, $arr:pat => $f:expr) => {
+        match $native {
+            NativeArray::U8($arr) => NativeArray::U8($f),
+            NativeArray::I32($arr) => NativeArray::I32($f),
+            NativeArray::F64($arr) => NativeArray::F64($f),
+        }
+    };
+}
+
+/// Values of a `WcsArray`, either in memory or mapped from a FITS file.
+///
+/// Values are stored in their own type. Values whose type is not f32 are
+/// converted to a new f32 array each time they are needed as f32, which is not
+/// kept. Mapped values are read in their own type the first time they are all
+/// needed, and kept. Some frames of mapped values not read yet or of values of
+/// another type can be converted alone with `WcsArray::with_frames`, or some
+/// of their pixels with `WcsArray::with_window`.
+#[derive(Clone)]
+pub struct Values(Storage);
+
+#[derive(Clone)]
+enum Storage {
+    InMemory(ArrayD<f32>),
+    Native(NativeArray),
+    Mapped(Arc<MappedArray>),
+}
+
+impl Values {
+    /// Make values of type `dtype` from values computed in double precision.
+    ///
+    /// Values are rounded to the nearest integer and saturated when converted
+    /// to integers. NaN is converted to 0.
+    pub(crate) fn from_f64(arr: ArrayD<f64>, dtype: DataType) -> Values {
+        match dtype {
+            DataType::F32 => Values::from(arr.mapv(|v| v as f32)),
+            dtype => Values::from(NativeArray::from_f64(arr, dtype)),
+        }
+    }
+
+    /// Get the shape of the array, without reading mapped values.
+    pub fn shape(&self) -> &[usize] {
+        match self.0 {
+            Storage::InMemory(ref arr) => arr.shape(),
+            Storage::Native(ref native) => native.shape(),
+            Storage::Mapped(ref mapped) => mapped.shape(),
+        }
+    }
+
+    /// Get the type of the values.
+    pub fn dtype(&self) -> DataType {
+        match self.0 {
+            Storage::Native(ref native) => native.dtype(),
+            Storage::Mapped(ref mapped) => mapped.dtype(),
+            Storage::InMemory(_) => DataType::F32,
+        }
+    }
+
+    /// Get the values in their own type, if it is not f32. Mapped values are
+    /// all read.
+    fn native(&self) -> Option<&NativeArray> {
+        match self.0 {
+            Storage::Native(ref native) => Some(native),
+            Storage::Mapped(ref mapped) if mapped.dtype() != DataType::F32 => Some(mapped.native()),
+            Storage::InMemory(_) | Storage::Mapped(_) => None,
+        }
+    }
+
+    /// Get the values in double precision.
+    fn to_f64(&self) -> ArrayD<f64> {
+        match self.native() {
+            Some(native) => native.to_f64(),
+            None => self.as_f32().mapv(f64::from),
+        }
+    }
+
+    /// Get the values as f32. Values of another type are converted to a new
+    /// array, and mapped values are all read.
+    pub fn as_f32(&self) -> Bow<'_, ArrayD<f32>> {
+        match self.0 {
+            Storage::InMemory(ref arr) => Bow::Borrowed(arr),
+            _ => match self.native() {
+                Some(native) => Bow::Owned(native.to_f32()),
+                None => Bow::Borrowed(self.f32_values()),
+            },
+        }
+    }
+
+    /// Get a reference to f32 values, such as a variance. Mapped values are
+    /// all read.
+    ///
+    /// Panic if the values are of another type.
+    fn f32_values(&self) -> &ArrayD<f32> {
+        match self.0 {
+            Storage::InMemory(ref arr) => arr,
+            Storage::Mapped(ref mapped) if mapped.dtype() == DataType::F32 => mapped.values(),
+            Storage::Native(_) | Storage::Mapped(_) => panic!("Values are not f32"),
+        }
+    }
+
+    /// Get a new array of the values as f32.
+    fn to_f32(&self) -> ArrayD<f32> {
+        match self.as_f32() {
+            Bow::Borrowed(arr) => arr.clone(),
+            Bow::Owned(arr) => arr,
+        }
+    }
+
+    /// Get the values as f32 in memory, to change them.
+    fn make_mut(&mut self) -> &mut ArrayD<f32> {
+        let converted = match self.0 {
+            Storage::InMemory(_) => None,
+            Storage::Native(_) | Storage::Mapped(_) => Some(self.to_f32()),
+        };
+        if let Some(arr) = converted {
+            self.0 = Storage::InMemory(arr);
+        }
+        match self.0 {
+            Storage::InMemory(ref mut arr) => arr,
+            Storage::Native(..) | Storage::Mapped(_) => unreachable!("Values were just converted"),
+        }
+    }
+
+    fn into_array(self) -> ArrayD<f32> {
+        match self.0 {
+            Storage::InMemory(arr) => arr,
+            Storage::Native(native) => native.to_f32(),
+            Storage::Mapped(_) => self.to_f32(),
+        }
+    }
+
+    /// Take the frames from `start` (included) to `end` (excluded) along the
+    /// first axis, keeping the type of the values.
+    ///
+    /// Mapped values that were not read yet are only read for these frames.
+    fn slice_frames(&self, start: usize, end: usize) -> Values {
+        let slice = Slice::from(start..end);
+        match self.0 {
+            Storage::Mapped(ref mapped) if !mapped.is_read() => match mapped.dtype() {
+                DataType::F32 => Values::from(mapped.frames(start, end)),
+                _ => Values::from(mapped.native_frames(start, end)),
+            },
+            _ => match self.native() {
+                Some(native) => Values::from(map_native!(
+                    *native,
+                    ref arr => arr.slice_axis(ArrayAxis(0), slice).to_owned()
+                )),
+                None => Values::from(self.f32_values().slice_axis(ArrayAxis(0), slice).to_owned()),
+            },
+        }
+    }
+
+    /// Take the frame at `index` along the first axis, keeping the type of
+    /// the values.
+    fn index_frame(&self, index: usize) -> Values {
+        match self.slice_frames(index, index + 1).0 {
+            Storage::InMemory(arr) => Values::from(arr.index_axis_move(ArrayAxis(0), 0)),
+            Storage::Native(native) => Values::from(map_native!(
+                native,
+                arr => arr.index_axis_move(ArrayAxis(0), 0)
+            )),
+            Storage::Mapped(_) => unreachable!("Frames are read from mapped values"),
+        }
+    }
+}
+
+impl From<ArrayD<f32>> for Values {
+    fn from(arr: ArrayD<f32>) -> Self {
+        Values(Storage::InMemory(arr))
+    }
+}
+
+impl From<NativeArray> for Values {
+    fn from(native: NativeArray) -> Self {
+        Values(Storage::Native(native))
+    }
+}
+
+impl PartialEq for Values {
+    fn eq(&self, other: &Self) -> bool {
+        if let (Storage::Mapped(m1), Storage::Mapped(m2)) = (&self.0, &other.0) {
+            if Arc::ptr_eq(m1, m2) {
+                return true;
+            }
+        }
+        match (self.native(), other.native()) {
+            (Some(n1), Some(n2)) => n1 == n2,
+            (Some(_), None) | (None, Some(_)) => false,
+            (None, None) => self.f32_values() == other.f32_values(),
+        }
+    }
+}
+
+impl fmt::Debug for Values {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Storage::InMemory(ref arr) => fmt::Debug::fmt(arr, f),
+            Storage::Native(ref native) => fmt::Debug::fmt(native, f),
+            Storage::Mapped(ref mapped) => fmt::Debug::fmt(mapped, f),
+        }
+    }
+}
+
+/// Type of the elements of an array.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum DataType {
+    U8,
+    I32,
+    F32,
+    F64,
+}
+
+impl DataType {
+    /// Parse a type name, one of "u8" (or "bool"), "i32", "f32" or "f64".
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "u8" | "bool" => Some(DataType::U8),
+            "i32" => Some(DataType::I32),
+            "f32" => Some(DataType::F32),
+            "f64" => Some(DataType::F64),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            DataType::U8 => "u8",
+            DataType::I32 => "i32",
+            DataType::F32 => "f32",
+            DataType::F64 => "f64",
+        }
+    }
+
+    pub fn is_integer(self) -> bool {
+        self == DataType::U8 || self == DataType::I32
+    }
+
+    /// Type of an array of type `self` multiplied by `factor`.
+    ///
+    /// Integers multiplied by a non-integer factor become f64. Otherwise,
+    /// integers are computed as i32, like in `DataType::promote`.
+    pub fn scaled(self, factor: f32) -> DataType {
+        if self.is_integer() && factor.fract() != 0.0 {
+            DataType::F64
+        } else if self.is_integer() {
+            DataType::I32
+        } else {
+            self
+        }
+    }
+
+    /// Type of the result of the sum or difference of arrays of type `self`
+    /// and `other`.
+    ///
+    /// Integers are computed as i32, so that u8 values do not overflow.
+    /// Floating point values are computed with the widest type, except that
+    /// i32 and f32 values are computed as f64, which represents both exactly.
+    pub fn promote(self, other: DataType) -> DataType {
+        use self::DataType::*;
+        match (self.max(other), self.min(other)) {
+            (U8, _) | (I32, _) => I32,
+            (F32, I32) => F64,
+            (max, _) => max,
+        }
+    }
+}
+
+/// Array of values whose type is not f32.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) enum NativeArray {
+    U8(ArrayD<u8>),
+    I32(ArrayD<i32>),
+    F64(ArrayD<f64>),
+}
+
+impl NativeArray {
+    /// Make values of type `dtype` from values computed in double precision,
+    /// as `Values::from_f64`.
+    ///
+    /// Panic if `dtype` is f32.
+    pub(crate) fn from_f64(arr: ArrayD<f64>, dtype: DataType) -> NativeArray {
+        match dtype {
+            DataType::F32 => panic!("f32 values are not stored as a NativeArray"),
+            DataType::F64 => NativeArray::F64(arr),
+            DataType::I32 => NativeArray::I32(arr.mapv(|v| {
+                if v.is_nan() {
+                    0
+                } else {
+                    v.round()
+                        .max(f64::from(::std::i32::MIN))
+                        .min(f64::from(::std::i32::MAX)) as i32
+                }
+            })),
+            DataType::U8 => NativeArray::U8(arr.mapv(|v| {
+                if v.is_nan() {
+                    0
+                } else {
+                    v.round().max(0.0).min(255.0) as u8
+                }
+            })),
+        }
+    }
+
+    fn shape(&self) -> &[usize] {
+        match *self {
+            NativeArray::U8(ref arr) => arr.shape(),
+            NativeArray::I32(ref arr) => arr.shape(),
+            NativeArray::F64(ref arr) => arr.shape(),
+        }
+    }
+
+    fn dtype(&self) -> DataType {
+        match *self {
+            NativeArray::U8(_) => DataType::U8,
+            NativeArray::I32(_) => DataType::I32,
+            NativeArray::F64(_) => DataType::F64,
+        }
+    }
+
+    fn to_f32(&self) -> ArrayD<f32> {
+        match *self {
+            NativeArray::U8(ref arr) => arr.mapv(f32::from),
+            NativeArray::I32(ref arr) => arr.mapv(|v| v as f32),
+            NativeArray::F64(ref arr) => arr.mapv(|v| v as f32),
+        }
+    }
+
+    fn to_f64(&self) -> ArrayD<f64> {
+        match *self {
+            NativeArray::U8(ref arr) => arr.mapv(f64::from),
+            NativeArray::I32(ref arr) => arr.mapv(f64::from),
+            NativeArray::F64(ref arr) => arr.clone(),
+        }
+    }
+
+    /// Scale the values as FITS data with `bscale` and `bzero`. Integers stay
+    /// integers if the scaled values are all integers that fit in i32.
+    fn scaled(self, bscale: f64, bzero: f64) -> NativeArray {
+        if bscale == 1.0 && bzero == 0.0 {
+            return self;
+        }
+        let is_integer = self.dtype().is_integer();
+        let scaled = self.to_f64().mapv(|v| bzero + bscale * v);
+        let fits_i32 = |v: f64| {
+            v.fract() == 0.0 && v >= f64::from(::std::i32::MIN) && v <= f64::from(::std::i32::MAX)
+        };
+        if is_integer && scaled.iter().all(|&v| fits_i32(v)) {
+            NativeArray::I32(scaled.mapv(|v| v as i32))
+        } else {
+            NativeArray::F64(scaled)
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -77,7 +511,7 @@ pub struct Axis {
 }
 
 impl Axis {
-    fn new(name: Option<String>, unit: Unit) -> Self {
+    pub(crate) fn new(name: Option<String>, unit: Unit) -> Self {
         Self { name, unit }
     }
 
@@ -108,18 +542,77 @@ fn read_string(hdu: &Hdu, key: &str) -> Option<String> {
 
 impl WcsArray {
     /// Make `WcsArray` from `Hdu` found in FITS file.
+    ///
+    /// Double precision and integer data keep their native type. Integer
+    /// data containing null values is converted to f32, with NaN in place of
+    /// the null values. Values are scaled with BSCALE and BZERO: scaled
+    /// integers stay integers if they are all integers that fit in i32, and
+    /// are converted to f64 otherwise.
     pub fn from_hdu(hdu: &Hdu) -> Result<WcsArray, FitsArrayReadError> {
+        let bscale = fits::float(hdu, "BSCALE").unwrap_or(1.0);
+        let bzero = fits::float(hdu, "BZERO").unwrap_or(0.0);
+        let scale_f32 = |mut image: ArrayD<f32>| {
+            if bscale != 1.0 || bzero != 0.0 {
+                image.mapv_inplace(|v| (bzero + bscale * f64::from(v)) as f32);
+            }
+            Self::from_hdu_header(hdu, image)
+        };
         let data = hdu.read_data();
-        let image = match data {
-            FitsData::FloatingPoint32(image) => FitsDataToArray::<IxDyn>::to_array(image)?,
-            FitsData::FloatingPoint64(image) => FitsDataToArray::<IxDyn>::to_array(image)?,
-            FitsData::IntegersI32(image) => FitsDataToArray::<IxDyn>::to_array(image)?,
-            FitsData::IntegersU32(image) => FitsDataToArray::<IxDyn>::to_array(image)?,
-            FitsData::Characters(_) => {
-                return Err(FitsArrayReadError::UnsupportedData("Characters"));
+        let native = match data {
+            FitsData::FloatingPoint32(image) => {
+                let image = FitsDataToArray::<IxDyn>::to_array(image)?;
+                return Ok(scale_f32(image));
+            }
+            FitsData::FloatingPoint64(image) => {
+                NativeArray::F64(fits::to_ndarray(image.shape, image.data)?)
+            }
+            FitsData::IntegersI32(image) => {
+                if image.data.iter().any(Option::is_none) {
+                    let image = FitsDataToArray::<IxDyn>::to_array(image)?;
+                    return Ok(scale_f32(image));
+                }
+                let data = image.data.into_iter().map(Option::unwrap).collect();
+                NativeArray::I32(fits::to_ndarray(image.shape, data)?)
+            }
+            FitsData::IntegersU32(image) => {
+                if image.data.iter().any(Option::is_none) {
+                    let image = FitsDataToArray::<IxDyn>::to_array(image)?;
+                    return Ok(scale_f32(image));
+                }
+                let data = image
+                    .data
+                    .into_iter()
+                    .map(|int| f64::from(int.unwrap()))
+                    .collect();
+                NativeArray::F64(fits::to_ndarray(image.shape, data)?)
+            }
+            // fitrs reads 8-bit unsigned integers (BITPIX = 8) as characters
+            FitsData::Characters(image) => {
+                let data: Vec<_> = image.data.into_iter().map(|c| c as u8).collect();
+                if let Some(blank) = fits::integer(hdu, "BLANK") {
+                    if data.iter().any(|&v| i64::from(v) == blank) {
+                        let data = data
+                            .into_iter()
+                            .map(|v| {
+                                if i64::from(v) == blank {
+                                    ::std::f32::NAN
+                                } else {
+                                    f32::from(v)
+                                }
+                            })
+                            .collect();
+                        return Ok(scale_f32(fits::to_ndarray(image.shape, data)?));
+                    }
+                }
+                NativeArray::U8(fits::to_ndarray(image.shape, data)?)
             }
         };
+        Ok(Self::from_hdu_header(hdu, native.scaled(bscale, bzero)))
+    }
 
+    /// Make `WcsArray` containing `image`, with the metadata found in the
+    /// header of `hdu`. The data of `hdu` is not read.
+    pub(crate) fn from_hdu_header<V: Into<Values>>(hdu: &Hdu, image: V) -> WcsArray {
         let vunit = read_unit(hdu, "BUNIT");
         let cunit1 = read_unit(hdu, "CUNIT1");
         let cunit2 = read_unit(hdu, "CUNIT2");
@@ -130,7 +623,7 @@ impl WcsArray {
         let ctype3 = read_string(hdu, "CTYPE3");
         let ctype4 = read_string(hdu, "CTYPE4");
         let wcs = WCS::new(hdu);
-        Ok(Self {
+        Self {
             meta: Some(MetaWcsArray {
                 wcs,
                 axes: [
@@ -140,8 +633,85 @@ impl WcsArray {
                     Axis::new(ctype4, cunit4),
                 ],
             }),
-            array: vunit.new(image),
-        })
+            array: vunit.new(image.into()),
+            variance: None,
+            mask: None,
+            keywords: vec![],
+        }
+    }
+
+    /// Make `WcsArray` whose values are mapped from a FITS file, with the
+    /// metadata found in the header of `hdu`.
+    pub(crate) fn from_mapped(hdu: &Hdu, mapped: MappedArray) -> WcsArray {
+        Self::from_hdu_header(hdu, Values(Storage::Mapped(Arc::new(mapped))))
+    }
+
+    /// Make a FITS `Hdu` containing the array, along with the unit of its
+    /// values (BUNIT), its world coordinates and its additional keywords.
+    ///
+    /// f64 arrays are written in double precision and integer arrays as
+    /// 32-bit integers.
+    pub fn to_hdu(&self) -> Hdu {
+        // 0-dim ndarrays contain a single scalar value, however they should be
+        // treated as a 1-dimensional data array of length 1 when embedded as
+        // FITS file.
+        let shape: Vec<_> = if self.shape().is_empty() {
+            vec![1]
+        } else {
+            self.shape().iter().rev().cloned().collect()
+        };
+        let mut hdu = match self.native() {
+            None => Hdu::new(&shape, self.scalar().iter().cloned().collect::<Vec<_>>()),
+            Some(NativeArray::F64(ref arr)) => {
+                Hdu::new(&shape, arr.iter().cloned().collect::<Vec<_>>())
+            }
+            Some(NativeArray::I32(ref arr)) => {
+                Hdu::new(&shape, arr.iter().cloned().collect::<Vec<_>>())
+            }
+            Some(NativeArray::U8(ref arr)) => Hdu::new(
+                &shape,
+                arr.iter().map(|&v| i32::from(v)).collect::<Vec<_>>(),
+            ),
+        };
+        for (key, value) in self.header_cards() {
+            hdu.insert(key, value);
+        }
+        hdu
+    }
+
+    /// Get the header cards describing the array: the unit of its values
+    /// (BUNIT), its world coordinates and its additional keywords.
+    ///
+    /// Coordinates are written as a linear transform of each axis, with the
+    /// first pixel as reference pixel.
+    pub(crate) fn header_cards(&self) -> Vec<(String, HeaderValue)> {
+        let ndim = self.shape().len().max(1);
+        let mut cards: Vec<(String, HeaderValue)> = vec![];
+        if let Unit::Custom(ref unit) = *self.array().unit() {
+            cards.push(("BUNIT".to_owned(), unit.as_str().into()));
+        }
+        if let Some(axes) = self.axes() {
+            for (i, axis) in axes.iter().enumerate().take(ndim) {
+                let n = i + 1;
+                if let Some(ref name) = axis.name {
+                    cards.push((format!("CTYPE{}", n), name.as_str().into()));
+                }
+                if let Unit::Custom(ref unit) = axis.unit {
+                    cards.push((format!("CUNIT{}", n), unit.as_str().into()));
+                }
+                if let (Some(start), Some(next)) = (self.pix2world(i, 0.0), self.pix2world(i, 1.0))
+                {
+                    cards.push((format!("CRPIX{}", n), 1.0.into()));
+                    cards.push((format!("CRVAL{}", n), f64::from(start).into()));
+                    cards.push((format!("CDELT{}", n), f64::from(next - start).into()));
+                }
+            }
+        }
+        for (key, value) in &self.keywords {
+            cards.retain(|(k, _)| k != key);
+            cards.push((key.clone(), fits::parse_value(value)));
+        }
+        cards
     }
 
     /// Convert position `pixel` (in pixel coordinates starting from 0) at axis
@@ -156,39 +726,343 @@ impl WcsArray {
     }
 
     /// Make a new array missing all metadata about axes and world coordinates.
-    pub fn from_array(array: Dimensioned<ArrayD<f32>>) -> Self {
-        Self { meta: None, array }
+    pub fn from_array<V: Into<Values>>(array: Dimensioned<V>) -> Self {
+        Self {
+            meta: None,
+            array: array.map(Into::into),
+            variance: None,
+            mask: None,
+            keywords: vec![],
+        }
+    }
+
+    /// Get the variance of each value of the array, if known. A mapped
+    /// variance is all read.
+    pub fn variance(&self) -> Option<&ArrayD<f32>> {
+        self.variance.as_ref().map(Values::f32_values)
     }
 
-    /// Get reference to contained *n*-dimensional array.
-    pub fn scalar(&self) -> &ArrayD<f32> {
-        self.array.scalar()
+    /// Get the variance of the frames from `start` (included) to `end`
+    /// (excluded) along the first axis, if known. Only these frames of a
+    /// mapped variance are read.
+    ///
+    /// Panic if the array has no dimension or if the frames are out of bounds.
+    pub(crate) fn variance_frames(&self, start: usize, end: usize) -> Option<ArrayD<f32>> {
+        self.variance
+            .as_ref()
+            .map(|variance| variance.slice_frames(start, end).into_array())
+    }
+
+    /// Attach the variance of each value of the array.
+    ///
+    /// Fail if `variance` does not have the same dimension as the array.
+    pub fn with_variance(self, variance: Option<ArrayD<f32>>) -> Result<Self, IOErr> {
+        self.with_variance_values(variance.map(Values::from))
+    }
+
+    /// Attach a variance mapped from a FITS file, which is read as f32 when
+    /// it is needed.
+    ///
+    /// Fail if the variance does not have the same dimension as the array.
+    pub(crate) fn with_mapped_variance(self, variance: MappedArray) -> Result<Self, IOErr> {
+        let variance = Values(Storage::Mapped(Arc::new(variance.into_f32())));
+        self.with_variance_values(Some(variance))
+    }
+
+    fn with_variance_values(mut self, variance: Option<Values>) -> Result<Self, IOErr> {
+        if let Some(ref variance) = variance {
+            if variance.shape() != self.shape() {
+                return Err(IOErr::UnexpectedInput(format!(
+                    "Variance has shape {:?}, but the image has shape {:?}.",
+                    variance.shape(),
+                    self.shape()
+                )));
+            }
+        }
+        self.variance = variance;
+        Ok(self)
+    }
+
+    /// Get the mask of bad values of the array, if any. Masked values are
+    /// `true`.
+    pub fn mask(&self) -> Option<&ArrayD<bool>> {
+        self.mask.as_ref()
+    }
+
+    /// Attach a mask of bad values to the array.
+    ///
+    /// Fail if `mask` does not have the same dimension as the array.
+    pub fn with_mask(mut self, mask: Option<ArrayD<bool>>) -> Result<Self, IOErr> {
+        if let Some(ref mask) = mask {
+            if mask.shape() != self.shape() {
+                return Err(IOErr::UnexpectedInput(format!(
+                    "Mask has shape {:?}, but the image has shape {:?}.",
+                    mask.shape(),
+                    self.shape()
+                )));
+            }
+        }
+        self.mask = mask;
+        Ok(self)
+    }
+
+    /// Get the additional FITS header keywords attached to the array.
+    pub fn keywords(&self) -> &[(String, String)] {
+        &self.keywords
+    }
+
+    /// Attach FITS header keywords to the array, overriding the keywords with
+    /// the same key.
+    pub fn with_keywords<I>(mut self, keywords: I) -> Self
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        for (key, value) in keywords {
+            self.keywords.retain(|(k, _)| *k != key);
+            self.keywords.push((key, value));
+        }
+        self
+    }
+
+    /// Get the contained *n*-dimensional array as f32.
+    ///
+    /// Values of another type are converted to a new array on each call, see
+    /// [`WcsArray::dtype`]. Values mapped from a FITS file are all read.
+    pub fn scalar(&self) -> Bow<'_, ArrayD<f32>> {
+        self.array.scalar().as_f32()
+    }
+
+    /// Get a copy of the contained *n*-dimensional array as f32.
+    ///
+    /// Values of another type are only converted, not copied again.
+    pub fn scalar_owned(&self) -> ArrayD<f32> {
+        match self.scalar() {
+            Bow::Owned(arr) => arr,
+            Bow::Borrowed(arr) => arr.clone(),
+        }
     }
 
     /// Get mutable reference to contained *n*-dimensional array.
+    ///
+    /// Values of another type are converted to f32 first, as values may be
+    /// changed, and stay f32: [`WcsArray::dtype`] is then [`DataType::F32`].
+    /// [`WcsArray::into_dtype`] converts them back if needed.
     pub fn scalar_mut(&mut self) -> &mut ArrayD<f32> {
-        self.array.scalar_mut()
+        self.array.scalar_mut().make_mut()
+    }
+
+    /// Get the shape of the array, without reading values mapped from a FITS
+    /// file.
+    pub fn shape(&self) -> &[usize] {
+        self.array.scalar().shape()
+    }
+
+    /// Whether the values are mapped from a FITS file and some of them were
+    /// not read yet. Getting all the values, e.g. with [`WcsArray::scalar`],
+    /// would then read them all.
+    pub fn is_partly_read(&self) -> bool {
+        match self.array.scalar().0 {
+            Storage::Mapped(ref mapped) => !mapped.is_read(),
+            _ => false,
+        }
+    }
+
+    /// Get the minimum and maximum of the values that are not NaN, if any.
+    ///
+    /// Values mapped from a FITS file and not read yet are read one frame at
+    /// a time, without keeping them.
+    pub fn value_bounds(&self) -> Option<(f32, f32)> {
+        fn fold<'a, I: Iterator<Item = &'a f32>>(bounds: (f32, f32), values: I) -> (f32, f32) {
+            values
+                .filter(|v| !v.is_nan())
+                .fold(bounds, |(min, max), &v| (min.min(v), max.max(v)))
+        }
+        let mut bounds = (::std::f32::INFINITY, ::std::f32::NEG_INFINITY);
+        match self.shape().first() {
+            Some(&frames) => {
+                for i in 0..frames {
+                    bounds = self.with_frames(i, i + 1, |frame| fold(bounds, frame.iter()));
+                }
+            }
+            None => bounds = fold(bounds, self.scalar().iter()),
+        }
+        if bounds.0 <= bounds.1 {
+            Some(bounds)
+        } else {
+            None
+        }
+    }
+
+    /// Call `f` with a view of the frames from `start` (included) to `end`
+    /// (excluded) along the first axis of the array.
+    ///
+    /// If the values are mapped from a FITS file and were not all read yet,
+    /// only these frames are read. Values of another type than f32 are only
+    /// converted in these frames.
+    ///
+    /// Panic if the array has no dimension or if the frames are out of bounds.
+    pub(crate) fn with_frames<F, R>(&self, start: usize, end: usize, f: F) -> R
+    where
+        F: FnOnce(ArrayViewD<f32>) -> R,
+    {
+        let slice = Slice::from(start..end);
+        let values = self.array.scalar();
+        match values.0 {
+            Storage::Mapped(ref mapped) if !mapped.is_read() => {
+                return f(mapped.frames(start, end).view())
+            }
+            _ => (),
+        }
+        match values.native() {
+            Some(native) => {
+                let frames = map_native!(
+                    *native,
+                    ref arr => arr.slice_axis(ArrayAxis(0), slice).to_owned()
+                );
+                f(frames.to_f32().view())
+            }
+            None => f(values.f32_values().slice_axis(ArrayAxis(0), slice)),
+        }
+    }
+
+    /// Call `f` with a view of the given frames (along the first axis), rows
+    /// and columns of a 3D array.
+    ///
+    /// If the values are mapped from a FITS file and were not all read yet,
+    /// only the values in this window are read. Values of another type than
+    /// f32 are only converted in this window.
+    ///
+    /// Panic if the array does not have 3 dimensions or if the window is out
+    /// of bounds.
+    pub(crate) fn with_window<F, R>(
+        &self,
+        frames: Range<usize>,
+        rows: Range<usize>,
+        columns: Range<usize>,
+        f: F,
+    ) -> R
+    where
+        F: FnOnce(ArrayView3<f32>) -> R,
+    {
+        let values = self.array.scalar();
+        match values.0 {
+            Storage::Mapped(ref mapped) if !mapped.is_read() => {
+                return f(mapped.window(frames, rows, columns).view())
+            }
+            _ => (),
+        }
+        match values.native() {
+            Some(native) => {
+                let window = map_native!(
+                    *native,
+                    ref arr => arr.slice(s![frames, rows, columns]).to_owned().into_dyn()
+                );
+                f(window
+                    .to_f32()
+                    .view()
+                    .into_dimensionality::<Ix3>()
+                    .expect("Array has 3 dimensions"))
+            }
+            None => f(values
+                .f32_values()
+                .slice(s![frames, rows, columns])
+                .into_dimensionality::<Ix3>()
+                .expect("Array has 3 dimensions")),
+        }
+    }
+
+    /// Get the type of the values of the array.
+    pub fn dtype(&self) -> DataType {
+        self.array.scalar().dtype()
+    }
+
+    /// Get the values of the array in double precision.
+    ///
+    /// Values of f64 arrays are kept exactly.
+    pub fn scalar_f64(&self) -> ArrayD<f64> {
+        self.array.scalar().to_f64()
     }
 
-    /// Get view to contained 1-dimensional array.
+    /// Convert the values of the array to `dtype`.
     ///
-    /// Panic if contained array is not 1-dimensional.
-    pub fn scalar1(&self) -> ArrayView1<f32> {
-        let i = self.array.scalar();
-        i.slice(s![..])
+    /// Floating point values are rounded to the nearest integer and saturated
+    /// when converted to integers. NaN is converted to 0.
+    pub fn into_dtype(self, dtype: DataType) -> Self {
+        if dtype == self.dtype() {
+            return self;
+        }
+        let values = Values::from_f64(self.scalar_f64(), dtype);
+        self.with_values(values)
+    }
+
+    /// Get the values of the array in their native type, if it is not f32.
+    pub(crate) fn native(&self) -> Option<&NativeArray> {
+        self.array.scalar().native()
     }
 
-    /// Get view to contained 2-dimensional array.
+    /// Make an array of type `dtype` promoted with the type of the array, by
+    /// applying `f` to each value. Variance, mask and metadata are kept, so
+    /// `f` must not change the variance (e.g. a negation, or values replaced
+    /// with NaN). Otherwise, the variance must be replaced.
     ///
-    /// Panic if contained array is not 2-dimensional.
-    pub fn scalar2(&self) -> ArrayView2<f32> {
-        let i = self.array.scalar();
-        i.slice(s![.., ..])
+    /// Values are computed in single precision if the promoted type is f32,
+    /// and in double precision otherwise.
+    pub(crate) fn map_values<F>(&self, dtype: DataType, f: F) -> WcsArray
+    where
+        F: Fn(f64) -> f64,
+    {
+        let values = match self.dtype().promote(dtype) {
+            DataType::F32 => Values::from(self.scalar().mapv(|v| f(f64::from(v)) as f32)),
+            dtype => Values::from_f64(self.scalar_f64().mapv(f), dtype),
+        };
+        self.clone_with_values(values)
+    }
+
+    /// Same as `map_values`, where `f` is also given the element of `other`
+    /// at the same index.
+    ///
+    /// Panic if `other` does not have the same shape as the array.
+    pub(crate) fn zip_values<T, F>(&self, dtype: DataType, other: &ArrayD<T>, f: F) -> WcsArray
+    where
+        F: Fn(f64, &T) -> f64,
+    {
+        let values = match self.dtype().promote(dtype) {
+            DataType::F32 => {
+                let mut out = self.scalar_owned();
+                out.zip_mut_with(other, |v, t| *v = f(f64::from(*v), t) as f32);
+                Values::from(out)
+            }
+            dtype => {
+                let mut out = self.scalar_f64();
+                out.zip_mut_with(other, |v, t| *v = f(*v, t));
+                Values::from_f64(out, dtype)
+            }
+        };
+        self.clone_with_values(values)
+    }
+
+    /// Replace the values of the array, keeping its variance, mask and
+    /// metadata.
+    fn with_values(mut self, values: Values) -> WcsArray {
+        *self.array.scalar_mut() = values;
+        self
+    }
+
+    /// Make an array containing `values`, with the same variance, mask and
+    /// metadata as the array.
+    fn clone_with_values(&self, values: Values) -> WcsArray {
+        WcsArray {
+            meta: self.meta.clone(),
+            array: self.array.with_new_value(values),
+            variance: self.variance.clone(),
+            mask: self.mask.clone(),
+            keywords: self.keywords.clone(),
+        }
     }
 
     /// Get reference to contained *n*-dimensional array, with the unit of the
     /// values contained in the array attached.
-    pub fn array(&self) -> &Dimensioned<ArrayD<f32>> {
+    pub fn array(&self) -> &Dimensioned<Values> {
         &self.array
     }
 
@@ -208,10 +1082,10 @@ impl WcsArray {
     ///
     /// TODO: This method is hard to understand, and is potentional buggy
     /// write-only code.
-    pub(crate) fn make_slice(
+    pub(crate) fn make_slice<V: Into<Values>>(
         &self,
         indices: &[(usize, f32, f32)],
-        array: Dimensioned<ArrayD<f32>>,
+        array: Dimensioned<V>,
     ) -> WcsArray {
         let slice_index: Vec<_> = indices.iter().map(|idx| idx.0).collect();
         let new_meta = self.meta.as_ref().map(|meta| {
@@ -232,7 +1106,95 @@ impl WcsArray {
         });
         WcsArray {
             meta: new_meta,
-            array,
+            array: array.map(Into::into),
+            variance: None,
+            mask: None,
+            keywords: self.keywords.clone(),
+        }
+    }
+
+    /// Take the frame at `index` along the first axis of the array (the last
+    /// axis in FITS order), keeping its type, variance and mask.
+    ///
+    /// Panic if `index` is out of bounds.
+    pub(crate) fn index_frame(&self, index: usize) -> WcsArray {
+        let frame = self.array.scalar().index_frame(index);
+        let ndim = frame.shape().len();
+        let mut out = self.make_slice(
+            &(0..ndim).map(|i| (i, 0.0, 1.0)).collect::<Vec<_>>(),
+            self.array.with_new_value(frame),
+        );
+        out.variance = self
+            .variance
+            .as_ref()
+            .map(|variance| variance.index_frame(index));
+        out.mask = self
+            .mask
+            .as_ref()
+            .map(|mask| mask.index_axis(ArrayAxis(0), index).to_owned());
+        out
+    }
+
+    /// Take the frames from `start` (included) to `end` (excluded) along the
+    /// first axis of the array, keeping its type, variance and mask.
+    ///
+    /// Panic if the array has no dimension or if the frames are out of bounds.
+    pub(crate) fn slice_frames(&self, start: usize, end: usize) -> WcsArray {
+        let ndim = self.shape().len();
+        let slice = Slice::from(start..end);
+        let mut out = self.make_slice(
+            &(0..ndim).map(|i| (i, 0.0, 1.0)).collect::<Vec<_>>(),
+            self.array
+                .with_new_value(self.array.scalar().slice_frames(start, end)),
+        );
+        out.variance = self
+            .variance
+            .as_ref()
+            .map(|variance| variance.slice_frames(start, end));
+        out.mask = self
+            .mask
+            .as_ref()
+            .map(|mask| mask.slice_axis(ArrayAxis(0), slice).to_owned());
+        out
+    }
+
+    /// Create a new `WcsArray` containing the provided `array`, with linear
+    /// world coordinates. Each axis of `array` (in FITS order) is described
+    /// by its name and unit, and by the world coordinates of its first pixel
+    /// and the step between its pixels.
+    ///
+    /// No world coordinates are set if `self` has none.
+    pub(crate) fn make_linear_axes<V: Into<Values>>(
+        &self,
+        axes: &[(Axis, f32, f32)],
+        array: Dimensioned<V>,
+    ) -> WcsArray {
+        let new_meta = self.meta.as_ref().map(|_| {
+            let mut hdu = Hdu::empty();
+            let mut new_axes = [
+                Axis::default(),
+                Axis::default(),
+                Axis::default(),
+                Axis::default(),
+            ];
+            for (i, (axis, start, step)) in axes.iter().enumerate().take(4) {
+                let n = i + 1;
+                hdu.insert(format!("CRPIX{}", n), 1.0);
+                hdu.insert(format!("CRVAL{}", n), f64::from(*start));
+                hdu.insert(format!("CDELT{}", n), f64::from(*step));
+                new_axes[i] = axis.clone();
+            }
+            MetaWcsArray {
+                wcs: WCS::new(&hdu),
+                axes: new_axes,
+            }
+        });
+        WcsArray {
+            meta: new_meta,
+            array: array.map(Into::into),
+            variance: None,
+            mask: None,
+            keywords: self.keywords.clone(),
         }
     }
 }
@@ -284,6 +1246,18 @@ impl<V> Dimensioned<V> {
             homogeneous: self.homogeneous,
         }
     }
+
+    /// Convert the contained value with `f`, keeping the same unit.
+    pub fn map<W, F>(self, f: F) -> Dimensioned<W>
+    where
+        F: FnOnce(V) -> W,
+    {
+        Dimensioned {
+            value: f(self.value),
+            unit: self.unit,
+            homogeneous: self.homogeneous,
+        }
+    }
 }
 
 impl<V, W> ops::Mul<W> for Dimensioned<V>
@@ -423,13 +1397,91 @@ impl fmt::Display for Unit {
     }
 }
 
+impl ops::Mul<f32> for Values {
+    type Output = Values;
+
+    fn mul(self, rhs: f32) -> Self::Output {
+        match self.dtype().scaled(rhs) {
+            DataType::F32 => Values::from(self.into_array() * rhs),
+            dtype => Values::from_f64(self.to_f64() * f64::from(rhs), dtype),
+        }
+    }
+}
+
+impl<'a> ops::Mul<f32> for &'a Values {
+    type Output = Values;
+
+    fn mul(self, rhs: f32) -> Self::Output {
+        match self.dtype().scaled(rhs) {
+            DataType::F32 => Values::from(&*self.as_f32() * rhs),
+            dtype => Values::from_f64(self.to_f64() * f64::from(rhs), dtype),
+        }
+    }
+}
+
+impl ops::Div<f32> for Values {
+    type Output = Values;
+
+    fn div(self, rhs: f32) -> Self::Output {
+        match self.dtype().scaled(1.0 / rhs) {
+            DataType::F32 => Values::from(self.into_array() / rhs),
+            dtype => Values::from_f64(self.to_f64() / f64::from(rhs), dtype),
+        }
+    }
+}
+
+impl<'a> ops::Div<f32> for &'a Values {
+    type Output = Values;
+
+    fn div(self, rhs: f32) -> Self::Output {
+        match self.dtype().scaled(1.0 / rhs) {
+            DataType::F32 => Values::from(&*self.as_f32() / rhs),
+            dtype => Values::from_f64(self.to_f64() / f64::from(rhs), dtype),
+        }
+    }
+}
+
+impl ops::Add for Values {
+    type Output = Values;
+
+    fn add(self, rhs: Values) -> Self::Output {
+        match self.dtype().promote(rhs.dtype()) {
+            DataType::F32 => Values::from(self.into_array() + &*rhs.as_f32()),
+            dtype => Values::from_f64(self.to_f64() + rhs.to_f64(), dtype),
+        }
+    }
+}
+
+impl ops::Sub for Values {
+    type Output = Values;
+
+    fn sub(self, rhs: Values) -> Self::Output {
+        &self - &rhs
+    }
+}
+
+impl<'a, 'b> ops::Sub<&'b Values> for &'a Values {
+    type Output = Values;
+
+    fn sub(self, rhs: &'b Values) -> Self::Output {
+        match self.dtype().promote(rhs.dtype()) {
+            DataType::F32 => Values::from(&*self.as_f32() - &*rhs.as_f32()),
+            dtype => Values::from_f64(self.to_f64() - rhs.to_f64(), dtype),
+        }
+    }
+}
+
 impl ops::Mul<f32> for WcsArray {
     type Output = WcsArray;
 
     fn mul(self, rhs: f32) -> Self::Output {
+        let variance = self.variance().map(|var| Values::from(var * (rhs * rhs)));
         WcsArray {
             meta: self.meta,
             array: self.array * rhs,
+            variance,
+            mask: self.mask,
+            keywords: self.keywords,
         }
     }
 }
@@ -441,6 +1493,9 @@ impl<'a> ops::Mul<f32> for &'a WcsArray {
         WcsArray {
             meta: self.meta.clone(),
             array: self.array() * rhs,
+            variance: self.variance().map(|var| Values::from(var * (rhs * rhs))),
+            mask: self.mask.clone(),
+            keywords: self.keywords.clone(),
         }
     }
 }
@@ -449,9 +1504,13 @@ impl ops::Div<f32> for WcsArray {
     type Output = WcsArray;
 
     fn div(self, rhs: f32) -> Self::Output {
+        let variance = self.variance().map(|var| Values::from(var / (rhs * rhs)));
         WcsArray {
             meta: self.meta,
             array: self.array / rhs,
+            variance,
+            mask: self.mask,
+            keywords: self.keywords,
         }
     }
 }
@@ -463,6 +1522,9 @@ impl<'a> ops::Div<f32> for &'a WcsArray {
         WcsArray {
             meta: self.meta.clone(),
             array: self.array() / rhs,
+            variance: self.variance().map(|var| Values::from(var / (rhs * rhs))),
+            mask: self.mask.clone(),
+            keywords: self.keywords.clone(),
         }
     }
 }
@@ -471,6 +1533,7 @@ impl ops::Add for WcsArray {
     type Output = WcsArray;
 
     fn add(self, rhs: WcsArray) -> Self::Output {
+        let variance = add_variances(self.variance(), rhs.variance());
         let meta = if self.meta == rhs.meta {
             self.meta
         } else {
@@ -479,6 +1542,9 @@ impl ops::Add for WcsArray {
         WcsArray {
             meta,
             array: self.array + rhs.array,
+            variance,
+            mask: or_masks(self.mask.as_ref(), rhs.mask.as_ref()),
+            keywords: self.keywords,
         }
     }
 }
@@ -487,6 +1553,7 @@ impl ops::Sub for WcsArray {
     type Output = WcsArray;
 
     fn sub(self, rhs: WcsArray) -> Self::Output {
+        let variance = add_variances(self.variance(), rhs.variance());
         let meta = if self.meta == rhs.meta {
             self.meta
         } else {
@@ -495,6 +1562,9 @@ impl ops::Sub for WcsArray {
         WcsArray {
             meta,
             array: self.array - rhs.array,
+            variance,
+            mask: or_masks(self.mask.as_ref(), rhs.mask.as_ref()),
+            keywords: self.keywords,
         }
     }
 }
@@ -506,6 +1576,125 @@ impl<'a, 'b> ops::Sub<&'b WcsArray> for &'a WcsArray {
         WcsArray {
             meta: self.meta.clone(),
             array: &self.array - &rhs.array,
+            variance: add_variances(self.variance(), rhs.variance()),
+            mask: or_masks(self.mask.as_ref(), rhs.mask.as_ref()),
+            keywords: self.keywords.clone(),
+        }
+    }
+}
+
+/// Variance of the sum or difference of two independent arrays. A missing
+/// variance is considered null.
+fn add_variances(v1: Option<&ArrayD<f32>>, v2: Option<&ArrayD<f32>>) -> Option<Values> {
+    match (v1, v2) {
+        (Some(v1), Some(v2)) => Some(Values::from(v1 + v2)),
+        (Some(v), None) | (None, Some(v)) => Some(Values::from(v.clone())),
+        (None, None) => None,
+    }
+}
+
+/// A value computed from two arrays is bad if either value is bad.
+fn or_masks(m1: Option<&ArrayD<bool>>, m2: Option<&ArrayD<bool>>) -> Option<ArrayD<bool>> {
+    match (m1, m2) {
+        (Some(m1), Some(m2)) => {
+            let mut mask = m1.clone();
+            mask.zip_mut_with(m2, |a, &b| *a = *a || b);
+            Some(mask)
         }
+        (Some(m), None) | (None, Some(m)) => Some(m.clone()),
+        (None, None) => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DataType, Dimensioned, Unit, WcsArray};
+    use ndarray::{ArrayD, Ix3};
+    use ron;
+
+    fn cube_with_variance() -> WcsArray {
+        let data = ArrayD::from_elem(vec![5, 2, 3], 1.0);
+        let variance = ArrayD::from_elem(vec![5, 2, 3], 4.0);
+        WcsArray::from_array(Dimensioned::new(data, Unit::None))
+            .with_variance(Some(variance))
+            .unwrap()
+    }
+
+    #[test]
+    fn with_variance_rejects_other_shape() {
+        let image = WcsArray::from_array(Dimensioned::new(
+            ArrayD::from_elem(vec![2, 3], 1.0),
+            Unit::None,
+        ));
+        assert!(image
+            .clone()
+            .with_variance(Some(ArrayD::from_elem(vec![3, 2], 1.0)))
+            .is_err());
+        assert!(image
+            .with_mask(Some(ArrayD::from_elem(vec![2], false)))
+            .is_err());
+    }
+
+    #[test]
+    fn index_frame_keeps_variance_and_mask() {
+        let mut mask = ArrayD::from_elem(vec![5, 2, 3], false);
+        mask[[1, 0, 2]] = true;
+        let frame = cube_with_variance()
+            .with_mask(Some(mask))
+            .unwrap()
+            .index_frame(1);
+        assert_eq!(frame.scalar().shape(), &[2, 3]);
+        assert_eq!(frame.variance(), Some(&ArrayD::from_elem(vec![2, 3], 4.0)));
+        let mask = frame.mask().unwrap();
+        assert!(mask[[0, 2]]);
+        assert_eq!(mask.iter().filter(|&&masked| masked).count(), 1);
+    }
+
+    #[test]
+    fn value_bounds_skip_nan() {
+        let mut values = ArrayD::from_shape_fn(vec![3, 2], |idx| (idx[0] * 2 + idx[1]) as f32);
+        values[[2, 1]] = ::std::f32::NAN;
+        let cube = WcsArray::from_array(Dimensioned::new(values, Unit::None));
+        assert_eq!(cube.value_bounds(), Some((0.0, 4.0)));
+        assert_eq!(
+            cube.into_dtype(DataType::I32).value_bounds(),
+            Some((0.0, 4.0))
+        );
+
+        let empty = WcsArray::from_array(Dimensioned::new(
+            ArrayD::from_elem(vec![2], ::std::f32::NAN),
+            Unit::None,
+        ));
+        assert_eq!(empty.value_bounds(), None);
+    }
+
+    #[test]
+    fn native_type_is_kept_and_promoted() {
+        let cube = cube_with_variance().into_dtype(DataType::U8);
+        assert_eq!(cube.index_frame(1).dtype(), DataType::U8);
+        assert_eq!(cube.slice_frames(1, 3).dtype(), DataType::U8);
+        assert_eq!(
+            cube.slice_frames(1, 3).variance().unwrap().shape(),
+            &[2, 2, 3]
+        );
+        assert_eq!(cube.map_values(cube.dtype(), |v| -v).dtype(), DataType::I32);
+        assert_eq!((&cube * 2.0).dtype(), DataType::I32);
+        assert_eq!((&cube / 2.0).dtype(), DataType::F64);
+
+        assert_eq!(
+            cube.with_window(1..3, 0..1, 1..3, |window| window.to_owned()),
+            ArrayD::from_elem(vec![2, 1, 2], 1.0)
+                .into_dimensionality::<Ix3>()
+                .unwrap()
+        );
+        assert_eq!(
+            cube.with_frames(2, 3, |frames| frames.shape().to_vec()),
+            vec![1, 2, 3]
+        );
+
+        let serialized = ron::ser::to_string(&cube).unwrap();
+        let deserialized: WcsArray = ron::de::from_str(&serialized).unwrap();
+        assert_eq!(deserialized.dtype(), DataType::U8);
+        assert_eq!(deserialized, cube);
     }
 }
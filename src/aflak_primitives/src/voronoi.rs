@@ -0,0 +1,395 @@
+//! Adaptive spatial binning of 2D data to a target signal-to-noise ratio.
+//!
+//! The implementation follows the Voronoi binning method of Cappellari &
+//! Copin (2003, MNRAS 342, 345): pixels are first accreted into compact bins
+//! until the target S/N is reached, then the bin generators are refined with
+//! a few iterations of a weighted Voronoi tessellation.
+use std::f32;
+
+use ndarray::{Array2, ArrayView2, ArrayView3};
+
+/// Maximum number of iterations used to regularize the tessellation.
+const MAX_WVT_ITERATIONS: usize = 50;
+/// Maximum roundness of a bin during accretion.
+const MAX_ROUNDNESS: f32 = 0.3;
+/// Bins reaching this fraction of the target S/N are kept after accretion.
+const MIN_SN_FRACTION: f32 = 0.8;
+
+/// Result of a Voronoi binning.
+#[derive(Debug)]
+pub struct VoronoiBins {
+    /// Index of the bin each pixel belongs to. `None` for pixels that could
+    /// not be binned (non-finite signal, noise or non-positive noise).
+    pub bin_map: Array2<Option<usize>>,
+    /// Total signal of each bin.
+    pub signal: Vec<f32>,
+    /// Total noise of each bin (noise of each pixel added in quadrature).
+    pub noise: Vec<f32>,
+}
+
+#[derive(Copy, Clone)]
+struct Pixel {
+    pos: (f32, f32),
+    signal: f32,
+    noise: f32,
+}
+
+impl Pixel {
+    fn sn(&self) -> f32 {
+        self.signal / self.noise
+    }
+}
+
+/// Running sums used to compute the S/N and the centroid of a bin.
+#[derive(Copy, Clone, Default)]
+struct BinStats {
+    signal: f32,
+    variance: f32,
+    x: f32,
+    y: f32,
+    count: usize,
+}
+
+impl BinStats {
+    fn add(&mut self, pixel: &Pixel) {
+        self.signal += pixel.signal;
+        self.variance += pixel.noise * pixel.noise;
+        self.x += pixel.pos.0;
+        self.y += pixel.pos.1;
+        self.count += 1;
+    }
+
+    fn with(&self, pixel: &Pixel) -> Self {
+        let mut stats = *self;
+        stats.add(pixel);
+        stats
+    }
+
+    fn sn(&self) -> f32 {
+        self.signal / self.variance.sqrt()
+    }
+
+    fn centroid(&self) -> (f32, f32) {
+        let n = self.count as f32;
+        (self.x / n, self.y / n)
+    }
+}
+
+fn dist2((x0, y0): (f32, f32), (x1, y1): (f32, f32)) -> f32 {
+    (x0 - x1) * (x0 - x1) + (y0 - y1) * (y0 - y1)
+}
+
+/// Compute the roundness of a set of pixels, as defined by Cappellari &
+/// Copin: 0 for a perfectly circular bin, growing for elongated bins.
+fn roundness(pixels: &[Pixel], members: &[usize], centroid: (f32, f32)) -> f32 {
+    let r_max = members
+        .iter()
+        .map(|&i| dist2(pixels[i].pos, centroid))
+        .fold(0.0, f32::max)
+        .sqrt();
+    let r_eff = (members.len() as f32 / f32::consts::PI).sqrt();
+    r_max / r_eff - 1.0
+}
+
+/// Bin the provided 2D `signal` and `noise` maps to reach `target_sn`.
+///
+/// If `wvt` is true, the bin accretion is followed by a weighted Voronoi
+/// tessellation, which makes the S/N of the bins more uniform.
+pub fn voronoi_binning(
+    signal: ArrayView2<f32>,
+    noise: ArrayView2<f32>,
+    target_sn: f32,
+    wvt: bool,
+) -> VoronoiBins {
+    let (h, w) = signal.dim();
+
+    // Only keep pixels with a valid signal and noise
+    let mut index = Array2::from_elem((h, w), None);
+    let mut pixels = Vec::new();
+    for ((i, j), &s) in signal.indexed_iter() {
+        let n = noise[(i, j)];
+        if s.is_finite() && n.is_finite() && n > 0.0 {
+            index[(i, j)] = Some(pixels.len());
+            pixels.push(Pixel {
+                pos: (j as f32, i as f32),
+                signal: s,
+                noise: n,
+            });
+        }
+    }
+
+    let generators = bin_accretion(&pixels, &index, target_sn);
+    let assignment = if generators.is_empty() {
+        vec![None; pixels.len()]
+    } else if wvt {
+        weighted_voronoi_tessellation(&pixels, generators, target_sn)
+    } else {
+        assign_to_generators(&pixels, &generators, &vec![1.0; generators.len()])
+    };
+
+    let bin_count = assignment
+        .iter()
+        .filter_map(|&bin| bin)
+        .max()
+        .map_or(0, |max| max + 1);
+    let mut stats = vec![BinStats::default(); bin_count];
+    for (pixel, bin) in pixels.iter().zip(&assignment) {
+        if let Some(bin) = *bin {
+            stats[bin].add(pixel);
+        }
+    }
+
+    let bin_map = index.map(|some_pixel| some_pixel.and_then(|p| assignment[p]));
+    VoronoiBins {
+        bin_map,
+        signal: stats.iter().map(|s| s.signal).collect(),
+        noise: stats.iter().map(|s| s.variance.sqrt()).collect(),
+    }
+}
+
+/// Accrete pixels into bins. Return the centroid of each successful bin.
+fn bin_accretion(
+    pixels: &[Pixel],
+    index: &Array2<Option<usize>>,
+    target_sn: f32,
+) -> Vec<(f32, f32)> {
+    let (h, w) = index.dim();
+    let neighbours = |p: &Pixel| {
+        let (x, y) = (p.pos.0 as isize, p.pos.1 as isize);
+        let mut out = Vec::with_capacity(8);
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let (i, j) = (y + dy, x + dx);
+                if (dx, dy) != (0, 0) && i >= 0 && j >= 0 && (i as usize) < h && (j as usize) < w {
+                    if let Some(n) = index[(i as usize, j as usize)] {
+                        out.push(n);
+                    }
+                }
+            }
+        }
+        out
+    };
+
+    let mut binned = vec![false; pixels.len()];
+    let mut generators = Vec::new();
+    let mut all_binned = BinStats::default();
+
+    // Start from the pixel with the highest S/N
+    let mut start = (0..pixels.len()).max_by(|&a, &b| {
+        pixels[a]
+            .sn()
+            .partial_cmp(&pixels[b].sn())
+            .unwrap_or(::std::cmp::Ordering::Equal)
+    });
+
+    while let Some(first) = start {
+        binned[first] = true;
+        let mut members = vec![first];
+        let mut stats = BinStats::default();
+        stats.add(&pixels[first]);
+
+        while stats.sn() < target_sn {
+            let centroid = stats.centroid();
+            // Candidate: unbinned pixel adjacent to the bin closest to its centroid
+            let candidate = members
+                .iter()
+                .flat_map(|&m| neighbours(&pixels[m]))
+                .filter(|&n| !binned[n])
+                .min_by(|&a, &b| {
+                    dist2(pixels[a].pos, centroid)
+                        .partial_cmp(&dist2(pixels[b].pos, centroid))
+                        .unwrap_or(::std::cmp::Ordering::Equal)
+                });
+            let candidate = match candidate {
+                Some(candidate) => candidate,
+                None => break,
+            };
+            let new_stats = stats.with(&pixels[candidate]);
+            members.push(candidate);
+            let too_elongated = roundness(pixels, &members, new_stats.centroid()) > MAX_ROUNDNESS;
+            let moves_away = (new_stats.sn() - target_sn).abs() > (stats.sn() - target_sn).abs();
+            if too_elongated || moves_away {
+                members.pop();
+                break;
+            }
+            binned[candidate] = true;
+            stats = new_stats;
+        }
+
+        if stats.sn() >= MIN_SN_FRACTION * target_sn {
+            generators.push(stats.centroid());
+            for &m in &members {
+                all_binned.add(&pixels[m]);
+            }
+        }
+        // Pixels of unsuccessful bins stay marked as binned during accretion
+        // so that they are not used as a starting point again. They are
+        // re-assigned to the closest successful bin afterwards.
+
+        // Next bin starts from the unbinned pixel closest to the centroid of
+        // all successfully binned pixels.
+        let reference = if all_binned.count > 0 {
+            all_binned.centroid()
+        } else {
+            stats.centroid()
+        };
+        start = (0..pixels.len()).filter(|&p| !binned[p]).min_by(|&a, &b| {
+            dist2(pixels[a].pos, reference)
+                .partial_cmp(&dist2(pixels[b].pos, reference))
+                .unwrap_or(::std::cmp::Ordering::Equal)
+        });
+    }
+
+    generators
+}
+
+/// Assign each pixel to the generator minimizing the scaled distance.
+fn assign_to_generators(
+    pixels: &[Pixel],
+    generators: &[(f32, f32)],
+    scales: &[f32],
+) -> Vec<Option<usize>> {
+    pixels
+        .iter()
+        .map(|p| {
+            generators
+                .iter()
+                .zip(scales)
+                .map(|(&g, &scale)| dist2(p.pos, g) / (scale * scale))
+                .enumerate()
+                .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(::std::cmp::Ordering::Equal))
+                .map(|(i, _)| i)
+        })
+        .collect()
+}
+
+/// Refine the generators with the weighted Voronoi tessellation of
+/// Diehl & Statler (2006). Return the bin of each pixel.
+fn weighted_voronoi_tessellation(
+    pixels: &[Pixel],
+    mut generators: Vec<(f32, f32)>,
+    target_sn: f32,
+) -> Vec<Option<usize>> {
+    let mut scales = vec![1.0; generators.len()];
+    let mut assignment = assign_to_generators(pixels, &generators, &scales);
+
+    for _ in 0..MAX_WVT_ITERATIONS {
+        let mut stats = vec![BinStats::default(); generators.len()];
+        let mut weighted = vec![(0.0, 0.0, 0.0); generators.len()];
+        for (pixel, bin) in pixels.iter().zip(&assignment) {
+            if let Some(bin) = *bin {
+                stats[bin].add(pixel);
+                let weight = pixel.sn() * pixel.sn();
+                let acc = &mut weighted[bin];
+                acc.0 += pixel.pos.0 * weight;
+                acc.1 += pixel.pos.1 * weight;
+                acc.2 += weight;
+            }
+        }
+        for (i, (stat, acc)) in stats.iter().zip(&weighted).enumerate() {
+            if stat.count == 0 {
+                continue;
+            }
+            generators[i] = if acc.2 > 0.0 {
+                (acc.0 / acc.2, acc.1 / acc.2)
+            } else {
+                stat.centroid()
+            };
+            let sn = stat.sn();
+            if sn.is_finite() && sn > 0.0 {
+                scales[i] = (stat.count as f32 * target_sn / sn).sqrt();
+            }
+        }
+
+        let new_assignment = assign_to_generators(pixels, &generators, &scales);
+        if new_assignment == assignment {
+            break;
+        }
+        assignment = new_assignment;
+    }
+
+    // Some generators may have lost all their pixels: renumber the bins
+    // so that bin indices are contiguous.
+    let mut renumber = vec![None; generators.len()];
+    let mut next = 0;
+    for bin in assignment.iter_mut() {
+        if let Some(b) = *bin {
+            let new = *renumber[b].get_or_insert_with(|| {
+                next += 1;
+                next - 1
+            });
+            *bin = Some(new);
+        }
+    }
+    assignment
+}
+
+/// Sum the spectra of a (wavelength, y, x) `cube` in each bin of `bin_map`.
+///
+/// Return an array of shape (wavelength, bin count), whose first axis is the
+/// wavelength axis of `cube`.
+pub fn sum_cube_per_bin(cube: ArrayView3<f32>, bin_map: ArrayView2<f32>) -> Array2<f32> {
+    let (wave_size, _, _) = cube.dim();
+    let bin_count = bin_map
+        .iter()
+        .filter_map(|&bin| bin_index(bin))
+        .max()
+        .map_or(0, |max| max + 1);
+    let mut out = Array2::zeros((wave_size, bin_count));
+    for ((i, j), &bin) in bin_map.indexed_iter() {
+        if let Some(bin) = bin_index(bin) {
+            for k in 0..wave_size {
+                let v = cube[(k, i, j)];
+                if !v.is_nan() {
+                    out[(k, bin)] += v;
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Convert a value read in a bin map into a bin index.
+pub fn bin_index(value: f32) -> Option<usize> {
+    if value.is_finite() && value >= 0.0 {
+        Some(value as usize)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::voronoi_binning;
+    use ndarray::Array2;
+
+    #[test]
+    fn bins_reach_target_sn() {
+        let signal = Array2::from_elem((10, 10), 1.0);
+        let noise = Array2::from_elem((10, 10), 1.0);
+        let bins = voronoi_binning(signal.view(), noise.view(), 4.0, true);
+
+        assert!(!bins.signal.is_empty());
+        assert!(bins.bin_map.iter().all(Option::is_some));
+        let sn: Vec<_> = bins
+            .signal
+            .iter()
+            .zip(&bins.noise)
+            .map(|(s, n)| s / n)
+            .collect();
+        let mean_sn = sn.iter().sum::<f32>() / sn.len() as f32;
+        assert!(mean_sn > 0.8 * 4.0);
+    }
+
+    #[test]
+    fn invalid_pixels_are_not_binned() {
+        let mut signal = Array2::from_elem((4, 4), 10.0);
+        signal[(0, 0)] = ::std::f32::NAN;
+        let noise = Array2::from_elem((4, 4), 1.0);
+        let bins = voronoi_binning(signal.view(), noise.view(), 5.0, false);
+
+        assert_eq!(bins.bin_map[(0, 0)], None);
+        // Each valid pixel already has S/N above target
+        assert_eq!(bins.signal.len(), 15);
+    }
+}
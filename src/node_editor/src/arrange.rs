@@ -0,0 +1,312 @@
+//! Layered layout of a graph, from left to right.
+//!
+//! Nodes are assigned to layers (columns) so that all links go from a layer
+//! to a layer on its right. Links spanning several layers go through a
+//! virtual node on each layer they cross. Nodes are then ordered in their
+//! layers to reduce the number of crossings between links, by sorting them
+//! according to the mean position of their neighbours (barycenter heuristic).
+use std::collections::BTreeMap;
+
+use cake;
+
+use vec2::Vec2;
+
+/// Horizontal space between layers
+const LAYER_GAP: f32 = 80.0;
+/// Vertical space between nodes of the same layer
+const NODE_GAP: f32 = 30.0;
+/// Height taken by a link going through a layer
+const VIRTUAL_NODE_HEIGHT: f32 = 10.0;
+/// Number of passes to order the nodes in their layers
+const ORDERING_PASSES: usize = 8;
+
+/// Compute the position of the nodes, relative to the top-left corner of
+/// the arranged graph.
+///
+/// `nodes` lists the nodes to arrange with their sizes. Links from or to a
+/// node that is not in `nodes` are ignored.
+pub fn arrange(
+    nodes: &[(cake::NodeId, Vec2)],
+    links: &[(cake::NodeId, cake::NodeId)],
+) -> Vec<(cake::NodeId, Vec2)> {
+    let indices: BTreeMap<_, _> = nodes
+        .iter()
+        .enumerate()
+        .map(|(i, (id, _))| (*id, i))
+        .collect();
+    let mut edges: Vec<_> = links
+        .iter()
+        .filter_map(|(from, to)| Some((*indices.get(from)?, *indices.get(to)?)))
+        .filter(|(from, to)| from != to)
+        .collect();
+    edges.sort();
+    edges.dedup();
+
+    let node_layers = assign_layers(nodes.len(), &edges);
+    let mut graph = LayeredGraph::new(&node_layers, &edges);
+    graph.order();
+
+    // Layers are as wide as their widest node
+    let layer_widths: Vec<f32> = graph
+        .layers
+        .iter()
+        .map(|layer| {
+            layer
+                .iter()
+                .filter(|&&v| v < nodes.len())
+                .map(|&v| (nodes[v].1).0)
+                .fold(0.0, f32::max)
+        })
+        .collect();
+    let height = |v: usize| {
+        if v < nodes.len() {
+            (nodes[v].1).1
+        } else {
+            VIRTUAL_NODE_HEIGHT
+        }
+    };
+    let layer_heights: Vec<f32> = graph
+        .layers
+        .iter()
+        .map(|layer| {
+            let heights: f32 = layer.iter().map(|&v| height(v)).sum();
+            heights + NODE_GAP * layer.len().saturating_sub(1) as f32
+        })
+        .collect();
+    let max_height = layer_heights.iter().cloned().fold(0.0, f32::max);
+
+    let mut positions = vec![Vec2::default(); nodes.len()];
+    let mut x = 0.0;
+    for (i, layer) in graph.layers.iter().enumerate() {
+        // Center layers vertically
+        let mut y = (max_height - layer_heights[i]) / 2.0;
+        for &v in layer {
+            if v < nodes.len() {
+                positions[v] = Vec2(x, y);
+            }
+            y += height(v) + NODE_GAP;
+        }
+        x += layer_widths[i] + LAYER_GAP;
+    }
+    nodes
+        .iter()
+        .zip(positions)
+        .map(|((id, _), pos)| (*id, pos))
+        .collect()
+}
+
+/// Assign each node to a layer, such that all edges go from a layer to a
+/// layer on its right.
+///
+/// Nodes are put as far to the left as possible, except for nodes without
+/// any predecessor, which are put right before their closest successor.
+fn assign_layers(node_count: usize, edges: &[(usize, usize)]) -> Vec<usize> {
+    let mut layers = vec![0; node_count];
+    let mut predecessor_counts = vec![0; node_count];
+    for &(_, to) in edges {
+        predecessor_counts[to] += 1;
+    }
+    // Topological sort
+    let mut ready: Vec<_> = (0..node_count)
+        .filter(|&v| predecessor_counts[v] == 0)
+        .collect();
+    let mut sorted = Vec::with_capacity(node_count);
+    while let Some(v) = ready.pop() {
+        sorted.push(v);
+        for &(from, to) in edges {
+            if from == v {
+                layers[to] = layers[to].max(layers[v] + 1);
+                predecessor_counts[to] -= 1;
+                if predecessor_counts[to] == 0 {
+                    ready.push(to);
+                }
+            }
+        }
+    }
+    // Move sources closer to their successors
+    for &v in sorted.iter().rev() {
+        let is_source = edges.iter().all(|&(_, to)| to != v);
+        let closest_successor = edges
+            .iter()
+            .filter(|&&(from, _)| from == v)
+            .map(|&(_, to)| layers[to])
+            .min();
+        if let (true, Some(layer)) = (is_source, closest_successor) {
+            layers[v] = layer - 1;
+        }
+    }
+    layers
+}
+
+/// Graph whose edges only link nodes on adjacent layers.
+///
+/// Real nodes are numbered first, followed by virtual nodes.
+struct LayeredGraph {
+    /// Nodes of each layer, from top to bottom
+    layers: Vec<Vec<usize>>,
+    /// Edges from each layer to the next one
+    edges: Vec<Vec<(usize, usize)>>,
+}
+
+impl LayeredGraph {
+    fn new(node_layers: &[usize], edges: &[(usize, usize)]) -> Self {
+        let layer_count = node_layers.iter().map(|&l| l + 1).max().unwrap_or(0);
+        let mut layers = vec![vec![]; layer_count];
+        for (v, &l) in node_layers.iter().enumerate() {
+            layers[l].push(v);
+        }
+        let mut layer_edges = vec![vec![]; layer_count.saturating_sub(1)];
+        let mut next_virtual = node_layers.len();
+        for &(from, to) in edges {
+            if node_layers[from] >= node_layers[to] {
+                // Only happens if the graph has cycles
+                continue;
+            }
+            // Split long edges with virtual nodes
+            let mut previous = from;
+            for l in node_layers[from] + 1..node_layers[to] {
+                layers[l].push(next_virtual);
+                layer_edges[l - 1].push((previous, next_virtual));
+                previous = next_virtual;
+                next_virtual += 1;
+            }
+            layer_edges[node_layers[to] - 1].push((previous, to));
+        }
+        LayeredGraph {
+            layers,
+            edges: layer_edges,
+        }
+    }
+
+    /// Order the nodes in their layers to reduce crossings. Keep the best
+    /// order found after sweeping layers alternately rightwards and leftwards.
+    fn order(&mut self) {
+        let mut best_layers = self.layers.clone();
+        let mut best_crossings = self.crossings();
+        for pass in 0..ORDERING_PASSES {
+            if best_crossings == 0 {
+                break;
+            }
+            if pass % 2 == 0 {
+                for l in 1..self.layers.len() {
+                    self.sort_by_barycenter(l, l - 1, false);
+                }
+            } else {
+                for l in (0..self.layers.len().saturating_sub(1)).rev() {
+                    self.sort_by_barycenter(l, l + 1, true);
+                }
+            }
+            let crossings = self.crossings();
+            if crossings < best_crossings {
+                best_crossings = crossings;
+                best_layers = self.layers.clone();
+            }
+        }
+        self.layers = best_layers;
+    }
+
+    /// Sort the nodes of layer `l` according to the mean position of their
+    /// neighbours on the adjacent layer `fixed`. Nodes without neighbours
+    /// keep their position.
+    fn sort_by_barycenter(&mut self, l: usize, fixed: usize, leftwards: bool) {
+        let edges = &self.edges[l.min(fixed)];
+        let fixed_layer = &self.layers[fixed];
+        let position = |v: usize| fixed_layer.iter().position(|&w| w == v).unwrap() as f32;
+        let mut keyed: Vec<(f32, usize)> = self.layers[l]
+            .iter()
+            .enumerate()
+            .map(|(i, &v)| {
+                let neighbours: Vec<f32> = edges
+                    .iter()
+                    .filter_map(|&(from, to)| {
+                        if leftwards && from == v {
+                            Some(position(to))
+                        } else if !leftwards && to == v {
+                            Some(position(from))
+                        } else {
+                            None
+                        }
+                    })
+                    .collect();
+                if neighbours.is_empty() {
+                    (i as f32, v)
+                } else {
+                    (neighbours.iter().sum::<f32>() / neighbours.len() as f32, v)
+                }
+            })
+            .collect();
+        // The sort is stable, so nodes with the same barycenter keep their order
+        keyed.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        self.layers[l] = keyed.into_iter().map(|(_, v)| v).collect();
+    }
+
+    /// Count the crossings between links.
+    fn crossings(&self) -> usize {
+        let mut count = 0;
+        for (l, edges) in self.edges.iter().enumerate() {
+            let position = |layer: &[usize], v: usize| layer.iter().position(|&w| w == v);
+            let segments: Vec<_> = edges
+                .iter()
+                .filter_map(|&(from, to)| {
+                    Some((
+                        position(&self.layers[l], from)?,
+                        position(&self.layers[l + 1], to)?,
+                    ))
+                })
+                .collect();
+            for (i, &(a1, b1)) in segments.iter().enumerate() {
+                for &(a2, b2) in &segments[i + 1..] {
+                    if (a1 < a2 && b1 > b2) || (a1 > a2 && b1 < b2) {
+                        count += 1;
+                    }
+                }
+            }
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{assign_layers, LayeredGraph};
+
+    #[test]
+    fn test_assign_layers() {
+        // 0 -> 1 -> 2, 0 -> 2, 3 -> 2 and 4 is alone
+        let edges = [(0, 1), (0, 2), (1, 2), (3, 2)];
+        let layers = assign_layers(5, &edges);
+        // 3 is put right before its successor
+        assert_eq!(layers, vec![0, 1, 2, 1, 0]);
+
+        let graph = LayeredGraph::new(&layers, &edges);
+        // The link from 0 to 2 goes through the virtual node 5
+        assert_eq!(graph.layers, vec![vec![0, 4], vec![1, 3, 5], vec![2]]);
+        assert_eq!(
+            graph.edges,
+            vec![vec![(0, 1), (0, 5)], vec![(5, 2), (1, 2), (3, 2)]]
+        );
+    }
+
+    #[test]
+    fn test_assign_layers_with_cycle() {
+        let edges = [(0, 1), (1, 0), (1, 2)];
+        let layers = assign_layers(3, &edges);
+        let graph = LayeredGraph::new(&layers, &edges);
+        let node_count: usize = graph.layers.iter().map(Vec::len).sum();
+        assert_eq!(node_count, 3);
+    }
+
+    #[test]
+    fn test_order_removes_crossings() {
+        // 0 -> 3 and 1 -> 2 cross each other
+        let edges = [(0, 3), (1, 2)];
+        let layers = assign_layers(4, &edges);
+        let mut graph = LayeredGraph::new(&layers, &edges);
+        assert_eq!(graph.layers, vec![vec![0, 1], vec![2, 3]]);
+        assert_eq!(graph.crossings(), 1);
+
+        graph.order();
+        assert_eq!(graph.crossings(), 0);
+        assert_eq!(graph.layers, vec![vec![0, 1], vec![3, 2]]);
+    }
+}
@@ -0,0 +1,46 @@
+//! Colors of the pins and links of the node editor, given by data type.
+use cake::TypeId;
+
+/// Color of pins accepting values of any type, such as the input of output
+/// nodes
+pub const ANY_TYPE_COLOR: [f32; 3] = [0.59, 0.59, 0.59];
+/// Opacity of pins that cannot be linked to the link being created
+pub const DIMMED_ALPHA: f32 = 0.2;
+const SATURATION: f32 = 0.55;
+const VALUE: f32 = 0.9;
+
+/// Get the color of a type. The hue is derived from a hash of the name of the
+/// type, so that a type keeps the same color across runs.
+pub fn type_color(type_id: TypeId) -> [f32; 3] {
+    // FNV-1a hash
+    let mut hash: u32 = 0x811c_9dc5;
+    for byte in type_id.name().bytes() {
+        hash ^= u32::from(byte);
+        hash = hash.wrapping_mul(0x0100_0193);
+    }
+    // The high bits are better distributed
+    let hue = (hash >> 16) as f32 / 65536.0 * 6.0;
+    hsv_to_rgb(hue, SATURATION, VALUE)
+}
+
+/// Add an opacity to a color
+pub fn with_alpha(color: [f32; 3], alpha: f32) -> [f32; 4] {
+    let [r, g, b] = color;
+    [r, g, b, alpha]
+}
+
+/// Convert a color from HSV to RGB. The hue is given in sixths of a turn.
+fn hsv_to_rgb(hue: f32, saturation: f32, value: f32) -> [f32; 3] {
+    let chroma = value * saturation;
+    let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+    let min = value - chroma;
+    let (r, g, b) = match hue as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    [r + min, g + min, b + min]
+}
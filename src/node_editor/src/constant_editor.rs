@@ -1,3 +1,5 @@
+use std::time::Instant;
+
 use imgui::{self, Ui};
 
 /// Trait to define how to draw constant editor.
@@ -10,4 +12,16 @@ pub trait ConstantEditor<T>: Default {
     fn editor<'a, I>(&self, ui: &Ui, constant: &T, id: I, read_only: bool) -> Option<T>
     where
         I: Into<imgui::Id<'a>>;
+
+    /// Draw a compact preview of a value flowing out of a node, shown when
+    /// hovering its output pin. `created_on` is the instant the value was
+    /// computed, so that what is derived from it can be kept until it
+    /// changes. Draw nothing by default.
+    fn preview(&self, _ui: &Ui, _value: &T, _created_on: Instant) {}
+
+    /// Get a short summary of a value flowing out of a node, shown next to its
+    /// output pin when inline previews are enabled. `None` by default.
+    fn summary(&self, _value: &T) -> Option<String> {
+        None
+    }
 }
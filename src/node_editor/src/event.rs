@@ -1,6 +1,9 @@
 use std::fmt;
 
-use cake::{macros, InputSlot, NodeId, Output, Transform, TransformIdx};
+use cake::{macros, InputSlot, NodeId, Output, Transform, TransformIdx, TransformMode};
+
+use layout::LinkExtremity;
+use vec2::Vec2;
 
 pub enum RenderEvent<T: 'static, E: 'static> {
     Connect(Output, InputSlot),
@@ -14,12 +17,29 @@ pub enum RenderEvent<T: 'static, E: 'static> {
         input_index: usize,
         val: Box<T>,
     },
-    RemoveNode(NodeId),
+    /// Remove nodes, undone in a single step
+    RemoveNodes(Vec<NodeId>),
+    /// Bypass, freeze or restore the normal computation of transforms, undone
+    /// in a single step
+    SetTransformModes(Vec<TransformIdx>, TransformMode),
     Import,
     Export,
     AddNewMacro,
     AddMacro(macros::MacroHandle<'static, T, E>),
     EditNode(NodeId),
+    /// Add a node, then link it to the pin from which the add-node popup was
+    /// opened
+    AddConnected {
+        node: Box<RenderEvent<T, E>>,
+        pin: LinkExtremity,
+    },
+    /// Paste a snippet of copied nodes with its top-left corner at `pos`
+    Paste {
+        snippet: String,
+        pos: Vec2,
+    },
+    Undo,
+    Redo,
 }
 
 impl<T, E> fmt::Debug for RenderEvent<T, E> {
@@ -39,12 +59,21 @@ impl<T, E> fmt::Debug for RenderEvent<T, E> {
                 "WriteDefaultInput {{ t_idx: {:?}, input_index: {:?}, .. }}",
                 t_idx, input_index
             ),
-            RemoveNode(node_id) => write!(f, "RemoveNode({:?})", node_id),
+            RemoveNodes(node_ids) => write!(f, "RemoveNodes({:?})", node_ids),
+            SetTransformModes(t_idxs, mode) => {
+                write!(f, "SetTransformModes({:?}, {:?})", t_idxs, mode)
+            }
             Import => write!(f, "Import"),
             Export => write!(f, "Export"),
             AddNewMacro => write!(f, "AddNewMacro"),
             AddMacro(handle) => write!(f, "AddMacro(id={}, name={:?})", handle.id(), handle.name()),
             EditNode(node_id) => write!(f, "EditNode({:?})", node_id),
+            AddConnected { node, pin } => {
+                write!(f, "AddConnected {{ node: {:?}, pin: {:?} }}", node, pin)
+            }
+            Paste { pos, .. } => write!(f, "Paste {{ pos: {:?}, .. }}", pos),
+            Undo => write!(f, "Undo"),
+            Redo => write!(f, "Redo"),
         }
     }
 }
@@ -64,12 +93,25 @@ pub trait ApplyRenderEvent<T, E> {
                 input_index,
                 val,
             } => self.write_default_input(t_idx, input_index, val),
-            RemoveNode(node_id) => self.remove_node(node_id),
+            RemoveNodes(node_ids) => {
+                for node_id in node_ids {
+                    self.remove_node(node_id);
+                }
+            }
+            SetTransformModes(t_idxs, mode) => {
+                for t_idx in t_idxs {
+                    self.set_transform_mode(t_idx, mode);
+                }
+            }
             Import => self.import(),
             Export => self.export(),
             AddNewMacro => self.add_new_macro(),
             AddMacro(handle) => self.add_macro(handle),
             EditNode(node_id) => self.edit_node(node_id),
+            AddConnected { node, pin } => self.add_connected(*node, pin),
+            Paste { snippet, pos } => self.paste(snippet, pos),
+            Undo => self.undo(),
+            Redo => self.redo(),
         }
     }
 
@@ -81,9 +123,14 @@ pub trait ApplyRenderEvent<T, E> {
     fn set_constant(&mut self, t_idx: TransformIdx, c: Box<T>);
     fn write_default_input(&mut self, t_idx: TransformIdx, input_index: usize, val: Box<T>);
     fn remove_node(&mut self, node_id: NodeId);
+    fn set_transform_mode(&mut self, t_idx: TransformIdx, mode: TransformMode);
     fn import(&mut self);
     fn export(&mut self);
     fn add_new_macro(&mut self);
     fn add_macro(&mut self, handle: macros::MacroHandle<'static, T, E>);
     fn edit_node(&mut self, node: NodeId);
+    fn add_connected(&mut self, node: RenderEvent<T, E>, pin: LinkExtremity);
+    fn paste(&mut self, snippet: String, pos: Vec2);
+    fn undo(&mut self);
+    fn redo(&mut self);
 }
@@ -0,0 +1,125 @@
+//! Titled frames drawn behind nodes to group them visually.
+//!
+//! Frames are only part of the layout of an editor and do not change the
+//! graph. Moving a frame moves the nodes inside it along. A collapsed frame
+//! only shows its title bar and hides the nodes it contains.
+use cake;
+
+use node_state::NodeState;
+use vec2::Vec2;
+
+pub const TITLE_BAR_HEIGHT: f32 = 20.0;
+/// Size of the square handle at the bottom-right corner of a frame
+pub const RESIZE_HANDLE_SIZE: f32 = 10.0;
+const MIN_SIZE: Vec2 = Vec2(80.0, 40.0);
+const DEFAULT_SIZE: Vec2 = Vec2(300.0, 200.0);
+/// Space between a new frame and the nodes it is made around
+const MARGIN: f32 = 20.0;
+
+/// Colors that can be given to a frame.
+pub const COLORS: [(&str, [f32; 3]); 6] = [
+    ("Gray", [0.45, 0.45, 0.5]),
+    ("Red", [0.7, 0.25, 0.25]),
+    ("Green", [0.25, 0.6, 0.3]),
+    ("Blue", [0.25, 0.4, 0.7]),
+    ("Yellow", [0.7, 0.6, 0.2]),
+    ("Purple", [0.55, 0.3, 0.65]),
+];
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Frame {
+    pub title: String,
+    pub pos: Vec2,
+    pub size: Vec2,
+    pub color: [f32; 3],
+    pub collapsed: bool,
+    /// Nodes hidden in the frame while it is collapsed
+    pub hidden_nodes: Vec<cake::NodeId>,
+}
+
+impl Frame {
+    /// Make an empty frame with its top-left corner at `pos`.
+    pub fn new(pos: Vec2) -> Self {
+        Self {
+            title: "Frame".to_owned(),
+            pos,
+            size: DEFAULT_SIZE,
+            color: COLORS[0].1,
+            collapsed: false,
+            hidden_nodes: vec![],
+        }
+    }
+
+    /// Make a frame around the given nodes.
+    pub fn around<'a, I>(node_states: I) -> Self
+    where
+        I: IntoIterator<Item = &'a NodeState>,
+    {
+        let mut bounds: Option<(Vec2, Vec2)> = None;
+        for state in node_states {
+            let (min, max) = (state.pos, state.pos + state.size);
+            bounds = Some(match bounds {
+                None => (min, max),
+                Some((bmin, bmax)) => (
+                    Vec2(bmin.0.min(min.0), bmin.1.min(min.1)),
+                    Vec2(bmax.0.max(max.0), bmax.1.max(max.1)),
+                ),
+            });
+        }
+        match bounds {
+            None => Self::new(Vec2::default()),
+            Some((min, max)) => Self {
+                pos: min - Vec2(MARGIN, MARGIN + TITLE_BAR_HEIGHT),
+                size: max - min + Vec2(2.0 * MARGIN, 2.0 * MARGIN + TITLE_BAR_HEIGHT),
+                ..Self::new(min)
+            },
+        }
+    }
+
+    /// Size of the frame as drawn, which is only its title bar if the frame
+    /// is collapsed.
+    pub fn drawn_size(&self) -> Vec2 {
+        if self.collapsed {
+            Vec2(self.size.0, TITLE_BAR_HEIGHT)
+        } else {
+            self.size
+        }
+    }
+
+    pub fn title_bar_contains(&self, point: Vec2) -> bool {
+        let rel = point - self.pos;
+        rel.0 >= 0.0 && rel.0 <= self.size.0 && rel.1 >= 0.0 && rel.1 <= TITLE_BAR_HEIGHT
+    }
+
+    pub fn resize_handle_contains(&self, point: Vec2) -> bool {
+        let rel = self.pos + self.size - point;
+        !self.collapsed
+            && rel.0 >= 0.0
+            && rel.0 <= RESIZE_HANDLE_SIZE
+            && rel.1 >= 0.0
+            && rel.1 <= RESIZE_HANDLE_SIZE
+    }
+
+    /// Check if the whole node is inside the frame.
+    pub fn contains_node(&self, state: &NodeState) -> bool {
+        let min = state.pos - self.pos;
+        let max = state.pos + state.size - self.pos;
+        min.0 >= 0.0 && min.1 >= 0.0 && max.0 <= self.size.0 && max.1 <= self.size.1
+    }
+
+    /// Check if the other frame is inside this frame.
+    pub fn contains_frame(&self, other: &Frame) -> bool {
+        let min = other.pos - self.pos;
+        let max = other.pos + other.drawn_size() - self.pos;
+        min.0 >= 0.0 && min.1 >= 0.0 && max.0 <= self.size.0 && max.1 <= self.size.1
+    }
+
+    pub fn resize(&mut self, delta: Vec2) {
+        let size = self.size + delta;
+        self.size = Vec2(size.0.max(MIN_SIZE.0), size.1.max(MIN_SIZE.1));
+    }
+
+    pub fn area(&self) -> f32 {
+        self.size.0 * self.size.1
+    }
+}
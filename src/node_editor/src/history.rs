@@ -0,0 +1,603 @@
+//! Undo and redo of the edits made in a node editor.
+//!
+//! Before a [`RenderEvent`] is applied, the change reverting it is computed
+//! from the current state of the graph and of its layout. Applying a change to
+//! undo an edit gives back the change to redo it.
+use std::time::{Duration, Instant};
+
+use cake::{self, InputSlot, DST};
+
+use event::RenderEvent;
+use layout::{LinkExtremity, NodeEditorLayout};
+use node_state::NodeState;
+
+/// Maximum number of steps that can be undone.
+const MAX_STEPS: usize = 100;
+/// Successive edits of the same value closer in time than this delay, such as
+/// the values taken while dragging a slider, are undone in a single step.
+const COALESCE_DELAY_MS: u64 = 500;
+
+/// Undo and redo stacks of a node editor.
+pub struct History<T: 'static, E: 'static> {
+    undo: Vec<Change<T, E>>,
+    redo: Vec<Change<T, E>>,
+    /// Value modified by the last recorded step, and when it was modified
+    last_edit: Option<(EditedValue, Instant)>,
+}
+
+impl<T, E> Default for History<T, E> {
+    fn default() -> Self {
+        Self {
+            undo: vec![],
+            redo: vec![],
+            last_edit: None,
+        }
+    }
+}
+
+/// What must be known about the graph to record an event, taken before the
+/// event is applied.
+pub enum Pending<T: 'static, E: 'static> {
+    Revert {
+        revert: Change<T, E>,
+        edited_value: Option<EditedValue>,
+        /// Link expected to exist once the event is applied
+        new_link: Option<(cake::Output, InputSlot)>,
+    },
+    /// Nodes that existed before some nodes were added, and the change
+    /// reverting the link replaced by a link to the new nodes, if any
+    Additions(Vec<cake::NodeId>, Option<Change<T, E>>),
+    Nothing,
+}
+
+/// A change of a graph and of the layout of its nodes.
+pub enum Change<T: 'static, E: 'static> {
+    Connect(cake::Output, InputSlot),
+    Disconnect(cake::Output, InputSlot),
+    SetConstant(cake::TransformIdx, T),
+    WriteDefaultInput(cake::TransformIdx, usize, T),
+    SetTransformMode(cake::TransformIdx, cake::TransformMode),
+    Remove(cake::NodeId),
+    Restore(cake::NodeSnapshot<'static, T, E>, Option<NodeState>),
+    /// Changes applied in order
+    Batch(Vec<Change<T, E>>),
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum EditedValue {
+    Constant(cake::TransformIdx),
+    DefaultInput(cake::TransformIdx, usize),
+}
+
+impl<T, E> History<T, E>
+where
+    T: Clone + cake::ConvertibleVariants,
+{
+    /// Prepare the recording of `event`, before it is applied to `dst`.
+    pub fn prepare(
+        &self,
+        event: &RenderEvent<T, E>,
+        dst: &DST<'static, T, E>,
+        layout: &NodeEditorLayout<T, E>,
+    ) -> Pending<T, E> {
+        use event::RenderEvent::*;
+        let (revert, edited_value, new_link) = match *event {
+            Connect(output, input_slot) => (
+                revert_connect(output, input_slot, dst),
+                None,
+                Some((output, input_slot)),
+            ),
+            Disconnect(output, input_slot) => {
+                (revert_disconnect(output, input_slot, dst), None, None)
+            }
+            SetConstant(t_idx, _) => (
+                revert_set_constant(t_idx, dst),
+                Some(EditedValue::Constant(t_idx)),
+                None,
+            ),
+            WriteDefaultInput {
+                t_idx, input_index, ..
+            } => (
+                revert_write_default_input(t_idx, input_index, dst),
+                Some(EditedValue::DefaultInput(t_idx, input_index)),
+                None,
+            ),
+            // All the nodes are put back with their links, in any order, as
+            // the snapshots are taken before any node is removed
+            RemoveNodes(ref node_ids) => (
+                batch(
+                    node_ids
+                        .iter()
+                        .filter_map(|node_id| revert_remove(*node_id, dst, layout)),
+                ),
+                None,
+                None,
+            ),
+            SetTransformModes(ref t_idxs, mode) => (
+                batch(
+                    t_idxs
+                        .iter()
+                        .filter_map(|t_idx| revert_set_transform_mode(*t_idx, mode, dst)),
+                ),
+                None,
+                None,
+            ),
+            AddTransform(_)
+            | CreateOutput
+            | AddConstant(_)
+            | AddNewMacro
+            | AddMacro(_)
+            | Paste { .. } => {
+                return Pending::Additions(dst.node_ids(), None);
+            }
+            AddConnected { pin, .. } => {
+                let replaced_link = match pin {
+                    LinkExtremity::Input(input_slot) => linked_output(input_slot, dst)
+                        .and_then(|previous| previous)
+                        .map(|previous| Change::Connect(previous, input_slot)),
+                    LinkExtremity::Output(_) => None,
+                };
+                return Pending::Additions(dst.node_ids(), replaced_link);
+            }
+            Import | Export | EditNode(_) | Undo | Redo => return Pending::Nothing,
+        };
+        match revert {
+            Some(revert) => Pending::Revert {
+                revert,
+                edited_value,
+                new_link,
+            },
+            None => Pending::Nothing,
+        }
+    }
+
+    /// Record the step reverting an event, once the event is applied to `dst`.
+    pub fn record(&mut self, pending: Pending<T, E>, dst: &DST<'static, T, E>) {
+        match pending {
+            Pending::Revert {
+                revert,
+                edited_value,
+                new_link,
+            } => {
+                if let Some((output, input_slot)) = new_link {
+                    let connected = dst
+                        .links_iter()
+                        .any(|(o, slot)| *o == output && slot == input_slot);
+                    if !connected {
+                        // The connection was refused, nothing changed
+                        return;
+                    }
+                }
+                let now = Instant::now();
+                let coalesce = match (edited_value, self.last_edit) {
+                    (Some(value), Some((last_value, last_time))) => {
+                        value == last_value
+                            && now.duration_since(last_time)
+                                < Duration::from_millis(COALESCE_DELAY_MS)
+                    }
+                    _ => false,
+                };
+                // The step that is already recorded reverts the value to what
+                // it was before the first edit
+                if !coalesce {
+                    self.push(revert);
+                }
+                self.last_edit = edited_value.map(|value| (value, now));
+            }
+            Pending::Additions(previous_ids, replaced_link) => {
+                let mut removals: Vec<_> = dst
+                    .node_ids()
+                    .into_iter()
+                    .filter(|id| !previous_ids.contains(id))
+                    .map(Change::Remove)
+                    .collect();
+                if !removals.is_empty() {
+                    removals.extend(replaced_link);
+                    self.push(Change::Batch(removals));
+                    self.last_edit = None;
+                }
+            }
+            Pending::Nothing => (),
+        }
+    }
+
+    /// Undo the last step. Do nothing if there is nothing to undo.
+    pub fn undo(&mut self, dst: &mut DST<'static, T, E>, layout: &mut NodeEditorLayout<T, E>) {
+        if let Some(change) = self.undo.pop() {
+            self.redo.push(change.apply(dst, layout));
+        }
+        self.last_edit = None;
+    }
+
+    /// Redo the last undone step. Do nothing if there is nothing to redo.
+    pub fn redo(&mut self, dst: &mut DST<'static, T, E>, layout: &mut NodeEditorLayout<T, E>) {
+        if let Some(change) = self.redo.pop() {
+            self.undo.push(change.apply(dst, layout));
+        }
+        self.last_edit = None;
+    }
+
+    fn push(&mut self, change: Change<T, E>) {
+        self.undo.push(change);
+        if self.undo.len() > MAX_STEPS {
+            self.undo.remove(0);
+        }
+        self.redo.clear();
+    }
+}
+
+impl<T, E> Change<T, E>
+where
+    T: Clone + cake::ConvertibleVariants,
+{
+    /// Apply the change and return the change reverting it.
+    fn apply(
+        self,
+        dst: &mut DST<'static, T, E>,
+        layout: &mut NodeEditorLayout<T, E>,
+    ) -> Change<T, E> {
+        if let Change::Batch(changes) = self {
+            let mut reverts: Vec<_> = changes
+                .into_iter()
+                .map(|change| change.apply(dst, layout))
+                .collect();
+            reverts.reverse();
+            return Change::Batch(reverts);
+        }
+
+        let revert = self
+            .revert(dst, layout)
+            .unwrap_or_else(|| Change::Batch(vec![]));
+        match self {
+            Change::Connect(output, InputSlot::Transform(input)) => {
+                if let Err(e) = dst.connect(output, input) {
+                    eprintln!("{:?}", e);
+                }
+            }
+            Change::Connect(output, InputSlot::Output(output_id)) => {
+                dst.update_output(output_id, output)
+            }
+            Change::Disconnect(output, InputSlot::Transform(input)) => {
+                dst.disconnect(&output, &input)
+            }
+            Change::Disconnect(_, InputSlot::Output(output_id)) => dst.detach_output(&output_id),
+            Change::SetConstant(t_idx, val) => {
+                if let Some(t) = dst.get_transform_mut(t_idx) {
+                    t.set_constant(val);
+                }
+            }
+            Change::WriteDefaultInput(t_idx, input_index, val) => {
+                if let Some(mut inputs) = dst.get_default_inputs_mut(t_idx) {
+                    inputs.write(input_index, val);
+                }
+            }
+            Change::SetTransformMode(t_idx, mode) => dst.set_transform_mode(t_idx, mode),
+            Change::Remove(node_id) => {
+                dst.remove_node(&node_id);
+                layout.forget_node(&node_id);
+            }
+            Change::Restore(snapshot, state) => {
+                let node_id = snapshot.node_id();
+                dst.restore_node(snapshot);
+                if let Some(state) = state {
+                    layout.restore_node_state(node_id, state);
+                }
+            }
+            Change::Batch(_) => unreachable!(),
+        }
+        revert
+    }
+
+    /// Get the change reverting this change, before it is applied.
+    ///
+    /// Return [`None`] if this change does not modify anything.
+    fn revert(
+        &self,
+        dst: &DST<'static, T, E>,
+        layout: &NodeEditorLayout<T, E>,
+    ) -> Option<Change<T, E>> {
+        match *self {
+            Change::Connect(output, input_slot) => revert_connect(output, input_slot, dst),
+            Change::Disconnect(output, input_slot) => revert_disconnect(output, input_slot, dst),
+            Change::SetConstant(t_idx, _) => revert_set_constant(t_idx, dst),
+            Change::WriteDefaultInput(t_idx, input_index, _) => {
+                revert_write_default_input(t_idx, input_index, dst)
+            }
+            Change::SetTransformMode(t_idx, mode) => revert_set_transform_mode(t_idx, mode, dst),
+            Change::Remove(node_id) => revert_remove(node_id, dst, layout),
+            Change::Restore(ref snapshot, _) => Some(Change::Remove(snapshot.node_id())),
+            Change::Batch(_) => unreachable!("Batches are reverted while they are applied"),
+        }
+    }
+}
+
+/// Make a single step of `changes`, if there are any.
+fn batch<T, E, I>(changes: I) -> Option<Change<T, E>>
+where
+    I: Iterator<Item = Change<T, E>>,
+{
+    let changes: Vec<_> = changes.collect();
+    if changes.is_empty() {
+        None
+    } else {
+        Some(Change::Batch(changes))
+    }
+}
+
+fn revert_connect<T, E>(
+    output: cake::Output,
+    input_slot: InputSlot,
+    dst: &DST<'static, T, E>,
+) -> Option<Change<T, E>> {
+    match linked_output(input_slot, dst)? {
+        Some(previous) if previous == output => None,
+        Some(previous) => Some(Change::Connect(previous, input_slot)),
+        None => Some(Change::Disconnect(output, input_slot)),
+    }
+}
+
+/// Get the output linked to `input_slot`, if any.
+///
+/// Return [`None`] if the slot does not exist.
+fn linked_output<T, E>(
+    input_slot: InputSlot,
+    dst: &DST<'static, T, E>,
+) -> Option<Option<cake::Output>> {
+    match input_slot {
+        InputSlot::Transform(input) => dst
+            .outputs_attached_to_transform(input.t_idx)?
+            .get(input.index())
+            .cloned(),
+        InputSlot::Output(output_id) => dst
+            .outputs_iter()
+            .find(|(id, _)| **id == output_id)
+            .map(|(_, output)| *output),
+    }
+}
+
+fn revert_disconnect<T, E>(
+    output: cake::Output,
+    input_slot: InputSlot,
+    dst: &DST<'static, T, E>,
+) -> Option<Change<T, E>> {
+    if dst
+        .links_iter()
+        .any(|(o, slot)| *o == output && slot == input_slot)
+    {
+        Some(Change::Connect(output, input_slot))
+    } else {
+        None
+    }
+}
+
+fn revert_set_constant<T, E>(
+    t_idx: cake::TransformIdx,
+    dst: &DST<'static, T, E>,
+) -> Option<Change<T, E>>
+where
+    T: Clone,
+{
+    dst.get_transform(t_idx).and_then(|t| {
+        if let cake::Algorithm::Constant(ref constant) = t.algorithm() {
+            Some(Change::SetConstant(t_idx, constant.clone()))
+        } else {
+            None
+        }
+    })
+}
+
+fn revert_write_default_input<T, E>(
+    t_idx: cake::TransformIdx,
+    input_index: usize,
+    dst: &DST<'static, T, E>,
+) -> Option<Change<T, E>>
+where
+    T: Clone + cake::VariantName,
+{
+    dst.get_default_inputs(t_idx)
+        .and_then(|defaults| defaults.get(input_index).cloned())
+        .and_then(|default| default)
+        .map(|default| Change::WriteDefaultInput(t_idx, input_index, default))
+}
+
+fn revert_set_transform_mode<T, E>(
+    t_idx: cake::TransformIdx,
+    mode: cake::TransformMode,
+    dst: &DST<'static, T, E>,
+) -> Option<Change<T, E>> {
+    dst.get_transform_mode(t_idx)
+        .filter(|previous| *previous != mode)
+        .map(|previous| Change::SetTransformMode(t_idx, previous))
+}
+
+fn revert_remove<T, E>(
+    node_id: cake::NodeId,
+    dst: &DST<'static, T, E>,
+    layout: &NodeEditorLayout<T, E>,
+) -> Option<Change<T, E>>
+where
+    T: Clone,
+{
+    dst.node_snapshot(&node_id).map(|snapshot| {
+        let state = layout.node_states().get(&node_id).cloned();
+        Change::Restore(snapshot, state)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use cake::{self, InputSlot, NodeId, Output, TransformIdx};
+
+    use event::{ApplyRenderEvent, RenderEvent};
+    use NodeEditor;
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    enum Value {
+        Integer(i64),
+    }
+
+    impl cake::VariantName for Value {
+        fn variant_name(&self) -> &'static str {
+            "Integer"
+        }
+        fn variant_names() -> &'static [&'static str] {
+            &["Integer"]
+        }
+    }
+
+    impl cake::ConvertibleVariants for Value {
+        const CONVERTION_TABLE: &'static [cake::ConvertibleVariant<Self>] = &[];
+    }
+
+    impl cake::DefaultFor for Value {
+        fn default_for(_: &str) -> Self {
+            Value::Integer(0)
+        }
+    }
+
+    impl cake::NamedAlgorithms<()> for Value {
+        fn get_transform(_: &str) -> Option<&'static cake::Transform<'static, Self, ()>> {
+            None
+        }
+    }
+
+    /// Apply `event` like a node editor does when rendering it.
+    fn edit(editor: &mut NodeEditor<Value, ()>, event: RenderEvent<Value, ()>) {
+        let pending = editor.history.prepare(&event, &editor.dst, &editor.layout);
+        editor.apply_event(event);
+        editor.history.record(pending, &editor.dst);
+    }
+
+    fn add_constant(editor: &mut NodeEditor<Value, ()>) -> TransformIdx {
+        edit(editor, RenderEvent::AddConstant("Integer"));
+        editor
+            .dst
+            .node_ids()
+            .into_iter()
+            .filter_map(|id| match id {
+                NodeId::Transform(t_idx) => Some(t_idx),
+                NodeId::Output(_) => None,
+            })
+            .last()
+            .unwrap()
+    }
+
+    fn constant(editor: &NodeEditor<Value, ()>, t_idx: TransformIdx) -> Option<Value> {
+        editor
+            .dst
+            .get_transform(t_idx)
+            .map(|t| match t.algorithm() {
+                cake::Algorithm::Constant(ref value) => value.clone(),
+                _ => panic!("Not a constant"),
+            })
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let mut editor = NodeEditor::default();
+        let t_idx = add_constant(&mut editor);
+        edit(&mut editor, RenderEvent::CreateOutput);
+        let output_id = *editor.dst.outputs_iter().next().unwrap().0;
+        let output = Output::new(t_idx, 0);
+        edit(
+            &mut editor,
+            RenderEvent::Connect(output, InputSlot::Output(output_id)),
+        );
+        let linked =
+            |editor: &NodeEditor<Value, ()>| editor.dst.outputs_iter().next().map(|(_, o)| *o);
+        assert_eq!(linked(&editor), Some(Some(output)));
+
+        edit(&mut editor, RenderEvent::Undo);
+        assert_eq!(linked(&editor), Some(None));
+        edit(&mut editor, RenderEvent::Undo);
+        assert_eq!(linked(&editor), None);
+        edit(&mut editor, RenderEvent::Redo);
+        edit(&mut editor, RenderEvent::Redo);
+        assert_eq!(linked(&editor), Some(Some(output)));
+        // Nothing is left to redo
+        edit(&mut editor, RenderEvent::Redo);
+        assert_eq!(linked(&editor), Some(Some(output)));
+
+        // A new edit clears the steps that could be redone
+        edit(&mut editor, RenderEvent::Undo);
+        edit(
+            &mut editor,
+            RenderEvent::RemoveNodes(vec![NodeId::Transform(t_idx)]),
+        );
+        edit(&mut editor, RenderEvent::Redo);
+        assert_eq!(linked(&editor), Some(None));
+        assert_eq!(constant(&editor, t_idx), None);
+        edit(&mut editor, RenderEvent::Undo);
+        assert_eq!(constant(&editor, t_idx), Some(Value::Integer(0)));
+    }
+
+    #[test]
+    fn test_undo_removal_of_linked_nodes() {
+        let mut editor = NodeEditor::default();
+        let t_idx = add_constant(&mut editor);
+        edit(&mut editor, RenderEvent::CreateOutput);
+        let output_id = *editor.dst.outputs_iter().next().unwrap().0;
+        let output = Output::new(t_idx, 0);
+        edit(
+            &mut editor,
+            RenderEvent::Connect(output, InputSlot::Output(output_id)),
+        );
+        let linked =
+            |editor: &NodeEditor<Value, ()>| editor.dst.outputs_iter().next().map(|(_, o)| *o);
+
+        edit(
+            &mut editor,
+            RenderEvent::RemoveNodes(vec![NodeId::Transform(t_idx), NodeId::Output(output_id)]),
+        );
+        assert_eq!(constant(&editor, t_idx), None);
+        assert_eq!(linked(&editor), None);
+
+        // Both nodes and their link are put back in one step
+        edit(&mut editor, RenderEvent::Undo);
+        assert_eq!(constant(&editor, t_idx), Some(Value::Integer(0)));
+        assert_eq!(linked(&editor), Some(Some(output)));
+        edit(&mut editor, RenderEvent::Redo);
+        assert_eq!(constant(&editor, t_idx), None);
+        assert_eq!(linked(&editor), None);
+    }
+
+    #[test]
+    fn test_coalesce_drag() {
+        let mut editor = NodeEditor::default();
+        let t_idx = add_constant(&mut editor);
+        // Values taken while dragging a slider
+        for i in 1..4 {
+            edit(
+                &mut editor,
+                RenderEvent::SetConstant(t_idx, Box::new(Value::Integer(i))),
+            );
+        }
+        assert_eq!(constant(&editor, t_idx), Some(Value::Integer(3)));
+
+        // The whole drag is undone in one step
+        edit(&mut editor, RenderEvent::Undo);
+        assert_eq!(constant(&editor, t_idx), Some(Value::Integer(0)));
+        edit(&mut editor, RenderEvent::Redo);
+        assert_eq!(constant(&editor, t_idx), Some(Value::Integer(3)));
+        edit(&mut editor, RenderEvent::Undo);
+        edit(&mut editor, RenderEvent::Undo);
+        assert_eq!(constant(&editor, t_idx), None);
+    }
+
+    #[test]
+    fn test_reset_on_import() {
+        let mut editor = NodeEditor::default();
+        let t_idx = add_constant(&mut editor);
+        edit(
+            &mut editor,
+            RenderEvent::SetConstant(t_idx, Box::new(Value::Integer(5))),
+        );
+        let mut exported = vec![];
+        editor.export_to_buf(&mut exported).unwrap();
+        editor.import_from_buf(&exported[..]).unwrap();
+
+        // The edits made before the import cannot be undone
+        edit(&mut editor, RenderEvent::Undo);
+        assert_eq!(constant(&editor, t_idx), Some(Value::Integer(5)));
+    }
+}
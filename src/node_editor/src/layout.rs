@@ -1,20 +1,27 @@
-use collections::HashSet;
+use collections::{BTreeMap, HashSet};
 use std::error::Error;
+use std::sync::Arc;
 
 use imgui::{
-    ChildWindow, ImString, Key, MenuItem, MouseButton, MouseCursor, Selectable, StyleColor,
-    StyleVar, Ui, WindowDrawList, WindowFocusedFlags,
+    ChildWindow, FocusedWidget, ImString, Key, MenuItem, MouseButton, MouseCursor, Selectable,
+    StyleColor, StyleVar, Ui, WindowDrawList, WindowFocusedFlags,
 };
 use serde::{Deserialize, Serialize};
 
 use cake::{self, InputSlot, Transform, VariantName, DST};
 
+use arrange;
+use color;
 use constant_editor::ConstantEditor;
 use event::RenderEvent;
+use frame::{self, Frame};
 use id_stack::GetId;
 use imgui_file_explorer::UiFileExplorer;
-use node_state::NodeStates;
+use minimap::{self, Minimap};
+use node_state::{NodeState, NodeStates};
+use palette;
 use scrolling::Scrolling;
+use snippet;
 use vec2::Vec2;
 
 pub struct NodeEditorLayout<T: 'static, E: 'static> {
@@ -22,6 +29,16 @@ pub struct NodeEditorLayout<T: 'static, E: 'static> {
     active_node: Option<cake::NodeId>,
     drag_node: Option<cake::NodeId>,
     creating_link: Option<LinkExtremity>,
+    /// Pin from which a link was dropped on an empty spot of the canvas.
+    /// The node added from the popup opened there is linked to it.
+    new_node_pin: Option<LinkExtremity>,
+    /// Whether the link being created was dragged away from its pin
+    link_dragged: bool,
+    /// Whether the link being created is over a pin refusing it
+    link_refused: bool,
+    /// Filter of the add-node popup
+    node_search: String,
+    focus_node_search: bool,
     deleting_link: Option<(cake::Output, InputSlot)>,
     delete_link_list: HashSet<Option<(cake::Output, InputSlot)>>,
     delete_flag: bool,
@@ -31,7 +48,32 @@ pub struct NodeEditorLayout<T: 'static, E: 'static> {
     show_top_pane: bool,
     show_connection_names: bool,
     scrolling: Scrolling,
+    /// Nodes moving to the position they were given by "Arrange"
+    arranging: BTreeMap<cake::NodeId, Scrolling>,
+    /// Arrange all nodes on next render if some nodes have no state, as is
+    /// the case of imported programs without layout information
+    arrange_unplaced_nodes: bool,
+    frames: Vec<Frame>,
+    frame_drag: Option<FrameDrag>,
+    /// Frame whose context menu is opened
+    context_frame: Option<usize>,
+    /// Transform whose context menu is opened
+    context_node: Option<cake::TransformIdx>,
+    /// Screen position of the origin of the graph canvas
+    canvas_offset: Vec2,
+    /// Size of the graph canvas on screen
+    canvas_size: Vec2,
+    /// Size on screen of a unit of the graph canvas
+    zoom: f32,
     show_grid: bool,
+    show_minimap: bool,
+    /// Show a summary of the value flowing out of each output pin
+    show_value_previews: bool,
+    /// Output pin whose value is previewed in a tooltip
+    hovered_output: Option<cake::Output>,
+    minimap_hovered: bool,
+    /// Area shown on the minimap while the view is moved on it
+    minimap_bounds: Option<(Vec2, Vec2)>,
     import_opened: bool,
     pub import_path: Option<std::path::PathBuf>,
     pub is_macro: bool,
@@ -47,6 +89,11 @@ impl<T, E> Default for NodeEditorLayout<T, E> {
             active_node: None,
             drag_node: None,
             creating_link: None,
+            new_node_pin: None,
+            link_dragged: false,
+            link_refused: false,
+            node_search: String::new(),
+            focus_node_search: false,
             deleting_link: Default::default(),
             delete_link_list: HashSet::new(),
             delete_flag: false,
@@ -56,7 +103,21 @@ impl<T, E> Default for NodeEditorLayout<T, E> {
             show_top_pane: true,
             show_connection_names: true,
             scrolling: Default::default(),
+            arranging: BTreeMap::new(),
+            arrange_unplaced_nodes: false,
+            frames: vec![],
+            frame_drag: None,
+            context_frame: None,
+            context_node: None,
+            canvas_offset: Vec2::default(),
+            canvas_size: Vec2::default(),
+            zoom: 1.0,
             show_grid: true,
+            show_minimap: true,
+            show_value_previews: false,
+            hovered_output: None,
+            minimap_hovered: false,
+            minimap_bounds: None,
             import_opened: false,
             import_path: None,
             is_macro: false,
@@ -66,14 +127,181 @@ impl<T, E> Default for NodeEditorLayout<T, E> {
     }
 }
 
+enum FrameDrag {
+    /// Move a frame along with the nodes and the frames inside it
+    Move {
+        frames: Vec<usize>,
+        nodes: Vec<cake::NodeId>,
+    },
+    Resize(usize),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum LinkExtremity {
     Output(cake::Output),
     Input(InputSlot),
 }
 
+impl LinkExtremity {
+    pub fn is_output(&self) -> bool {
+        match *self {
+            LinkExtremity::Output(_) => true,
+            LinkExtremity::Input(_) => false,
+        }
+    }
+
+    /// Check if a link can be made between this pin and `other`.
+    ///
+    /// Return the error `dst` would give when connecting them if it refuses
+    /// the link, or `Err(None)` if both pins are inputs or both are outputs.
+    pub fn check_link<T, E>(
+        &self,
+        dst: &DST<'static, T, E>,
+        other: &LinkExtremity,
+    ) -> Result<(), Option<cake::DSTError>>
+    where
+        T: VariantName + cake::ConvertibleVariants,
+    {
+        match self.link_with(other) {
+            None => Err(None),
+            Some((output, InputSlot::Transform(input))) => {
+                dst.check_connect(output, input).map_err(Some)
+            }
+            // Output nodes accept values of any type
+            Some((_, InputSlot::Output(_))) => Ok(()),
+        }
+    }
+
+    /// Get the link between this pin and `other`, or `None` if both pins are
+    /// inputs or both are outputs.
+    pub fn link_with(&self, other: &LinkExtremity) -> Option<(cake::Output, InputSlot)> {
+        match (*self, *other) {
+            (LinkExtremity::Output(output), LinkExtremity::Input(input_slot))
+            | (LinkExtremity::Input(input_slot), LinkExtremity::Output(output)) => {
+                Some((output, input_slot))
+            }
+            _ => None,
+        }
+    }
+
+    /// Get the type of the values going through this pin, or `None` if it
+    /// accepts values of any type.
+    pub fn type_id<T, E>(&self, dst: &DST<'static, T, E>) -> Option<cake::TypeId>
+    where
+        T: VariantName,
+    {
+        match *self {
+            LinkExtremity::Output(output) => dst
+                .get_transform(output.t_idx)?
+                .outputs()
+                .get(output.index())
+                .cloned(),
+            LinkExtremity::Input(InputSlot::Transform(input)) => dst
+                .get_transform(input.t_idx)?
+                .input_types()
+                .get(input.index())
+                .cloned(),
+            LinkExtremity::Input(InputSlot::Output(_)) => None,
+        }
+    }
+
+    /// Find the first slot of a node with the given input and output types
+    /// that can be linked to this pin.
+    ///
+    /// Return the index of an input slot if this pin is an output, and the
+    /// index of an output slot otherwise.
+    pub fn compatible_slot<T, E>(
+        &self,
+        dst: &DST<'static, T, E>,
+        inputs: &[cake::TypeId],
+        outputs: &[cake::TypeId],
+    ) -> Option<usize>
+    where
+        T: VariantName + cake::ConvertibleVariants,
+    {
+        match *self {
+            LinkExtremity::Output(output) => {
+                let from = *dst
+                    .get_transform(output.t_idx)?
+                    .outputs()
+                    .get(output.index())?;
+                inputs
+                    .iter()
+                    .position(|input| T::convertible(from.name(), input.name()))
+            }
+            LinkExtremity::Input(InputSlot::Transform(input)) => {
+                let into = *dst
+                    .get_transform(input.t_idx)?
+                    .input_types()
+                    .get(input.index())?;
+                outputs
+                    .iter()
+                    .position(|output| T::convertible(output.name(), into.name()))
+            }
+            // Output nodes accept values of any type
+            LinkExtremity::Input(InputSlot::Output(_)) => {
+                if outputs.is_empty() {
+                    None
+                } else {
+                    Some(0)
+                }
+            }
+        }
+    }
+
+    /// Get the link between this pin and the node `node_id` of `dst`.
+    pub fn link_to<T, E>(
+        &self,
+        dst: &DST<'static, T, E>,
+        node_id: cake::NodeId,
+    ) -> Option<(cake::Output, InputSlot)>
+    where
+        T: VariantName + cake::ConvertibleVariants,
+    {
+        match (*self, node_id) {
+            (LinkExtremity::Output(output), cake::NodeId::Output(output_id)) => {
+                Some((output, InputSlot::Output(output_id)))
+            }
+            (LinkExtremity::Input(_), cake::NodeId::Output(_)) => None,
+            (_, cake::NodeId::Transform(t_idx)) => {
+                let t = dst.get_transform(t_idx)?;
+                let slot_idx = self.compatible_slot(dst, &t.input_types(), &t.outputs())?;
+                Some(match *self {
+                    LinkExtremity::Output(output) => (
+                        output,
+                        InputSlot::Transform(cake::Input::new(t_idx, slot_idx)),
+                    ),
+                    LinkExtremity::Input(input_slot) => {
+                        (cake::Output::new(t_idx, slot_idx), input_slot)
+                    }
+                })
+            }
+        }
+    }
+}
+
 const NODE_FRAME_COLOR: [f32; 3] = [0.39, 0.39, 0.39];
+const ERROR_COLOR: [f32; 4] = [1.0, 0.4, 0.4, 1.0];
+const BYPASSED_COLOR: [f32; 4] = [0.9, 0.6, 0.2, 1.0];
+const FROZEN_COLOR: [f32; 4] = [0.4, 0.7, 1.0, 1.0];
+/// Index of the 'B' key, bypassing the selected nodes
+const KEY_B_INDEX: u32 = 11;
+/// Index of the 'D' key in the keys of imgui's IO, which are indexed by
+/// winit's virtual key codes.
+const KEY_D_INDEX: u32 = 13;
+/// Index of the 'F' key, fitting the selected nodes in view
+const KEY_F_INDEX: u32 = 15;
+/// Offset of duplicated nodes from the original nodes.
+const DUPLICATE_OFFSET: Vec2 = Vec2(20.0, 20.0);
 const NODE_WINDOW_PADDING: Vec2 = Vec2(5.0, 5.0);
-const CURRENT_FONT_WINDOW_SCALE: f32 = 1.0;
+/// Size assumed for nodes that were not drawn yet, when they are arranged.
+const ARRANGED_NODE_MIN_SIZE: Vec2 = Vec2(120.0, 60.0);
+const MIN_ZOOM: f32 = 0.25;
+const MAX_ZOOM: f32 = 2.0;
+/// Factor applied to the zoom for each step of the mouse wheel
+const ZOOM_STEP: f32 = 1.1;
+/// Space left around nodes fit in view, on screen
+const FIT_MARGIN: f32 = 40.0;
 
 impl<T, E> NodeEditorLayout<T, E>
 where
@@ -89,10 +317,13 @@ where
     E: 'static + Error,
 {
     /// Draw the full node editor on the current window.
+    ///
+    /// The values flowing out of the nodes are previewed from `cache`, if any.
     pub fn render<ED>(
         &mut self,
         ui: &Ui,
         dst: &DST<'static, T, E>,
+        cache: Option<&cake::Cache<T, cake::compute::ComputeError<E>>>,
         addable_nodes: &[&'static Transform<T, E>],
         addable_macros: &cake::macros::MacroManager<'static, T, E>,
         constant_editor: &ED,
@@ -102,23 +333,60 @@ where
     {
         self.events = vec![];
 
+        if self.arrange_unplaced_nodes {
+            self.arrange_unplaced_nodes = false;
+            if dst
+                .node_ids()
+                .iter()
+                .any(|id| self.node_states.get(id).is_none())
+            {
+                self.node_states.deselect_all();
+                self.arrange(dst, false);
+            }
+        }
+        // Move arranged nodes closer to their target position
+        let mut settled = vec![];
+        for (node_id, moving) in &mut self.arranging {
+            moving.tick();
+            if let Some(state) = self.node_states.get_mut(node_id) {
+                state.pos = if moving.is_settled() {
+                    moving.get_target()
+                } else {
+                    moving.get_current()
+                };
+            }
+            if moving.is_settled() {
+                settled.push(*node_id);
+            }
+        }
+        for node_id in settled {
+            self.arranging.remove(&node_id);
+        }
+
         for idx in dst.node_ids() {
             // Initialization of node states
             let win_pos: Vec2 = ui.cursor_screen_pos().into();
             let scroll = self.scrolling.get_current();
             let clue = if ui.is_window_focused() {
-                let offset = win_pos - scroll;
+                let offset = win_pos - scroll * self.zoom;
                 let mouse_pos: Vec2 = ui.io().mouse_pos.into();
-                mouse_pos * 0.7 - offset
+                (mouse_pos * 0.7 - offset) * (1.0 / self.zoom)
             } else {
                 scroll + Vec2(30.0, 30.0)
             };
             self.node_states.init_node(&idx, clue);
         }
         if self.show_left_pane {
-            self.render_left_pane(ui, dst);
+            self.render_left_pane(ui, dst, cache);
         }
-        self.render_graph_node(ui, dst, addable_nodes, addable_macros, constant_editor);
+        self.render_graph_node(
+            ui,
+            dst,
+            cache,
+            addable_nodes,
+            addable_macros,
+            constant_editor,
+        );
 
         if ui.is_window_focused_with_flags(WindowFocusedFlags::CHILD_WINDOWS)
             && !ui.io().want_capture_keyboard
@@ -128,13 +396,82 @@ where
             if ui.is_key_pressed(delete_index) || ui.is_key_pressed(backspace_index) {
                 self.delete_selected_nodes();
             }
+            if ui.io().key_ctrl {
+                let c_index = ui.key_index(Key::C);
+                let v_index = ui.key_index(Key::V);
+                if ui.is_key_pressed(c_index) {
+                    if let Some((snippet, _)) = self.copy_selected_nodes(dst) {
+                        ui.set_clipboard_text(&ImString::new(snippet));
+                    }
+                } else if ui.is_key_pressed(v_index) {
+                    if let Some(snippet) = ui.clipboard_text() {
+                        let mouse_pos: Vec2 = ui.io().mouse_pos.into();
+                        self.events.push(RenderEvent::Paste {
+                            snippet: snippet.to_str().to_owned(),
+                            pos: (mouse_pos - self.canvas_offset) * (1.0 / self.zoom),
+                        });
+                    }
+                } else if ui.is_key_pressed(KEY_D_INDEX) {
+                    if let Some((snippet, origin)) = self.copy_selected_nodes(dst) {
+                        self.events.push(RenderEvent::Paste {
+                            snippet,
+                            pos: origin + DUPLICATE_OFFSET,
+                        });
+                    }
+                }
+            }
+            if !ui.io().key_ctrl {
+                let home_index = ui.key_index(Key::Home);
+                if ui.is_key_pressed(KEY_F_INDEX) {
+                    self.fit_in_view(true);
+                } else if ui.is_key_pressed(home_index) {
+                    self.fit_in_view(false);
+                } else if ui.is_key_pressed(KEY_B_INDEX) {
+                    self.bypass_selected_nodes(dst);
+                }
+            }
+            let z_index = ui.key_index(Key::Z);
+            let y_index = ui.key_index(Key::Y);
+            if ui.io().key_ctrl {
+                if ui.is_key_pressed(z_index) && !ui.io().key_shift {
+                    self.events.push(RenderEvent::Undo);
+                } else if ui.is_key_pressed(z_index) || ui.is_key_pressed(y_index) {
+                    self.events.push(RenderEvent::Redo);
+                }
+            }
         }
         self.scrolling.tick();
 
         ::std::mem::replace(&mut self.events, vec![])
     }
 
-    fn render_left_pane(&mut self, ui: &Ui, dst: &DST<'static, T, E>) {
+    /// Serialize the selected nodes. Return the snippet along with the
+    /// position of its top-left corner, or `None` if no node is selected.
+    fn copy_selected_nodes(&self, dst: &DST<'static, T, E>) -> Option<(String, Vec2)> {
+        let selected_node_ids: Vec<_> = self
+            .node_states
+            .iter()
+            .filter(|(_, state)| state.selected)
+            .map(|(id, _)| *id)
+            .collect();
+        if selected_node_ids.is_empty() {
+            return None;
+        }
+        match snippet::copy(dst, &self.node_states, &selected_node_ids) {
+            Ok(copied) => Some(copied),
+            Err(e) => {
+                eprintln!("Error on copy! {}", e);
+                None
+            }
+        }
+    }
+
+    fn render_left_pane(
+        &mut self,
+        ui: &Ui,
+        dst: &DST<'static, T, E>,
+        cache: Option<&cake::Cache<T, cake::compute::ComputeError<E>>>,
+    ) {
         const LEFT_PANE_DEFAULT_RELATIVE_WIDTH: f32 = 0.2;
         let window_size = Vec2::new(ui.window_size());
         let pane_width = *self
@@ -155,6 +492,19 @@ where
                     self.show_node_list(ui, dst);
                 }
                 ui.separator();
+                if let Some(cache) = cache {
+                    ui.spacing();
+                    ui.separator();
+                    if ui
+                        .collapsing_header(im_str!("Errors##error_list"))
+                        .default_open(true)
+                        .build()
+                    {
+                        ui.separator();
+                        self.show_error_list(ui, dst, cache);
+                    }
+                    ui.separator();
+                }
                 if let Some(node_id) = self.active_node {
                     ui.spacing();
                     ui.separator();
@@ -215,8 +565,6 @@ where
     }
 
     fn show_node_list(&mut self, ui: &Ui, dst: &DST<'static, T, E>) {
-        const SCROLL_OVER_NODE_OFFSET: Vec2 = Vec2(-50.0, -50.0);
-
         for (idx, node) in dst.nodes_iter() {
             let stack = ui.push_id(idx.id());
             let selected = self.node_states.get_state(&idx, |state| state.selected);
@@ -227,18 +575,57 @@ where
                 }
                 self.node_states.toggle_select(&idx);
                 self.active_node = Some(idx);
-                self.scrolling.set_target(
-                    self.node_states.get_state(&idx, |s| s.pos) + SCROLL_OVER_NODE_OFFSET,
-                )
+                self.scroll_to_node(&idx);
             }
             stack.pop(ui);
         }
     }
 
+    /// List the nodes whose last computation failed. Clicking on a node
+    /// selects it and scrolls the canvas to it.
+    fn show_error_list(
+        &mut self,
+        ui: &Ui,
+        dst: &DST<'static, T, E>,
+        cache: &cake::Cache<T, cake::compute::ComputeError<E>>,
+    ) {
+        let mut no_error = true;
+        for (idx, node) in dst.nodes_iter() {
+            let error = match idx {
+                cake::NodeId::Transform(t_idx) => cached_error(cache, t_idx),
+                cake::NodeId::Output(_) => None,
+            };
+            if let Some(error) = error {
+                no_error = false;
+                let stack = ui.push_id(idx.id());
+                let selected = self.node_states.get_state(&idx, |state| state.selected);
+                let root_cause = error.root_cause().to_string();
+                let name = ImString::new(format!(
+                    "{}: {}",
+                    node.name(&idx),
+                    root_cause.lines().next().unwrap_or_default()
+                ));
+                let color_stack = ui.push_style_color(StyleColor::Text, ERROR_COLOR);
+                if Selectable::new(&name).selected(selected).build(ui) {
+                    self.focus_node(idx);
+                }
+                color_stack.pop(ui);
+                if ui.is_item_hovered() {
+                    ui.tooltip(|| show_error(ui, error.root_cause()));
+                }
+                stack.pop(ui);
+            }
+        }
+        if no_error {
+            ui.text("No errors.");
+        }
+    }
+
     fn render_graph_node<ED>(
         &mut self,
         ui: &Ui,
         dst: &DST<'static, T, E>,
+        cache: Option<&cake::Cache<T, cake::compute::ComputeError<E>>>,
         addable_nodes: &[&'static Transform<T, E>],
         addable_macros: &cake::macros::MacroManager<'static, T, E>,
         constant_editor: &ED,
@@ -259,7 +646,38 @@ where
                     &mut self.show_connection_names,
                 );
                 ui.same_line_with_spacing(0.0, 15.0);
+                ui.checkbox(im_str!("Show minimap."), &mut self.show_minimap);
+                ui.same_line_with_spacing(0.0, 15.0);
+                ui.checkbox(
+                    im_str!("Show value previews."),
+                    &mut self.show_value_previews,
+                );
+                ui.same_line_with_spacing(0.0, 15.0);
                 ui.text(im_str!("Scroll with Ctrl+LMB or Alt+LMB."));
+                ui.same_line(ui.window_size()[0] - 390.0);
+                if ui.button(im_str!("Add frame"), [0.0, 0.0]) {
+                    self.add_frame();
+                }
+                if ui.is_item_hovered() {
+                    ui.tooltip(|| {
+                        ui.text(im_str!(
+                            "Add a frame around the selected nodes. \
+                             Right-click on the title of a frame to edit it."
+                        ));
+                    });
+                }
+                ui.same_line(ui.window_size()[0] - 310.0);
+                if ui.button(im_str!("Arrange"), [0.0, 0.0]) {
+                    self.arrange(dst, true);
+                }
+                if ui.is_item_hovered() {
+                    ui.tooltip(|| {
+                        ui.text(im_str!(
+                            "Arrange the selected nodes, or all nodes if none is selected, \
+                             in columns from left to right."
+                        ));
+                    });
+                }
                 ui.same_line(ui.window_size()[0] - 240.0);
                 if ui.button(im_str!("Import"), [0.0, 0.0]) {
                     if !self.is_macro {
@@ -329,7 +747,33 @@ where
                 ui.same_line(ui.window_size()[0] - 120.0);
                 ui.checkbox(im_str!("Show grid"), &mut self.show_grid);
                 ui.text(im_str!(
-                    "Press Delete or Backspace key to remove selected nodes."
+                    "Press Delete or Backspace key to remove selected nodes. \
+                     Undo with Ctrl+Z, redo with Ctrl+Shift+Z."
+                ));
+                ui.text(im_str!(
+                    "Copy, paste and duplicate selected nodes with Ctrl+C, Ctrl+V and Ctrl+D."
+                ));
+                ui.text(im_str!(
+                    "Right-click on the canvas to add a node, \
+                     or drop a link out of any slot to add a node linked to it."
+                ));
+                ui.text(im_str!(
+                    "Drag frames by their title, and double-click on it to collapse them."
+                ));
+                ui.text(im_str!(
+                    "Links are colored by data type. Dashed links convert values to another type."
+                ));
+                ui.text(im_str!(
+                    "Hover an output slot to preview the value flowing out of it."
+                ));
+                ui.text(im_str!(
+                    "Right-click on a node to bypass or freeze it. Press B to bypass selected nodes."
+                ));
+                ui.text(im_str!(
+                    "Zoom with the mouse wheel, and move the view by dragging on the minimap."
+                ));
+                ui.text(im_str!(
+                    "Press F to fit the selected nodes in view, and Home to fit all nodes."
                 ));
 
                 style_stack.pop(ui);
@@ -344,10 +788,10 @@ where
                 .movable(false)
                 .scrollable(false)
                 .build(ui, || {
-                    // TODO: Manage scaling (and font-scaling)
                     self.render_graph_canvas(
                         ui,
                         dst,
+                        cache,
                         addable_nodes,
                         addable_macros,
                         constant_editor,
@@ -355,6 +799,13 @@ where
                 });
             color_stack.pop(ui);
             style_stack.pop(ui);
+            // Drawn once the draw list of the canvas is released, so that the
+            // preview can use its own draw list
+            if let (Some(output), Some(cache)) = (self.hovered_output, cache) {
+                ui.tooltip(|| {
+                    value_preview(ui, cache.get_timed(&output).as_ref(), constant_editor)
+                });
+            }
         });
     }
 
@@ -362,34 +813,49 @@ where
         &mut self,
         ui: &Ui,
         dst: &DST<'static, T, E>,
+        cache: Option<&cake::Cache<T, cake::compute::ComputeError<E>>>,
         addable_nodes: &[&'static Transform<T, E>],
         addable_macros: &cake::macros::MacroManager<'static, T, E>,
         constant_editor: &ED,
     ) where
         ED: ConstantEditor<T>,
     {
-        const NODE_SLOT_RADIUS: f32 = 6.0 * CURRENT_FONT_WINDOW_SCALE;
-        const NODE_CLICK_BOX_RADIUS: f32 = 1.3 * NODE_SLOT_RADIUS;
-        const NODE_CLICK_BOX_RADIUS_SQUARED: f32 = NODE_CLICK_BOX_RADIUS * NODE_CLICK_BOX_RADIUS;
+        let canvas_size = Vec2::new(ui.window_size());
+        let win_pos = Vec2::new(ui.cursor_screen_pos());
+        self.canvas_size = canvas_size;
+        // Submitted before nodes so that it is hovered over them
+        let minimap = self.handle_minimap(ui, win_pos);
+        ui.set_cursor_screen_pos(win_pos.into());
+        if ui.is_window_hovered() && !self.minimap_hovered {
+            self.zoom_with_mouse_wheel(ui, win_pos);
+        }
+        // Scale the text and widgets of the nodes
+        ui.set_window_font_scale(self.zoom);
+
+        let zoom = self.zoom;
+        let node_slot_radius = 6.0 * zoom;
+        let node_click_box_radius = 1.3 * node_slot_radius;
+        let node_click_box_radius_squared = node_click_box_radius * node_click_box_radius;
         // We don't detect "mouse release" events while dragging links onto slots.
         // Instead we check that our mouse delta is small enough. Otherwise we couldn't
         // hover other slots while dragging links.
-        const BASE_NODE_WIDTH: f32 = 120.0 * CURRENT_FONT_WINDOW_SCALE;
-        let item_width_stack = ui.push_item_width(BASE_NODE_WIDTH);
+        const BASE_NODE_WIDTH: f32 = 120.0;
+        let item_width_stack = ui.push_item_width(BASE_NODE_WIDTH * zoom);
+        self.link_refused = false;
+        self.hovered_output = None;
         let draw_list = ui.get_window_draw_list();
         draw_list.channels_split(5, |channels| {
-            let canvas_size = Vec2::new(ui.window_size());
-            let win_pos = Vec2::new(ui.cursor_screen_pos());
-            let offset = win_pos - self.scrolling.get_current();
+            let offset = win_pos - self.scrolling.get_current() * zoom;
+            self.canvas_offset = offset;
 
             if self.show_grid {
                 let cursor_pos = Vec2::new(ui.cursor_pos());
-                let offset2 = cursor_pos - self.scrolling.get_current();
+                let offset2 = cursor_pos - self.scrolling.get_current() * zoom;
                 const GRID_COLOR: [f32; 4] = [0.78, 0.78, 0.78, 0.16];
                 const GRID_SIZE: f32 = 64.0;
                 const GRID_LINE_WIDTH: f32 = 1.0;
-                let grid_sz = CURRENT_FONT_WINDOW_SCALE * GRID_SIZE;
-                let grid_line_width = CURRENT_FONT_WINDOW_SCALE * GRID_LINE_WIDTH;
+                let grid_sz = zoom * GRID_SIZE;
+                let grid_line_width = zoom * GRID_LINE_WIDTH;
                 let mut x = offset2.0 % grid_sz;
                 while x < canvas_size.0 {
                     let p1 = [x + win_pos.0, win_pos.1];
@@ -411,7 +877,7 @@ where
                     y += grid_sz;
                 }
             }
-            if ui.is_window_hovered() {
+            if ui.is_window_hovered() && !self.minimap_hovered {
                 // Create new node with a popup
                 if ui.is_mouse_clicked(MouseButton::Right) {
                     let mouse_pos = ui.io().mouse_pos;
@@ -420,7 +886,22 @@ where
                         && win_pos.1 < mouse_pos[1]
                         && mouse_pos[1] < win_pos.1 + canvas_size.1
                     {
-                        ui.open_popup(im_str!("add-new-node"));
+                        let canvas_mouse_pos = (Vec2::new(mouse_pos) - offset) * (1.0 / zoom);
+                        if let Some(cake::NodeId::Transform(t_idx)) = self.node_at(canvas_mouse_pos)
+                        {
+                            self.context_node = Some(t_idx);
+                            ui.open_popup(im_str!("node-context"));
+                        } else if let Some(i) = self
+                            .frames
+                            .iter()
+                            .rposition(|frame| frame.title_bar_contains(canvas_mouse_pos))
+                        {
+                            self.context_frame = Some(i);
+                            ui.open_popup(im_str!("frame-context"));
+                        } else {
+                            self.new_node_pin = None;
+                            self.open_add_node_popup(ui);
+                        }
                     }
                 }
                 // Scroll
@@ -431,21 +912,24 @@ where
                 {
                     ui.set_mouse_cursor(Some(MouseCursor::ResizeAll));
                     let delta = Vec2(0.0, 0.0) - ui.io().mouse_delta.into();
-                    self.scrolling.set_delta(delta);
+                    self.scrolling.set_delta(delta * (1.0 / zoom));
                 }
             }
 
             // Bezier control point of the links
             const LINK_CONTROL_POINT_DISTANCE: f32 = 50.0;
-            let link_cp = Vec2::new((LINK_CONTROL_POINT_DISTANCE * CURRENT_FONT_WINDOW_SCALE, 0.0));
+            let link_cp = Vec2::new((LINK_CONTROL_POINT_DISTANCE * zoom, 0.0));
             const LINK_LINE_WIDTH: f32 = 3.0;
-            let link_line_width = LINK_LINE_WIDTH * CURRENT_FONT_WINDOW_SCALE;
+            let link_line_width = LINK_LINE_WIDTH * zoom;
             // NODE LINK CULLING?
 
             for idx in dst.node_ids() {
+                if self.is_hidden(&idx) {
+                    continue;
+                }
                 let node_pos = self
                     .node_states
-                    .get_state(&idx, |state| state.get_pos(CURRENT_FONT_WINDOW_SCALE));
+                    .get_state(&idx, |state| state.get_pos(zoom));
                 let id_stack = ui.push_id(idx.id());
 
                 // Display node contents first in the foreground
@@ -454,170 +938,221 @@ where
                 let node_rect_min = offset + node_pos;
                 let node_rect_max = self
                     .node_states
-                    .get_state(&idx, |state| node_rect_min + state.size);
-                ui.set_cursor_screen_pos((node_rect_min + NODE_WINDOW_PADDING).into());
+                    .get_state(&idx, |state| node_rect_min + state.size * zoom);
+                ui.set_cursor_screen_pos((node_rect_min + NODE_WINDOW_PADDING * zoom).into());
                 self.draw_node_inside(ui, dst, &draw_list, &idx, constant_editor);
 
                 let node = dst.get_node(&idx).unwrap();
                 let node_states = &mut self.node_states;
                 let item_rect_size = Vec2::new(ui.item_rect_size());
                 node_states.set_state(&idx, |state| {
-                    state.size = item_rect_size + NODE_WINDOW_PADDING * 2.0;
+                    state.size = item_rect_size * (1.0 / zoom) + NODE_WINDOW_PADDING * 2.0;
                 });
 
                 channels.set_current(if self.active_node == Some(idx) { 3 } else { 1 });
                 ui.set_cursor_screen_pos(node_rect_min.into());
                 ui.invisible_button(
                     im_str!("node##nodeinvbtn"),
-                    node_states.get_state(&idx, |state| state.size.into()),
+                    node_states.get_state(&idx, |state| (state.size * zoom).into()),
                 );
+                let node_hovered = ui.is_item_hovered();
+                let node_error = match idx {
+                    cake::NodeId::Transform(t_idx) => {
+                        cache.and_then(|cache| cached_error(cache, t_idx))
+                    }
+                    cake::NodeId::Output(_) => None,
+                };
+                let node_mode = match idx {
+                    cake::NodeId::Transform(t_idx) => dst.get_transform_mode(t_idx),
+                    cake::NodeId::Output(_) => None,
+                }
+                .unwrap_or(cake::TransformMode::Normal);
                 // TODO: Handle selection
 
                 const NODE_ROUNDING: f32 = 4.0;
                 const NODE_COLOR: [f32; 3] = [0.24, 0.24, 0.24];
-                let node_bg_color = NODE_COLOR;
+                const BYPASSED_NODE_COLOR: [f32; 3] = [0.17, 0.17, 0.17];
+                let node_bg_color = if node_mode == cake::TransformMode::Bypassed {
+                    BYPASSED_NODE_COLOR
+                } else {
+                    NODE_COLOR
+                };
                 draw_list
                     .add_rect(node_rect_min.into(), node_rect_max.into(), node_bg_color)
                     .rounding(NODE_ROUNDING)
                     .filled(true)
                     .build();
 
-                // Display frame
+                // Display frame, outlined in red if the node failed, or in
+                // the color of its mode if it is bypassed or frozen
+                let mode_label = match node_mode {
+                    cake::TransformMode::Normal => None,
+                    cake::TransformMode::Bypassed => Some(("Bypassed", BYPASSED_COLOR)),
+                    cake::TransformMode::Frozen => Some(("Frozen", FROZEN_COLOR)),
+                };
                 let line_thickness = if node_states.get_state(&idx, |s| s.selected) {
                     3.0
+                } else if node_error.is_some() || mode_label.is_some() {
+                    2.0
                 } else {
                     1.0
-                } * CURRENT_FONT_WINDOW_SCALE;
+                } * zoom;
+                let frame_color = match (&node_error, mode_label) {
+                    (Some(_), _) => ERROR_COLOR,
+                    (None, Some((_, mode_color))) => mode_color,
+                    (None, None) => color::with_alpha(NODE_FRAME_COLOR, 1.0),
+                };
                 draw_list
-                    .add_rect(node_rect_min.into(), node_rect_max.into(), NODE_FRAME_COLOR)
+                    .add_rect(node_rect_min.into(), node_rect_max.into(), frame_color)
                     .thickness(line_thickness)
                     .rounding(NODE_ROUNDING)
                     .build();
+                if let Some((label, mode_color)) = mode_label {
+                    let label_height = ui.calc_text_size(&ImString::new(label), false, -1.0)[1];
+                    draw_list.add_text(
+                        [node_rect_min.0, node_rect_min.1 - label_height - 2.0 * zoom],
+                        mode_color,
+                        label,
+                    );
+                }
 
                 // Display connectors
-                const CONNECTOR_BORDER_THICKNESS: f32 = NODE_SLOT_RADIUS * 0.25;
-                const INPUT_SLOT_COLOR: [f32; 4] = [0.59, 0.59, 0.59, 0.59];
+                let connector_border_thickness = node_slot_radius * 0.25;
                 for (slot_idx, slot_name) in node.input_slot_names_iter().into_iter().enumerate() {
-                    let connector_pos = Vec2::new(node_states.get_state(&idx, |state| {
-                        state.get_input_slot_pos(
-                            slot_idx,
-                            node.inputs_count(),
-                            CURRENT_FONT_WINDOW_SCALE,
-                        )
+                    let pin = LinkExtremity::Input(match idx {
+                        cake::NodeId::Transform(t_idx) => {
+                            InputSlot::Transform(cake::Input::new(t_idx, slot_idx))
+                        }
+                        cake::NodeId::Output(output_id) => InputSlot::Output(output_id),
+                    });
+                    let link_check = self
+                        .creating_link
+                        .map(|creating_link| creating_link.check_link(dst, &pin));
+                    let connector_pos = Vec2::new(self.node_states.get_state(&idx, |state| {
+                        state.get_input_slot_pos(slot_idx, node.inputs_count(), zoom)
                     }));
                     let connector_screen_pos = offset + connector_pos;
                     draw_list
                         .add_circle(
                             connector_screen_pos.into(),
-                            NODE_SLOT_RADIUS,
-                            INPUT_SLOT_COLOR,
+                            node_slot_radius,
+                            pin_color(dst, &pin, link_check.as_ref(), self.creating_link),
                         )
-                        .thickness(CONNECTOR_BORDER_THICKNESS)
+                        .thickness(connector_border_thickness)
                         .filled(true)
                         .build();
                     if self.show_connection_names {
                         let slot_name = ImString::new(slot_name);
                         let name_size = ui.calc_text_size(&slot_name, false, -1.0);
                         ui.set_cursor_screen_pos([
-                            connector_screen_pos.0 - NODE_SLOT_RADIUS - name_size[0],
+                            connector_screen_pos.0 - node_slot_radius - name_size[0],
                             connector_screen_pos.1 - name_size[1],
                         ]);
                         ui.text(slot_name);
                     }
-                    if ui.is_mouse_clicked(MouseButton::Left) {
+                    if ui.is_mouse_clicked(MouseButton::Left) && !self.minimap_hovered {
                         let mouse_pos: Vec2 = ui.io().mouse_pos.into();
                         if (mouse_pos - connector_screen_pos).squared_norm()
-                            <= NODE_CLICK_BOX_RADIUS_SQUARED
+                            <= node_click_box_radius_squared
                         {
                             self.drag_node = None;
-                            self.creating_link = Some(LinkExtremity::Input(match idx {
-                                cake::NodeId::Transform(t_idx) => {
-                                    InputSlot::Transform(cake::Input::new(t_idx, slot_idx))
-                                }
-                                cake::NodeId::Output(output_id) => InputSlot::Output(output_id),
-                            }));
+                            self.creating_link = Some(pin);
                         }
                     }
-                    if let Some(LinkExtremity::Output(link_output)) = self.creating_link {
+                    if let Some(link_check) = link_check {
                         // Check if we hover slot!
                         let mouse_pos: Vec2 = ui.io().mouse_pos.into();
                         if (mouse_pos - connector_screen_pos).squared_norm()
-                            <= NODE_CLICK_BOX_RADIUS_SQUARED
+                            <= node_click_box_radius_squared
                         {
-                            self.new_link = Some((
-                                link_output,
-                                match idx {
-                                    cake::NodeId::Transform(t_idx) => {
-                                        InputSlot::Transform(cake::Input::new(t_idx, slot_idx))
-                                    }
-                                    cake::NodeId::Output(output_id) => InputSlot::Output(output_id),
-                                },
-                            ));
-                            self.creating_link = None;
+                            self.hover_pin(ui, pin, link_check);
                         }
                     }
                 }
 
                 // Show outputs for transform nodes
                 if let cake::NodeId::Transform(t_idx) = idx {
-                    const OUTPUT_SLOT_COLOR: [f32; 4] = [0.59, 0.59, 0.59, 0.59];
                     for (slot_idx, type_id) in node.outputs_iter().into_iter().enumerate() {
                         let slot_name = type_id.name();
-                        let connector_pos = node_states.get_state(&idx, |state| {
-                            state.get_output_slot_pos(
-                                slot_idx,
-                                node.outputs_count(),
-                                CURRENT_FONT_WINDOW_SCALE,
-                            )
+                        let output = cake::Output::new(t_idx, slot_idx);
+                        let pin = LinkExtremity::Output(output);
+                        let link_check = self
+                            .creating_link
+                            .map(|creating_link| creating_link.check_link(dst, &pin));
+                        let connector_pos = self.node_states.get_state(&idx, |state| {
+                            state.get_output_slot_pos(slot_idx, node.outputs_count(), zoom)
                         });
                         let connector_screen_pos = offset + connector_pos;
                         draw_list
                             .add_circle(
                                 connector_screen_pos.into(),
-                                NODE_SLOT_RADIUS,
-                                OUTPUT_SLOT_COLOR,
+                                node_slot_radius,
+                                pin_color(dst, &pin, link_check.as_ref(), self.creating_link),
                             )
-                            .thickness(CONNECTOR_BORDER_THICKNESS)
+                            .thickness(connector_border_thickness)
                             .filled(true)
                             .build();
                         if self.show_connection_names {
                             let name_size =
                                 ui.calc_text_size(&ImString::new(slot_name), false, -1.0);
                             ui.set_cursor_screen_pos([
-                                connector_screen_pos.0 + NODE_SLOT_RADIUS,
+                                connector_screen_pos.0 + node_slot_radius,
                                 connector_screen_pos.1 - name_size[1],
                             ]);
                             ui.text(&ImString::new(slot_name));
                         }
-                        if ui.is_mouse_clicked(MouseButton::Left) {
+                        if let Some(value) = cache.and_then(|cache| cache.get(&output)) {
+                            if self.show_value_previews {
+                                draw_value_badge(
+                                    ui,
+                                    &draw_list,
+                                    connector_screen_pos + Vec2(node_slot_radius * 1.5, 0.0),
+                                    &value,
+                                    constant_editor,
+                                );
+                            }
+                        }
+                        let mouse_pos: Vec2 = ui.io().mouse_pos.into();
+                        if self.creating_link.is_none()
+                            && !self.minimap_hovered
+                            && (mouse_pos - connector_screen_pos).squared_norm()
+                                <= node_click_box_radius_squared
+                        {
+                            self.hovered_output = Some(output);
+                        }
+                        if ui.is_mouse_clicked(MouseButton::Left) && !self.minimap_hovered {
                             let mouse_pos: Vec2 = ui.io().mouse_pos.into();
                             if (mouse_pos - connector_screen_pos).squared_norm()
-                                <= NODE_CLICK_BOX_RADIUS_SQUARED
+                                <= node_click_box_radius_squared
                             {
                                 self.drag_node = None;
-                                self.creating_link =
-                                    Some(LinkExtremity::Output(cake::Output::new(t_idx, slot_idx)));
+                                self.creating_link = Some(pin);
                             }
                         }
-                        if let Some(LinkExtremity::Input(link_input)) = self.creating_link {
+                        if let Some(link_check) = link_check {
                             // Check if we hover slot!
                             let mouse_pos: Vec2 = ui.io().mouse_pos.into();
                             if (mouse_pos - connector_screen_pos).squared_norm()
-                                <= NODE_CLICK_BOX_RADIUS_SQUARED
+                                <= node_click_box_radius_squared
                             {
-                                self.new_link =
-                                    Some((cake::Output::new(t_idx, slot_idx), link_input));
-                                self.creating_link = None;
+                                self.hover_pin(ui, pin, link_check);
                             }
                         }
                     }
                 }
+                if let Some(error) = node_error {
+                    // Output pins show their own preview
+                    if node_hovered && self.creating_link.is_none() && self.hovered_output.is_none()
+                    {
+                        ui.tooltip(|| show_error(ui, error.root_cause()));
+                    }
+                }
                 id_stack.pop(ui);
             }
             // Preview new link
-            const NEW_LINK_COLOR: [f32; 3] = [0.78, 0.78, 0.39];
             if let Some(ref creating_link) = self.creating_link {
                 if ui.is_mouse_dragging(MouseButton::Left) {
+                    self.link_dragged = true;
                     let (p1, cp1, cp2, p2) = match *creating_link {
                         LinkExtremity::Output(output) => {
                             let output_node_count =
@@ -629,7 +1164,7 @@ where
                             let connector_pos = output_node_state.get_output_slot_pos(
                                 output.index(),
                                 output_node_count,
-                                CURRENT_FONT_WINDOW_SCALE,
+                                zoom,
                             );
                             let p1 = offset + connector_pos;
                             let p2: Vec2 = ui.io().mouse_pos.into();
@@ -649,7 +1184,7 @@ where
                                     input_node_state.get_input_slot_pos(
                                         input.index(),
                                         input_node_count,
-                                        CURRENT_FONT_WINDOW_SCALE,
+                                        zoom,
                                     )
                                 }
                                 InputSlot::Output(output_id) => {
@@ -657,11 +1192,7 @@ where
                                         .node_states
                                         .get(&cake::NodeId::Output(output_id))
                                         .unwrap();
-                                    input_node_state.get_input_slot_pos(
-                                        0usize,
-                                        1usize,
-                                        CURRENT_FONT_WINDOW_SCALE,
-                                    )
+                                    input_node_state.get_input_slot_pos(0usize, 1usize, zoom)
                                 }
                             };
                             let p1 = offset + connector_pos;
@@ -671,19 +1202,34 @@ where
                             (p1, cp1, cp2, p2)
                         }
                     };
+                    let new_link_color = creating_link
+                        .type_id(dst)
+                        .map_or(color::ANY_TYPE_COLOR, color::type_color);
                     let (p1, cp1, cp2, p2) = (p1.into(), cp1.into(), cp2.into(), p2.into());
                     draw_list
-                        .add_bezier_curve(p1, cp1, cp2, p2, NEW_LINK_COLOR)
+                        .add_bezier_curve(p1, cp1, cp2, p2, new_link_color)
                         .thickness(link_line_width)
                         .build();
                 }
             }
             if self.creating_link.is_some() && !ui.is_mouse_down(MouseButton::Left) {
-                self.creating_link = None;
+                let pin = self.creating_link.take();
+                // Dropping the link on a pin refusing it cancels the link
+                if self.link_dragged && !self.link_refused {
+                    // The link was dropped out of any slot
+                    self.new_node_pin = pin;
+                    self.open_add_node_popup(ui);
+                }
+            }
+            if self.creating_link.is_none() {
+                self.link_dragged = false;
             }
 
-            // Display links
+            // Display frames behind links
             channels.set_current(0);
+            self.render_frames(ui, &draw_list, offset);
+
+            // Display links
             for (output, input_slot) in dst.links_iter() {
                 let connector_in_pos = match input_slot {
                     cake::InputSlot::Transform(input) => {
@@ -693,22 +1239,14 @@ where
                             .node_states
                             .get(&cake::NodeId::Transform(input.t_idx))
                             .unwrap();
-                        input_node_state.get_input_slot_pos(
-                            input.index(),
-                            input_node_count,
-                            CURRENT_FONT_WINDOW_SCALE,
-                        )
+                        input_node_state.get_input_slot_pos(input.index(), input_node_count, zoom)
                     }
                     cake::InputSlot::Output(output_id) => {
                         let input_node_state = self
                             .node_states
                             .get(&cake::NodeId::Output(output_id))
                             .unwrap();
-                        input_node_state.get_input_slot_pos(
-                            0usize,
-                            1usize,
-                            CURRENT_FONT_WINDOW_SCALE,
-                        )
+                        input_node_state.get_input_slot_pos(0usize, 1usize, zoom)
                     }
                 };
                 let mut is_deleting_link = false;
@@ -733,43 +1271,70 @@ where
                         },
                     };
                 }
-                let p1 = offset + connector_in_pos;
+                let mut p1 = offset + connector_in_pos;
                 let output_node_count = dst.get_transform(output.t_idx).unwrap().outputs().len();
                 let output_node_state = self
                     .node_states
                     .get(&cake::NodeId::Transform(output.t_idx))
                     .unwrap();
 
-                let connector_out_pos = output_node_state.get_output_slot_pos(
-                    output.index(),
-                    output_node_count,
-                    CURRENT_FONT_WINDOW_SCALE,
-                );
-                let p2 = offset + connector_out_pos;
+                let connector_out_pos =
+                    output_node_state.get_output_slot_pos(output.index(), output_node_count, zoom);
+                let mut p2 = offset + connector_out_pos;
+                // Links to nodes in collapsed frames end on the frame
+                let input_frame = self.collapsed_frame_of(&match input_slot {
+                    cake::InputSlot::Transform(input) => cake::NodeId::Transform(input.t_idx),
+                    cake::InputSlot::Output(output_id) => cake::NodeId::Output(output_id),
+                });
+                let output_frame = self.collapsed_frame_of(&cake::NodeId::Transform(output.t_idx));
+                match (input_frame, output_frame) {
+                    (Some(i), Some(o)) if i == o => continue,
+                    _ => (),
+                }
+                if let Some(i) = input_frame {
+                    p1 = offset
+                        + (self.frames[i].pos + Vec2(0.0, frame::TITLE_BAR_HEIGHT / 2.0)) * zoom;
+                }
+                if let Some(o) = output_frame {
+                    let frame = &self.frames[o];
+                    p2 = offset
+                        + (frame.pos + Vec2(frame.size.0, frame::TITLE_BAR_HEIGHT / 2.0)) * zoom;
+                }
                 let cp1 = p1 - link_cp;
                 let cp2 = p2 + link_cp;
-                const LINK_COLOR: [f32; 3] = [0.78, 0.78, 0.39];
                 const DELETE_LINK_COLOR: [f32; 3] = [1.0, 0.0, 0.0];
-                let (p1, cp1, cp2, p2) = (p1.into(), cp1.into(), cp2.into(), p2.into());
-                if is_deleting_link {
-                    draw_list
-                        .add_bezier_curve(p1, cp1, cp2, p2, DELETE_LINK_COLOR)
-                        .thickness(link_line_width)
-                        .build();
+                let output_type = LinkExtremity::Output(*output).type_id(dst);
+                let input_type = LinkExtremity::Input(input_slot).type_id(dst);
+                // Values going through the link are converted to another type
+                let is_conversion = input_type.map_or(false, |t| Some(t) != output_type);
+                let link_color = if is_deleting_link {
                     if self.delete_flag {
                         self.delete_flag = false;
                     }
+                    DELETE_LINK_COLOR
+                } else {
+                    output_type.map_or(color::ANY_TYPE_COLOR, color::type_color)
+                };
+                if is_conversion {
+                    add_dashed_bezier_curve(
+                        &draw_list,
+                        [p1, cp1, cp2, p2],
+                        link_color,
+                        link_line_width,
+                    );
                 } else {
+                    let (p1, cp1, cp2, p2) = (p1.into(), cp1.into(), cp2.into(), p2.into());
                     draw_list
-                        .add_bezier_curve(p1, cp1, cp2, p2, LINK_COLOR)
+                        .add_bezier_curve(p1, cp1, cp2, p2, link_color)
                         .thickness(link_line_width)
                         .build();
                 }
+                let (p1, p2): ([f32; 2], [f32; 2]) = (p1.into(), p2.into());
                 let mouse_pos = ui.io().mouse_pos;
-                if ui.is_mouse_clicked(MouseButton::Right) {
+                if ui.is_mouse_clicked(MouseButton::Right) && !self.minimap_hovered {
                     if (p1[0] - mouse_pos[0]) * (p1[0] - mouse_pos[0])
                         + (p1[1] - mouse_pos[1]) * (p1[1] - mouse_pos[1])
-                        < NODE_SLOT_RADIUS * NODE_SLOT_RADIUS
+                        < node_slot_radius * node_slot_radius
                     {
                         self.delete_link_list
                             .retain(|&e| e.unwrap().1 == input_slot);
@@ -777,7 +1342,7 @@ where
                         ui.open_popup(im_str!("delete-link"));
                     } else if (p2[0] - mouse_pos[0]) * (p2[0] - mouse_pos[0])
                         + (p2[1] - mouse_pos[1]) * (p2[1] - mouse_pos[1])
-                        < NODE_SLOT_RADIUS * NODE_SLOT_RADIUS
+                        < node_slot_radius * node_slot_radius
                     {
                         self.delete_link_list.retain(|&e| e.unwrap().0 == *output);
                         self.delete_link_list.insert(Some((*output, input_slot)));
@@ -786,6 +1351,9 @@ where
                 }
             }
         });
+        if let Some(minimap) = minimap {
+            self.draw_minimap(&draw_list, &minimap);
+        }
         item_width_stack.pop(ui);
 
         if let Some((output, input_slot)) = self.new_link {
@@ -866,45 +1434,353 @@ where
             ui.text("Add node");
             color_stack.pop(ui);
             ui.separator();
-            for (i, node) in addable_nodes.iter().enumerate() {
-                let id_stack = ui.push_id(i as i32);
-                if MenuItem::new(&ImString::new(node.name())).build(ui) {
-                    self.events.push(RenderEvent::AddTransform(node));
-                }
-                id_stack.pop(ui);
+            if self.focus_node_search {
+                ui.set_keyboard_focus_here(FocusedWidget::Next);
+                self.focus_node_search = false;
+            }
+            let mut search = ImString::with_capacity(256);
+            search.push_str(&self.node_search);
+            if ui.input_text(im_str!("Search"), &mut search).build() {
+                self.node_search = search.to_str().to_owned();
             }
             ui.separator();
-            if MenuItem::new(im_str!("Create new macro")).build(ui) {
-                self.events.push(RenderEvent::AddNewMacro);
+
+            let pin = self.new_node_pin;
+            let items: Vec<_> =
+                palette::items(addable_nodes, addable_macros, T::editable_variants())
+                    .into_iter()
+                    .filter(|item| pin.map_or(true, |pin| item.accepts(&pin, dst)))
+                    .collect();
+            let mut chosen = None;
+            if self.node_search.trim().is_empty() {
+                for category in palette::categories(&items) {
+                    if let Some(menu) = ui.begin_menu(&ImString::new(category), true) {
+                        for (i, item) in items.iter().enumerate() {
+                            if item.category() == category && palette_menu_item(ui, i, item, None) {
+                                chosen = Some(item.event());
+                            }
+                        }
+                        menu.end(ui);
+                    }
+                }
+            } else {
+                // Show items whose name matches first
+                let mut matches: Vec<_> = items
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, item)| {
+                        item.matches(&self.node_search)
+                            .map(|name_matches| (!name_matches, i, item))
+                    })
+                    .collect();
+                matches.sort_by_key(|&(description_only, i, _)| (description_only, i));
+                if matches.is_empty() {
+                    ui.text("No matching node");
+                }
+                for (_, i, item) in matches {
+                    if palette_menu_item(ui, i, item, Some(item.category())) {
+                        chosen = Some(item.event());
+                    }
+                }
+            }
+            if let Some(event) = chosen {
+                self.events.push(match self.new_node_pin.take() {
+                    Some(pin) => RenderEvent::AddConnected {
+                        node: Box::new(event),
+                        pin,
+                    },
+                    None => event,
+                });
             }
+        });
+        self.render_frame_popup(ui);
+        self.render_node_popup(ui, dst);
+    }
 
-            let mut macro_list_started = false;
-            for macr in addable_macros.macros() {
-                if !macro_list_started {
-                    ui.separator();
-                    let color_stack = ui.push_style_color(StyleColor::Text, HEADER_COLOR);
-                    ui.text("Add macro node");
-                    color_stack.pop(ui);
-                    macro_list_started = true;
+    fn render_frames(&mut self, ui: &Ui, draw_list: &WindowDrawList, offset: Vec2) {
+        const FRAME_ALPHA: f32 = 0.2;
+        const TITLE_BAR_ALPHA: f32 = 0.8;
+        const FRAME_ROUNDING: f32 = 4.0;
+        const TITLE_COLOR: [f32; 3] = [1.0, 1.0, 1.0];
+        const TITLE_PADDING: Vec2 = Vec2(6.0, 3.0);
+
+        let zoom = self.zoom;
+        for frame in &self.frames {
+            let [r, g, b] = frame.color;
+            let min = offset + frame.pos * zoom;
+            if !frame.collapsed {
+                let max = min + frame.size * zoom;
+                draw_list
+                    .add_rect(min.into(), max.into(), [r, g, b, FRAME_ALPHA])
+                    .rounding(FRAME_ROUNDING)
+                    .filled(true)
+                    .build();
+                let handle_min =
+                    max - Vec2(frame::RESIZE_HANDLE_SIZE, frame::RESIZE_HANDLE_SIZE) * zoom;
+                draw_list
+                    .add_rect(handle_min.into(), max.into(), [r, g, b, TITLE_BAR_ALPHA])
+                    .filled(true)
+                    .build();
+            }
+            let title_bar_max = min + Vec2(frame.size.0, frame::TITLE_BAR_HEIGHT) * zoom;
+            draw_list
+                .add_rect(min.into(), title_bar_max.into(), [r, g, b, TITLE_BAR_ALPHA])
+                .rounding(FRAME_ROUNDING)
+                .filled(true)
+                .build();
+            let title = if frame.collapsed {
+                format!("{} ({} nodes)", frame.title, frame.hidden_nodes.len())
+            } else {
+                frame.title.clone()
+            };
+            draw_list.add_text((min + TITLE_PADDING * zoom).into(), TITLE_COLOR, title);
+        }
+
+        let mouse_pos = (Vec2::new(ui.io().mouse_pos) - offset) * (1.0 / zoom);
+        if ui.is_window_hovered()
+            && !self.minimap_hovered
+            && ui.is_mouse_clicked(MouseButton::Left)
+            && self.drag_node.is_none()
+            && self.creating_link.is_none()
+            && !ui.io().key_ctrl
+            && !ui.io().key_alt
+        {
+            // Frames drawn last are on top
+            if let Some(i) = self
+                .frames
+                .iter()
+                .rposition(|frame| frame.resize_handle_contains(mouse_pos))
+            {
+                self.frame_drag = Some(FrameDrag::Resize(i));
+            } else if let Some(i) = self
+                .frames
+                .iter()
+                .rposition(|frame| frame.title_bar_contains(mouse_pos))
+            {
+                if ui.is_mouse_double_clicked(MouseButton::Left) {
+                    self.toggle_frame(i);
+                } else {
+                    self.frame_drag = Some(self.start_frame_move(i));
                 }
-                let id_stack = ui.push_id(macr.id().as_fields().0 as i32);
-                if MenuItem::new(&ImString::new(macr.name())).build(ui) {
-                    self.events.push(RenderEvent::AddMacro(macr.clone()));
+            }
+        }
+        match self.frame_drag {
+            Some(ref drag) if ui.is_mouse_down(MouseButton::Left) => {
+                let delta = Vec2::new(ui.io().mouse_delta) * (1.0 / zoom);
+                match drag {
+                    FrameDrag::Move { frames, nodes } => {
+                        for &i in frames {
+                            self.frames[i].pos = self.frames[i].pos + delta;
+                        }
+                        for node_id in nodes {
+                            if let Some(state) = self.node_states.get_mut(node_id) {
+                                state.pos = state.pos + delta;
+                            }
+                        }
+                    }
+                    FrameDrag::Resize(i) => self.frames[*i].resize(delta),
                 }
-                id_stack.pop(ui);
             }
-            ui.separator();
-            if MenuItem::new(im_str!("Output node")).build(ui) {
-                self.events.push(RenderEvent::CreateOutput);
+            Some(_) => self.frame_drag = None,
+            None => (),
+        }
+    }
+
+    fn render_frame_popup(&mut self, ui: &Ui) {
+        let i = match self.context_frame {
+            Some(i) if i < self.frames.len() => i,
+            _ => return,
+        };
+        let mut toggle = false;
+        let mut delete = false;
+        ui.popup(im_str!("frame-context"), || {
+            let frame = &mut self.frames[i];
+            let mut title = ImString::with_capacity(256);
+            title.push_str(&frame.title);
+            if ui.input_text(im_str!("Title"), &mut title).build() {
+                frame.title = title.to_str().to_owned();
             }
-            ui.separator();
-            for constant_type in T::editable_variants() {
-                let item_name = ImString::new(format!("Input node: {}", constant_type));
-                if MenuItem::new(&item_name).build(ui) {
-                    self.events.push(RenderEvent::AddConstant(constant_type));
+            if let Some(menu) = ui.begin_menu(im_str!("Color"), true) {
+                for (name, color) in frame::COLORS.iter() {
+                    if MenuItem::new(&ImString::new(*name))
+                        .selected(frame.color == *color)
+                        .build(ui)
+                    {
+                        frame.color = *color;
+                    }
                 }
+                menu.end(ui);
+            }
+            let toggle_label = if frame.collapsed {
+                im_str!("Expand")
+            } else {
+                im_str!("Collapse")
+            };
+            if MenuItem::new(toggle_label).build(ui) {
+                toggle = true;
+            }
+            if MenuItem::new(im_str!("Delete frame")).build(ui) {
+                delete = true;
             }
         });
+        if toggle {
+            self.toggle_frame(i);
+        }
+        if delete {
+            // Nodes hidden in the frame are shown again
+            self.frames.remove(i);
+            self.context_frame = None;
+        }
+    }
+
+    fn render_node_popup(&mut self, ui: &Ui, dst: &DST<'static, T, E>) {
+        use cake::TransformMode::*;
+
+        let t_idx = match self.context_node {
+            Some(t_idx) => t_idx,
+            None => return,
+        };
+        let mode = match dst.get_transform_mode(t_idx) {
+            Some(mode) => mode,
+            None => return,
+        };
+        let is_macro = self.is_macro;
+        let mut new_mode = None;
+        ui.popup(im_str!("node-context"), || {
+            if MenuItem::new(im_str!("Bypass"))
+                .shortcut(im_str!("B"))
+                .selected(mode == Bypassed)
+                .build(ui)
+            {
+                new_mode = Some(if mode == Bypassed { Normal } else { Bypassed });
+            }
+            if ui.is_item_hovered() {
+                ui.tooltip(|| {
+                    ui.text("Pass the first compatible input through, without computing the node.")
+                });
+            }
+            // Macros are computed anew on each call, so they do not keep any
+            // result to freeze
+            if MenuItem::new(im_str!("Freeze"))
+                .selected(mode == Frozen)
+                .enabled(!is_macro || mode == Frozen)
+                .build(ui)
+            {
+                new_mode = Some(if mode == Frozen { Normal } else { Frozen });
+            }
+            if ui.is_item_hovered() {
+                ui.tooltip(|| {
+                    if is_macro {
+                        ui.text("Nodes cannot be frozen in macros.")
+                    } else {
+                        ui.text(
+                            "Keep the last computed result, even if the inputs of the node change.",
+                        )
+                    }
+                });
+            }
+        });
+        if let Some(new_mode) = new_mode {
+            self.events
+                .push(RenderEvent::SetTransformModes(vec![t_idx], new_mode));
+            self.context_node = None;
+        }
+    }
+
+    /// Arrange the selected nodes, or all nodes if none is selected, in
+    /// layers from left to right, following the links between them. The
+    /// top-left corner of the arranged nodes is kept in place.
+    fn arrange(&mut self, dst: &DST<'static, T, E>, animate: bool) {
+        let selected_node_ids: Vec<_> = self
+            .node_states
+            .iter()
+            .filter(|(_, state)| state.selected)
+            .map(|(id, _)| *id)
+            .collect();
+        let node_ids = if selected_node_ids.is_empty() {
+            dst.node_ids()
+        } else {
+            selected_node_ids
+        };
+        let nodes: Vec<_> = node_ids
+            .iter()
+            .map(|id| {
+                let size = self
+                    .node_states
+                    .get(id)
+                    .map(|state| state.size)
+                    .unwrap_or_default();
+                let size = Vec2(
+                    size.0.max(ARRANGED_NODE_MIN_SIZE.0),
+                    size.1.max(ARRANGED_NODE_MIN_SIZE.1),
+                );
+                (*id, size)
+            })
+            .collect();
+        let links: Vec<_> = dst
+            .links_iter()
+            .map(|(output, input_slot)| {
+                let input_node_id = match input_slot {
+                    InputSlot::Transform(input) => cake::NodeId::Transform(input.t_idx),
+                    InputSlot::Output(output_id) => cake::NodeId::Output(output_id),
+                };
+                (cake::NodeId::Transform(output.t_idx), input_node_id)
+            })
+            .collect();
+        let origin = node_ids
+            .iter()
+            .filter_map(|id| self.node_states.get(id))
+            .map(|state| state.pos)
+            .fold(None, |origin: Option<Vec2>, pos| {
+                Some(origin.map_or(pos, |o| Vec2(o.0.min(pos.0), o.1.min(pos.1))))
+            })
+            .unwrap_or_else(|| self.scrolling.get_current() + Vec2(30.0, 30.0));
+
+        for (node_id, pos) in arrange::arrange(&nodes, &links) {
+            let target = origin + pos;
+            match self.node_states.get(&node_id) {
+                Some(state) if animate => {
+                    let mut moving = Scrolling::new(state.pos);
+                    moving.set_target(target);
+                    self.arranging.insert(node_id, moving);
+                }
+                state => {
+                    let mut state = state.cloned().unwrap_or_default();
+                    state.pos = target;
+                    self.node_states.insert(node_id, state);
+                    self.arranging.remove(&node_id);
+                }
+            }
+        }
+    }
+
+    /// Handle the mouse hovering `pin` while a link is created. Make the link
+    /// if it can be made, and explain why it is refused otherwise.
+    fn hover_pin(
+        &mut self,
+        ui: &Ui,
+        pin: LinkExtremity,
+        link_check: Result<(), Option<cake::DSTError>>,
+    ) {
+        match link_check {
+            Ok(()) => {
+                self.new_link = self
+                    .creating_link
+                    .take()
+                    .and_then(|creating_link| creating_link.link_with(&pin));
+            }
+            Err(Some(e)) => {
+                self.link_refused = true;
+                ui.tooltip(|| ui.text(e.to_string()));
+            }
+            Err(None) => (),
+        }
+    }
+
+    fn open_add_node_popup(&mut self, ui: &Ui) {
+        self.node_search.clear();
+        self.focus_node_search = true;
+        ui.open_popup(im_str!("add-new-node"));
     }
 
     fn draw_node_inside<ED>(
@@ -1014,16 +1890,14 @@ where
             3.0
         } else {
             1.0
-        } * CURRENT_FONT_WINDOW_SCALE;
+        } * self.zoom;
+        let padding = NODE_WINDOW_PADDING * self.zoom;
         draw_list
             .add_line(
+                [p[0] - padding.0, p[1] + title_bar_height + padding.1 / 2.0],
                 [
-                    p[0] - NODE_WINDOW_PADDING.0,
-                    p[1] + title_bar_height + NODE_WINDOW_PADDING.1 / 2.0,
-                ],
-                [
-                    p[0] + node_size[0] + NODE_WINDOW_PADDING.0,
-                    p[1] + title_bar_height + NODE_WINDOW_PADDING.1 / 2.0,
+                    p[0] + node_size[0] + padding.0,
+                    p[1] + title_bar_height + padding.1 / 2.0,
                 ],
                 NODE_FRAME_COLOR,
             )
@@ -1033,6 +1907,8 @@ where
         if ui.is_item_hovered() && !ui.is_item_active() && ui.is_mouse_clicked(MouseButton::Left) {
             self.active_node = Some(*id);
             self.drag_node = Some(*id);
+            // Stop moving arranged nodes
+            self.arranging.clear();
             if !ui.io().key_ctrl {
                 node_states.deselect_all();
             }
@@ -1040,9 +1916,9 @@ where
         }
         if self.drag_node == Some(*id) {
             if ui.is_mouse_dragging(MouseButton::Left) {
-                let delta = ui.io().mouse_delta;
+                let delta = Vec2::new(ui.io().mouse_delta) * (1.0 / self.zoom);
                 node_states.set_state(id, |state| {
-                    state.pos = state.pos + delta.into();
+                    state.pos = state.pos + delta;
                 });
             } else if !ui.is_mouse_down(MouseButton::Left) {
                 self.drag_node = None;
@@ -1069,12 +1945,37 @@ where
             .filter(|(_, state)| state.selected)
             .map(|(id, _)| *id)
             .collect();
-        for node_id in selected_node_ids {
-            self.events.push(RenderEvent::RemoveNode(node_id));
-            self.node_states.remove_node(&node_id);
-            if self.active_node == Some(node_id) {
-                self.active_node.take();
-            }
+        if !selected_node_ids.is_empty() {
+            self.events
+                .push(RenderEvent::RemoveNodes(selected_node_ids));
+        }
+    }
+
+    /// Bypass the selected transforms, or restore them if they are all
+    /// bypassed already.
+    fn bypass_selected_nodes(&mut self, dst: &DST<'static, T, E>) {
+        use cake::TransformMode::*;
+
+        let selected_t_idxs: Vec<_> = self
+            .node_states
+            .iter()
+            .filter(|(_, state)| state.selected)
+            .filter_map(|(id, _)| match *id {
+                cake::NodeId::Transform(t_idx) => Some(t_idx),
+                cake::NodeId::Output(_) => None,
+            })
+            .collect();
+        let all_bypassed = selected_t_idxs
+            .iter()
+            .all(|t_idx| dst.get_transform_mode(*t_idx) == Some(Bypassed));
+        let mode = if all_bypassed { Normal } else { Bypassed };
+        let changed_t_idxs: Vec<_> = selected_t_idxs
+            .into_iter()
+            .filter(|t_idx| dst.get_transform_mode(*t_idx) != Some(mode))
+            .collect();
+        if !changed_t_idxs.is_empty() {
+            self.events
+                .push(RenderEvent::SetTransformModes(changed_t_idxs, mode));
         }
     }
 }
@@ -1088,16 +1989,475 @@ impl<T, E> NodeEditorLayout<T, E> {
         &self.node_states
     }
 
-    pub fn import(&mut self, node_states: NodeStates, scrolling: Scrolling) {
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    /// Select node `idx` only, and scroll the canvas to it.
+    pub fn focus_node(&mut self, idx: cake::NodeId) {
+        if self.node_states.get(&idx).is_some() {
+            self.node_states.deselect_all();
+            self.node_states.toggle_select(&idx);
+            self.active_node = Some(idx);
+            self.scroll_to_node(&idx);
+        }
+    }
+
+    fn scroll_to_node(&mut self, idx: &cake::NodeId) {
+        const SCROLL_OVER_NODE_OFFSET: Vec2 = Vec2(-50.0, -50.0);
+        self.scrolling
+            .set_target(self.node_states.get_state(idx, |s| s.pos) + SCROLL_OVER_NODE_OFFSET);
+    }
+
+    /// Zoom in or out with the mouse wheel, keeping the point of the canvas
+    /// under the mouse in place.
+    fn zoom_with_mouse_wheel(&mut self, ui: &Ui, win_pos: Vec2) {
+        let wheel = ui.io().mouse_wheel;
+        if wheel == 0.0 {
+            return;
+        }
+        let zoom = (self.zoom * ZOOM_STEP.powf(wheel))
+            .max(MIN_ZOOM)
+            .min(MAX_ZOOM);
+        let mouse_pos = Vec2::new(ui.io().mouse_pos) - win_pos;
+        let mouse_canvas_pos = self.scrolling.get_current() + mouse_pos * (1.0 / self.zoom);
+        self.scrolling = Scrolling::new(mouse_canvas_pos - mouse_pos * (1.0 / zoom));
+        self.zoom = zoom;
+    }
+
+    /// Zoom and scroll so that the selected nodes, or all nodes, fit in the
+    /// canvas. All nodes are fit if none is selected. Nodes are never shown
+    /// larger than their actual size.
+    fn fit_in_view(&mut self, selected_only: bool) {
+        let (min, max) = match self
+            .content_bounds(selected_only)
+            .or_else(|| self.content_bounds(false))
+        {
+            Some(bounds) => bounds,
+            None => return,
+        };
+        let size = max - min;
+        let available = self.canvas_size - Vec2(2.0 * FIT_MARGIN, 2.0 * FIT_MARGIN);
+        let zoom = (available.0 / size.0.max(1.0))
+            .min(available.1 / size.1.max(1.0))
+            .max(MIN_ZOOM)
+            .min(1.0);
+        // Zoom around the center of the view, then scroll fluidly to the nodes
+        let half_canvas = self.canvas_size * 0.5;
+        let view_center = self.scrolling.get_current() + half_canvas * (1.0 / self.zoom);
+        self.scrolling = Scrolling::new(view_center - half_canvas * (1.0 / zoom));
+        self.scrolling
+            .set_target((min + max) * 0.5 - half_canvas * (1.0 / zoom));
+        self.zoom = zoom;
+    }
+
+    /// Get the bounds on the canvas of the visible nodes, only the selected
+    /// ones if `selected_only` is true. Frames are included if all nodes are.
+    fn content_bounds(&self, selected_only: bool) -> Option<(Vec2, Vec2)> {
+        let mut bounds = None;
+        for (id, state) in self.node_states.iter() {
+            if !self.is_hidden(id) && (state.selected || !selected_only) {
+                bounds = Some(extend_bounds(bounds, state.pos, state.pos + state.size));
+            }
+        }
+        if !selected_only {
+            for frame in &self.frames {
+                bounds = Some(extend_bounds(
+                    bounds,
+                    frame.pos,
+                    frame.pos + frame.drawn_size(),
+                ));
+            }
+        }
+        bounds
+    }
+
+    /// Make the minimap of the canvas, and move the view to the point clicked
+    /// on it. Return `None` if the minimap is hidden.
+    fn handle_minimap(&mut self, ui: &Ui, win_pos: Vec2) -> Option<Minimap> {
+        self.minimap_hovered = false;
+        if !self.show_minimap {
+            return None;
+        }
+        // The minimap is not rescaled while the view is moved on it
+        let (min, max) = match self.minimap_bounds {
+            Some(bounds) => bounds,
+            None => {
+                let viewport_min = self.scrolling.get_current();
+                let viewport_max = viewport_min + self.canvas_size * (1.0 / self.zoom);
+                extend_bounds(self.content_bounds(false), viewport_min, viewport_max)
+            }
+        };
+        let minimap = Minimap::new(win_pos, self.canvas_size, min, max);
+        ui.set_cursor_screen_pos(minimap.pos.into());
+        ui.invisible_button(im_str!("minimap"), minimap::SIZE.into());
+        self.minimap_hovered = ui.is_item_hovered() || ui.is_item_active();
+        if ui.is_item_active() {
+            self.minimap_bounds = Some((min, max));
+            let center = minimap.to_canvas(ui.io().mouse_pos.into());
+            self.scrolling = Scrolling::new(center - self.canvas_size * (0.5 / self.zoom));
+        } else {
+            self.minimap_bounds = None;
+        }
+        Some(minimap)
+    }
+
+    fn draw_minimap(&self, draw_list: &WindowDrawList, minimap: &Minimap) {
+        const BACKGROUND_COLOR: [f32; 4] = [0.12, 0.12, 0.14, 0.85];
+        const BORDER_COLOR: [f32; 3] = [0.39, 0.39, 0.39];
+        const NODE_COLOR: [f32; 3] = [0.59, 0.59, 0.59];
+        const SELECTED_NODE_COLOR: [f32; 3] = [0.78, 0.78, 0.39];
+        const FRAME_ALPHA: f32 = 0.5;
+        const VIEWPORT_COLOR: [f32; 3] = [1.0, 1.0, 1.0];
+
+        let min = minimap.pos;
+        let max = minimap.pos + minimap::SIZE;
+        draw_list
+            .add_rect(min.into(), max.into(), BACKGROUND_COLOR)
+            .filled(true)
+            .build();
+        draw_list.with_clip_rect_intersect(min.into(), max.into(), || {
+            for frame in &self.frames {
+                let [r, g, b] = frame.color;
+                draw_list
+                    .add_rect(
+                        minimap.to_screen(frame.pos).into(),
+                        minimap.to_screen(frame.pos + frame.drawn_size()).into(),
+                        [r, g, b, FRAME_ALPHA],
+                    )
+                    .filled(true)
+                    .build();
+            }
+            for (id, state) in self.node_states.iter() {
+                if self.is_hidden(id) {
+                    continue;
+                }
+                let color = if state.selected {
+                    SELECTED_NODE_COLOR
+                } else {
+                    NODE_COLOR
+                };
+                draw_list
+                    .add_rect(
+                        minimap.to_screen(state.pos).into(),
+                        minimap.to_screen(state.pos + state.size).into(),
+                        color,
+                    )
+                    .filled(true)
+                    .build();
+            }
+            let viewport_min = self.scrolling.get_current();
+            let viewport_max = viewport_min + self.canvas_size * (1.0 / self.zoom);
+            draw_list
+                .add_rect(
+                    minimap.to_screen(viewport_min).into(),
+                    minimap.to_screen(viewport_max).into(),
+                    VIEWPORT_COLOR,
+                )
+                .build();
+        });
+        draw_list
+            .add_rect(min.into(), max.into(), BORDER_COLOR)
+            .build();
+    }
+
+    /// Add a frame around the selected nodes, or an empty frame if no node is
+    /// selected.
+    fn add_frame(&mut self) {
+        let selected_states: Vec<_> = self
+            .node_states
+            .iter()
+            .map(|(_, state)| state)
+            .filter(|state| state.selected)
+            .collect();
+        let frame = if selected_states.is_empty() {
+            Frame::new(self.scrolling.get_current() + Vec2(50.0, 50.0))
+        } else {
+            Frame::around(selected_states)
+        };
+        self.frames.push(frame);
+        // Draw larger frames first, so that smaller frames are on top
+        self.frames
+            .sort_by(|a, b| b.area().partial_cmp(&a.area()).unwrap());
+    }
+
+    /// Collapse a frame, hiding the nodes inside it, or expand it.
+    fn toggle_frame(&mut self, i: usize) {
+        let frame = &mut self.frames[i];
+        if frame.collapsed {
+            frame.collapsed = false;
+            frame.hidden_nodes.clear();
+        } else {
+            frame.hidden_nodes = self
+                .node_states
+                .iter()
+                .filter(|(_, state)| frame.contains_node(state))
+                .map(|(id, _)| *id)
+                .collect();
+            for node_id in &frame.hidden_nodes {
+                if let Some(state) = self.node_states.get_mut(node_id) {
+                    state.selected = false;
+                }
+            }
+            frame.collapsed = true;
+        }
+    }
+
+    fn start_frame_move(&self, i: usize) -> FrameDrag {
+        let frame = &self.frames[i];
+        let frames: Vec<_> = (0..self.frames.len())
+            .filter(|&j| j == i || (!frame.collapsed && frame.contains_frame(&self.frames[j])))
+            .collect();
+        let mut nodes: Vec<_> = frame.hidden_nodes.clone();
+        if !frame.collapsed {
+            nodes.extend(
+                self.node_states
+                    .iter()
+                    .filter(|(id, state)| !self.is_hidden(id) && frame.contains_node(state))
+                    .map(|(id, _)| *id),
+            );
+        }
+        // Move the nodes hidden in the frames inside
+        for &j in &frames {
+            if j != i {
+                nodes.extend(self.frames[j].hidden_nodes.iter().cloned());
+            }
+        }
+        nodes.sort();
+        nodes.dedup();
+        FrameDrag::Move { frames, nodes }
+    }
+
+    /// Check if the node is hidden in a collapsed frame.
+    fn is_hidden(&self, node_id: &cake::NodeId) -> bool {
+        self.collapsed_frame_of(node_id).is_some()
+    }
+
+    /// Get the visible node at `point`, in canvas coordinates, if any.
+    fn node_at(&self, point: Vec2) -> Option<cake::NodeId> {
+        self.node_states
+            .iter()
+            .rev()
+            .find(|(id, state)| {
+                !self.is_hidden(id)
+                    && state.pos.0 <= point.0
+                    && point.0 <= state.pos.0 + state.size.0
+                    && state.pos.1 <= point.1
+                    && point.1 <= state.pos.1 + state.size.1
+            })
+            .map(|(id, _)| *id)
+    }
+
+    /// Get the index of the collapsed frame hiding the node, if any.
+    fn collapsed_frame_of(&self, node_id: &cake::NodeId) -> Option<usize> {
+        self.frames
+            .iter()
+            .position(|frame| frame.collapsed && frame.hidden_nodes.contains(node_id))
+    }
+
+    /// Forget the state of a node removed from the graph and return it.
+    pub fn forget_node(&mut self, node_id: &cake::NodeId) -> Option<NodeState> {
+        if self.active_node == Some(*node_id) {
+            self.active_node = None;
+        }
+        if self.drag_node == Some(*node_id) {
+            self.drag_node = None;
+        }
+        self.creating_link = None;
+        self.new_node_pin = None;
+        self.new_link = None;
+        self.arranging.remove(node_id);
+        for frame in &mut self.frames {
+            frame.hidden_nodes.retain(|id| id != node_id);
+        }
+        self.node_states.remove_node(node_id)
+    }
+
+    /// Select pasted nodes, and only them.
+    pub fn select_pasted_nodes(&mut self, node_states: Vec<(cake::NodeId, NodeState)>) {
+        self.node_states.deselect_all();
+        for (node_id, mut state) in node_states {
+            state.selected = true;
+            self.node_states.insert(node_id, state);
+        }
+    }
+
+    /// Put back the state of a node restored in the graph.
+    pub fn restore_node_state(&mut self, node_id: cake::NodeId, state: NodeState) {
+        self.node_states.insert(node_id, state);
+    }
+
+    pub fn import(&mut self, node_states: NodeStates, scrolling: Scrolling, frames: Vec<Frame>) {
         // Set UI node states
         self.node_states = node_states;
         // Set scrolling offset
         self.scrolling = scrolling;
 
+        self.frames = frames;
+        self.arranging.clear();
+        self.arrange_unplaced_nodes = true;
+
         // Reset all temporary values
         self.active_node = None;
         self.drag_node = None;
         self.creating_link = None;
+        self.new_node_pin = None;
         self.new_link = None;
+        self.frame_drag = None;
+        self.context_frame = None;
+        self.context_node = None;
+    }
+}
+
+/// Draw a preview of the value flowing out of an output pin. `value` is `None`
+/// if the value has not been computed yet.
+fn value_preview<T, E, ED>(
+    ui: &Ui,
+    value: Option<&cake::Timed<Result<Arc<T>, Arc<cake::compute::ComputeError<E>>>>>,
+    constant_editor: &ED,
+) where
+    T: VariantName,
+    E: Error,
+    ED: ConstantEditor<T>,
+{
+    match value {
+        None => ui.text("Not computed yet."),
+        Some(timed) => match **timed {
+            Ok(ref value) => {
+                ui.text(format!("Value of type '{}'", value.variant_name()));
+                constant_editor.preview(ui, value, cake::Timed::created_on(timed));
+            }
+            Err(ref e) => show_error(ui, &**e),
+        },
+    }
+}
+
+/// Show an error in red with its message.
+fn show_error<E: Error>(ui: &Ui, error: &cake::compute::ComputeError<E>) {
+    ui.text_colored(ERROR_COLOR, "Error!");
+    let stack = ui.push_text_wrap_pos(400.0);
+    ui.text_wrapped(&ImString::new(error.to_string()));
+    stack.pop(ui);
+}
+
+/// Get the error the last computation of node `t_idx` failed with, if any.
+fn cached_error<T, E>(
+    cache: &cake::Cache<T, cake::compute::ComputeError<E>>,
+    t_idx: cake::TransformIdx,
+) -> Option<Arc<cake::compute::ComputeError<E>>> {
+    match cache.get(&cake::Output::new(t_idx, 0)) {
+        Some(Err(e)) => Some(e),
+        _ => None,
+    }
+}
+
+/// Draw the summary of the value flowing out of an output pin in a badge whose
+/// top-left corner is at `pos`.
+fn draw_value_badge<T, E, ED>(
+    ui: &Ui,
+    draw_list: &WindowDrawList,
+    pos: Vec2,
+    value: &Result<Arc<T>, Arc<cake::compute::ComputeError<E>>>,
+    constant_editor: &ED,
+) where
+    ED: ConstantEditor<T>,
+{
+    const BADGE_COLOR: [f32; 4] = [0.1, 0.1, 0.1, 0.8];
+    const BADGE_TEXT_COLOR: [f32; 4] = [0.86, 0.86, 0.86, 1.0];
+    let (summary, text_color) = match value {
+        Ok(value) => match constant_editor.summary(value) {
+            Some(summary) => (summary, BADGE_TEXT_COLOR),
+            None => return,
+        },
+        Err(_) => ("error".to_owned(), ERROR_COLOR),
+    };
+    let summary = ImString::new(summary);
+    let text_size = Vec2::new(ui.calc_text_size(&summary, false, -1.0));
+    let padding = Vec2(0.3, 0.1) * text_size.1;
+    let size = text_size + padding * 2.0;
+    draw_list
+        .add_rect(pos.into(), (pos + size).into(), BADGE_COLOR)
+        .rounding(padding.0)
+        .filled(true)
+        .build();
+    draw_list.add_text((pos + padding).into(), text_color, summary.to_str());
+}
+
+/// Get the color of a pin, dimmed if the link being created cannot be linked
+/// to it.
+fn pin_color<T, E>(
+    dst: &DST<'static, T, E>,
+    pin: &LinkExtremity,
+    link_check: Option<&Result<(), Option<cake::DSTError>>>,
+    creating_link: Option<LinkExtremity>,
+) -> [f32; 4]
+where
+    T: VariantName,
+{
+    let color = pin
+        .type_id(dst)
+        .map_or(color::ANY_TYPE_COLOR, color::type_color);
+    let dimmed = match link_check {
+        Some(Err(_)) => creating_link != Some(*pin),
+        _ => false,
+    };
+    color::with_alpha(color, if dimmed { color::DIMMED_ALPHA } else { 1.0 })
+}
+
+/// Draw a bezier curve given by its end and control points as a dashed line.
+fn add_dashed_bezier_curve(
+    draw_list: &WindowDrawList,
+    points: [Vec2; 4],
+    color: [f32; 3],
+    thickness: f32,
+) {
+    const SEGMENTS: usize = 24;
+    let [p1, cp1, cp2, p2] = points;
+    let point = |t: f32| {
+        let u = 1.0 - t;
+        p1 * (u * u * u) + cp1 * (3.0 * u * u * t) + cp2 * (3.0 * u * t * t) + p2 * (t * t * t)
+    };
+    for i in (0..SEGMENTS).step_by(2) {
+        let start = point(i as f32 / SEGMENTS as f32);
+        let end = point((i + 1) as f32 / SEGMENTS as f32);
+        draw_list
+            .add_line(start.into(), end.into(), color)
+            .thickness(thickness)
+            .build();
+    }
+}
+
+/// Extend the bounds given by their min and max corners to include the
+/// rectangle between `min` and `max`.
+fn extend_bounds(bounds: Option<(Vec2, Vec2)>, min: Vec2, max: Vec2) -> (Vec2, Vec2) {
+    match bounds {
+        None => (min, max),
+        Some((bmin, bmax)) => (
+            Vec2(bmin.0.min(min.0), bmin.1.min(min.1)),
+            Vec2(bmax.0.max(max.0), bmax.1.max(max.1)),
+        ),
+    }
+}
+
+/// Show an item of the add-node popup, with its description as tooltip.
+/// Return true if the item is clicked.
+fn palette_menu_item<T: VariantName, E>(
+    ui: &Ui,
+    id: usize,
+    item: &palette::PaletteItem<T, E>,
+    category: Option<&'static str>,
+) -> bool {
+    let id_stack = ui.push_id(id as i32);
+    let name = ImString::new(item.name());
+    let category = category.map(ImString::new);
+    let mut menu_item = MenuItem::new(&name);
+    if let Some(ref category) = category {
+        menu_item = menu_item.shortcut(category);
+    }
+    let clicked = menu_item.build(ui);
+    if ui.is_item_hovered() {
+        ui.tooltip(|| ui.text(item.description()));
     }
+    id_stack.pop(ui);
+    clicked
 }
@@ -12,13 +12,20 @@ extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 
+mod arrange;
+mod color;
 mod constant_editor;
 mod event;
 mod export;
+mod frame;
+mod history;
 mod id_stack;
 mod layout;
+mod minimap;
 mod node_state;
+mod palette;
 mod scrolling;
+mod snippet;
 mod vec2;
 
 use std::{collections, error, fmt, fs, io, path};
@@ -29,6 +36,7 @@ use imgui_file_explorer::UiFileExplorer;
 
 pub use constant_editor::ConstantEditor;
 use event::ApplyRenderEvent;
+use history::History;
 use layout::NodeEditorLayout;
 
 /// The node editor instance.
@@ -38,6 +46,7 @@ pub struct NodeEditor<T: 'static, E: 'static> {
     cache: cake::Cache<T, cake::compute::ComputeError<E>>,
     macros: cake::macros::MacroManager<'static, T, E>,
     layout: NodeEditorLayout<T, E>,
+    history: History<T, E>,
     error_stack: Vec<Box<dyn error::Error>>,
     success_stack: Vec<ImString>,
 
@@ -48,6 +57,7 @@ pub struct NodeEditor<T: 'static, E: 'static> {
 struct InnerNodeEditor<T: 'static, E: 'static> {
     handle: cake::macros::MacroHandle<'static, T, E>,
     layout: NodeEditorLayout<T, E>,
+    history: History<T, E>,
     opened: bool,
     focus: bool,
 
@@ -60,6 +70,7 @@ impl<T, E> InnerNodeEditor<T, E> {
         Self {
             handle,
             layout: Default::default(),
+            history: Default::default(),
             opened: true,
             focus: true,
             error_stack: vec![],
@@ -152,6 +163,18 @@ where
 }
 
 impl<T, E> NodeEditor<T, E> {
+    /// Select the node identified by `id` and scroll the node editor to it.
+    ///
+    /// Return `false` if there is no such node.
+    pub fn focus_node(&mut self, id: cake::TransformIdx) -> bool {
+        if self.dst.get_transform(id).is_some() {
+            self.layout.focus_node(cake::NodeId::Transform(id));
+            true
+        } else {
+            false
+        }
+    }
+
     /// Get reference to value of contant node identified by `id`.
     pub fn constant_node_value(&self, id: cake::TransformIdx) -> Option<&T> {
         self.dst.get_transform(id).and_then(|t| {
@@ -186,11 +209,18 @@ where
     ) where
         ED: ConstantEditor<T>,
     {
-        let events =
-            self.layout
-                .render(ui, &self.dst, addable_nodes, &self.macros, constant_editor);
+        let events = self.layout.render(
+            ui,
+            &self.dst,
+            Some(&self.cache),
+            addable_nodes,
+            &self.macros,
+            constant_editor,
+        );
         for event in events {
+            let pending = self.history.prepare(&event, &self.dst, &self.layout);
             self.apply_event(event);
+            self.history.record(pending, &self.dst);
         }
     }
 
@@ -256,26 +286,35 @@ where
         for (i, node_edit) in self.nodes_edit.iter_mut().enumerate() {
             let mut opened = node_edit.opened;
             if opened {
-                if node_edit.focus {
-                    unsafe { imgui::sys::igSetNextWindowFocus() };
-                    node_edit.focus = false;
-                }
+                let focus = node_edit.focus;
+                node_edit.focus = false;
                 imgui::Window::new(&imgui::ImString::new(format!(
                     "Macro editor: '{}'###{}",
                     node_edit.handle.name(),
                     i,
                 )))
                 .size(MACRO_WINDOW_DEFAULT_SIZE, imgui::Condition::FirstUseEver)
+                .focused(focus)
                 .opened(&mut opened)
                 .build(ui, || {
                     let events = {
                         let lock = node_edit.handle.read();
                         let dst = lock.dst();
-                        node_edit
-                            .layout
-                            .render(ui, dst, addable_nodes, macros, constant_editor)
+                        node_edit.layout.render(
+                            ui,
+                            dst,
+                            None,
+                            addable_nodes,
+                            macros,
+                            constant_editor,
+                        )
                     };
                     for event in events {
+                        let pending = node_edit.history.prepare(
+                            &event,
+                            node_edit.handle.read().dst(),
+                            &node_edit.layout,
+                        );
                         if let event::RenderEvent::AddNewMacro = event {
                             let new_macr = macros.create_macro().clone();
                             node_edit
@@ -295,9 +334,14 @@ where
                         } else if let event::RenderEvent::Import = event {
                             *import_macro = Some(node_edit.handle.clone());
                             import_macro_focus = true;
+                        } else if let event::RenderEvent::Paste { snippet, pos } = event {
+                            node_edit.paste_with_macros(&snippet, pos, macros);
                         } else {
                             node_edit.apply_event(event);
                         }
+                        node_edit
+                            .history
+                            .record(pending, node_edit.handle.read().dst());
                     }
                 });
                 node_edit.opened = opened;
@@ -316,9 +360,6 @@ where
 
         let mut opened = true;
         if let Some(handle) = import_macro {
-            if import_macro_focus {
-                unsafe { imgui::sys::igSetNextWindowFocus() };
-            }
             let mut selected_path = None;
             let mut cancelled = false;
             let mouse_pos = ui.io().mouse_pos;
@@ -327,6 +368,7 @@ where
                 handle.name(),
             )))
             .opened(&mut opened)
+            .focused(import_macro_focus)
             .save_settings(false)
             .position(mouse_pos, imgui::Condition::Appearing)
             .size([400.0, 410.0], imgui::Condition::Appearing)
@@ -485,6 +527,10 @@ where
     }
     fn remove_node(&mut self, node_id: cake::NodeId) {
         self.dst.remove_node(&node_id);
+        self.layout.forget_node(&node_id);
+    }
+    fn set_transform_mode(&mut self, t_idx: cake::TransformIdx, mode: cake::TransformMode) {
+        self.dst.set_transform_mode(t_idx, mode);
     }
     fn import(&mut self) {
         if let Some(path) = self.layout.import_path.take() {
@@ -524,6 +570,36 @@ where
             }
         }
     }
+    fn add_connected(&mut self, node: event::RenderEvent<T, E>, pin: layout::LinkExtremity) {
+        let previous_ids = self.dst.node_ids();
+        self.apply_event(node);
+        let new_link = self
+            .dst
+            .node_ids()
+            .into_iter()
+            .find(|id| !previous_ids.contains(id))
+            .and_then(|node_id| pin.link_to(&self.dst, node_id));
+        if let Some((output, input_slot)) = new_link {
+            self.connect(output, input_slot);
+        }
+    }
+    fn paste(&mut self, snippet: String, pos: vec2::Vec2) {
+        let pasted = snippet::Snippet::parse(&snippet, &self.macros)
+            .and_then(|snippet| snippet.paste(&mut self.dst, &mut self.macros, pos));
+        match pasted {
+            Ok(node_states) => self.layout.select_pasted_nodes(node_states),
+            Err(e) => {
+                eprintln!("Error on paste! {}", e);
+                self.error_stack.push(Box::new(e));
+            }
+        }
+    }
+    fn undo(&mut self) {
+        self.history.undo(&mut self.dst, &mut self.layout);
+    }
+    fn redo(&mut self) {
+        self.history.redo(&mut self.dst, &mut self.layout);
+    }
 }
 
 impl<T, E> ApplyRenderEvent<T, E> for InnerNodeEditor<T, E>
@@ -582,6 +658,13 @@ where
     }
     fn remove_node(&mut self, node_id: cake::NodeId) {
         self.handle.write().dst_mut().remove_node(&node_id);
+        self.layout.forget_node(&node_id);
+    }
+    fn set_transform_mode(&mut self, t_idx: cake::TransformIdx, mode: cake::TransformMode) {
+        self.handle
+            .write()
+            .dst_mut()
+            .set_transform_mode(t_idx, mode);
     }
     fn import(&mut self) {
         unreachable!("Import can only be handled in NodeEditor's context!");
@@ -617,6 +700,70 @@ where
     fn edit_node(&mut self, _: cake::NodeId) {
         unreachable!("Macro can only be edited in NodeEditor's context!");
     }
+    fn add_connected(&mut self, node: event::RenderEvent<T, E>, pin: layout::LinkExtremity) {
+        let previous_ids = self.handle.read().dst().node_ids();
+        self.apply_event(node);
+        let new_link = {
+            let lock = self.handle.read();
+            let dst = lock.dst();
+            dst.node_ids()
+                .into_iter()
+                .find(|id| !previous_ids.contains(id))
+                .and_then(|node_id| pin.link_to(dst, node_id))
+        };
+        if let Some((output, input_slot)) = new_link {
+            self.connect(output, input_slot);
+        }
+    }
+    fn paste(&mut self, _: String, _: vec2::Vec2) {
+        unreachable!("Paste in macro is handled with the macros of NodeEditor!");
+    }
+    fn undo(&mut self) {
+        let mut lock = self.handle.write();
+        self.history.undo(lock.dst_mut(), &mut self.layout);
+    }
+    fn redo(&mut self) {
+        let mut lock = self.handle.write();
+        self.history.redo(lock.dst_mut(), &mut self.layout);
+    }
+}
+
+impl<T, E> InnerNodeEditor<T, E>
+where
+    T: Clone
+        + cake::VariantName
+        + cake::ConvertibleVariants
+        + cake::NamedAlgorithms<E>
+        + for<'de> serde::Deserialize<'de>,
+{
+    fn paste_with_macros(
+        &mut self,
+        snippet: &str,
+        pos: vec2::Vec2,
+        macros: &mut cake::macros::MacroManager<'static, T, E>,
+    ) {
+        // The macros of the snippet are only added to `macros` once it is
+        // pasted, so that a rejected snippet leaves them untouched.
+        match snippet::Snippet::parse(snippet, macros) {
+            Ok(snippet) => {
+                // FIXME: Prevent non-trivial recursive macros
+                if snippet.uses_macro(&self.handle) {
+                    self.error_stack.push(InnerEditorError::SelfDefiningMacro {
+                        name: self.handle.name(),
+                    });
+                } else {
+                    match snippet.paste(self.handle.write().dst_mut(), macros, pos) {
+                        Ok(node_states) => self.layout.select_pasted_nodes(node_states),
+                        Err(e) => self.error_stack.push(InnerEditorError::PasteError(e)),
+                    }
+                }
+            }
+            Err(e) => {
+                eprintln!("Error on paste in macro! {}", e);
+                self.error_stack.push(InnerEditorError::PasteError(e));
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -624,6 +771,7 @@ enum InnerEditorError {
     IncorrectNodeConnection(cake::DSTError),
     SelfDefiningMacro { name: String },
     ExportError(export::ExportError),
+    PasteError(export::ImportError),
 }
 
 impl fmt::Display for InnerEditorError {
@@ -633,6 +781,7 @@ impl fmt::Display for InnerEditorError {
             IncorrectNodeConnection(e) => write!(f, "{}", e),
             SelfDefiningMacro { name } => write!(f, "Cannot re-use macro '{}' in itself!", name),
             ExportError(e) => write!(f, "Error on export macro! {}", e),
+            PasteError(e) => write!(f, "Error on paste in macro! {}", e),
         }
     }
 }
@@ -665,6 +814,7 @@ struct SerialEditor<'e, T: 'e> {
     dst: cake::macros::SerdeDSTStandAlone<T>,
     node_states: Vec<(&'e cake::NodeId, &'e node_state::NodeState)>,
     scrolling: vec2::Vec2,
+    frames: &'e [frame::Frame],
 
     nodes_edit: Vec<SerialInnerEditor>,
 }
@@ -678,6 +828,7 @@ where
             dst: cake::macros::SerdeDSTStandAlone::from(&editor.dst),
             node_states: editor.layout.node_states().iter().collect(),
             scrolling: editor.layout.scrolling().get_current(),
+            frames: editor.layout.frames(),
             nodes_edit: editor
                 .nodes_edit
                 .iter()
@@ -693,6 +844,9 @@ struct DeserEditor<T> {
     dst: cake::macros::SerdeDSTStandAlone<T>,
     node_states: Vec<(cake::NodeId, node_state::NodeState)>,
     scrolling: vec2::Vec2,
+    /// Editors exported before frames were introduced have no frame
+    #[serde(default)]
+    frames: Vec<frame::Frame>,
 
     nodes_edit: Vec<SerialInnerEditor>,
 }
@@ -727,7 +881,9 @@ where
             node_states
         };
         let scrolling = scrolling::Scrolling::new(deserialized.scrolling);
-        self.layout.import(node_states, scrolling);
+        self.layout
+            .import(node_states, scrolling, deserialized.frames);
+        self.history = History::default();
 
         // Reset cache
         self.output_results = collections::BTreeMap::new();
@@ -775,6 +931,7 @@ impl<T, E> Default for NodeEditor<T, E> {
             cache: cake::Cache::new(),
             macros: cake::macros::MacroManager::new(),
             layout: Default::default(),
+            history: Default::default(),
             error_stack: vec![],
             success_stack: vec![],
             nodes_edit: vec![],
@@ -809,6 +966,8 @@ struct SerialInnerEditor {
     macro_id: cake::uuid::Uuid,
     node_states: Vec<(cake::NodeId, node_state::NodeState)>,
     scrolling: vec2::Vec2,
+    #[serde(default)]
+    frames: Vec<frame::Frame>,
 }
 
 impl SerialInnerEditor {
@@ -822,6 +981,7 @@ impl SerialInnerEditor {
                 .map(|(id, state)| (*id, state.clone()))
                 .collect(),
             scrolling: editor.layout.scrolling().get_current(),
+            frames: editor.layout.frames().to_vec(),
         }
     }
 
@@ -839,10 +999,11 @@ impl SerialInnerEditor {
                 node_states
             };
             let scrolling = scrolling::Scrolling::new(self.scrolling);
-            layout.import(node_states, scrolling);
+            layout.import(node_states, scrolling, self.frames);
             Ok(InnerNodeEditor {
                 handle: handle.clone(),
                 layout,
+                history: Default::default(),
                 opened: false,
                 focus: false,
                 error_stack: vec![],
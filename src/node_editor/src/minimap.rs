@@ -0,0 +1,44 @@
+//! Overview of the whole graph drawn in a corner of the canvas.
+//!
+//! The minimap shows the nodes, the frames and the part of the canvas that is
+//! currently visible (the viewport).
+use vec2::Vec2;
+
+/// Size of the minimap on screen
+pub const SIZE: Vec2 = Vec2(200.0, 150.0);
+/// Space between the minimap and the border of the canvas
+const MARGIN: f32 = 10.0;
+/// Space around the graph inside the minimap
+const PADDING: f32 = 8.0;
+
+/// Mapping between the canvas and the minimap.
+pub struct Minimap {
+    /// Screen position of the top-left corner of the minimap
+    pub pos: Vec2,
+    /// Canvas position shown at the top-left corner of the minimap
+    origin: Vec2,
+    /// Size on the minimap of a unit on the canvas
+    scale: f32,
+}
+
+impl Minimap {
+    /// Make a minimap at the bottom-right corner of the canvas showing the
+    /// area of the canvas between `min` and `max`.
+    pub fn new(win_pos: Vec2, canvas_size: Vec2, min: Vec2, max: Vec2) -> Self {
+        let pos = win_pos + canvas_size - SIZE - Vec2(MARGIN, MARGIN);
+        let area = max - min;
+        let inner = SIZE - Vec2(2.0 * PADDING, 2.0 * PADDING);
+        let scale = (inner.0 / area.0.max(1.0)).min(inner.1 / area.1.max(1.0));
+        // Center the area on the minimap
+        let origin = min - (SIZE * (1.0 / scale) - area) * 0.5;
+        Minimap { pos, origin, scale }
+    }
+
+    pub fn to_screen(&self, canvas_pos: Vec2) -> Vec2 {
+        self.pos + (canvas_pos - self.origin) * self.scale
+    }
+
+    pub fn to_canvas(&self, screen_pos: Vec2) -> Vec2 {
+        self.origin + (screen_pos - self.pos) * (1.0 / self.scale)
+    }
+}
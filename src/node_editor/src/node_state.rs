@@ -34,10 +34,9 @@ impl NodeState {
         font_window_scale: f32,
     ) -> Vec2 {
         Vec2::new((
-            self.pos.0 * font_window_scale,
-            self.pos.1 * font_window_scale
-                + self.size.1 * (slot_idx.into() + 1) as f32 / (slot_cnt.into() + 1) as f32,
-        ))
+            self.pos.0,
+            self.pos.1 + self.size.1 * (slot_idx.into() + 1) as f32 / (slot_cnt.into() + 1) as f32,
+        )) * font_window_scale
     }
 
     pub fn get_output_slot_pos<I: Into<usize>, C: Into<usize>>(
@@ -47,10 +46,9 @@ impl NodeState {
         font_window_scale: f32,
     ) -> Vec2 {
         Vec2::new((
-            self.pos.0 * font_window_scale + self.size.0,
-            self.pos.1 * font_window_scale
-                + self.size.1 * (slot_idx.into() + 1) as f32 / (slot_cnt.into() + 1) as f32,
-        ))
+            self.pos.0 + self.size.0,
+            self.pos.1 + self.size.1 * (slot_idx.into() + 1) as f32 / (slot_cnt.into() + 1) as f32,
+        )) * font_window_scale
     }
 }
 
@@ -62,6 +60,10 @@ impl NodeStates {
         self.0.get(id)
     }
 
+    pub fn get_mut(&mut self, id: &cake::NodeId) -> Option<&mut NodeState> {
+        self.0.get_mut(id)
+    }
+
     pub fn iter(&self) -> btree_map::Iter<cake::NodeId, NodeState> {
         self.0.iter()
     }
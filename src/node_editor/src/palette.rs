@@ -0,0 +1,183 @@
+//! Palette listing the nodes that can be added to a node editor.
+use std::borrow::Cow;
+
+use cake::{self, macros::MacroHandle, Transform, TypeId, VariantName, DST};
+
+use event::RenderEvent;
+use layout::LinkExtremity;
+
+/// Category of the transforms that were not given any.
+const OTHER_CATEGORY: &str = "Other";
+const CONSTANTS_CATEGORY: &str = "Constants";
+const MACROS_CATEGORY: &str = "Macros";
+const OUTPUTS_CATEGORY: &str = "Outputs";
+
+/// A kind of node that can be added from the palette.
+pub enum PaletteItem<'a, T: 'static, E: 'static> {
+    Transform(&'static Transform<'static, T, E>),
+    NewMacro,
+    Macro(&'a MacroHandle<'static, T, E>),
+    Output,
+    Constant(&'static str),
+}
+
+/// List all the nodes that can be added, in the order they are shown.
+pub fn items<'a, T, E>(
+    addable_nodes: &[&'static Transform<'static, T, E>],
+    addable_macros: &'a cake::macros::MacroManager<'static, T, E>,
+    constant_types: &[&'static str],
+) -> Vec<PaletteItem<'a, T, E>> {
+    let mut items: Vec<_> = addable_nodes
+        .iter()
+        .map(|t| PaletteItem::Transform(*t))
+        .collect();
+    items.extend(constant_types.iter().map(|c| PaletteItem::Constant(*c)));
+    items.push(PaletteItem::NewMacro);
+    items.extend(addable_macros.macros().map(PaletteItem::Macro));
+    items.push(PaletteItem::Output);
+    items
+}
+
+/// Categories of the items, in order of first appearance.
+pub fn categories<T: VariantName, E>(items: &[PaletteItem<T, E>]) -> Vec<&'static str> {
+    let mut categories = vec![];
+    for item in items {
+        let category = item.category();
+        if !categories.contains(&category) {
+            categories.push(category);
+        }
+    }
+    categories
+}
+
+impl<'a, T: VariantName, E> PaletteItem<'a, T, E> {
+    pub fn name(&self) -> Cow<'static, str> {
+        match *self {
+            PaletteItem::Transform(t) => t.name(),
+            PaletteItem::NewMacro => Cow::Borrowed("Create new macro"),
+            PaletteItem::Macro(handle) => Cow::Owned(handle.name()),
+            PaletteItem::Output => Cow::Borrowed("Output node"),
+            PaletteItem::Constant(constant_type) => {
+                Cow::Owned(format!("Input node: {}", constant_type))
+            }
+        }
+    }
+
+    pub fn description(&self) -> Cow<'static, str> {
+        match *self {
+            PaletteItem::Transform(t) => t.description(),
+            PaletteItem::NewMacro => Cow::Borrowed("Create an empty macro and add a node of it"),
+            PaletteItem::Macro(handle) => {
+                Cow::Owned(format!("Macro with name '{}'", handle.name()))
+            }
+            PaletteItem::Output => Cow::Borrowed("Output of the graph, shown in its own window"),
+            PaletteItem::Constant(constant_type) => {
+                Cow::Owned(format!("Constant variable of type '{}'", constant_type))
+            }
+        }
+    }
+
+    pub fn category(&self) -> &'static str {
+        match *self {
+            PaletteItem::Transform(t) => t.category().unwrap_or(OTHER_CATEGORY),
+            PaletteItem::NewMacro | PaletteItem::Macro(_) => MACROS_CATEGORY,
+            PaletteItem::Output => OUTPUTS_CATEGORY,
+            PaletteItem::Constant(_) => CONSTANTS_CATEGORY,
+        }
+    }
+
+    /// Check if the name of the item contains the characters of `pattern` in
+    /// order, or if its description contains `pattern`. Case is ignored.
+    ///
+    /// Return `Some(true)` if the name matches, `Some(false)` if only the
+    /// description does, and `None` if the item does not match.
+    pub fn matches(&self, pattern: &str) -> Option<bool> {
+        let pattern = pattern.trim().to_lowercase();
+        if fuzzy_match(&pattern, &self.name().to_lowercase()) {
+            Some(true)
+        } else if self.description().to_lowercase().contains(&pattern) {
+            Some(false)
+        } else {
+            None
+        }
+    }
+
+    pub fn event(&self) -> RenderEvent<T, E> {
+        match *self {
+            PaletteItem::Transform(t) => RenderEvent::AddTransform(t),
+            PaletteItem::NewMacro => RenderEvent::AddNewMacro,
+            PaletteItem::Macro(handle) => RenderEvent::AddMacro(handle.clone()),
+            PaletteItem::Output => RenderEvent::CreateOutput,
+            PaletteItem::Constant(constant_type) => RenderEvent::AddConstant(constant_type),
+        }
+    }
+}
+
+impl<'a, T, E> PaletteItem<'a, T, E>
+where
+    T: VariantName + cake::ConvertibleVariants,
+{
+    /// Check if the node can be connected to `pin` once added to `dst`.
+    pub fn accepts(&self, pin: &LinkExtremity, dst: &DST<'static, T, E>) -> bool {
+        let (inputs, outputs) = match *self {
+            PaletteItem::Transform(t) => (t.input_types(), t.outputs()),
+            PaletteItem::NewMacro => return false,
+            PaletteItem::Macro(handle) => (handle.input_types(), handle.outputs()),
+            // Output nodes accept values of any type
+            PaletteItem::Output => return pin.is_output(),
+            PaletteItem::Constant(constant_type) => (vec![], vec![TypeId(constant_type)]),
+        };
+        pin.compatible_slot(dst, &inputs, &outputs).is_some()
+    }
+}
+
+/// Check if the characters of `pattern` appear in `text` in the same order.
+fn fuzzy_match(pattern: &str, text: &str) -> bool {
+    let mut text = text.chars();
+    pattern
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .all(|p| text.any(|c| c == p))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{fuzzy_match, PaletteItem};
+    use cake::VariantName;
+
+    struct Value;
+
+    impl VariantName for Value {
+        fn variant_name(&self) -> &'static str {
+            "Float"
+        }
+        fn variant_names() -> &'static [&'static str] {
+            &["Float"]
+        }
+    }
+
+    #[test]
+    fn test_fuzzy_match_keeps_order() {
+        assert!(fuzzy_match("mkfl", "make_float"));
+        assert!(fuzzy_match("make float", "make_float"));
+        assert!(!fuzzy_match("flmk", "make_float"));
+    }
+
+    #[test]
+    fn test_fuzzy_match_no_match() {
+        assert!(!fuzzy_match("fitz", "open_fits"));
+        assert!(!fuzzy_match("fitss", "open_fits"));
+        assert!(fuzzy_match("", "open_fits"));
+    }
+
+    #[test]
+    fn test_matches_ignores_case() {
+        let item: PaletteItem<Value, ()> = PaletteItem::Constant("Float");
+        // The name is "Input node: Float"
+        assert_eq!(item.matches("FLOAT"), Some(true));
+        assert_eq!(item.matches("  inFl "), Some(true));
+        // Only the description mentions the word "variable"
+        assert_eq!(item.matches("VARIABLE"), Some(false));
+        assert_eq!(item.matches("integer"), None);
+    }
+}
@@ -18,6 +18,15 @@ impl Scrolling {
         self.current
     }
 
+    pub fn get_target(&self) -> Vec2 {
+        self.target
+    }
+
+    /// Check if current is close enough to target to stop moving
+    pub fn is_settled(&self) -> bool {
+        (self.target - self.current).squared_norm() < 0.25
+    }
+
     /// Set target fluidly
     pub fn set_target(&mut self, target: Vec2) {
         self.target = target;
@@ -0,0 +1,141 @@
+//! Copy and paste of nodes.
+//!
+//! Copied nodes are written in RON format, like the graph of an exported
+//! editor: the nodes and the links between them are serialized as a
+//! `SerialDST`, followed by the macros they use and by the positions of the
+//! nodes. Snippets can thus be pasted in another editor, or in another
+//! instance of aflak through the system clipboard.
+use cake::{
+    self,
+    macros::{MacroHandle, MacroManager},
+    DST,
+};
+use ron;
+use serde::{Deserialize, Serialize};
+
+use export::{ExportError, ImportError};
+use node_state::{NodeState, NodeStates};
+use vec2::Vec2;
+
+/// Copied nodes, as written to the clipboard.
+#[derive(Serialize, Deserialize)]
+#[serde(bound(deserialize = "T: Deserialize<'de>"))]
+struct SerialSnippet<T> {
+    dst: cake::macros::SerdeDSTStandAlone<T>,
+    /// Positions of the nodes relative to the top-left corner of the snippet
+    node_states: Vec<(cake::NodeId, NodeState)>,
+}
+
+/// Serialize the nodes with given IDs and the links between them.
+///
+/// Return the snippet along with the position of its top-left corner.
+pub fn copy<T, E>(
+    dst: &DST<'static, T, E>,
+    node_states: &NodeStates,
+    node_ids: &[cake::NodeId],
+) -> Result<(String, Vec2), ExportError>
+where
+    T: Clone + cake::VariantName + Serialize,
+{
+    let sub_dst = dst.extract(node_ids);
+    let states: Vec<_> = sub_dst
+        .node_ids()
+        .into_iter()
+        .filter_map(|id| node_states.get(&id).map(|state| (id, state.clone())))
+        .collect();
+    let origin = states
+        .iter()
+        .map(|(_, state)| state.pos)
+        .fold(None, |origin: Option<Vec2>, pos| {
+            Some(origin.map_or(pos, |o| Vec2(o.0.min(pos.0), o.1.min(pos.1))))
+        })
+        .unwrap_or_default();
+    let snippet = SerialSnippet {
+        dst: cake::macros::SerdeDSTStandAlone::from(&sub_dst),
+        node_states: states
+            .into_iter()
+            .map(|(id, mut state)| {
+                state.pos = state.pos - origin;
+                state.selected = false;
+                (id, state)
+            })
+            .collect(),
+    };
+    let serialized = ron::ser::to_string_pretty(&snippet, Default::default())?;
+    Ok((serialized, origin))
+}
+
+/// Nodes read from a snippet, ready to be pasted.
+pub struct Snippet<T: 'static, E: 'static> {
+    dst: DST<'static, T, E>,
+    node_states: Vec<(cake::NodeId, NodeState)>,
+    /// Macros used by the snippet that were not managed when it was read
+    new_macros: Vec<MacroHandle<'static, T, E>>,
+}
+
+impl<T, E> Snippet<T, E>
+where
+    T: Clone
+        + cake::VariantName
+        + cake::ConvertibleVariants
+        + cake::NamedAlgorithms<E>
+        + for<'de> Deserialize<'de>,
+{
+    /// Read a snippet using the macros of `macros`. The macros it uses that
+    /// are not managed by `macros` are only added to it when the snippet is
+    /// pasted.
+    pub fn parse(snippet: &str, macros: &MacroManager<'static, T, E>) -> Result<Self, ImportError> {
+        let deserialized: SerialSnippet<T> = ron::de::from_str(snippet)?;
+        let (dst, new_macros) = deserialized.dst.into_dst_with_macros(macros)?;
+        Ok(Snippet {
+            dst,
+            node_states: deserialized.node_states,
+            new_macros,
+        })
+    }
+}
+
+impl<T, E> Snippet<T, E> {
+    /// Check if the snippet contains a node of the given macro.
+    pub fn uses_macro(&self, handle: &MacroHandle<'static, T, E>) -> bool {
+        self.dst.nodes_iter().any(|(_, node)| match node {
+            cake::Node::Transform(t) => match t.algorithm() {
+                cake::Algorithm::Macro { handle: h } => h == handle,
+                _ => false,
+            },
+            cake::Node::Output(_) => false,
+        })
+    }
+
+    /// Add the nodes of the snippet to `dst`, with the top-left corner of
+    /// the snippet at `pos`, and the macros they use to `macros`.
+    ///
+    /// Return the IDs of the new nodes along with their states.
+    pub fn paste(
+        self,
+        dst: &mut DST<'static, T, E>,
+        macros: &mut MacroManager<'static, T, E>,
+        pos: Vec2,
+    ) -> Result<Vec<(cake::NodeId, NodeState)>, ImportError>
+    where
+        T: Clone,
+    {
+        for handle in self.new_macros {
+            macros.add_handle(handle)?;
+        }
+        let node_states = self.node_states;
+        Ok(dst
+            .merge(self.dst)
+            .into_iter()
+            .map(|(old_id, new_id)| {
+                let mut state = node_states
+                    .iter()
+                    .find(|(id, _)| *id == old_id)
+                    .map(|(_, state)| state.clone())
+                    .unwrap_or_default();
+                state.pos = state.pos + pos;
+                (new_id, state)
+            })
+            .collect())
+    }
+}
@@ -25,6 +25,7 @@ pub struct Aflak {
     pub quit: bool,
     file_dialog: Option<FileDialog>,
     recent_files: Vec<PathBuf>,
+    constant_editor: MyConstantEditor,
 }
 
 impl Aflak {
@@ -37,6 +38,7 @@ impl Aflak {
             quit: false,
             file_dialog: None,
             recent_files: vec![],
+            constant_editor: MyConstantEditor::default(),
         }
     }
 
@@ -100,10 +102,10 @@ impl Aflak {
             .size(size, Condition::FirstUseEver)
             .build(ui, || {
                 self.node_editor
-                    .render(ui, addable_nodes, &MyConstantEditor);
+                    .render(ui, addable_nodes, &self.constant_editor);
             });
         self.node_editor
-            .inner_editors_render(ui, addable_nodes, &MyConstantEditor);
+            .inner_editors_render(ui, addable_nodes, &self.constant_editor);
         self.node_editor.render_popups(ui);
     }
 
@@ -1,11 +1,28 @@
+use std::cell::RefCell;
+use std::time::Instant;
+
 use imgui_file_explorer::{UiFileExplorer, TOP_FOLDER};
 use node_editor::ConstantEditor;
-use primitives::{self, IOValue};
+use primitives::{
+    self,
+    ndarray::{Array2, ArrayView2, Axis, Ix2, Slice},
+    IOValue, WcsArray,
+};
 
 use imgui::{ChildWindow, Id, ImString, Ui};
 
+/// Extensions of the files that can be opened.
+#[cfg(feature = "hdf5")]
+const FILE_EXTENSIONS: &[&str] = &["fits", "fit", "h5", "hdf5", "npy"];
+#[cfg(not(feature = "hdf5"))]
+const FILE_EXTENSIONS: &[&str] = &["fits", "fit", "npy"];
+
 #[derive(Default)]
-pub struct MyConstantEditor;
+pub struct MyConstantEditor {
+    /// Preview of the last previewed image, kept until another image is
+    /// previewed
+    image_preview: RefCell<Option<ImagePreview>>,
+}
 
 impl ConstantEditor<primitives::IOValue> for MyConstantEditor {
     fn editor<'a, I>(&self, ui: &Ui, constant: &IOValue, id: I, read_only: bool) -> Option<IOValue>
@@ -26,6 +43,177 @@ impl ConstantEditor<primitives::IOValue> for MyConstantEditor {
 
         some_new_value
     }
+
+    fn preview(&self, ui: &Ui, value: &IOValue, created_on: Instant) {
+        match *value {
+            IOValue::Str(ref string) => ui.text(format!("{:?}", string)),
+            IOValue::Path(ref path) => ui.text(format!("{}", path.display())),
+            IOValue::Image(ref image) => {
+                let mut preview = self.image_preview.borrow_mut();
+                let key = (created_on, image as *const WcsArray as usize);
+                match *preview {
+                    Some(ref cached) if cached.key == key => (),
+                    _ => *preview = Some(ImagePreview::new(image, key)),
+                }
+                if let Some(ref preview) = *preview {
+                    preview.draw(ui, image);
+                }
+            }
+            IOValue::Fits(_) | IOValue::Map2dTo3dCoords(_) | IOValue::Roi(_) => (),
+            _ => {
+                if let Some(summary) = self.summary(value) {
+                    ui.text(summary);
+                }
+            }
+        }
+    }
+
+    fn summary(&self, value: &IOValue) -> Option<String> {
+        match *value {
+            IOValue::Integer(int) => Some(format!("{}", int)),
+            IOValue::Float(float) => Some(format!("{:.4}", float)),
+            IOValue::Float2([x, y]) => Some(format!("[{:.4}, {:.4}]", x, y)),
+            IOValue::Float3([x, y, z]) => Some(format!("[{:.4}, {:.4}, {:.4}]", x, y, z)),
+            IOValue::Bool(b) => Some(format!("{}", b)),
+            IOValue::Str(ref string) => {
+                const MAX_CHARS: usize = 16;
+                if string.chars().count() > MAX_CHARS {
+                    let start: String = string.chars().take(MAX_CHARS).collect();
+                    Some(format!("\"{}...\"", start))
+                } else {
+                    Some(format!("{:?}", string))
+                }
+            }
+            IOValue::Image(ref image) => {
+                let unit = image.array().unit().repr();
+                let shape = shape_repr(image.shape());
+                if unit.is_empty() {
+                    Some(shape)
+                } else {
+                    Some(format!("{} {}", shape, unit))
+                }
+            }
+            IOValue::FitsExtensions(ref extensions) => {
+                let names: Vec<_> = extensions
+                    .iter()
+                    .map(|(name, _)| if name.is_empty() { "?" } else { name.as_str() })
+                    .collect();
+                Some(format!("Extensions: {}", names.join(", ")))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Represent the shape of an array, e.g. "640x480".
+fn shape_repr(shape: &[usize]) -> String {
+    let dims: Vec<_> = shape.iter().map(|dim| dim.to_string()).collect();
+    dims.join("x")
+}
+
+/// Maximum number of points of the sparkline of a 1D image
+const MAX_POINTS: usize = 256;
+/// Maximum number of pixels per side of the thumbnail of a 2D image
+const MAX_PIXELS: usize = 64;
+
+/// Bounds and plot of an image shown in its preview, computed once for each
+/// computed image.
+struct ImagePreview {
+    /// Instant the image was computed, and its address
+    key: (Instant, usize),
+    /// Minimum and maximum of the values that are not NaN, if any
+    bounds: Option<(f32, f32)>,
+    plot: PreviewPlot,
+}
+
+enum PreviewPlot {
+    None,
+    /// Values of a 1D image, sampled to at most `MAX_POINTS` points
+    Sparkline(Vec<f32>),
+    /// Values of a 2D image, sampled to at most `MAX_PIXELS` pixels per side
+    Thumbnail(Array2<f32>),
+}
+
+impl ImagePreview {
+    fn new(image: &WcsArray, key: (Instant, usize)) -> Self {
+        let bounds = image.value_bounds();
+        let shape = image.shape();
+        let plot = match shape.len() {
+            1 => {
+                let step = ((shape[0] + MAX_POINTS - 1) / MAX_POINTS).max(1);
+                let points = image.scalar().iter().step_by(step).cloned().collect();
+                PreviewPlot::Sparkline(points)
+            }
+            2 if bounds.is_some() => {
+                let step = ((shape[0].max(shape[1]) + MAX_PIXELS - 1) / MAX_PIXELS).max(1);
+                let array = image.scalar();
+                let mut sampled = array.view();
+                for axis in 0..2 {
+                    sampled.slice_axis_inplace(Axis(axis), Slice::new(0, None, step as isize));
+                }
+                let sampled = sampled
+                    .into_dimensionality::<Ix2>()
+                    .expect("Image is 2-dimensional");
+                PreviewPlot::Thumbnail(sampled.to_owned())
+            }
+            _ => PreviewPlot::None,
+        };
+        ImagePreview { key, bounds, plot }
+    }
+
+    /// Show the shape, bounds and unit of an image, with a sparkline for 1D
+    /// images and a thumbnail for 2D images.
+    fn draw(&self, ui: &Ui, image: &WcsArray) {
+        ui.text(format!("Shape: {}", shape_repr(image.shape())));
+        let unit = image.array().unit().repr();
+        if !unit.is_empty() {
+            ui.text(format!("Unit: {}", unit));
+        }
+        if let Some((min, max)) = self.bounds {
+            ui.text(format!("Min: {}, Max: {}", min, max));
+        }
+        match (&self.plot, self.bounds) {
+            (PreviewPlot::Sparkline(ref points), _) => {
+                ui.plot_lines(im_str!("##sparkline"), points)
+                    .graph_size([200.0, 50.0])
+                    .build();
+            }
+            (PreviewPlot::Thumbnail(ref values), Some((min, max))) => {
+                thumbnail(ui, values.view(), min, max)
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Draw a thumbnail of a 2D image in shades of grey, one square per pixel,
+/// from black for `vmin` to white for `vmax`. NaN values are left transparent.
+fn thumbnail(ui: &Ui, image: ArrayView2<f32>, vmin: f32, vmax: f32) {
+    const PIXEL_SIZE: f32 = 2.0;
+    let (rows, cols) = image.dim();
+    let range = vmax - vmin;
+    let [x, y] = ui.cursor_screen_pos();
+    let draw_list = ui.get_window_draw_list();
+    for row in 0..rows {
+        for col in 0..cols {
+            let val = image[[row, col]];
+            if val.is_nan() {
+                continue;
+            }
+            let grey = if range > 0.0 {
+                (val - vmin) / range
+            } else {
+                0.5
+            };
+            let min = [x + col as f32 * PIXEL_SIZE, y + row as f32 * PIXEL_SIZE];
+            let max = [min[0] + PIXEL_SIZE, min[1] + PIXEL_SIZE];
+            draw_list
+                .add_rect(min, max, [grey, grey, grey])
+                .filled(true)
+                .build();
+        }
+    }
+    ui.dummy([cols as f32 * PIXEL_SIZE, rows as f32 * PIXEL_SIZE]);
 }
 
 fn inner_editor(ui: &Ui, constant: &IOValue, read_only: bool) -> Option<IOValue> {
@@ -123,7 +311,7 @@ fn inner_editor(ui: &Ui, constant: &IOValue, read_only: bool) -> Option<IOValue>
                     .size([size[0].max(400.0), 150.0])
                     .horizontal_scrollbar(true)
                     .build(ui, || {
-                        ret = ui.file_explorer(TOP_FOLDER, &["fits", "fit"]);
+                        ret = ui.file_explorer(TOP_FOLDER, FILE_EXTENSIONS);
                     });
                 if let Ok(Some(new_file)) = ret {
                     if *file != new_file {
@@ -139,7 +327,7 @@ fn inner_editor(ui: &Ui, constant: &IOValue, read_only: bool) -> Option<IOValue>
         IOValue::Roi(ref roi) => {
             match roi {
                 primitives::ROI::All => ui.text("Whole image"),
-                primitives::ROI::PixelList(_) => {
+                _ => {
                     ui.text("Non-writable");
                     if ui.is_item_hovered() {
                         ui.tooltip(|| {
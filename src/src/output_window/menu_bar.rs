@@ -3,6 +3,7 @@ use std::fmt;
 use std::fs;
 use std::io::{self, Write};
 use std::path::Path;
+use std::sync::Arc;
 use std::time::Instant;
 
 use glium;
@@ -15,12 +16,8 @@ use aflak_plot::{
     plot::UiImage1d,
     AxisTransform, InteractionIterMut, ValueIter,
 };
-use cake::OutputId;
-use primitives::{
-    self,
-    fitrs::{Fits, Hdu},
-    IOValue, ROI,
-};
+use cake::{Bow, OutputId};
+use primitives::{self, fitrs::Fits, IOValue, ROI};
 
 use super::{AflakNodeEditor, EditableValues, OutputWindow};
 
@@ -143,11 +140,23 @@ fn update_state_from_editor(
     }
 }
 
+/// The image viewer counts rows from the bottom of the image, while ROIs use
+/// the row of the image. `height` is the height of the displayed 2D image, used
+/// to convert between both.
 fn update_editor_from_state(
     value_iter: ValueIter,
     store: &mut EditableValues,
     node_editor: &mut AflakNodeEditor,
+    height: Option<usize>,
 ) {
+    let height = height.unwrap_or(0);
+    let flip_pixels = |pixels: Vec<(usize, usize)>| {
+        pixels
+            .into_iter()
+            .filter(|&(_, y)| y < height)
+            .map(|(x, y)| (x, height - 1 - y))
+            .collect()
+    };
     for (id, value) in value_iter {
         use aflak_plot::Value;
         let val = match value {
@@ -155,9 +164,12 @@ fn update_editor_from_state(
             Value::Float(f) => IOValue::Float(f),
             Value::Float2(f) => IOValue::Float2(f),
             Value::Float3(f) => IOValue::Float3(f),
-            Value::FinedGrainedROI(pixels) => IOValue::Roi(ROI::PixelList(pixels)),
-            Value::Line(pixels) => IOValue::Roi(ROI::PixelList(pixels)),
-            Value::Circle(pixels) => IOValue::Roi(ROI::PixelList(pixels)),
+            Value::FinedGrainedROI(pixels) => IOValue::Roi(ROI::PixelList(flip_pixels(pixels))),
+            Value::Line(pixels) => IOValue::Roi(ROI::PixelList(flip_pixels(pixels))),
+            Value::Circle((x, y), radius) => IOValue::Roi(ROI::Circle {
+                center: (x, height as f32 - y),
+                radius,
+            }),
         };
         if store.contains_key(id) {
             let t_idx = *store.get(id).unwrap();
@@ -300,16 +312,17 @@ impl MenuBar for ROI {
     }
 
     fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ExportError> {
-        write_to_file_as_display(path, &format!("{:?}", self))?;
+        let regions = self.to_ds9().map_err(ExportError::Unsupported)?;
+        write_to_file_as_bytes(path, regions.as_bytes())?;
         Ok(())
     }
 
-    const EXTENSION: &'static str = "txt";
+    const EXTENSION: &'static str = "reg";
 }
 
 impl MenuBar for primitives::WcsArray {
     fn file_submenu(&self, ui: &Ui, window: &mut OutputWindow) {
-        match self.scalar().ndim() {
+        match self.shape().len() {
             1 | 2 => {
                 let has_wcs_data = self.wcs().is_some();
                 MenuItem::new(im_str!("Show pixels"))
@@ -324,7 +337,7 @@ impl MenuBar for primitives::WcsArray {
     }
 
     fn other_menu(&self, ui: &Ui, window: &mut OutputWindow) {
-        match self.scalar().ndim() {
+        match self.shape().len() {
             2 => {
                 if let Some(menu) = ui.begin_menu(im_str!("Others"), true) {
                     MenuItem::new(im_str!("Approx Line"))
@@ -340,13 +353,11 @@ impl MenuBar for primitives::WcsArray {
     where
         F: glium::backend::Facade,
     {
-        use primitives::ndarray::Dimension;
         let ui = &ctx.ui;
-        match self.scalar().dim().ndim() {
+        match self.shape().len() {
             0 => {
-                let arr = self.array();
-                let val = arr.scalar()[[]];
-                let unit = arr.unit().repr();
+                let val = self.scalar()[[]];
+                let unit = self.array().unit().repr();
                 ui.text(format!("{} {}", val, unit));
             }
             1 => {
@@ -370,13 +381,19 @@ impl MenuBar for primitives::WcsArray {
                         _ => None,
                     }
                 };
-                if let Err(e) = ui.image1d(&self.scalar1(), "", unit, transform.as_ref(), state) {
+                let values = self.scalar();
+                let values = values
+                    .view()
+                    .into_dimensionality()
+                    .expect("Image is 1-dimensional");
+                if let Err(e) = ui.image1d(&values, "", unit, transform.as_ref(), state) {
                     ui.text(format!("Error on drawing plot! {}", e))
                 }
                 update_editor_from_state(
                     state.stored_values(),
                     &mut ctx.window.editable_values,
                     ctx.node_editor,
+                    None,
                 );
             }
             2 => {
@@ -401,9 +418,7 @@ impl MenuBar for primitives::WcsArray {
                                     })
                                 }),
                                 Some(AxisTransform::new(axis0.name(), axis1.unit(), {
-                                    let max_height =
-                                        (self.scalar().dim().as_array_view().first().unwrap() - 1)
-                                            as f32;
+                                    let max_height = (self.shape()[0] - 1) as f32;
                                     move |t| wcs.pix2world([0.0, max_height - t, 0.0, 0.0])[1]
                                 })),
                             )
@@ -417,13 +432,20 @@ impl MenuBar for primitives::WcsArray {
                     None => true,
                 };
                 if new_incoming_image {
-                    let value_ref: ArcRef<_> = ctx.value.clone().into();
-                    let image_ref = value_ref.map(|value| {
-                        if let IOValue::Image(image) = value {
-                            image.scalar()
-                        } else {
-                            unreachable!("Expect an Image")
-                        }
+                    // Values of another type are converted once to be shown
+                    let value = if self.dtype() == primitives::DataType::F32 {
+                        ctx.value.clone()
+                    } else {
+                        let image = self.clone().into_dtype(primitives::DataType::F32);
+                        Arc::new(IOValue::Image(image))
+                    };
+                    let value_ref: ArcRef<_> = value.into();
+                    let image_ref = value_ref.map(|value| match value {
+                        IOValue::Image(image) => match image.scalar() {
+                            Bow::Borrowed(array) => array,
+                            Bow::Owned(_) => unreachable!("Values were converted to f32"),
+                        },
+                        _ => unreachable!("Expect an Image"),
                     });
                     if let Err(e) = state.set_image(
                         image_ref,
@@ -450,32 +472,20 @@ impl MenuBar for primitives::WcsArray {
                     state.stored_values(),
                     &mut ctx.window.editable_values,
                     ctx.node_editor,
+                    Some(self.shape()[0]),
                 );
             }
             _ => {
                 ui.text(format!(
                     "Unimplemented for image of dimension {}",
-                    self.scalar().ndim()
+                    self.shape().len()
                 ));
             }
         }
     }
 
     fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ExportError> {
-        let arr = self.scalar();
-        // 0-dim ndarrays contain a single scalar value, however they should be
-        // treated as a 1-dimensional data array of length 1 when embedded as
-        // FITS file.
-        let shape = if arr.ndim() == 0 { &[1] } else { arr.shape() };
-        Fits::create(
-            path,
-            Hdu::new(
-                shape,
-                arr.as_slice()
-                    .expect("Could not get slice out of array")
-                    .to_owned(),
-            ),
-        )?;
+        Fits::create(path, self.to_hdu())?;
         Ok(())
     }
 
@@ -498,12 +508,14 @@ fn write_to_file_as_bytes<P: AsRef<Path>>(path: P, buf: &[u8]) -> io::Result<()>
 #[derive(Debug)]
 pub enum ExportError {
     IOError(io::Error),
+    Unsupported(String),
 }
 
 impl fmt::Display for ExportError {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         match self {
             ExportError::IOError(e) => write!(fmt, "{}", e),
+            ExportError::Unsupported(msg) => write!(fmt, "{}", msg),
         }
     }
 }
@@ -5,18 +5,18 @@ mod menu_bar;
 mod visualizable;
 
 use glium;
-use imgui::{Ui, Window};
+use imgui::{ImString, Selectable, Ui, Window};
 use owning_ref::ArcRef;
 
 use aflak_plot::{
     imshow::{self, Textures},
     plot, InteractionId,
 };
-use cake::{OutputId, TransformIdx};
-use primitives::{ndarray, IOValue, SuccessOut};
+use cake::{compute::ComputeError, OutputId, TransformIdx};
+use primitives::{ndarray, IOErr, IOValue, SuccessOut};
 
 use self::menu_bar::MenuBar;
-use self::visualizable::{Initializing, Unimplemented, Visualizable};
+use self::visualizable::{FitsHeaders, Initializing, Unimplemented, Visualizable};
 use aflak::AflakNodeEditor;
 
 #[derive(Default)]
@@ -25,6 +25,7 @@ pub struct OutputWindow {
     image2d_state: imshow::State<ArcRef<IOValue, ndarray::ArrayD<f32>>>,
     editable_values: EditableValues,
     show_pixels: bool,
+    fits_header_filter: String,
 }
 
 type EditableValues = HashMap<InteractionId, TransformIdx>;
@@ -49,7 +50,7 @@ impl OutputWindow {
                 vec![]
             }
             Some(Err(e)) => {
-                e.draw(ui, window);
+                window.build(ui, || show_error(ui, &e, node_editor));
                 vec![]
             }
             Some(Ok(result)) => {
@@ -75,7 +76,20 @@ impl OutputWindow {
                     IOValue::Image(ref image) => image.draw(ctx, window),
                     IOValue::Roi(ref roi) => roi.draw(ctx, window),
                     IOValue::Fits(ref fits) => {
-                        fits.draw(ui, window);
+                        let filter = &mut ctx.window.fits_header_filter;
+                        window.build(ui, || {
+                            let mut search = ImString::with_capacity(256);
+                            search.push_str(filter);
+                            if ui.input_text(im_str!("Search"), &mut search).build() {
+                                *filter = search.to_str().to_owned();
+                            }
+                            ui.separator();
+                            FitsHeaders {
+                                fits,
+                                filter: filter.as_str(),
+                            }
+                            .visualize(ui);
+                        });
                         vec![]
                     }
                     val => {
@@ -87,3 +101,20 @@ impl OutputWindow {
         }
     }
 }
+
+/// Show an error with the stack of nodes it went through. Clicking on an
+/// `in node #N` line selects and shows node #N in the node editor.
+fn show_error(ui: &Ui, error: &ComputeError<IOErr>, node_editor: &mut AflakNodeEditor) {
+    ui.text_wrapped(&ImString::new(error.root_message()));
+    for (i, (t_idx, t_name)) in error.node_stack().into_iter().enumerate() {
+        let id_stack = ui.push_id(i as i32);
+        let line = format!("    in node #{} {}", t_idx.id(), t_name);
+        if Selectable::new(&ImString::new(line)).build(ui) {
+            node_editor.focus_node(t_idx);
+        }
+        if ui.is_item_hovered() {
+            ui.tooltip_text("Click to show the node in the node editor");
+        }
+        id_stack.pop(ui);
+    }
+}
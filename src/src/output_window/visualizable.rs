@@ -1,6 +1,4 @@
-use std::fmt;
-
-use imgui::{ImString, Ui, Window};
+use imgui::{Condition, ImString, Ui, Window};
 
 use cake;
 use primitives::fitrs::Fits;
@@ -42,16 +40,20 @@ impl Visualizable for Unimplemented {
     }
 }
 
-impl<E: fmt::Display> Visualizable for cake::compute::ComputeError<E> {
-    fn visualize(&self, ui: &Ui) {
-        ui.text_wrapped(&ImString::new(format!("{}", self)));
-    }
+/// Headers of a FITS file.
+///
+/// Only the cards whose key or value contain `filter` (ignoring case) are
+/// shown.
+pub struct FitsHeaders<'a> {
+    pub fits: &'a Fits,
+    pub filter: &'a str,
 }
 
-impl Visualizable for Fits {
+impl<'a> Visualizable for FitsHeaders<'a> {
     fn visualize(&self, ui: &Ui) {
+        let filter = self.filter.to_lowercase();
         let mut has_hdus = false;
-        for (i, hdu) in self.iter().enumerate() {
+        for (i, hdu) in self.fits.iter().enumerate() {
             use primitives::fitrs::HeaderValue::*;
             use std::borrow::Cow;
 
@@ -69,22 +71,29 @@ impl Visualizable for Fits {
             };
 
             let id_stack = ui.push_id(i as i32);
-            ui.tree_node(&tree_name).build(|| {
+            let mut tree_node = ui.tree_node(&tree_name);
+            if !filter.is_empty() {
+                tree_node = tree_node.opened(true, Condition::Always);
+            }
+            tree_node.build(|| {
                 for (key, value) in &hdu {
+                    let value = value.as_ref().map(|value| match value {
+                        CharacterString(s) => Cow::Borrowed(s.as_str()),
+                        Logical(true) => Cow::Borrowed("True"),
+                        Logical(false) => Cow::Borrowed("False"),
+                        IntegerNumber(i) => Cow::Owned(format!("{}", i)),
+                        RealFloatingNumber(f) => Cow::Owned(format!("{:E}", f)),
+                        ComplexIntegerNumber(a, b) => Cow::Owned(format!("{} + {}i", a, b)),
+                        ComplexFloatingNumber(a, b) => Cow::Owned(format!("{:E} + {:E}i", a, b)),
+                    });
+                    let matches = |text: &str| text.to_lowercase().contains(&filter);
+                    if !matches(key) && !value.as_ref().map_or(false, |value| matches(value)) {
+                        continue;
+                    }
+
                     ui.text(key);
                     if let Some(value) = value {
                         ui.same_line(150.0);
-                        let value = match value {
-                            CharacterString(s) => Cow::Borrowed(s.as_str()),
-                            Logical(true) => Cow::Borrowed("True"),
-                            Logical(false) => Cow::Borrowed("False"),
-                            IntegerNumber(i) => Cow::Owned(format!("{}", i)),
-                            RealFloatingNumber(f) => Cow::Owned(format!("{:E}", f)),
-                            ComplexIntegerNumber(a, b) => Cow::Owned(format!("{} + {}i", a, b)),
-                            ComplexFloatingNumber(a, b) => {
-                                Cow::Owned(format!("{:E} + {:E}i", a, b))
-                            }
-                        };
                         ui.text(value);
                     }
                     ui.separator();